@@ -9,55 +9,48 @@ use windows::Win32::Graphics::Direct3D::ID3DBlob;
 fn main() -> Result<(), Box<dyn Error>> {
     static VERTEX_SHADER: &str = include_str!("src/shaders/vertex_shader.vs_4_0");
     static PIXEL_SHADER: &str = include_str!("src/shaders/pixel_shader.ps_4_0");
+    static COMPOSITE_VERTEX_SHADER: &str =
+        include_str!("src/shaders/composite_vertex_shader.vs_4_0");
+    static COMPOSITE_PIXEL_SHADER: &str = include_str!("src/shaders/composite_pixel_shader.ps_4_0");
+
+    compile_shader(VERTEX_SHADER, b"vs_4_0\0", "vertex_shader.vs_4_0")?;
+    compile_shader(PIXEL_SHADER, b"ps_4_0\0", "pixel_shader.ps_4_0")?;
+    compile_shader(
+        COMPOSITE_VERTEX_SHADER,
+        b"vs_4_0\0",
+        "composite_vertex_shader.vs_4_0",
+    )?;
+    compile_shader(
+        COMPOSITE_PIXEL_SHADER,
+        b"ps_4_0\0",
+        "composite_pixel_shader.ps_4_0",
+    )?;
+    Ok(())
+}
 
-    let mut vs_blob = None;
-    let mut ps_blob = None;
-
+fn compile_shader(source: &str, target: &[u8], shader_name: &str) -> Result<(), Box<dyn Error>> {
+    let mut blob = None;
     let mut err = None;
 
     unsafe {
-        // Compile vertex shader
-        D3DCompile(
-            VERTEX_SHADER.as_ptr().cast(),
-            VERTEX_SHADER.len(),
-            None,
-            None,
-            None,
-            PCSTR(b"main\0".as_ptr()),
-            PCSTR(b"vs_4_0\0".as_ptr()),
-            0,
-            0,
-            &mut vs_blob,
-            Some(&mut err),
-        )?;
-
-        check_shader_err(&err)?;
-
-        if let Some(vs_blob) = vs_blob {
-            write_blob("vertex_shader.vs_4_0", vs_blob);
-        }
-    }
-
-    unsafe {
-        // Compile vertex shader
         D3DCompile(
-            PIXEL_SHADER.as_ptr().cast(),
-            PIXEL_SHADER.len(),
+            source.as_ptr().cast(),
+            source.len(),
             None,
             None,
             None,
             PCSTR(b"main\0".as_ptr()),
-            PCSTR(b"ps_4_0\0".as_ptr()),
+            PCSTR(target.as_ptr()),
             0,
             0,
-            &mut ps_blob,
+            &mut blob,
             Some(&mut err),
         )?;
 
         check_shader_err(&err)?;
 
-        if let Some(ps_blob) = ps_blob {
-            write_blob("pixel_shader.ps_4_0", ps_blob);
+        if let Some(blob) = blob {
+            write_blob(shader_name, blob);
         }
     }
     Ok(())
@@ -0,0 +1,59 @@
+/// A texture drawn onto the framebuffer by `Direct3D11ImguiRenderer::composite`, outside of any
+/// ImGui draw list.
+///
+/// The texture is blended as premultiplied alpha.
+#[derive(Debug, Copy, Clone)]
+pub struct Composite {
+    /// The top left corner of the texture, in pixels from the top left of the framebuffer.
+    pub position: [f32; 2],
+    /// The size the texture is drawn at, in pixels.
+    pub size: [f32; 2],
+    /// The size of the framebuffer, in pixels.
+    pub framebuffer: [f32; 2],
+    /// Multiplied with every channel of the texture.
+    pub opacity: f32,
+    /// Whether the texture holds BGRA texels, and has to be swizzled to RGBA when sampled.
+    pub bgra: bool,
+    /// Whether the alpha channel of the texture is ignored.
+    pub opaque: bool,
+    /// Whether the texture's colour is already multiplied by its alpha.
+    pub premultiplied: bool,
+    /// How the colour of the texture is encoded.
+    pub decode: ColorEncoding,
+    /// How colour is encoded when it is written to the framebuffer.
+    pub encode: ColorEncoding,
+}
+
+/// How colour is encoded in a texture or framebuffer.
+///
+/// The composite pass converts between encodings through linear sRGB, in which 1.0 is
+/// SDR white.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorEncoding {
+    /// sRGB primaries and transfer function.
+    Srgb = 0,
+    /// Linear values with sRGB primaries.
+    Linear = 1,
+    /// BT.2020 primaries with the PQ transfer function, with SDR white at 203 nits.
+    Hdr10 = 2,
+}
+
+impl Composite {
+    /// The left, top, right and bottom edges of the texture in normalized device coordinates.
+    pub(crate) fn rect(&self, y_down: bool) -> [f32; 4] {
+        let [x, y] = self.position;
+        let [width, height] = self.size;
+        let [fb_width, fb_height] = self.framebuffer;
+
+        let left = x / fb_width * 2.0 - 1.0;
+        let right = (x + width) / fb_width * 2.0 - 1.0;
+        let top = y / fb_height * 2.0 - 1.0;
+        let bottom = (y + height) / fb_height * 2.0 - 1.0;
+
+        if y_down {
+            [left, top, right, bottom]
+        } else {
+            [left, -top, right, -bottom]
+        }
+    }
+}
@@ -4,25 +4,29 @@ use windows::Win32::Graphics::Direct3D11::{
     ID3D11BlendState, ID3D11Buffer, ID3D11DepthStencilState, ID3D11Device, ID3D11InputLayout,
     ID3D11PixelShader, ID3D11RasterizerState, ID3D11SamplerState, ID3D11ShaderResourceView,
     ID3D11VertexShader, D3D11_BIND_CONSTANT_BUFFER, D3D11_BIND_SHADER_RESOURCE, D3D11_BLEND_DESC,
-    D3D11_BLEND_INV_SRC_ALPHA, D3D11_BLEND_OP_ADD, D3D11_BLEND_SRC_ALPHA, D3D11_BLEND_ZERO,
-    D3D11_BUFFER_DESC, D3D11_COLOR_WRITE_ENABLE_ALL, D3D11_COMPARISON_ALWAYS,
+    D3D11_BLEND_INV_SRC_ALPHA, D3D11_BLEND_ONE, D3D11_BLEND_OP_ADD, D3D11_BLEND_SRC_ALPHA,
+    D3D11_BLEND_ZERO, D3D11_BUFFER_DESC, D3D11_COLOR_WRITE_ENABLE_ALL, D3D11_COMPARISON_ALWAYS,
     D3D11_CPU_ACCESS_WRITE, D3D11_CULL_NONE, D3D11_DEPTH_STENCILOP_DESC, D3D11_DEPTH_STENCIL_DESC,
-    D3D11_DEPTH_WRITE_MASK_ALL, D3D11_FILL_SOLID, D3D11_FILTER_MIN_MAG_MIP_LINEAR,
+    D3D11_DEPTH_WRITE_MASK_ALL, D3D11_FILL_SOLID, D3D11_FILTER, D3D11_FILTER_MIN_MAG_MIP_LINEAR,
     D3D11_INPUT_ELEMENT_DESC, D3D11_INPUT_PER_VERTEX_DATA, D3D11_RASTERIZER_DESC,
     D3D11_RENDER_TARGET_BLEND_DESC, D3D11_RESOURCE_MISC_FLAG, D3D11_SAMPLER_DESC,
     D3D11_SHADER_RESOURCE_VIEW_DESC, D3D11_SHADER_RESOURCE_VIEW_DESC_0, D3D11_STENCIL_OP_KEEP,
-    D3D11_SUBRESOURCE_DATA, D3D11_TEX2D_SRV, D3D11_TEXTURE2D_DESC, D3D11_TEXTURE_ADDRESS_WRAP,
-    D3D11_USAGE_DEFAULT, D3D11_USAGE_DYNAMIC,
+    D3D11_SUBRESOURCE_DATA, D3D11_TEX2D_SRV, D3D11_TEXTURE2D_DESC, D3D11_TEXTURE_ADDRESS_CLAMP,
+    D3D11_TEXTURE_ADDRESS_WRAP, D3D11_USAGE_DEFAULT, D3D11_USAGE_DYNAMIC,
 };
 use windows::Win32::Graphics::Dxgi::Common::{
     DXGI_FORMAT_R32G32_FLOAT, DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_SAMPLE_DESC,
 };
 
-use crate::renderer::VertexConstantBuffer;
+use crate::renderer::{CompositeConstantBuffer, VertexConstantBuffer};
 use crate::ImguiTexture;
 
 const VERTEX_SHADER: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/vertex_shader.vs_4_0"));
 const PIXEL_SHADER: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/pixel_shader.ps_4_0"));
+const COMPOSITE_VERTEX_SHADER: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/composite_vertex_shader.vs_4_0"));
+const COMPOSITE_PIXEL_SHADER: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/composite_pixel_shader.ps_4_0"));
 
 #[derive(Debug)]
 pub(crate) struct RendererDeviceObjects {
@@ -109,6 +113,60 @@ impl FontTexture {
     }
 }
 
+/// The objects drawing composited textures, outside of the ImGui pipeline.
+#[derive(Debug)]
+pub(crate) struct CompositeDeviceObjects {
+    pub vertex_shader: ID3D11VertexShader,
+    pub pixel_shader: ID3D11PixelShader,
+    pub constant_buffer: ID3D11Buffer,
+    pub blend_state: ID3D11BlendState,
+    pub sampler: ID3D11SamplerState,
+    pub filter: D3D11_FILTER,
+}
+
+impl CompositeDeviceObjects {
+    pub fn new(device: &ID3D11Device) -> HResult<CompositeDeviceObjects> {
+        let vertex_shader = unsafe { device.CreateVertexShader(&COMPOSITE_VERTEX_SHADER, None)? };
+        let pixel_shader = unsafe { device.CreatePixelShader(&COMPOSITE_PIXEL_SHADER, None)? };
+        let constant_buffer =
+            create_const_buffer(device, std::mem::size_of::<CompositeConstantBuffer>())?;
+        let blend_state = create_premultiplied_blend_state(device)?;
+        let sampler = create_composite_sampler(device, D3D11_FILTER_MIN_MAG_MIP_LINEAR)?;
+        Ok(CompositeDeviceObjects {
+            vertex_shader,
+            pixel_shader,
+            constant_buffer,
+            blend_state,
+            sampler,
+            filter: D3D11_FILTER_MIN_MAG_MIP_LINEAR,
+        })
+    }
+}
+
+/// Create the sampler composited textures are sampled with.
+///
+/// Unlike the font sampler this clamps, so that the edges of the texture do not bleed.
+#[must_use]
+pub(crate) fn create_composite_sampler(
+    device: &ID3D11Device,
+    filter: D3D11_FILTER,
+) -> HResult<ID3D11SamplerState> {
+    let sampler_desc = D3D11_SAMPLER_DESC {
+        Filter: filter,
+        AddressU: D3D11_TEXTURE_ADDRESS_CLAMP,
+        AddressV: D3D11_TEXTURE_ADDRESS_CLAMP,
+        AddressW: D3D11_TEXTURE_ADDRESS_CLAMP,
+        MipLODBias: 0.0,
+        MaxAnisotropy: 0,
+        ComparisonFunc: D3D11_COMPARISON_ALWAYS,
+        BorderColor: [0.0; 4],
+        MinLOD: 0.0,
+        MaxLOD: 0.0,
+    };
+
+    unsafe { device.CreateSamplerState(&sampler_desc) }
+}
+
 impl RendererDeviceObjects {
     pub fn new(device: &ID3D11Device) -> HResult<RendererDeviceObjects> {
         let (vertex_shader, input_layout) = create_vertex_shader(device)?;
@@ -136,8 +194,13 @@ fn create_pixel_shader(device: &ID3D11Device) -> HResult<ID3D11PixelShader> {
 
 #[must_use]
 fn create_vertex_const_buffer(device: &ID3D11Device) -> HResult<ID3D11Buffer> {
+    create_const_buffer(device, std::mem::size_of::<VertexConstantBuffer>())
+}
+
+#[must_use]
+fn create_const_buffer(device: &ID3D11Device, size: usize) -> HResult<ID3D11Buffer> {
     let buffer_desc = D3D11_BUFFER_DESC {
-        ByteWidth: std::mem::size_of::<VertexConstantBuffer>() as u32,
+        ByteWidth: size as u32,
         Usage: D3D11_USAGE_DYNAMIC,
         BindFlags: D3D11_BIND_CONSTANT_BUFFER,
         CPUAccessFlags: D3D11_CPU_ACCESS_WRITE,
@@ -207,6 +270,29 @@ fn create_blend_state(device: &ID3D11Device) -> HResult<ID3D11BlendState> {
     unsafe { device.CreateBlendState(&blend_desc) }
 }
 
+// Composited textures are premultiplied by the pixel shader, whatever they hold.
+#[must_use]
+fn create_premultiplied_blend_state(device: &ID3D11Device) -> HResult<ID3D11BlendState> {
+    let mut blend_desc = D3D11_BLEND_DESC {
+        AlphaToCoverageEnable: false.into(),
+        IndependentBlendEnable: false.into(),
+        RenderTarget: [Default::default(); 8],
+    };
+
+    blend_desc.RenderTarget[0] = D3D11_RENDER_TARGET_BLEND_DESC {
+        BlendEnable: true.into(),
+        SrcBlend: D3D11_BLEND_ONE,
+        DestBlend: D3D11_BLEND_INV_SRC_ALPHA,
+        BlendOp: D3D11_BLEND_OP_ADD,
+        SrcBlendAlpha: D3D11_BLEND_ONE,
+        DestBlendAlpha: D3D11_BLEND_INV_SRC_ALPHA,
+        BlendOpAlpha: D3D11_BLEND_OP_ADD,
+        RenderTargetWriteMask: D3D11_COLOR_WRITE_ENABLE_ALL.0 as u8,
+    };
+
+    unsafe { device.CreateBlendState(&blend_desc) }
+}
+
 #[must_use]
 fn create_rasterizer_state(device: &ID3D11Device) -> HResult<ID3D11RasterizerState> {
     let rasterizer_desc = D3D11_RASTERIZER_DESC {
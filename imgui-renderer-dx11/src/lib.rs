@@ -2,6 +2,7 @@
 
 mod backup;
 mod buffers;
+mod composite;
 mod device_objects;
 mod renderer;
 
@@ -46,5 +47,6 @@ impl ImguiTexture for ID3D11ShaderResourceView {
     }
 }
 
+pub use composite::{ColorEncoding, Composite};
 pub use renderer::RenderToken;
 pub use renderer::Renderer as Direct3D11ImguiRenderer;
@@ -1,11 +1,13 @@
 use imgui::internal::RawWrapper;
-use imgui::{DrawCmd, DrawData, DrawIdx, DrawVert};
+use imgui::{DrawCmd, DrawData, DrawIdx, DrawVert, TextureId};
 use windows::core::Result as HResult;
 use windows::Win32::Foundation::RECT;
-use windows::Win32::Graphics::Direct3D::D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST;
+use windows::Win32::Graphics::Direct3D::{
+    D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST, D3D11_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP,
+};
 use windows::Win32::Graphics::Direct3D11::{
-    ID3D11Device, ID3D11DeviceContext, ID3D11ShaderResourceView, D3D11_MAP_WRITE_DISCARD,
-    D3D11_VIEWPORT,
+    ID3D11Device, ID3D11DeviceContext, ID3D11ShaderResourceView, D3D11_FILTER,
+    D3D11_MAP_WRITE_DISCARD, D3D11_VIEWPORT,
 };
 use windows::Win32::Graphics::Dxgi::Common::{
     DXGI_FORMAT, DXGI_FORMAT_R16_UINT, DXGI_FORMAT_R32_UINT,
@@ -13,7 +15,10 @@ use windows::Win32::Graphics::Dxgi::Common::{
 
 use crate::backup::StateBackup;
 use crate::buffers::{IndexBuffer, VertexBuffer};
-use crate::device_objects::{FontTexture, RendererDeviceObjects};
+use crate::composite::Composite;
+use crate::device_objects::{
+    create_composite_sampler, CompositeDeviceObjects, FontTexture, RendererDeviceObjects,
+};
 use crate::RenderError;
 
 #[repr(C)]
@@ -21,6 +26,18 @@ pub(crate) struct VertexConstantBuffer {
     mvp: [[f32; 4]; 4],
 }
 
+// Constant buffers are sized in multiples of 16 bytes.
+#[repr(C, align(16))]
+pub(crate) struct CompositeConstantBuffer {
+    rect: [f32; 4],
+    opacity: f32,
+    bgra: u32,
+    opaque: u32,
+    premultiplied: u32,
+    decode: u32,
+    encode: u32,
+}
+
 pub struct RenderToken;
 
 #[derive(Debug)]
@@ -28,6 +45,7 @@ pub struct Renderer {
     device: ID3D11Device,
     context: ID3D11DeviceContext,
     device_objects: Option<RendererDeviceObjects>,
+    composite: Option<CompositeDeviceObjects>,
     font: Option<FontTexture>,
     vertex_buffer: VertexBuffer,
     index_buffer: IndexBuffer,
@@ -57,6 +75,7 @@ impl Renderer {
             device,
             context,
             device_objects: None,
+            composite: None,
             font: None,
             vertex_buffer,
             index_buffer,
@@ -68,14 +87,121 @@ impl Renderer {
 
     pub fn create_device_objects(&mut self, imgui: &mut imgui::Context) -> Result<(), RenderError> {
         let device_objects = RendererDeviceObjects::new(&self.device)?;
+        let composite = CompositeDeviceObjects::new(&self.device)?;
         let mut imgui_fonts = imgui.fonts();
         let fonts = FontTexture::new(&mut imgui_fonts, &self.device)?;
         imgui_fonts.tex_id = fonts.tex_id();
         self.font = Some(fonts);
         self.device_objects = Some(device_objects);
+        self.composite = Some(composite);
         Ok(())
     }
 
+    /// Point the ImGui font atlas at this renderer's font texture.
+    ///
+    /// This must be called before rendering if more than one renderer shares the same context.
+    pub fn bind_fonts(&self, imgui: &mut imgui::Context) {
+        if let Some(font) = &self.font {
+            imgui.fonts().tex_id = font.tex_id();
+        }
+    }
+
+    /// Sample composited textures with `filter`, which is linear until this is called.
+    pub fn set_filter(&mut self, filter: D3D11_FILTER) -> Result<(), RenderError> {
+        if let Some(composite) = &mut self.composite {
+            if composite.filter != filter {
+                composite.sampler = create_composite_sampler(&self.device, filter)?;
+                composite.filter = filter;
+            }
+        }
+        Ok(())
+    }
+
+    /// Draw a texture with premultiplied alpha blending, independently of any draw data.
+    ///
+    /// Like a texture drawn by ImGui, the reference `texture` was created with is released.
+    pub fn composite(
+        &mut self,
+        texture: TextureId,
+        composite: &Composite,
+    ) -> Result<RenderToken, RenderError> {
+        let texture = unsafe { texture_view(texture) };
+
+        let objects = match &self.composite {
+            Some(objects) => objects,
+            None => return Ok(RenderToken),
+        };
+
+        let [fb_width, fb_height] = composite.framebuffer;
+        if fb_width <= 0.0 || fb_height <= 0.0 {
+            return Ok(RenderToken);
+        }
+
+        unsafe {
+            let state = StateBackup::new(&self.context);
+            let ctx = &self.context;
+
+            let const_resource =
+                ctx.Map(&objects.constant_buffer, 0, D3D11_MAP_WRITE_DISCARD, 0)?;
+            *const_resource.pData.cast::<CompositeConstantBuffer>() = CompositeConstantBuffer {
+                rect: composite.rect(false),
+                opacity: composite.opacity,
+                bgra: composite.bgra as u32,
+                opaque: composite.opaque as u32,
+                premultiplied: composite.premultiplied as u32,
+                decode: composite.decode as u32,
+                encode: composite.encode as u32,
+            };
+            ctx.Unmap(&objects.constant_buffer, 0);
+
+            let viewport = D3D11_VIEWPORT {
+                TopLeftX: 0.0,
+                TopLeftY: 0.0,
+                Width: fb_width,
+                Height: fb_height,
+                MinDepth: 0.0,
+                MaxDepth: 1.0,
+            };
+            ctx.RSSetViewports(Some(&[viewport]));
+            ctx.RSSetScissorRects(Some(&[RECT {
+                left: 0,
+                top: 0,
+                right: fb_width as i32,
+                bottom: fb_height as i32,
+            }]));
+
+            // The quad is generated from the vertex id, so no vertex buffer is bound.
+            ctx.IASetInputLayout(None);
+            ctx.IASetPrimitiveTopology(D3D11_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP);
+            ctx.VSSetShader(&objects.vertex_shader, None);
+            ctx.VSSetConstantBuffers(0, Some(&[objects.constant_buffer.clone().into()]));
+            ctx.PSSetShader(&objects.pixel_shader, None);
+            ctx.PSSetConstantBuffers(0, Some(&[objects.constant_buffer.clone().into()]));
+            ctx.PSSetSamplers(0, Some(&[objects.sampler.clone().into()]));
+            ctx.PSSetShaderResources(0, Some(&[texture.clone().into()]));
+            ctx.GSSetShader(None, None);
+            ctx.HSSetShader(None, None);
+            ctx.DSSetShader(None, None);
+            ctx.CSSetShader(None, None);
+
+            let blend_factor = [0.0; 4];
+            ctx.OMSetBlendState(
+                &objects.blend_state,
+                Some(blend_factor.as_ptr()),
+                0xFFFFFFFF,
+            );
+
+            if let Some(device_objects) = &self.device_objects {
+                ctx.OMSetDepthStencilState(&device_objects.depth_stencil_state, 0);
+                ctx.RSSetState(&device_objects.rasterizer_state);
+            }
+
+            ctx.Draw(4, 0);
+            drop(state)
+        }
+        Ok(RenderToken)
+    }
+
     pub fn render(&mut self, draw_data: &DrawData) -> Result<RenderToken, RenderError> {
         // Avoid rendering when minimized
         if draw_data.display_size[0] <= 0.0
@@ -252,22 +378,7 @@ impl Renderer {
                         self.context.RSSetScissorRects(Some(&[rect]));
 
                         // srv will be dropped after rendering.
-
-                        #[cfg(not(feature = "strict-provenance"))]
-                        let texture_srv: ID3D11ShaderResourceView =
-                            std::mem::transmute(cmd_params.texture_id.id());
-
-                        // 'Preserve' provenance of texids.
-                        // doesn't really actually do anything, but semantically expresses the correct
-                        // intent.
-                        #[cfg(feature = "strict-provenance")]
-                        let texture_srv: ID3D11ShaderResourceView = {
-                            std::mem::transmute(std::ptr::from_exposed_addr_mut::<
-                                *mut core::ffi::c_void,
-                            >(
-                                cmd_params.texture_id.id()
-                            ))
-                        };
+                        let texture_srv = texture_view(cmd_params.texture_id);
 
                         // Bind texture, Draw
                         self.context
@@ -287,6 +398,27 @@ impl Renderer {
     }
 }
 
+/// Take back the shader resource view behind a texture id.
+///
+/// The view is released when the result is dropped, balancing the reference taken by
+/// `as_tex_id`.
+unsafe fn texture_view(texture_id: TextureId) -> ID3D11ShaderResourceView {
+    #[cfg(not(feature = "strict-provenance"))]
+    let texture_srv: ID3D11ShaderResourceView = std::mem::transmute(texture_id.id());
+
+    // 'Preserve' provenance of texids.
+    // doesn't really actually do anything, but semantically expresses the correct
+    // intent.
+    #[cfg(feature = "strict-provenance")]
+    let texture_srv: ID3D11ShaderResourceView = {
+        std::mem::transmute(std::ptr::from_exposed_addr_mut::<*mut core::ffi::c_void>(
+            texture_id.id(),
+        ))
+    };
+
+    texture_srv
+}
+
 const IDX_FORMAT: DXGI_FORMAT = idx_format();
 const fn idx_format() -> DXGI_FORMAT {
     if std::mem::size_of::<DrawIdx>() == 2 {
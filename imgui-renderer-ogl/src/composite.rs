@@ -0,0 +1,210 @@
+/// A texture drawn onto the framebuffer by `OpenGLImguiRenderer::composite`, outside of any
+/// ImGui draw list.
+///
+/// The texture is blended as premultiplied alpha.
+#[derive(Debug, Copy, Clone)]
+pub struct Composite {
+    /// The top left corner of the texture, in pixels from the top left of the framebuffer.
+    pub position: [f32; 2],
+    /// The size the texture is drawn at, in pixels.
+    pub size: [f32; 2],
+    /// The size of the framebuffer, in pixels.
+    pub framebuffer: [f32; 2],
+    /// Multiplied with every channel of the texture.
+    pub opacity: f32,
+    /// Whether the texture holds BGRA texels, and has to be swizzled to RGBA when sampled.
+    pub bgra: bool,
+    /// Whether the alpha channel of the texture is ignored.
+    pub opaque: bool,
+    /// Whether the texture's colour is already multiplied by its alpha.
+    pub premultiplied: bool,
+    /// How the colour of the texture is encoded.
+    pub decode: ColorEncoding,
+    /// How colour is encoded when it is written to the framebuffer.
+    pub encode: ColorEncoding,
+}
+
+/// How colour is encoded in a texture or framebuffer.
+///
+/// The composite pass converts between encodings through linear sRGB, in which 1.0 is
+/// SDR white.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorEncoding {
+    /// sRGB primaries and transfer function.
+    Srgb = 0,
+    /// Linear values with sRGB primaries.
+    Linear = 1,
+    /// BT.2020 primaries with the PQ transfer function, with SDR white at 203 nits.
+    Hdr10 = 2,
+}
+
+impl Composite {
+    /// The left, top, right and bottom edges of the texture in normalized device coordinates.
+    pub(crate) fn rect(&self, y_down: bool) -> [f32; 4] {
+        let [x, y] = self.position;
+        let [width, height] = self.size;
+        let [fb_width, fb_height] = self.framebuffer;
+
+        let left = x / fb_width * 2.0 - 1.0;
+        let right = (x + width) / fb_width * 2.0 - 1.0;
+        let top = y / fb_height * 2.0 - 1.0;
+        let bottom = (y + height) / fb_height * 2.0 - 1.0;
+
+        if y_down {
+            [left, top, right, bottom]
+        } else {
+            [left, -top, right, -bottom]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! The composite fragment shader, evaluated on the CPU with the constants it declares, and
+    //! blended with the blend function the composite pass sets.
+
+    const SHADER: &str = include_str!("shaders/composite_fragment_shader.130.glsl");
+
+    /// The values of the constant `name` declared in the shader, in the order they are listed.
+    fn constant(name: &str) -> Vec<f32> {
+        let start = SHADER
+            .find(&format!(" {} = ", name))
+            .unwrap_or_else(|| panic!("{} is not declared", name));
+        let declaration = &SHADER[start..];
+        let value =
+            &declaration[declaration.find('=').unwrap() + 1..declaration.find(';').unwrap()];
+        value
+            .trim()
+            .trim_start_matches("mat3(")
+            .trim_end_matches(')')
+            .split(',')
+            .map(|term| {
+                let mut terms = term.split('/').map(|v| v.trim().parse::<f32>().unwrap());
+                let value = terms.next().unwrap();
+                terms.fold(value, |value, divisor| value / divisor)
+            })
+            .collect()
+    }
+
+    fn scalar(name: &str) -> f32 {
+        constant(name)[0]
+    }
+
+    /// Multiply `v` by a matrix given as columns, as GLSL does.
+    fn mul(m: &[f32], v: [f32; 3]) -> [f32; 3] {
+        let mut out = [0.0; 3];
+        for (row, out) in out.iter_mut().enumerate() {
+            *out = (0..3).map(|column| m[column * 3 + row] * v[column]).sum();
+        }
+        out
+    }
+
+    fn to_hdr10(color: [f32; 3]) -> [f32; 3] {
+        let (m1, m2) = (scalar("PQ_M1"), scalar("PQ_M2"));
+        let (c1, c2, c3) = (scalar("PQ_C1"), scalar("PQ_C2"), scalar("PQ_C3"));
+        mul(&constant("BT709_TO_BT2020"), color).map(|c| {
+            let y = (c * scalar("SDR_WHITE")).max(0.0).powf(m1);
+            ((c1 + c2 * y) / (1.0 + c3 * y)).powf(m2)
+        })
+    }
+
+    fn from_hdr10(color: [f32; 3]) -> [f32; 3] {
+        let (m1, m2) = (scalar("PQ_M1"), scalar("PQ_M2"));
+        let (c1, c2, c3) = (scalar("PQ_C1"), scalar("PQ_C2"), scalar("PQ_C3"));
+        let nits = color.map(|c| {
+            let p = c.max(0.0).powf(1.0 / m2);
+            ((p - c1).max(0.0) / (c2 - c3 * p)).powf(1.0 / m1)
+        });
+        mul(
+            &constant("BT2020_TO_BT709"),
+            nits.map(|n| n / scalar("SDR_WHITE")),
+        )
+    }
+
+    /// The colour the shader writes for `texel`, with the encodings left as they are.
+    fn shade(texel: [f32; 4], premultiplied: bool, opacity: f32) -> [f32; 4] {
+        let [mut r, mut g, mut b, a] = texel;
+        if premultiplied && a > 0.0 {
+            (r, g, b) = (r / a, g / a, b / a);
+        }
+        [r * a, g * a, b * a, a].map(|c| c * opacity)
+    }
+
+    /// Blend `src` over `dst` with `ONE, ONE_MINUS_SRC_ALPHA` for colour and alpha.
+    fn blend(src: [f32; 4], dst: [f32; 4]) -> [f32; 4] {
+        let mut out = [0.0; 4];
+        for (i, out) in out.iter_mut().enumerate() {
+            *out = src[i] + dst[i] * (1.0 - src[3]);
+        }
+        out
+    }
+
+    fn assert_near<const N: usize>(actual: [f32; N], expected: [f32; N], tolerance: f32) {
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                (actual - expected).abs() <= tolerance,
+                "{:?} is not {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn premultiplied_blend() {
+        let dst = [0.2, 0.4, 0.6, 1.0];
+
+        // A premultiplied texel is drawn as it is, scaled by the opacity.
+        let texel = [0.4, 0.2, 0.1, 0.5];
+        assert_near(shade(texel, true, 1.0), texel, 1e-6);
+        assert_near(
+            blend(shade(texel, true, 1.0), dst),
+            [0.5, 0.4, 0.4, 1.0],
+            1e-6,
+        );
+        assert_near(
+            blend(shade(texel, true, 0.5), dst),
+            [0.35, 0.4, 0.5, 1.0],
+            1e-6,
+        );
+
+        // A straight alpha texel is premultiplied by the shader.
+        let straight = [0.8, 0.4, 0.2, 0.5];
+        assert_near(shade(straight, false, 1.0), texel, 1e-6);
+
+        // A transparent texel leaves the framebuffer as it is.
+        assert_near(blend(shade([0.0; 4], true, 1.0), dst), dst, 0.0);
+    }
+
+    #[test]
+    fn primaries_invert() {
+        let to_bt2020 = constant("BT709_TO_BT2020");
+        let to_bt709 = constant("BT2020_TO_BT709");
+        assert_eq!((to_bt2020.len(), to_bt709.len()), (9, 9));
+
+        for column in 0..3 {
+            let mut unit = [0.0; 3];
+            unit[column] = 1.0;
+            assert_near(mul(&to_bt709, mul(&to_bt2020, unit)), unit, 1e-3);
+        }
+        // White is the same in both.
+        assert_near(mul(&to_bt2020, [1.0; 3]), [1.0; 3], 1e-3);
+    }
+
+    #[test]
+    fn pq_constants() {
+        assert_eq!(scalar("PQ_M1"), 2610.0 / 16384.0);
+        assert_eq!(scalar("PQ_M2"), 2523.0 / 4096.0 * 128.0);
+        assert_eq!(scalar("PQ_C1"), 3424.0 / 4096.0);
+        assert_eq!(scalar("PQ_C2"), 2413.0 / 4096.0 * 32.0);
+        assert_eq!(scalar("PQ_C3"), 2392.0 / 4096.0 * 32.0);
+
+        // SDR white is 203 nits, which PQ encodes at about 0.58.
+        assert_near(to_hdr10([1.0; 3]), [0.5807; 3], 1e-3);
+        assert_near(
+            from_hdr10(to_hdr10([0.25, 0.5, 1.0])),
+            [0.25, 0.5, 1.0],
+            1e-3,
+        );
+    }
+}
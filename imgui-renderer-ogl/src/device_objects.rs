@@ -17,6 +17,11 @@ const VERTEX_120: &'static [u8] = include_bytes!("shaders/vertex_shader.120.glsl
 const VERTEX_130: &'static [u8] = include_bytes!("shaders/vertex_shader.130.glsl");
 const VERTEX_300: &'static [u8] = include_bytes!("shaders/vertex_shader.300.glsl");
 
+const COMPOSITE_FRAGMENT_130: &'static [u8] =
+    include_bytes!("shaders/composite_fragment_shader.130.glsl");
+const COMPOSITE_VERTEX_130: &'static [u8] =
+    include_bytes!("shaders/composite_vertex_shader.130.glsl");
+
 struct Shader {
     source: &'static [u8],
     version: &'static [u8],
@@ -94,6 +99,23 @@ impl Shader {
         }
     }
 
+    // The composite shaders rely on gl_VertexID, so there is no GLSL 1.20 version.
+    pub const fn composite_fragment_shader(version: GlVersion) -> Shader {
+        Shader {
+            version: Shader::glsl_version(version),
+            source: COMPOSITE_FRAGMENT_130,
+            ty: FRAGMENT_SHADER,
+        }
+    }
+
+    pub const fn composite_vertex_shader(version: GlVersion) -> Shader {
+        Shader {
+            version: Shader::glsl_version(version),
+            source: COMPOSITE_VERTEX_130,
+            ty: VERTEX_SHADER,
+        }
+    }
+
     fn check_shader(&self, gl: &Gl, handle: GLuint) -> Result<(), RenderError> {
         let mut status = 0;
         let mut log_length = 0;
@@ -192,8 +214,65 @@ impl Drop for Program<'_> {
     }
 }
 
+pub(crate) struct CompositeProgram<'gl> {
+    pub handle: GLuint,
+    pub uniform_loc_tex: GLint,
+    pub uniform_loc_rect: GLint,
+    pub uniform_loc_opacity: GLint,
+    pub uniform_loc_bgra: GLint,
+    pub uniform_loc_opaque: GLint,
+    pub uniform_loc_premultiplied: GLint,
+    pub uniform_loc_decode: GLint,
+    pub uniform_loc_encode: GLint,
+    gl: &'gl Gl,
+}
+
+impl<'gl> CompositeProgram<'gl> {
+    pub fn new(gl: &'gl Gl, version: GlVersion) -> Result<CompositeProgram<'gl>, RenderError> {
+        let vertex_shader = Shader::composite_vertex_shader(version).compile(gl)?;
+        let fragment_shader = Shader::composite_fragment_shader(version).compile(gl)?;
+
+        unsafe {
+            let handle = gl.CreateProgram();
+            gl.AttachShader(handle, vertex_shader.shader);
+            gl.AttachShader(handle, fragment_shader.shader);
+            gl.LinkProgram(handle);
+
+            Program::check_program(gl, handle)?;
+
+            gl.DetachShader(handle, vertex_shader.shader);
+            gl.DetachShader(handle, fragment_shader.shader);
+
+            let uniform = |name: &[u8]| gl.GetUniformLocation(handle, name.as_ptr() as _);
+
+            Ok(CompositeProgram {
+                handle,
+                uniform_loc_tex: uniform(b"Texture\0"),
+                uniform_loc_rect: uniform(b"Rect\0"),
+                uniform_loc_opacity: uniform(b"Opacity\0"),
+                uniform_loc_bgra: uniform(b"Bgra\0"),
+                uniform_loc_opaque: uniform(b"Opaque\0"),
+                uniform_loc_premultiplied: uniform(b"Premultiplied\0"),
+                uniform_loc_decode: uniform(b"Decode\0"),
+                uniform_loc_encode: uniform(b"Encode\0"),
+                gl,
+            })
+        }
+    }
+}
+
+impl Drop for CompositeProgram<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteProgram(self.handle);
+        }
+    }
+}
+
 pub(crate) struct RendererDeviceObjects<'gl> {
     pub shader: Program<'gl>,
+    /// The program drawing composited textures, which needs at least GLSL 1.30.
+    pub composite: Option<CompositeProgram<'gl>>,
     pub vertex_buffer_obj: GLuint,
     pub elements_buffer_obj: GLuint,
     gl: &'gl Gl,
@@ -213,6 +292,11 @@ impl<'gl> RendererDeviceObjects<'gl> {
         }
 
         let shader = Program::new(gl, version)?;
+        let composite = if version.0 >= 130 {
+            Some(CompositeProgram::new(gl, version)?)
+        } else {
+            None
+        };
 
         let mut vbo = 0;
         let mut elements = 0;
@@ -232,6 +316,7 @@ impl<'gl> RendererDeviceObjects<'gl> {
         Ok(RendererDeviceObjects {
             gl,
             shader,
+            composite,
             vertex_buffer_obj: vbo,
             elements_buffer_obj: elements,
         })
@@ -1,4 +1,5 @@
 mod backup;
+mod composite;
 mod device_objects;
 mod renderer;
 
@@ -30,5 +31,6 @@ pub enum RenderError {
     MissingExtensionError(Box<&'static str>),
 }
 
+pub use composite::{ColorEncoding, Composite};
 pub use renderer::RenderToken;
 pub use renderer::Renderer as OpenGLImguiRenderer;
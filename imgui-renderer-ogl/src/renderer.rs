@@ -4,7 +4,7 @@ use std::os::raw::c_char;
 
 use field_offset::offset_of;
 use imgui::internal::RawWrapper;
-use imgui::{DrawCmd, DrawData, DrawIdx, DrawVert};
+use imgui::{DrawCmd, DrawData, DrawIdx, DrawVert, TextureId};
 use ouroboros::self_referencing;
 
 use opengl_bindings::types::{GLenum, GLint, GLsizei, GLuint};
@@ -12,10 +12,11 @@ use opengl_bindings::{
     Gl, ARRAY_BUFFER, BLEND, CLIP_ORIGIN, CULL_FACE, DEPTH_TEST, ELEMENT_ARRAY_BUFFER, FILL,
     FRONT_AND_BACK, FUNC_ADD, MAJOR_VERSION, MINOR_VERSION, ONE, ONE_MINUS_SRC_ALPHA,
     PRIMITIVE_RESTART, SCISSOR_TEST, SRC_ALPHA, STENCIL_TEST, STREAM_DRAW, TEXTURE_2D, TRIANGLES,
-    UNSIGNED_INT, UNSIGNED_SHORT, UPPER_LEFT, VERSION,
+    TRIANGLE_STRIP, UNSIGNED_INT, UNSIGNED_SHORT, UPPER_LEFT, VERSION,
 };
 
 use crate::backup::StateBackup;
+use crate::composite::Composite;
 use crate::device_objects::{FontTexture, RendererDeviceObjects};
 use crate::RenderError;
 
@@ -35,9 +36,32 @@ impl Renderer {
         self.0.with_renderer_mut(|r| r.create_device_objects(imgui))
     }
 
+    /// Point the ImGui font atlas at this renderer's font texture.
+    ///
+    /// This must be called before rendering if more than one renderer shares the same context.
+    pub fn bind_fonts(&self, imgui: &mut imgui::Context) {
+        self.0.with_renderer(|r| {
+            if let Some(font) = &r.font {
+                imgui.fonts().tex_id = font.tex_id();
+            }
+        })
+    }
+
     pub fn render(&mut self, draw_data: &DrawData) -> RenderToken {
         self.0.with_renderer_mut(|r| r.render(draw_data))
     }
+
+    /// Draw a texture with premultiplied alpha blending, independently of any draw data.
+    ///
+    /// This needs GLSL 1.30, and fails with a missing extension otherwise.
+    pub fn composite(
+        &mut self,
+        texture: TextureId,
+        composite: &Composite,
+    ) -> Result<RenderToken, RenderError> {
+        self.0
+            .with_renderer_mut(|r| r.composite(texture, composite))
+    }
 }
 
 // We bother with all this because we only want to make a single clone of Gl, which is
@@ -171,6 +195,88 @@ impl<'gl> RendererInner<'gl> {
         RenderToken
     }
 
+    fn composite(
+        &mut self,
+        texture: TextureId,
+        composite: &Composite,
+    ) -> Result<RenderToken, RenderError> {
+        let program = match self
+            .device_objects
+            .as_ref()
+            .and_then(|d| d.composite.as_ref())
+        {
+            Some(program) => program,
+            None => return Err(RenderError::MissingExtensionError(Box::new("GLSL 1.30"))),
+        };
+
+        let [fb_width, fb_height] = composite.framebuffer;
+        if fb_height <= 0.0 || fb_width <= 0.0 {
+            return Ok(RenderToken);
+        }
+
+        unsafe {
+            let gl = self.gl;
+            let state = StateBackup::new(gl);
+            let mut vertex_array = 0;
+
+            // Core profiles can not draw without a vertex array, even one without attributes.
+            if gl.GenVertexArrays.is_loaded() {
+                gl.GenVertexArrays(1, &mut vertex_array);
+                gl.BindVertexArray(vertex_array);
+            }
+
+            gl.Enable(BLEND);
+            gl.BlendEquation(FUNC_ADD);
+            gl.BlendFuncSeparate(ONE, ONE_MINUS_SRC_ALPHA, ONE, ONE_MINUS_SRC_ALPHA);
+            gl.Disable(CULL_FACE);
+            gl.Disable(DEPTH_TEST);
+            gl.Disable(STENCIL_TEST);
+            gl.Disable(SCISSOR_TEST);
+
+            if gl.PolygonMode.is_loaded() {
+                gl.PolygonMode(FRONT_AND_BACK, FILL);
+            }
+
+            let mut clip_origin_lower_left = true;
+            if gl.ClipControl.is_loaded() {
+                let mut current_clip_origin = 0;
+                gl.GetIntegerv(CLIP_ORIGIN, &mut current_clip_origin);
+                if current_clip_origin == UPPER_LEFT as GLint {
+                    clip_origin_lower_left = false;
+                }
+            }
+
+            gl.Viewport(0, 0, fb_width as GLsizei, fb_height as GLsizei);
+
+            let rect = composite.rect(!clip_origin_lower_left);
+            gl.UseProgram(program.handle);
+            gl.Uniform1i(program.uniform_loc_tex, 0);
+            gl.Uniform4fv(program.uniform_loc_rect, 1, rect.as_ptr());
+            gl.Uniform1f(program.uniform_loc_opacity, composite.opacity);
+            gl.Uniform1i(program.uniform_loc_bgra, composite.bgra as GLint);
+            gl.Uniform1i(program.uniform_loc_opaque, composite.opaque as GLint);
+            gl.Uniform1i(
+                program.uniform_loc_premultiplied,
+                composite.premultiplied as GLint,
+            );
+            gl.Uniform1i(program.uniform_loc_decode, composite.decode as GLint);
+            gl.Uniform1i(program.uniform_loc_encode, composite.encode as GLint);
+
+            if gl.BindSampler.is_loaded() {
+                gl.BindSampler(0, 0);
+            }
+
+            gl.BindTexture(TEXTURE_2D, texture.id() as _);
+            gl.DrawArrays(TRIANGLE_STRIP, 0, 4);
+
+            if gl.DeleteVertexArrays.is_loaded() {
+                gl.DeleteVertexArrays(1, &vertex_array);
+            }
+            drop(state);
+        }
+        Ok(RenderToken)
+    }
+
     // Safety: vertex_array is not 0 if gl.BindVertexArray is loaded
     unsafe fn setup_render_state(
         &self,
@@ -0,0 +1,11 @@
+#ifdef GL_ES
+precision highp float;
+#endif
+uniform vec4 Rect;
+out vec2 Frag_UV;
+void main()
+{
+    vec2 corner = vec2(float(gl_VertexID & 1), float(gl_VertexID >> 1));
+    Frag_UV = corner;
+    gl_Position = vec4(mix(Rect.xy, Rect.zw, corner), 0, 1);
+}
\ No newline at end of file
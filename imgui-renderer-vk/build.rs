@@ -0,0 +1,72 @@
+use std::error::Error;
+use std::{env, fs};
+
+use shaderc::{CompileOptions, Compiler, ShaderKind};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    static VERTEX_SHADER: &str = include_str!("src/shaders/vertex_shader.vert");
+    static FRAGMENT_SHADER: &str = include_str!("src/shaders/fragment_shader.frag");
+    static COMPOSITE_VERTEX_SHADER: &str = include_str!("src/shaders/composite_vertex_shader.vert");
+    static COMPOSITE_FRAGMENT_SHADER: &str =
+        include_str!("src/shaders/composite_fragment_shader.frag");
+
+    println!("cargo:rerun-if-changed=src/shaders");
+
+    let compiler = Compiler::new().ok_or("Unable to initialize shaderc")?;
+    let options = CompileOptions::new().ok_or("Unable to initialize shaderc options")?;
+
+    compile(
+        &compiler,
+        &options,
+        VERTEX_SHADER,
+        ShaderKind::Vertex,
+        "vertex_shader.vert",
+    )?;
+
+    compile(
+        &compiler,
+        &options,
+        FRAGMENT_SHADER,
+        ShaderKind::Fragment,
+        "fragment_shader.frag",
+    )?;
+
+    compile(
+        &compiler,
+        &options,
+        COMPOSITE_VERTEX_SHADER,
+        ShaderKind::Vertex,
+        "composite_vertex_shader.vert",
+    )?;
+
+    compile(
+        &compiler,
+        &options,
+        COMPOSITE_FRAGMENT_SHADER,
+        ShaderKind::Fragment,
+        "composite_fragment_shader.frag",
+    )?;
+
+    Ok(())
+}
+
+fn compile(
+    compiler: &Compiler,
+    options: &CompileOptions,
+    source: &str,
+    kind: ShaderKind,
+    shader_name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let artifact = compiler.compile_into_spirv(source, kind, shader_name, "main", Some(options))?;
+
+    if artifact.get_num_warnings() > 0 {
+        println!("cargo:warning={}", artifact.get_warning_messages());
+    }
+
+    let out_dir = env::var("OUT_DIR")?;
+    fs::write(
+        format!("{}/{}.spv", out_dir, shader_name),
+        artifact.as_binary_u8(),
+    )
+    .map_err(|e| panic!("Unable to write {} shader to out dir: {:?}", shader_name, e))
+}
@@ -0,0 +1,210 @@
+use std::marker::PhantomData;
+
+use ash::vk;
+
+use crate::{find_memory_type, RenderError};
+
+const VERTEX_BUF_ADD_CAPACITY: usize = 5000;
+const INDEX_BUF_ADD_CAPACITY: usize = 10000;
+
+/// A host visible buffer that grows to fit the draw data of a frame.
+#[derive(Debug)]
+pub struct ImGuiBuffer<T, const ADD_CAPACITY: usize, const USAGE: u32> {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    len: usize,
+    _draw_ty: PhantomData<T>,
+}
+
+impl<T, const ADD_CAPACITY: usize, const USAGE: u32> ImGuiBuffer<T, { ADD_CAPACITY }, { USAGE }> {
+    pub fn new(
+        device: &ash::Device,
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> Result<Self, RenderError> {
+        let (buffer, memory, len) = Self::create_buffer(device, memory_properties, 0)?;
+        Ok(ImGuiBuffer {
+            buffer,
+            memory,
+            len,
+            _draw_ty: PhantomData,
+        })
+    }
+
+    pub const fn buffer(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    pub fn reserve(
+        &mut self,
+        device: &ash::Device,
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        count: usize,
+    ) -> Result<(), RenderError> {
+        if self.len() < count {
+            let (buffer, memory, len) = Self::create_buffer(device, memory_properties, count)?;
+            // The buffer may still be in use by a previous frame that used the same slot,
+            // but the caller waits on that frame before recording a new one.
+            self.destroy(device);
+            self.buffer = buffer;
+            self.memory = memory;
+            self.len = len;
+        }
+        Ok(())
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Map the buffer, returning a slice of its entire capacity.
+    ///
+    /// # Safety
+    /// The buffer must not be in use by the device, and must be unmapped with `unmap`.
+    pub unsafe fn map(&mut self, device: &ash::Device) -> Result<&mut [T], RenderError> {
+        let ptr = device.map_memory(self.memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())?;
+        Ok(std::slice::from_raw_parts_mut(ptr.cast::<T>(), self.len))
+    }
+
+    /// # Safety
+    /// The buffer must have been mapped with `map`.
+    pub unsafe fn unmap(&mut self, device: &ash::Device) {
+        device.unmap_memory(self.memory)
+    }
+
+    /// Destroy the buffer. The buffer must not be in use by the device.
+    pub fn destroy(&mut self, device: &ash::Device) {
+        unsafe {
+            device.destroy_buffer(self.buffer, None);
+            device.free_memory(self.memory, None);
+        }
+        self.buffer = vk::Buffer::null();
+        self.memory = vk::DeviceMemory::null();
+        self.len = 0;
+    }
+
+    fn create_buffer(
+        device: &ash::Device,
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        count: usize,
+    ) -> Result<(vk::Buffer, vk::DeviceMemory, usize), RenderError> {
+        let len = count + ADD_CAPACITY;
+        let (buffer, memory) = create_host_buffer(
+            device,
+            memory_properties,
+            (len * std::mem::size_of::<T>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::from_raw(USAGE),
+        )?;
+        Ok((buffer, memory, len))
+    }
+}
+
+/// Create a host visible and coherent buffer of `size` bytes.
+fn create_host_buffer(
+    device: &ash::Device,
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+) -> Result<(vk::Buffer, vk::DeviceMemory), RenderError> {
+    let buffer_info = vk::BufferCreateInfo::builder()
+        .size(size)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+    unsafe {
+        let buffer = device.create_buffer(&buffer_info, None)?;
+        let requirements = device.get_buffer_memory_requirements(buffer);
+
+        let memory_type = find_memory_type(
+            memory_properties,
+            requirements.memory_type_bits,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        );
+
+        let memory_type = match memory_type {
+            Some(memory_type) => memory_type,
+            None => {
+                device.destroy_buffer(buffer, None);
+                return Err(RenderError::MemoryTypeError(Box::new("host buffers")));
+            }
+        };
+
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type);
+
+        let memory = match device.allocate_memory(&alloc_info, None) {
+            Ok(memory) => memory,
+            Err(e) => {
+                device.destroy_buffer(buffer, None);
+                return Err(e.into());
+            }
+        };
+
+        if let Err(e) = device.bind_buffer_memory(buffer, memory, 0) {
+            device.destroy_buffer(buffer, None);
+            device.free_memory(memory, None);
+            return Err(e.into());
+        }
+
+        Ok((buffer, memory))
+    }
+}
+
+/// A host visible buffer holding data to be copied to the device.
+pub struct StagingBuffer {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+}
+
+impl StagingBuffer {
+    pub fn new(
+        device: &ash::Device,
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        data: &[u8],
+    ) -> Result<StagingBuffer, RenderError> {
+        let (buffer, memory) = create_host_buffer(
+            device,
+            memory_properties,
+            data.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+        )?;
+
+        let staging = StagingBuffer { buffer, memory };
+        unsafe {
+            match device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()) {
+                Ok(ptr) => {
+                    std::ptr::copy_nonoverlapping(data.as_ptr(), ptr.cast::<u8>(), data.len());
+                    device.unmap_memory(memory);
+                }
+                Err(e) => {
+                    staging.destroy(device);
+                    return Err(e.into());
+                }
+            }
+        }
+        Ok(staging)
+    }
+
+    pub const fn buffer(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    /// Destroy the buffer. The buffer must not be in use by the device.
+    pub fn destroy(self, device: &ash::Device) {
+        unsafe {
+            device.destroy_buffer(self.buffer, None);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
+
+pub type VertexBuffer = ImGuiBuffer<
+    imgui::DrawVert,
+    VERTEX_BUF_ADD_CAPACITY,
+    { vk::BufferUsageFlags::VERTEX_BUFFER.as_raw() },
+>;
+pub type IndexBuffer = ImGuiBuffer<
+    imgui::DrawIdx,
+    INDEX_BUF_ADD_CAPACITY,
+    { vk::BufferUsageFlags::INDEX_BUFFER.as_raw() },
+>;
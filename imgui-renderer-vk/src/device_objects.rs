@@ -0,0 +1,645 @@
+use std::ffi::CStr;
+use std::io::Cursor;
+
+use ash::vk;
+use imgui::{DrawVert, TextureId};
+
+use crate::buffers::StagingBuffer;
+use crate::renderer::RendererDevice;
+use crate::{find_memory_type, ImguiTexture, RenderError};
+
+const VERTEX_SHADER: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/vertex_shader.vert.spv"));
+const FRAGMENT_SHADER: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/fragment_shader.frag.spv"));
+const COMPOSITE_VERTEX_SHADER: &[u8] = include_bytes!(concat!(
+    env!("OUT_DIR"),
+    "/composite_vertex_shader.vert.spv"
+));
+const COMPOSITE_FRAGMENT_SHADER: &[u8] = include_bytes!(concat!(
+    env!("OUT_DIR"),
+    "/composite_fragment_shader.frag.spv"
+));
+
+/// The number of textures that can be registered at once, including the font texture.
+const MAX_TEXTURES: u32 = 64;
+
+const ENTRY_POINT: &CStr = unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") };
+
+/// Scale and translation from ImGui display space into clip space.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct PushConstants {
+    pub scale: [f32; 2],
+    pub translate: [f32; 2],
+}
+
+/// The rectangle and colour options of a composited texture, shared by both stages.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct CompositePushConstants {
+    pub rect: [f32; 4],
+    pub opacity: f32,
+    pub bgra: u32,
+    pub opaque: u32,
+    pub premultiplied: u32,
+    pub decode: u32,
+    pub encode: u32,
+}
+
+pub struct RendererDeviceObjects {
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub descriptor_pool: vk::DescriptorPool,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+    pub composite_pipeline_layout: vk::PipelineLayout,
+    pub composite_pipeline: vk::Pipeline,
+    pub sampler: vk::Sampler,
+}
+
+fn create_shader_module(
+    device: &ash::Device,
+    spv: &'static [u8],
+    name: &'static str,
+) -> Result<vk::ShaderModule, RenderError> {
+    let code = ash::util::read_spv(&mut Cursor::new(spv))
+        .map_err(|_| RenderError::ShaderError(Box::new(name)))?;
+    let info = vk::ShaderModuleCreateInfo::builder().code(&code);
+    Ok(unsafe { device.create_shader_module(&info, None)? })
+}
+
+impl RendererDeviceObjects {
+    pub fn new(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+    ) -> Result<RendererDeviceObjects, RenderError> {
+        // Every object is pushed into this as it is created, so that a failure part way
+        // through releases everything created before it.
+        let mut objects = RendererDeviceObjects {
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+            composite_pipeline_layout: vk::PipelineLayout::null(),
+            composite_pipeline: vk::Pipeline::null(),
+            sampler: vk::Sampler::null(),
+        };
+
+        match objects.create(device, render_pass) {
+            Ok(()) => Ok(objects),
+            Err(e) => {
+                objects.destroy(device);
+                Err(e)
+            }
+        }
+    }
+
+    fn create(
+        &mut self,
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+    ) -> Result<(), RenderError> {
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::REPEAT)
+            .address_mode_v(vk::SamplerAddressMode::REPEAT)
+            .address_mode_w(vk::SamplerAddressMode::REPEAT)
+            .min_lod(-1000.0)
+            .max_lod(1000.0)
+            .max_anisotropy(1.0);
+        self.sampler = unsafe { device.create_sampler(&sampler_info, None)? };
+
+        let bindings = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build()];
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+        self.descriptor_set_layout =
+            unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: MAX_TEXTURES,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+            .max_sets(MAX_TEXTURES)
+            .pool_sizes(&pool_sizes);
+        self.descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            offset: 0,
+            size: std::mem::size_of::<PushConstants>() as u32,
+        }];
+        let set_layouts = [self.descriptor_set_layout];
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        self.pipeline_layout =
+            unsafe { device.create_pipeline_layout(&pipeline_layout_info, None)? };
+
+        let vertex_module = create_shader_module(device, VERTEX_SHADER, "vertex shader")?;
+        let fragment_module = match create_shader_module(device, FRAGMENT_SHADER, "fragment shader")
+        {
+            Ok(module) => module,
+            Err(e) => {
+                unsafe { device.destroy_shader_module(vertex_module, None) };
+                return Err(e);
+            }
+        };
+
+        let pipeline = self.create_pipeline(device, render_pass, vertex_module, fragment_module);
+
+        unsafe {
+            device.destroy_shader_module(vertex_module, None);
+            device.destroy_shader_module(fragment_module, None);
+        }
+
+        self.pipeline = pipeline?;
+
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: std::mem::size_of::<CompositePushConstants>() as u32,
+        }];
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        self.composite_pipeline_layout =
+            unsafe { device.create_pipeline_layout(&pipeline_layout_info, None)? };
+
+        let vertex_module =
+            create_shader_module(device, COMPOSITE_VERTEX_SHADER, "composite vertex shader")?;
+        let fragment_module = match create_shader_module(
+            device,
+            COMPOSITE_FRAGMENT_SHADER,
+            "composite fragment shader",
+        ) {
+            Ok(module) => module,
+            Err(e) => {
+                unsafe { device.destroy_shader_module(vertex_module, None) };
+                return Err(e);
+            }
+        };
+
+        let pipeline =
+            self.create_composite_pipeline(device, render_pass, vertex_module, fragment_module);
+
+        unsafe {
+            device.destroy_shader_module(vertex_module, None);
+            device.destroy_shader_module(fragment_module, None);
+        }
+
+        self.composite_pipeline = pipeline?;
+        Ok(())
+    }
+
+    fn create_pipeline(
+        &self,
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        vertex_module: vk::ShaderModule,
+        fragment_module: vk::ShaderModule,
+    ) -> Result<vk::Pipeline, RenderError> {
+        let stages = [
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(vertex_module)
+                .name(ENTRY_POINT)
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(fragment_module)
+                .name(ENTRY_POINT)
+                .build(),
+        ];
+
+        let binding_descriptions = [vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<DrawVert>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }];
+
+        // DrawVert is laid out as pos: [f32; 2], uv: [f32; 2], col: [u8; 4]
+        let attribute_descriptions = [
+            vk::VertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: 0,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: 8,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 2,
+                binding: 0,
+                format: vk::Format::R8G8B8A8_UNORM,
+                offset: 16,
+            },
+        ];
+
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&binding_descriptions)
+            .vertex_attribute_descriptions(&attribute_descriptions);
+
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+
+        let viewport = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let rasterization = vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .line_width(1.0);
+
+        let multisample = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+
+        let color_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+            .build()];
+        let color_blend =
+            vk::PipelineColorBlendStateCreateInfo::builder().attachments(&color_attachments);
+
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::builder();
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+
+        let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport)
+            .rasterization_state(&rasterization)
+            .multisample_state(&multisample)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&color_blend)
+            .dynamic_state(&dynamic_state)
+            .layout(self.pipeline_layout)
+            .render_pass(render_pass)
+            .subpass(0);
+
+        let pipelines = unsafe {
+            device.create_graphics_pipelines(
+                vk::PipelineCache::null(),
+                &[pipeline_info.build()],
+                None,
+            )
+        };
+
+        match pipelines {
+            Ok(pipelines) => Ok(pipelines[0]),
+            Err((_, e)) => Err(e.into()),
+        }
+    }
+
+    /// Create the pipeline drawing composited textures, which generates a quad from the vertex
+    /// index and blends it as premultiplied alpha.
+    fn create_composite_pipeline(
+        &self,
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        vertex_module: vk::ShaderModule,
+        fragment_module: vk::ShaderModule,
+    ) -> Result<vk::Pipeline, RenderError> {
+        let stages = [
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(vertex_module)
+                .name(ENTRY_POINT)
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(fragment_module)
+                .name(ENTRY_POINT)
+                .build(),
+        ];
+
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::builder();
+
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_STRIP);
+
+        let viewport = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let rasterization = vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .line_width(1.0);
+
+        let multisample = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+
+        let color_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::ONE)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+            .build()];
+        let color_blend =
+            vk::PipelineColorBlendStateCreateInfo::builder().attachments(&color_attachments);
+
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::builder();
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+
+        let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport)
+            .rasterization_state(&rasterization)
+            .multisample_state(&multisample)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&color_blend)
+            .dynamic_state(&dynamic_state)
+            .layout(self.composite_pipeline_layout)
+            .render_pass(render_pass)
+            .subpass(0);
+
+        let pipelines = unsafe {
+            device.create_graphics_pipelines(
+                vk::PipelineCache::null(),
+                &[pipeline_info.build()],
+                None,
+            )
+        };
+
+        match pipelines {
+            Ok(pipelines) => Ok(pipelines[0]),
+            Err((_, e)) => Err(e.into()),
+        }
+    }
+
+    /// Allocate a descriptor set that samples `view`, to be used as a texture id.
+    pub fn allocate_texture(
+        &self,
+        device: &ash::Device,
+        view: vk::ImageView,
+        layout: vk::ImageLayout,
+    ) -> Result<vk::DescriptorSet, RenderError> {
+        let set_layouts = [self.descriptor_set_layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&set_layouts);
+
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info)?[0] };
+
+        let image_info = [vk::DescriptorImageInfo {
+            sampler: self.sampler,
+            image_view: view,
+            image_layout: layout,
+        }];
+        let writes = [vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_info)
+            .build()];
+
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+        Ok(descriptor_set)
+    }
+
+    pub fn free_texture(&self, device: &ash::Device, descriptor_set: vk::DescriptorSet) {
+        unsafe {
+            device
+                .free_descriptor_sets(self.descriptor_pool, &[descriptor_set])
+                .unwrap_or(());
+        }
+    }
+
+    /// Destroy every object. None of them may be in use by the device.
+    pub fn destroy(&mut self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_pipeline(self.composite_pipeline, None);
+            device.destroy_pipeline_layout(self.composite_pipeline_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+pub struct FontTexture {
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+    descriptor_set: vk::DescriptorSet,
+}
+
+impl FontTexture {
+    pub fn new(
+        fonts: &mut imgui::FontAtlasRefMut<'_>,
+        device: &RendererDevice,
+        objects: &RendererDeviceObjects,
+    ) -> Result<FontTexture, RenderError> {
+        let font_tex_data = fonts.build_rgba32_texture();
+        let extent = vk::Extent3D {
+            width: font_tex_data.width,
+            height: font_tex_data.height,
+            depth: 1,
+        };
+
+        let mut texture = FontTexture {
+            image: vk::Image::null(),
+            memory: vk::DeviceMemory::null(),
+            view: vk::ImageView::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+        };
+
+        match texture.create(device, objects, extent, font_tex_data.data) {
+            Ok(()) => Ok(texture),
+            Err(e) => {
+                texture.destroy(device, objects);
+                Err(e)
+            }
+        }
+    }
+
+    fn create(
+        &mut self,
+        device: &RendererDevice,
+        objects: &RendererDeviceObjects,
+        extent: vk::Extent3D,
+        data: &[u8],
+    ) -> Result<(), RenderError> {
+        let vk_device = &device.device;
+
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk::Format::R8G8B8A8_UNORM)
+            .extent(extent)
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+        unsafe {
+            self.image = vk_device.create_image(&image_info, None)?;
+            let requirements = vk_device.get_image_memory_requirements(self.image);
+            let memory_type = find_memory_type(
+                &device.memory_properties,
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .ok_or(RenderError::MemoryTypeError(Box::new("font texture")))?;
+
+            let alloc_info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(requirements.size)
+                .memory_type_index(memory_type);
+            self.memory = vk_device.allocate_memory(&alloc_info, None)?;
+            vk_device.bind_image_memory(self.image, self.memory, 0)?;
+
+            let view_info = vk::ImageViewCreateInfo::builder()
+                .image(self.image)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(vk::Format::R8G8B8A8_UNORM)
+                .subresource_range(COLOR_SUBRESOURCE_RANGE);
+            self.view = vk_device.create_image_view(&view_info, None)?;
+        }
+
+        self.descriptor_set = objects.allocate_texture(
+            vk_device,
+            self.view,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )?;
+
+        let staging = StagingBuffer::new(vk_device, &device.memory_properties, data)?;
+        let result = device.submit_once(|command_buffer| {
+            upload_image(
+                vk_device,
+                command_buffer,
+                staging.buffer(),
+                self.image,
+                extent,
+            )
+        });
+        staging.destroy(vk_device);
+        result
+    }
+
+    pub fn tex_id(&self) -> TextureId {
+        self.descriptor_set.as_tex_id()
+    }
+
+    /// Destroy the texture. It may not be in use by the device.
+    pub fn destroy(&mut self, device: &RendererDevice, objects: &RendererDeviceObjects) {
+        let vk_device = &device.device;
+        if self.descriptor_set != vk::DescriptorSet::null() {
+            objects.free_texture(vk_device, self.descriptor_set);
+        }
+        unsafe {
+            vk_device.destroy_image_view(self.view, None);
+            vk_device.destroy_image(self.image, None);
+            vk_device.free_memory(self.memory, None);
+        }
+    }
+}
+
+pub const COLOR_SUBRESOURCE_RANGE: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
+    aspect_mask: vk::ImageAspectFlags::COLOR,
+    base_mip_level: 0,
+    level_count: 1,
+    base_array_layer: 0,
+    layer_count: 1,
+};
+
+/// Record a copy from `staging` into `image`, leaving the image ready to be sampled.
+fn upload_image(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    staging: vk::Buffer,
+    image: vk::Image,
+    extent: vk::Extent3D,
+) {
+    let to_transfer = vk::ImageMemoryBarrier::builder()
+        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(COLOR_SUBRESOURCE_RANGE);
+
+    let region = vk::BufferImageCopy::builder()
+        .image_subresource(vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .image_extent(extent);
+
+    let to_shader = vk::ImageMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ)
+        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(COLOR_SUBRESOURCE_RANGE);
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::HOST,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_transfer.build()],
+        );
+        device.cmd_copy_buffer_to_image(
+            command_buffer,
+            staging,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[region.build()],
+        );
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_shader.build()],
+        );
+    }
+}
@@ -0,0 +1,54 @@
+mod buffers;
+mod composite;
+mod device_objects;
+mod renderer;
+
+use ash::vk;
+use ash::vk::Handle;
+use imgui::TextureId;
+
+pub trait ImguiTexture {
+    fn as_tex_id(&self) -> TextureId;
+}
+
+impl ImguiTexture for vk::DescriptorSet {
+    fn as_tex_id(&self) -> TextureId {
+        // Non-dispatchable handles are 64-bit, even on 32-bit platforms.
+        static_assertions::const_assert!(
+            std::mem::size_of::<u64>() <= std::mem::size_of::<usize>()
+        );
+        TextureId::new(self.as_raw() as usize)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RenderError {
+    #[error("Vulkan error: {0}")]
+    VulkanError(#[from] vk::Result),
+
+    #[error("No memory type is suitable for {0}")]
+    MemoryTypeError(Box<&'static str>),
+
+    #[error("Failed to load SPIR-V for {0}")]
+    ShaderError(Box<&'static str>),
+}
+
+/// Find the index of a memory type allowed by `type_bits` with all of `flags`.
+pub fn find_memory_type(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    type_bits: u32,
+    flags: vk::MemoryPropertyFlags,
+) -> Option<u32> {
+    memory_properties.memory_types[..memory_properties.memory_type_count as usize]
+        .iter()
+        .enumerate()
+        .find(|(index, memory_type)| {
+            type_bits & (1 << index) != 0 && memory_type.property_flags.contains(flags)
+        })
+        .map(|(index, _)| index as u32)
+}
+
+pub use composite::{ColorEncoding, Composite};
+pub use renderer::RenderToken;
+pub use renderer::Renderer as VulkanImguiRenderer;
+pub use renderer::RendererDevice;
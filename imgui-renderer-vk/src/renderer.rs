@@ -0,0 +1,486 @@
+use std::mem;
+
+use ash::vk;
+use ash::vk::Handle;
+use imgui::internal::RawWrapper;
+use imgui::{DrawCmd, DrawData, DrawIdx, TextureId};
+
+use crate::buffers::{IndexBuffer, VertexBuffer};
+use crate::composite::Composite;
+use crate::device_objects::{
+    CompositePushConstants, FontTexture, PushConstants, RendererDeviceObjects,
+};
+use crate::RenderError;
+
+/// The device the renderer draws with, and the queue textures are uploaded on.
+#[derive(Clone)]
+pub struct RendererDevice {
+    pub device: ash::Device,
+    pub memory_properties: vk::PhysicalDeviceMemoryProperties,
+    pub queue: vk::Queue,
+    pub queue_family_index: u32,
+}
+
+impl RendererDevice {
+    /// Record a command buffer with `f`, submit it to the upload queue and wait for it to complete.
+    pub(crate) fn submit_once<F: FnOnce(vk::CommandBuffer)>(
+        &self,
+        f: F,
+    ) -> Result<(), RenderError> {
+        let device = &self.device;
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(self.queue_family_index);
+
+        unsafe {
+            let pool = device.create_command_pool(&pool_info, None)?;
+            let result = (|| {
+                let alloc_info = vk::CommandBufferAllocateInfo::builder()
+                    .command_pool(pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(1);
+                let command_buffer = device.allocate_command_buffers(&alloc_info)?[0];
+
+                let begin_info = vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+                device.begin_command_buffer(command_buffer, &begin_info)?;
+                f(command_buffer);
+                device.end_command_buffer(command_buffer)?;
+
+                let fence = device.create_fence(&vk::FenceCreateInfo::default(), None)?;
+                let command_buffers = [command_buffer];
+                let submit_info = vk::SubmitInfo::builder().command_buffers(&command_buffers);
+                let result = device
+                    .queue_submit(self.queue, &[submit_info.build()], fence)
+                    .and_then(|_| device.wait_for_fences(&[fence], true, u64::MAX));
+                device.destroy_fence(fence, None);
+                result
+            })();
+            device.destroy_command_pool(pool, None);
+            Ok(result?)
+        }
+    }
+}
+
+/// Per-frame draw buffers. A slot may only be reused once the frame that last used it
+/// has completed.
+struct FrameBuffers {
+    vertex_buffer: VertexBuffer,
+    index_buffer: IndexBuffer,
+}
+
+pub struct Renderer {
+    device: RendererDevice,
+    device_objects: RendererDeviceObjects,
+    font: Option<FontTexture>,
+    frames: Vec<FrameBuffers>,
+    frame_index: usize,
+}
+
+pub struct RenderToken;
+
+impl Renderer {
+    /// Create a renderer that draws into subpass 0 of render passes compatible with `render_pass`.
+    ///
+    /// `frames_in_flight` draw buffers are kept, and used in turn by each call to `render`.
+    pub fn new(
+        device: &RendererDevice,
+        render_pass: vk::RenderPass,
+        frames_in_flight: usize,
+        imgui: &mut imgui::Context,
+    ) -> Result<Self, RenderError> {
+        let device_objects = RendererDeviceObjects::new(&device.device, render_pass)?;
+
+        let mut renderer = Renderer {
+            device: device.clone(),
+            device_objects,
+            font: None,
+            frames: Vec::with_capacity(frames_in_flight),
+            frame_index: 0,
+        };
+
+        for _ in 0..frames_in_flight.max(1) {
+            renderer.frames.push(FrameBuffers {
+                vertex_buffer: VertexBuffer::new(&device.device, &device.memory_properties)?,
+                index_buffer: IndexBuffer::new(&device.device, &device.memory_properties)?,
+            });
+        }
+
+        renderer.create_device_objects(imgui)?;
+        Self::set_imgui_metadata(imgui);
+        Ok(renderer)
+    }
+
+    fn set_imgui_metadata(imgui: &mut imgui::Context) {
+        imgui.io_mut().backend_flags |= imgui::BackendFlags::RENDERER_HAS_VTX_OFFSET;
+        imgui.set_renderer_name(Some(format!(
+            "imgui-renderer-vk@{}",
+            env!("CARGO_PKG_VERSION")
+        )));
+    }
+
+    pub fn create_device_objects(&mut self, imgui: &mut imgui::Context) -> Result<(), RenderError> {
+        if let Some(mut font) = self.font.take() {
+            font.destroy(&self.device, &self.device_objects);
+        }
+
+        let mut imgui_fonts = imgui.fonts();
+        let font = FontTexture::new(&mut imgui_fonts, &self.device, &self.device_objects)?;
+        imgui_fonts.tex_id = font.tex_id();
+        self.font = Some(font);
+        Ok(())
+    }
+
+    /// Point the ImGui font atlas at this renderer's font texture.
+    ///
+    /// This must be called before rendering if more than one renderer shares the same context.
+    pub fn bind_fonts(&self, imgui: &mut imgui::Context) {
+        if let Some(font) = &self.font {
+            imgui.fonts().tex_id = font.tex_id();
+        }
+    }
+
+    /// Register an image view to be drawn by ImGui, returning the descriptor set used
+    /// as its texture id. The view must be in `layout` whenever it is drawn.
+    pub fn register_texture(
+        &self,
+        view: vk::ImageView,
+        layout: vk::ImageLayout,
+    ) -> Result<vk::DescriptorSet, RenderError> {
+        self.device_objects
+            .allocate_texture(&self.device.device, view, layout)
+    }
+
+    /// Release a texture registered with `register_texture`. It may not be in use by the device.
+    pub fn unregister_texture(&self, descriptor_set: vk::DescriptorSet) {
+        self.device_objects
+            .free_texture(&self.device.device, descriptor_set)
+    }
+
+    /// Record the draw data into `command_buffer`, which must be inside a render pass compatible
+    /// with the one this renderer was created with.
+    pub fn render(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        draw_data: &DrawData,
+    ) -> Result<RenderToken, RenderError> {
+        // Avoid rendering when minimized
+        let fb_width = draw_data.display_size[0] * draw_data.framebuffer_scale[0];
+        let fb_height = draw_data.display_size[1] * draw_data.framebuffer_scale[1];
+
+        if fb_height <= 0.0 || fb_width <= 0.0 || draw_data.draw_lists_count() == 0 {
+            return Ok(RenderToken);
+        }
+
+        let frame_index = self.frame_index;
+        self.frame_index = (self.frame_index + 1) % self.frames.len();
+
+        self.upload_buffers(frame_index, draw_data)?;
+
+        unsafe {
+            self.setup_render_state(command_buffer, frame_index, draw_data, fb_width, fb_height);
+            self.render_cmd_lists(command_buffer, frame_index, draw_data, fb_width, fb_height);
+        }
+        Ok(RenderToken)
+    }
+
+    /// Record a draw of `texture`, registered with `register_texture`, into `command_buffer`,
+    /// blending it as premultiplied alpha independently of any draw data.
+    ///
+    /// `command_buffer` must be inside a render pass compatible with the one this renderer was
+    /// created with.
+    pub fn composite(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        texture: TextureId,
+        composite: &Composite,
+    ) -> Result<RenderToken, RenderError> {
+        let [fb_width, fb_height] = composite.framebuffer;
+        if fb_width <= 0.0 || fb_height <= 0.0 {
+            return Ok(RenderToken);
+        }
+
+        let device = &self.device.device;
+        let push_constants = CompositePushConstants {
+            rect: composite.rect(true),
+            opacity: composite.opacity,
+            bgra: composite.bgra as u32,
+            opaque: composite.opaque as u32,
+            premultiplied: composite.premultiplied as u32,
+            decode: composite.decode as u32,
+            encode: composite.encode as u32,
+        };
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.device_objects.composite_pipeline,
+            );
+
+            device.cmd_set_viewport(
+                command_buffer,
+                0,
+                &[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: fb_width,
+                    height: fb_height,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }],
+            );
+            device.cmd_set_scissor(
+                command_buffer,
+                0,
+                &[vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent: vk::Extent2D {
+                        width: fb_width as u32,
+                        height: fb_height as u32,
+                    },
+                }],
+            );
+
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.device_objects.composite_pipeline_layout,
+                0,
+                &[vk::DescriptorSet::from_raw(texture.id() as u64)],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.device_objects.composite_pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                std::slice::from_raw_parts(
+                    (&push_constants as *const CompositePushConstants).cast::<u8>(),
+                    mem::size_of::<CompositePushConstants>(),
+                ),
+            );
+
+            device.cmd_draw(command_buffer, 4, 1, 0, 0);
+        }
+        Ok(RenderToken)
+    }
+
+    fn upload_buffers(
+        &mut self,
+        frame_index: usize,
+        draw_data: &DrawData,
+    ) -> Result<(), RenderError> {
+        let device = &self.device.device;
+        let frame = &mut self.frames[frame_index];
+
+        frame.vertex_buffer.reserve(
+            device,
+            &self.device.memory_properties,
+            draw_data.total_vtx_count as usize,
+        )?;
+        frame.index_buffer.reserve(
+            device,
+            &self.device.memory_properties,
+            draw_data.total_idx_count as usize,
+        )?;
+
+        unsafe {
+            let vertices = frame.vertex_buffer.map(device)?;
+            let indices = match frame.index_buffer.map(device) {
+                Ok(indices) => indices,
+                Err(e) => {
+                    frame.vertex_buffer.unmap(device);
+                    return Err(e);
+                }
+            };
+
+            let mut vtx_offset = 0;
+            let mut idx_offset = 0;
+            for draw_list in draw_data.draw_lists() {
+                let vtx_buffer = draw_list.vtx_buffer();
+                let idx_buffer = draw_list.idx_buffer();
+
+                vertices[vtx_offset..vtx_offset + vtx_buffer.len()].copy_from_slice(vtx_buffer);
+                indices[idx_offset..idx_offset + idx_buffer.len()].copy_from_slice(idx_buffer);
+
+                vtx_offset += vtx_buffer.len();
+                idx_offset += idx_buffer.len();
+            }
+
+            frame.vertex_buffer.unmap(device);
+            frame.index_buffer.unmap(device);
+        }
+        Ok(())
+    }
+
+    unsafe fn setup_render_state(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        draw_data: &DrawData,
+        fb_width: f32,
+        fb_height: f32,
+    ) {
+        let device = &self.device.device;
+        let frame = &self.frames[frame_index];
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.device_objects.pipeline,
+        );
+
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &[frame.vertex_buffer.buffer()], &[0]);
+        device.cmd_bind_index_buffer(command_buffer, frame.index_buffer.buffer(), 0, IDX_TYPE);
+
+        device.cmd_set_viewport(
+            command_buffer,
+            0,
+            &[vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: fb_width,
+                height: fb_height,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }],
+        );
+
+        // Vulkan clip space has Y pointing down, the same as ImGui.
+        let scale = [
+            2.0 / draw_data.display_size[0],
+            2.0 / draw_data.display_size[1],
+        ];
+        let push_constants = PushConstants {
+            scale,
+            translate: [
+                -1.0 - draw_data.display_pos[0] * scale[0],
+                -1.0 - draw_data.display_pos[1] * scale[1],
+            ],
+        };
+
+        device.cmd_push_constants(
+            command_buffer,
+            self.device_objects.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            std::slice::from_raw_parts(
+                (&push_constants as *const PushConstants).cast::<u8>(),
+                mem::size_of::<PushConstants>(),
+            ),
+        );
+    }
+
+    unsafe fn render_cmd_lists(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        draw_data: &DrawData,
+        fb_width: f32,
+        fb_height: f32,
+    ) {
+        let device = &self.device.device;
+        let clip_off = draw_data.display_pos;
+        let clip_scale = draw_data.framebuffer_scale;
+
+        let mut global_vtx_offset = 0;
+        let mut global_idx_offset = 0;
+
+        for draw_list in draw_data.draw_lists() {
+            for cmd in draw_list.commands() {
+                match cmd {
+                    DrawCmd::RawCallback { callback, raw_cmd } => {
+                        callback(draw_list.raw(), raw_cmd)
+                    }
+                    DrawCmd::ResetRenderState => self.setup_render_state(
+                        command_buffer,
+                        frame_index,
+                        draw_data,
+                        fb_width,
+                        fb_height,
+                    ),
+                    DrawCmd::Elements { count, cmd_params } => {
+                        // X Y Z W
+                        let [clip_x, clip_y, clip_z, clip_w] = cmd_params.clip_rect;
+                        let [off_x, off_y] = clip_off;
+                        let [scale_x, scale_y] = clip_scale;
+
+                        let clip_min = (
+                            ((clip_x - off_x) * scale_x).max(0.0),
+                            ((clip_y - off_y) * scale_y).max(0.0),
+                        );
+                        let clip_max = (
+                            ((clip_z - off_x) * scale_x).min(fb_width),
+                            ((clip_w - off_y) * scale_y).min(fb_height),
+                        );
+
+                        if clip_max.0 <= clip_min.0 || clip_max.1 <= clip_min.1 {
+                            continue;
+                        }
+
+                        device.cmd_set_scissor(
+                            command_buffer,
+                            0,
+                            &[vk::Rect2D {
+                                offset: vk::Offset2D {
+                                    x: clip_min.0 as i32,
+                                    y: clip_min.1 as i32,
+                                },
+                                extent: vk::Extent2D {
+                                    width: (clip_max.0 - clip_min.0) as u32,
+                                    height: (clip_max.1 - clip_min.1) as u32,
+                                },
+                            }],
+                        );
+
+                        let descriptor_set =
+                            vk::DescriptorSet::from_raw(cmd_params.texture_id.id() as u64);
+                        device.cmd_bind_descriptor_sets(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            self.device_objects.pipeline_layout,
+                            0,
+                            &[descriptor_set],
+                            &[],
+                        );
+
+                        device.cmd_draw_indexed(
+                            command_buffer,
+                            count as u32,
+                            1,
+                            (cmd_params.idx_offset + global_idx_offset) as u32,
+                            (cmd_params.vtx_offset + global_vtx_offset) as i32,
+                            0,
+                        );
+                    }
+                }
+            }
+
+            global_vtx_offset += draw_list.vtx_buffer().len();
+            global_idx_offset += draw_list.idx_buffer().len();
+        }
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        if let Some(mut font) = self.font.take() {
+            font.destroy(&self.device, &self.device_objects);
+        }
+        for frame in &mut self.frames {
+            frame.vertex_buffer.destroy(&self.device.device);
+            frame.index_buffer.destroy(&self.device.device);
+        }
+        self.device_objects.destroy(&self.device.device);
+    }
+}
+
+const IDX_TYPE: vk::IndexType = idx_type();
+const fn idx_type() -> vk::IndexType {
+    if mem::size_of::<DrawIdx>() == 2 {
+        vk::IndexType::UINT16
+    } else {
+        vk::IndexType::UINT32
+    }
+}
@@ -0,0 +1,79 @@
+#version 450 core
+layout(set = 0, binding = 0) uniform sampler2D Texture;
+
+layout(push_constant) uniform PushConstants {
+    vec4 Rect;
+    float Opacity;
+    uint Bgra;
+    uint Opaque;
+    uint Premultiplied;
+    uint Decode;
+    uint Encode;
+} pc;
+
+layout(location = 0) in vec2 Frag_UV;
+
+layout(location = 0) out vec4 Out_Color;
+
+// Columns of the matrices converting between BT.709 and BT.2020 primaries.
+const mat3 BT709_TO_BT2020 = mat3(
+    0.6274, 0.0691, 0.0164,
+    0.3293, 0.9195, 0.0880,
+    0.0433, 0.0114, 0.8956);
+const mat3 BT2020_TO_BT709 = mat3(
+    1.6605, -0.1246, -0.0182,
+    -0.5876, 1.1329, -0.1006,
+    -0.0728, -0.0083, 1.1187);
+
+// SDR white in HDR10, as a fraction of the 10000 nits PQ encodes.
+const float SDR_WHITE = 203.0 / 10000.0;
+
+const float PQ_M1 = 0.1593017578125;
+const float PQ_M2 = 78.84375;
+const float PQ_C1 = 0.8359375;
+const float PQ_C2 = 18.8515625;
+const float PQ_C3 = 18.6875;
+
+vec3 ToLinear(vec3 color, uint encoding)
+{
+    if (encoding == 0)
+        return mix(color / 12.92, pow((color + 0.055) / 1.055, vec3(2.4)), step(0.04045, color));
+    if (encoding == 2)
+    {
+        vec3 p = pow(max(color, 0.0), vec3(1.0 / PQ_M2));
+        vec3 nits = pow(max(p - PQ_C1, 0.0) / (PQ_C2 - PQ_C3 * p), vec3(1.0 / PQ_M1));
+        return BT2020_TO_BT709 * (nits / SDR_WHITE);
+    }
+    return color;
+}
+
+vec3 FromLinear(vec3 color, uint encoding)
+{
+    if (encoding == 0)
+    {
+        color = max(color, 0.0);
+        return mix(color * 12.92, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, color));
+    }
+    if (encoding == 2)
+    {
+        vec3 y = pow(max(BT709_TO_BT2020 * color * SDR_WHITE, 0.0), vec3(PQ_M1));
+        return pow((PQ_C1 + PQ_C2 * y) / (1.0 + PQ_C3 * y), vec3(PQ_M2));
+    }
+    return color;
+}
+
+void main()
+{
+    vec4 color = texture(Texture, Frag_UV.st);
+    if (pc.Bgra != 0)
+        color = color.bgra;
+    if (pc.Opaque != 0)
+        color.a = 1.0;
+    // Colour is converted unpremultiplied, since the transfer functions are not linear.
+    if (pc.Premultiplied != 0 && color.a > 0.0)
+        color.rgb /= color.a;
+    if (pc.Decode != pc.Encode)
+        color.rgb = FromLinear(ToLinear(color.rgb, pc.Decode), pc.Encode);
+    color.rgb *= color.a;
+    Out_Color = color * pc.Opacity;
+}
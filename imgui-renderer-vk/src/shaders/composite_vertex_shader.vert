@@ -0,0 +1,23 @@
+#version 450 core
+layout(push_constant) uniform PushConstants {
+    vec4 Rect;
+    float Opacity;
+    uint Bgra;
+    uint Opaque;
+    uint Premultiplied;
+    uint Decode;
+    uint Encode;
+} pc;
+
+layout(location = 0) out vec2 Frag_UV;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main()
+{
+    vec2 corner = vec2(gl_VertexIndex & 1, gl_VertexIndex >> 1);
+    Frag_UV = corner;
+    gl_Position = vec4(mix(pc.Rect.xy, pc.Rect.zw, corner), 0, 1);
+}
@@ -0,0 +1,23 @@
+#version 450 core
+layout(location = 0) in vec2 Position;
+layout(location = 1) in vec2 UV;
+layout(location = 2) in vec4 Color;
+
+layout(push_constant) uniform PushConstants {
+    vec2 Scale;
+    vec2 Translate;
+} pc;
+
+layout(location = 0) out vec4 Frag_Color;
+layout(location = 1) out vec2 Frag_UV;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main()
+{
+    Frag_Color = Color;
+    Frag_UV = UV;
+    gl_Position = vec4(Position * pc.Scale + pc.Translate, 0, 1);
+}
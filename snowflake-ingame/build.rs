@@ -0,0 +1,73 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+/// The name of the layer, as enabled by applications and in `VK_INSTANCE_LAYERS`.
+const LAYER_NAME: &str = "VK_LAYER_SNOWFLAKE_ingame";
+
+/// The file name of the generated layer manifest.
+const MANIFEST_NAME: &str = "VkLayer_snowflake_ingame.json";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+
+    let target_os = env::var("CARGO_CFG_TARGET_OS")?;
+    let (library, platform) = match target_os.as_str() {
+        "windows" => (".\\\\snowflake_ingame.dll", "WINDOWS"),
+        "linux" => ("./libsnowflake_ingame.so", "LINUX"),
+        _ => return Ok(()),
+    };
+
+    let manifest = out_dir.join(MANIFEST_NAME);
+    write_manifest(&manifest, library, platform)?;
+    println!(
+        "cargo:rustc-env=SNOWFLAKE_INGAME_LAYER_MANIFEST={}",
+        manifest.display()
+    );
+    Ok(())
+}
+
+/// Write the layer manifest to `path`, in the build script's output directory.
+///
+/// The manifest names the library relative to itself, so it is copied next to the built library
+/// when the layer is packaged. The same manifest works as an explicit layer, found through
+/// `VK_LAYER_PATH` or `VK_ADD_LAYER_PATH`, and as an implicit layer installed into an
+/// `implicit_layer.d` directory. Implicit layers are only enabled while
+/// `ENABLE_SNOWFLAKE_INGAME_VULKAN` is set, and never while `DISABLE_SNOWFLAKE_INGAME_VULKAN` is.
+fn write_manifest(path: &Path, library: &str, platform: &str) -> Result<(), Box<dyn Error>> {
+    let manifest = format!(
+        r#"{{
+  "file_format_version": "1.2.0",
+  "layer": {{
+    "name": "{name}",
+    "type": "GLOBAL",
+    "library_path": "{library}",
+    "api_version": "1.3.224",
+    "implementation_version": "{version}",
+    "description": "Snowflake Ingame Runtime",
+    "platforms": [ "{platform}" ],
+    "functions": {{
+      "vkNegotiateLoaderLayerInterfaceVersion": "vk_main",
+      "vkGetInstanceProcAddr": "get_instance_proc_addr",
+      "vkGetDeviceProcAddr": "get_device_proc_addr"
+    }},
+    "enable_environment": {{
+      "ENABLE_SNOWFLAKE_INGAME_VULKAN": "1"
+    }},
+    "disable_environment": {{
+      "DISABLE_SNOWFLAKE_INGAME_VULKAN": "1"
+    }}
+  }}
+}}
+"#,
+        name = LAYER_NAME,
+        library = library,
+        version = env::var("CARGO_PKG_VERSION_MAJOR")?,
+        platform = platform,
+    );
+
+    fs::write(path, manifest)
+        .map_err(|e| format!("Unable to write {}: {:?}", MANIFEST_NAME, e).into())
+}
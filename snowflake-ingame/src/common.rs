@@ -1,6 +1,8 @@
 use crate::ipc::cmd::GameWindowCommand;
-use imgui::{Condition, Image, StyleVar, TextureId, Ui, Window, WindowFlags};
+use crate::overlay::OverlayFormat;
+#[cfg(windows)]
 use windows::Win32::Foundation::HANDLE;
+#[cfg(windows)]
 use windows::Win32::Graphics::Direct3D11::D3D11_TEXTURE2D_DESC;
 
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
@@ -30,6 +32,7 @@ impl From<Dimensions> for [f32; 2] {
     }
 }
 
+#[cfg(windows)]
 impl From<D3D11_TEXTURE2D_DESC> for Dimensions {
     fn from(item: D3D11_TEXTURE2D_DESC) -> Self {
         Dimensions {
@@ -39,25 +42,20 @@ impl From<D3D11_TEXTURE2D_DESC> for Dimensions {
     }
 }
 
-pub struct OverlayWindow;
-impl OverlayWindow {
-    pub fn new(ui: &Ui, tid: TextureId, dim: Dimensions) {
-        let _style_pad = ui.push_style_var(StyleVar::WindowPadding([0.0, 0.0]));
-        let _style_border = ui.push_style_var(StyleVar::WindowBorderSize(0.0));
-        // We don't care if the window isn't rendered.
-        Window::new("BrowserWindow")
-            .size(dim.into(), Condition::Always)
-            .position([0.0, 0.0], Condition::Always)
-            .flags(
-                WindowFlags::NO_DECORATION
-                    | WindowFlags::NO_MOVE
-                    | WindowFlags::NO_RESIZE
-                    | WindowFlags::NO_BACKGROUND,
-            )
-            .no_decoration()
-            .build(ui, || Image::new(tid, dim.into()).build(ui))
-            .unwrap_or(())
-    }
+/// The graphics adapter the game renders with, as identified by its graphics API.
+///
+/// The orchestrator allocates the overlay texture on the matching adapter, as importing
+/// a texture from another adapter either fails or is slow.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub struct AdapterIdentity {
+    /// The LUID of the adapter, as reported by DXGI and Vulkan on Windows.
+    pub luid: Option<[u8; 8]>,
+    /// The device UUID, as reported by Vulkan and `GL_EXT_memory_object`.
+    pub uuid: Option<[u8; 16]>,
+    /// The PCI vendor id, or 0 if it is not known.
+    pub vendor_id: u32,
+    /// The PCI device id, or 0 if it is not known.
+    pub device_id: u32,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -68,24 +66,58 @@ pub enum RenderError {
     #[error("A internal OpenGL error occurred ({0:?}).")]
     OpenGLInternalError(#[from] imgui_renderer_ogl::RenderError),
 
+    #[cfg(windows)]
     #[error("A internal Direct3D11 error occurred ({0:?}).")]
     Direct3D11InternalError(#[from] imgui_renderer_dx11::RenderError),
 
+    #[error("A internal Vulkan renderer error occurred ({0:?}).")]
+    VulkanInternalError(#[from] imgui_renderer_vk::RenderError),
+
+    #[error("An OpenGL error occurred ({0:#x}).")]
+    OpenGLError(u32),
+
+    #[error("A Vulkan error occurred ({0:?}).")]
+    VulkanError(#[from] ash::vk::Result),
+
+    #[error("The Vulkan device is missing required extensions: {0}")]
+    VulkanMissingExtension(&'static str),
+
+    #[cfg(windows)]
     #[error("A internal DXGI error occurred ({0:x?}).")]
     DXGIInternalError(#[from] windows::core::Error),
 
     #[error("The requested renderer has not been initialized.")]
     RendererNotReady,
 
+    #[cfg(windows)]
     #[error("Error occurred when trying to open shared handle {0:x?} ({1:x?}).")]
     OverlayHandleError(HANDLE, windows::core::Error), // 128 + 64
 
+    #[cfg(target_os = "linux")]
+    #[error("Error occurred when trying to import overlay file descriptor ({0}).")]
+    OverlayFdError(std::io::Error),
+
+    #[error("The overlay texture format {0:#x} is not supported.")]
+    OverlayFormatUnsupported(u32),
+
+    #[error("The overlay texture was announced as {0:?}, but has format {1:#x}.")]
+    OverlayFormatMismatch(OverlayFormat, u32),
+
     #[error("The overlay texture handle has not been initialized.")]
     OverlayHandleNotReady,
 
     #[error("The overlay mutex could not be acquired.")]
     OverlayMutexNotReady,
 
+    #[error("The orchestrator has not finished writing an overlay frame.")]
+    OverlayFrameNotReady,
+
+    #[error("Overlay frames can not be uploaded from shared memory by this backend.")]
+    OverlaySharedMemoryUnsupported,
+
+    #[error("A ring of overlay textures can not be imported by this backend.")]
+    OverlayRingUnsupported,
+
     #[error("The overlay could not be initialized. {0}")]
     OverlayPaintNotReady(Box<RenderError>),
 
@@ -1,15 +1,34 @@
 use crate::common::{Dimensions, RenderError};
-use crate::d3d11::overlay_d3d11::Direct3D11Overlay;
+use crate::overlay::{OverlayEncoding, OverlayFilter, OverlayPaint};
 use imgui::{Context, DrawData};
-use imgui_renderer_dx11::{Direct3D11ImguiRenderer, RenderToken};
+use imgui_renderer_dx11::{ColorEncoding, Composite, Direct3D11ImguiRenderer, RenderToken};
 use parking_lot::RwLock;
 use std::ptr;
 use std::sync::Arc;
 use windows::core::Result as HResult;
 use windows::Win32::Foundation::HWND;
-use windows::Win32::Graphics::Direct3D11::{ID3D11Device, ID3D11RenderTargetView, ID3D11Texture2D};
+use windows::Win32::Graphics::Direct3D11::{
+    ID3D11Device, ID3D11RenderTargetView, ID3D11Texture2D, D3D11_FILTER,
+    D3D11_FILTER_MIN_MAG_MIP_LINEAR, D3D11_FILTER_MIN_MAG_MIP_POINT,
+};
 use windows::Win32::Graphics::Dxgi::{IDXGISwapChain, DXGI_SWAP_CHAIN_DESC};
 
+const fn d3d11_filter(filter: OverlayFilter) -> D3D11_FILTER {
+    match filter {
+        OverlayFilter::Linear => D3D11_FILTER_MIN_MAG_MIP_LINEAR,
+        OverlayFilter::Nearest => D3D11_FILTER_MIN_MAG_MIP_POINT,
+    }
+}
+
+/// The encoding the composite pass of the renderer converts colour from or to.
+const fn color_encoding(encoding: OverlayEncoding) -> ColorEncoding {
+    match encoding {
+        OverlayEncoding::Srgb => ColorEncoding::Srgb,
+        OverlayEncoding::Linear => ColorEncoding::Linear,
+        OverlayEncoding::Hdr10 => ColorEncoding::Hdr10,
+    }
+}
+
 pub(in crate::d3d11) struct Render<'a> {
     render: Option<&'a mut Direct3D11ImguiRenderer>,
 }
@@ -22,6 +41,31 @@ impl Render<'_> {
             Err(RenderError::RendererNotReady)
         }
     }
+
+    /// Composite the overlay texture onto the framebuffer, before the ImGui draw data.
+    pub fn composite(&mut self, paint: &OverlayPaint) -> Result<(), RenderError> {
+        let composite = Composite {
+            position: paint.placement.position.map(|p| p as f32),
+            size: paint.placement.size.into(),
+            framebuffer: paint.viewport.into(),
+            opacity: paint.placement.opacity,
+            bgra: paint.texels.bgra,
+            opaque: paint.texels.opaque,
+            premultiplied: paint.texels.premultiplied,
+            decode: color_encoding(paint.texels.encoding),
+            encode: color_encoding(paint.output.written()),
+        };
+
+        if let Some(renderer) = &mut self.render {
+            // The renderer composites with a sampler of its own,
+            // so the layer's filter is set on the renderer.
+            renderer.set_filter(d3d11_filter(paint.filter))?;
+            renderer.composite(paint.texture, &composite)?;
+            Ok(())
+        } else {
+            Err(RenderError::RendererNotReady)
+        }
+    }
 }
 
 pub(in crate::d3d11) struct Direct3D11ImguiController {
@@ -51,19 +95,20 @@ impl Direct3D11ImguiController {
         self.rtv.is_some()
     }
 
-    pub fn frame<
-        'a,
-        F: FnOnce(&mut Context, Render, &mut Direct3D11Overlay) -> Result<RenderToken, RenderError>,
-    >(
+    pub fn frame<'a, F: FnOnce(&mut Context, Render) -> Result<RenderToken, RenderError>>(
         &mut self,
-        overlay: &mut Direct3D11Overlay,
         f: F,
     ) -> Result<RenderToken, RenderError> {
+        let mut imgui = self.imgui.write();
+        if let Some(renderer) = &self.renderer {
+            renderer.bind_fonts(&mut imgui);
+        }
+
         let renderer = Render {
             render: self.renderer.as_mut(),
         };
 
-        f(&mut self.imgui.write(), renderer, overlay)
+        f(&mut imgui, renderer)
     }
 
     fn init_renderer(
@@ -3,40 +3,52 @@ use parking_lot::{RwLock, RwLockWriteGuard};
 use std::error::Error;
 use std::mem::ManuallyDrop;
 use std::sync::Arc;
+use windows::core::{Interface, Vtable};
+use windows::Win32::Foundation::HWND;
 use windows::Win32::Graphics::Direct3D11::{ID3D11Device1, ID3D11Texture2D, D3D11_TEXTURE2D_DESC};
+use windows::Win32::Graphics::Dxgi::Common::*;
 use windows::Win32::Graphics::Dxgi::*;
+use windows::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, IsWindow};
 
-use crate::common::{OverlayWindow, RenderError};
+use crate::common::RenderError;
 use crate::d3d11::hook::{Direct3D11HookContext, FnPresentHook, FnResizeBuffersHook};
 use crate::d3d11::imgui::Direct3D11ImguiController;
-use crate::d3d11::overlay::Direct3D11Overlay;
+use crate::d3d11::overlay::{Direct3D11Overlay, Direct3D11OverlayBackend};
 use crate::hook::{HookChain, HookHandle};
-use crate::ipc::cmd::{GameWindowCommand, GameWindowCommandType};
-use crate::ipc::IpcHandle;
+use crate::ipc::cmd::GameWindowCommandType;
+use crate::overlay::{
+    claim_window, take_commands, OverlayBackend, OverlayEncoding, OverlayFormat, OverlayOutput,
+    OverlayStatus, OverlayTargetPolicy, Ownership, SurfaceMap,
+};
 use crate::{FrameKernel, KernelContext};
 
+/// Overlay and renderer state for a single swapchain.
+struct Direct3D11Surface {
+    overlay: Direct3D11Overlay,
+    imgui: Direct3D11ImguiController,
+}
+
 /// Kernel for a D3D11 hook.
 ///
-/// `overlay` and `imgui` should never be accessed outside of the Direct3D11 rendering thread.
-/// The mutexes to which should only ever be acquired inside an `FnPresentHook` or `FnResizeBuffersHook`,
+/// `surfaces` should never be accessed outside of the Direct3D11 rendering thread.
+/// The mutex to which should only ever be acquired inside an `FnPresentHook` or `FnResizeBuffersHook`,
 /// to ensure the soundness of the Send and Sync impls for Direct3D11Overlay
 pub struct Direct3D11Kernel {
     hook: Direct3D11HookContext,
-    overlay: Arc<RwLock<Direct3D11Overlay>>,
-    imgui: Arc<RwLock<Direct3D11ImguiController>>,
-    ipc: IpcHandle,
+    surfaces: Arc<RwLock<SurfaceMap<usize, Direct3D11Surface>>>,
+    status: OverlayStatus,
+    context: KernelContext,
 }
 
 impl FrameKernel for Direct3D11Kernel {
     type Handle = impl HookHandle;
 
     fn new(context: KernelContext) -> Result<Self, Box<dyn Error>> {
-        let KernelContext { ipc, imgui } = context;
         Ok(Direct3D11Kernel {
             hook: Direct3D11HookContext::init()?,
-            overlay: Arc::new(RwLock::new(Direct3D11Overlay::new())),
-            imgui: Arc::new(RwLock::new(Direct3D11ImguiController::new(imgui))),
-            ipc,
+            surfaces: Arc::new(RwLock::new(SurfaceMap::new(OverlayTargetPolicy::Largest))),
+            status: OverlayStatus::new("dx11"),
+            context,
         })
     }
 
@@ -48,28 +60,64 @@ impl FrameKernel for Direct3D11Kernel {
             .persist();
         Ok(handle)
     }
+
+    fn status(&self) -> OverlayStatus {
+        self.status.clone()
+    }
 }
 
-impl Direct3D11Kernel {
-    fn present_impl(
-        handle: IpcHandle,
-        mut overlay: RwLockWriteGuard<Direct3D11Overlay>,
-        mut imgui: RwLockWriteGuard<Direct3D11ImguiController>,
-        this: &IDXGISwapChain,
-    ) -> Result<RenderToken, RenderError> {
-        // Handle update of any overlay here.
-        if let Ok(cmd) = &handle.try_recv() {
-            match cmd.ty {
-                GameWindowCommandType::OVERLAY_TEXTURE => {
-                    eprintln!("[dx11] received overlay texture event");
-                    overlay
-                        .refresh(unsafe { cmd.params.overlay_event })
-                        .unwrap_or_else(|e| eprintln!("[dx11] handle error: {}", e));
-                }
-                _ => {}
+/// The framebuffer of a swapchain whose backbuffers are in `format`.
+///
+/// DXGI does not report the colorspace a swapchain was set to, so a 10-bit swapchain is
+/// taken to be HDR10 when the output it is presented on is.
+fn swapchain_output(this: &IDXGISwapChain, format: DXGI_FORMAT) -> OverlayOutput {
+    let (format, encoding, srgb_writes) = match format {
+        DXGI_FORMAT_B8G8R8A8_UNORM => (Some(OverlayFormat::Bgra8), OverlayEncoding::Srgb, false),
+        DXGI_FORMAT_B8G8R8A8_UNORM_SRGB => {
+            (Some(OverlayFormat::Bgra8), OverlayEncoding::Srgb, true)
+        }
+        DXGI_FORMAT_R8G8B8A8_UNORM => (Some(OverlayFormat::Rgba8), OverlayEncoding::Srgb, false),
+        DXGI_FORMAT_R8G8B8A8_UNORM_SRGB => {
+            (Some(OverlayFormat::Rgba8), OverlayEncoding::Srgb, true)
+        }
+        DXGI_FORMAT_R16G16B16A16_FLOAT => (
+            Some(OverlayFormat::Rgba16Float),
+            OverlayEncoding::Linear,
+            false,
+        ),
+        DXGI_FORMAT_R10G10B10A2_UNORM => {
+            let hdr10 = unsafe {
+                this.GetContainingOutput()
+                    .and_then(|output| output.cast::<IDXGIOutput6>())
+                    .and_then(|output| output.GetDesc1())
             }
+            .map_or(false, |desc| {
+                desc.ColorSpace == DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020
+            });
+            let encoding = if hdr10 {
+                OverlayEncoding::Hdr10
+            } else {
+                OverlayEncoding::Srgb
+            };
+            (Some(OverlayFormat::Rgb10A2), encoding, false)
         }
+        _ => (None, OverlayEncoding::Srgb, false),
+    };
+
+    OverlayOutput {
+        format,
+        encoding,
+        srgb_writes,
+    }
+}
 
+impl Direct3D11Kernel {
+    fn present_impl(
+        context: &KernelContext,
+        status: &OverlayStatus,
+        mut surfaces: RwLockWriteGuard<SurfaceMap<usize, Direct3D11Surface>>,
+        this: &IDXGISwapChain,
+    ) -> Result<Option<RenderToken>, RenderError> {
         let swapchain_desc = unsafe { this.GetDesc()? };
         let backbuffer = unsafe { this.GetBuffer::<ID3D11Texture2D>(0)? };
 
@@ -80,22 +128,77 @@ impl Direct3D11Kernel {
         };
 
         let size = backbuffer_desc.into();
-        if !overlay.size_matches_viewpoint(&size) {
-            handle.send(GameWindowCommand::window_resize(
-                &size,
-                !overlay.ready_to_initialize(),
-            ))?;
+        let key = Vtable::as_raw(this) as usize;
+
+        surfaces.present(key, swapchain_desc.OutputWindow.0, size, || {
+            Direct3D11Surface {
+                overlay: Direct3D11Overlay::with_status(
+                    Direct3D11OverlayBackend::new(),
+                    status.clone(),
+                ),
+                imgui: Direct3D11ImguiController::new(context.imgui.clone()),
+            }
+        });
+
+        // Drop the state of any swapchain whose window has gone away.
+        drop(surfaces.evict(|window| unsafe { IsWindow(HWND(window)) }.as_bool()));
+
+        surfaces.select_target(unsafe { GetForegroundWindow() }.0);
+
+        if !surfaces.is_target(&key) {
+            return Ok(None);
         }
 
-        if !overlay.ready_to_initialize() {
-            return Err(RenderError::OverlayHandleNotReady);
+        let window = match surfaces.get_mut(&key) {
+            Some(surface) => surface.window,
+            None => return Ok(None),
+        };
+
+        let ownership = claim_window(window, Direct3D11OverlayBackend::NAME);
+
+        // Only the backend that owns the window the commands are routed to receives them,
+        // so that the overlay texture is handed to the overlay that paints it. Every command
+        // that arrived since the last frame is handled, so that frames the orchestrator
+        // announced are not painted late.
+        let cmds = if ownership.is_owner() {
+            take_commands(window, &context.ipc)
+        } else {
+            Vec::new()
+        };
+
+        for cmd in &cmds {
+            if cmd.ty == GameWindowCommandType::OVERLAY_TARGET {
+                surfaces.set_policy(unsafe { cmd.params.overlay_target_event }.into());
+            }
+        }
+
+        let Direct3D11Surface { overlay, imgui } = match surfaces.get_mut(&key) {
+            Some(surface) => &mut surface.state,
+            None => return Ok(None),
+        };
+
+        match ownership {
+            Ownership::Owned | Ownership::Acquired => {}
+            Ownership::Revoked => {
+                overlay.reset();
+                return Ok(None);
+            }
+            Ownership::Denied => return Ok(None),
+        }
+
+        // Handle update of any overlay here.
+        for cmd in &cmds {
+            overlay.handle_command(cmd);
         }
 
         let device = unsafe { this.GetDevice::<ID3D11Device1>()? };
 
-        overlay
-            .prepare_paint(device, swapchain_desc.OutputWindow)
-            .map_err(|e| RenderError::OverlayPaintNotReady(Box::new(e)))?;
+        overlay.prepare_frame(
+            &context.ipc,
+            size,
+            swapchain_output(this, backbuffer_desc.Format),
+            (device, swapchain_desc.OutputWindow),
+        )?;
 
         imgui
             .prepare_paint(&this, size)
@@ -104,36 +207,39 @@ impl Direct3D11Kernel {
         // imgui stuff here.
         // We don't need an external mutex here because the overlay will not change underneath us,
         // since overlay is updated within Present now.
-        if let Some(_kmt) = overlay.acquire_sync() {
-            imgui.frame(&mut overlay, |ctx, render, overlay| {
+        // The overlay paints a copy of the texture, so the game's frame is rendered
+        // whether or not the orchestrator released the latest one.
+        imgui
+            .frame(|ctx, mut render| {
                 let ui = ctx.frame();
-                overlay.paint(|tid, dim| OverlayWindow::new(&ui, tid, dim));
+                overlay
+                    .acquire_sync()
+                    .paint(|paint| render.composite(paint))?;
                 ui.show_demo_window(&mut false);
                 ui.show_metrics_window(&mut false);
                 render.render(ui.render())
             })
-        } else {
-            Err(RenderError::OverlayMutexNotReady)
-        }
+            .map(Some)
     }
 
-    fn resize_impl(mut imgui: RwLockWriteGuard<Direct3D11ImguiController>) {
-        imgui.invalidate_rtv();
+    fn resize_impl(
+        mut surfaces: RwLockWriteGuard<SurfaceMap<usize, Direct3D11Surface>>,
+        this: &IDXGISwapChain,
+    ) {
+        if let Some(surface) = surfaces.get_mut(&(Vtable::as_raw(this) as usize)) {
+            surface.state.imgui.invalidate_rtv();
+        }
     }
 
     fn make_present(&self) -> FnPresentHook {
-        let handle = self.ipc.clone();
-        let overlay = self.overlay.clone();
-        let imgui = self.imgui.clone();
+        let context = self.context.clone();
+        let surfaces = self.surfaces.clone();
+        let status = self.status.clone();
         Box::new(
             move |this: IDXGISwapChain, sync: u32, flags: u32, mut next| {
-                let handle = handle.clone();
-                match Direct3D11Kernel::present_impl(handle, overlay.write(), imgui.write(), &this)
-                {
+                match Direct3D11Kernel::present_impl(&context, &status, surfaces.write(), &this) {
                     Ok(_) => {}
-                    Err(e) => {
-                        eprintln!("[dx11] {}", e)
-                    }
+                    Err(e) => status.fail(&e),
                 }
                 let fp = next.fp_next();
                 fp(this, sync, flags, next)
@@ -142,10 +248,10 @@ impl Direct3D11Kernel {
     }
 
     fn make_resize(&self) -> FnResizeBuffersHook {
-        let imgui = self.imgui.clone();
+        let surfaces = self.surfaces.clone();
         Box::new(
             move |this: IDXGISwapChain, buf_cnt, width, height, format, flags, mut next| {
-                Direct3D11Kernel::resize_impl(imgui.write());
+                Direct3D11Kernel::resize_impl(surfaces.write(), &this);
                 let fp = next.fp_next();
                 fp(this, buf_cnt, width, height, format, flags, next)
             },
@@ -1,34 +1,89 @@
+use std::cell::{Cell, RefCell};
+
 use imgui::TextureId;
-use windows::core::Interface;
-use windows::Win32::Foundation::{HANDLE, HWND};
+use windows::core::{Interface, Vtable};
+use windows::Win32::Foundation::{HANDLE, HWND, S_OK};
 use windows::Win32::Graphics::Direct3D::D3D11_SRV_DIMENSION_TEXTURE2D;
 use windows::Win32::Graphics::Direct3D11::{
-    ID3D11Device1, ID3D11ShaderResourceView, ID3D11Texture2D, D3D11_SHADER_RESOURCE_VIEW_DESC,
-    D3D11_SHADER_RESOURCE_VIEW_DESC_0, D3D11_TEX2D_SRV,
+    ID3D11Device1, ID3D11DeviceContext, ID3D11ShaderResourceView, ID3D11Texture2D,
+    D3D11_BIND_SHADER_RESOURCE, D3D11_BOX, D3D11_SHADER_RESOURCE_VIEW_DESC,
+    D3D11_SHADER_RESOURCE_VIEW_DESC_0, D3D11_TEX2D_SRV, D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT,
+};
+use windows::Win32::Graphics::Dxgi::Common::{
+    DXGI_FORMAT, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_R10G10B10A2_UNORM,
+    DXGI_FORMAT_R16G16B16A16_FLOAT, DXGI_FORMAT_R8G8B8A8_UNORM,
 };
-use windows::Win32::Graphics::Dxgi::IDXGIKeyedMutex;
+use windows::Win32::Graphics::Dxgi::{IDXGIDevice, IDXGIKeyedMutex};
 
 use imgui_renderer_dx11::ImguiTexture;
 
-use crate::common::{Dimensions, RenderError};
-use crate::ipc::cmd::OverlayTextureEventParams;
+use crate::common::{AdapterIdentity, Dimensions, RenderError};
+use crate::ipc::cmd::{
+    GraphicsBackends, OverlayCapabilities, OverlayPixelFormat, OverlayPixelFormats,
+    OverlayTextureEventParams,
+};
+use crate::overlay::{
+    Overlay, OverlayBackend, OverlayDamage, OverlayDescriptor, OverlayFormat, OverlayTexels,
+    OverlayTransfer, SyncedFrame, OVERLAY_SYNC_TIMEOUT_MS,
+};
 use crate::win32::handle::{try_close_handle, try_duplicate_handle, HandleError};
 
-pub(in crate::d3d11) struct Direct3D11Overlay {
-    keyed_mutex: Option<IDXGIKeyedMutex>,
+pub(in crate::d3d11) type Direct3D11Overlay = Overlay<Direct3D11OverlayBackend>;
+
+pub(in crate::d3d11) struct Direct3D11OverlayBackend {
+    context: Option<ID3D11DeviceContext>,
+    /// The textures shared by the orchestrator, which is a single texture unless
+    /// it renders into a ring.
+    shared: Vec<SharedTexture>,
+    /// Whether `shared` is a ring, in which only the texture holding the newest frame
+    /// is copied out of.
+    ring: bool,
+    /// The shared texture to copy the next frame out of, or `None` if the newest frame
+    /// of the ring has already been copied.
+    ready: Cell<Option<usize>>,
+    /// The texels of the ring that changed since the frame in `composited` was copied.
+    damage: RefCell<OverlayDamage>,
+    /// The dimensions of the shared textures, and the number of bytes of their texels.
+    extent: (Dimensions, u32),
+    /// A private copy of the last frame a keyed mutex was acquired for, which is what
+    /// is painted, so that it can be painted again while the orchestrator holds the mutex.
+    composited: Option<ID3D11Texture2D>,
     shader_resource_view: Option<ID3D11ShaderResourceView>,
-    texture: Option<ID3D11Texture2D>,
-    handle: HANDLE,
+    /// How the texels of `composited` are read.
+    texels: OverlayTexels,
+    /// Whether a frame has been copied into `composited`.
+    has_frame: Cell<bool>,
+    /// The view of the texture painted before the shared textures were replaced, which is
+    /// painted until a frame has been copied out of the new ones.
+    retained: RefCell<Option<(ID3D11ShaderResourceView, OverlayTexels)>>,
     window: HWND,
-    dimensions: Dimensions,
 }
 
-// SAFETY: An instance of Direct3D11Overlay must only
+// SAFETY: An instance of Direct3D11OverlayBackend must only
 // ever be called  within IDXGISwapChain::Present or IDXGISwapChain::ResizeBuffers
-unsafe impl Send for Direct3D11Overlay {}
-unsafe impl Sync for Direct3D11Overlay {}
+unsafe impl Send for Direct3D11OverlayBackend {}
+unsafe impl Sync for Direct3D11OverlayBackend {}
 
-pub(in crate::d3d11) struct KeyedMutexHandle(IDXGIKeyedMutex, u64);
+/// A texture shared by the orchestrator, and its keyed mutex.
+struct SharedTexture {
+    texture: ID3D11Texture2D,
+    keyed_mutex: IDXGIKeyedMutex,
+}
+
+/// The DXGI format of a shared texture created by the orchestrator in `format`.
+///
+/// The texture is sampled through a view of the same format, which already orders
+/// the channels.
+const fn dxgi_format(format: OverlayFormat) -> DXGI_FORMAT {
+    match format {
+        OverlayFormat::Bgra8 => DXGI_FORMAT_B8G8R8A8_UNORM,
+        OverlayFormat::Rgba8 => DXGI_FORMAT_R8G8B8A8_UNORM,
+        OverlayFormat::Rgba16Float => DXGI_FORMAT_R16G16B16A16_FLOAT,
+        OverlayFormat::Rgb10A2 => DXGI_FORMAT_R10G10B10A2_UNORM,
+    }
+}
+
+struct KeyedMutexHandle(IDXGIKeyedMutex, u64);
 impl Drop for KeyedMutexHandle {
     fn drop(&mut self) {
         unsafe {
@@ -39,78 +94,126 @@ impl Drop for KeyedMutexHandle {
 
 impl KeyedMutexHandle {
     pub fn new(kmt: &IDXGIKeyedMutex, key: u64, ms: u32) -> Option<Self> {
-        let kmt = kmt.clone();
-        unsafe {
-            kmt.AcquireSync(key, ms)
-                .map(|_| Some(KeyedMutexHandle(kmt, 0)))
-                .unwrap_or(None)
-        }
+        // A timeout is reported as the success code WAIT_TIMEOUT, which the
+        // wrapper does not tell apart from acquiring the mutex.
+        let hr = unsafe { (Vtable::vtable(kmt).AcquireSync)(Vtable::as_raw(kmt), key, ms) };
+        (hr == S_OK).then(|| KeyedMutexHandle(kmt.clone(), key))
     }
 }
 
-impl Direct3D11Overlay {
-    #[inline]
-    pub fn ready_to_initialize(&self) -> bool {
-        self.handle != HANDLE(0)
-    }
+/// The frame in the composited texture, which is painted instead of the shared texture.
+pub(in crate::d3d11) enum CompositedFrame {
+    /// The latest frame was copied out of the shared texture just now.
+    Copied(OverlayTransfer),
+    /// The latest frame was copied out of the shared texture.
+    Latest,
+    /// The orchestrator held the shared texture, so the last frame copied is painted again.
+    Stale,
+}
 
-    #[inline]
-    const fn ready_to_paint(&self) -> bool {
-        self.shader_resource_view.is_some() && self.keyed_mutex.is_some() && self.texture.is_some()
+impl SyncedFrame for CompositedFrame {
+    fn is_stale(&self) -> bool {
+        matches!(self, CompositedFrame::Stale)
     }
 
-    pub fn new() -> Direct3D11Overlay {
-        Direct3D11Overlay {
-            keyed_mutex: None,
-            shader_resource_view: None,
-            texture: None,
-            handle: HANDLE::default(),
-            window: HWND::default(),
-            dimensions: Dimensions::new(0, 0),
+    fn transfer(&self) -> Option<OverlayTransfer> {
+        match self {
+            CompositedFrame::Copied(transfer) => Some(*transfer),
+            _ => None,
         }
     }
+}
 
-    #[inline]
-    pub fn size_matches_viewpoint(&self, size: &Dimensions) -> bool {
-        self.dimensions == *size
-    }
-
-    pub fn acquire_sync(&self) -> Option<KeyedMutexHandle> {
-        if let Some(kmt) = &self.keyed_mutex {
-            KeyedMutexHandle::new(kmt, 0, u32::MAX)
-        } else {
-            None
+impl Direct3D11OverlayBackend {
+    pub fn new() -> Direct3D11OverlayBackend {
+        Direct3D11OverlayBackend {
+            context: None,
+            shared: Vec::new(),
+            ring: false,
+            ready: Cell::new(None),
+            damage: RefCell::new(OverlayDamage::Full),
+            extent: (Dimensions::new(0, 0), 0),
+            composited: None,
+            shader_resource_view: None,
+            texels: OverlayTexels::RGBA,
+            has_frame: Cell::new(false),
+            retained: RefCell::new(None),
+            window: HWND::default(),
         }
     }
 
-    fn invalidate(&mut self) {
-        self.shader_resource_view = None;
-        self.texture = None;
-        self.keyed_mutex = None;
-    }
-
-    #[must_use]
-    pub fn prepare_paint(
+    /// Open the textures shared by the orchestrator, and create the texture their frames
+    /// are copied into.
+    fn import_shared(
         &mut self,
-        device: ID3D11Device1,
+        descriptors: &[OverlayDescriptor<HANDLE>],
+        device: &ID3D11Device1,
         output_window: HWND,
-    ) -> Result<(), RenderError> {
-        if self.ready_to_paint() && self.window == output_window {
-            return Ok(());
-        }
+    ) -> Result<Dimensions, RenderError> {
+        let mut shared = Vec::with_capacity(descriptors.len());
+        let mut tex_desc = D3D11_TEXTURE2D_DESC::default();
+        let mut texels = OverlayTexels::RGBA;
+        let mut texel_size = 0;
+        for descriptor in descriptors {
+            let (format, encoding) = descriptor.announced()?;
+            let tex_2d: ID3D11Texture2D = unsafe { device.OpenSharedResource1(descriptor.handle) }
+                .map_err(|e| RenderError::OverlayHandleError(descriptor.handle, e))?;
 
-        self.invalidate();
+            let tex_mtx: IDXGIKeyedMutex = Interface::cast(&tex_2d)?;
 
-        let tex_2d: ID3D11Texture2D = unsafe { device.OpenSharedResource1(self.handle) }
-            .map_err(|e| RenderError::OverlayHandleError(self.handle, e))?;
+            let mut desc = Default::default();
+            unsafe {
+                tex_2d.GetDesc(&mut desc);
+            }
 
-        let tex_mtx: IDXGIKeyedMutex = Interface::cast(&tex_2d)?;
+            if desc.Format != dxgi_format(format) {
+                return Err(RenderError::OverlayFormatMismatch(
+                    format,
+                    desc.Format.0 as u32,
+                ));
+            }
 
-        let mut tex_desc = Default::default();
-        unsafe {
-            tex_2d.GetDesc(&mut tex_desc);
+            // Every texture of a ring is copied into the same texture, so they must match.
+            if !shared.is_empty()
+                && (desc.Width, desc.Height, desc.Format, desc.MipLevels)
+                    != (
+                        tex_desc.Width,
+                        tex_desc.Height,
+                        tex_desc.Format,
+                        tex_desc.MipLevels,
+                    )
+            {
+                return Err(RenderError::OverlayFormatUnsupported(desc.Format.0 as u32));
+            }
+
+            tex_desc = desc;
+            texel_size = format.texel_size();
+            texels = OverlayTexels {
+                encoding,
+                ..OverlayTexels::RGBA
+            };
+            shared.push(SharedTexture {
+                texture: tex_2d,
+                keyed_mutex: tex_mtx,
+            });
         }
 
+        // The composited texture has to match the shared texture for it to be copied,
+        // but is not shared.
+        let composited_desc = D3D11_TEXTURE2D_DESC {
+            Width: tex_desc.Width,
+            Height: tex_desc.Height,
+            MipLevels: tex_desc.MipLevels,
+            ArraySize: tex_desc.ArraySize,
+            Format: tex_desc.Format,
+            SampleDesc: tex_desc.SampleDesc,
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: D3D11_BIND_SHADER_RESOURCE,
+            ..Default::default()
+        };
+
+        let composited = unsafe { device.CreateTexture2D(&composited_desc, None)? };
+
         let srv_desc = D3D11_SHADER_RESOURCE_VIEW_DESC {
             Format: tex_desc.Format,
             ViewDimension: D3D11_SRV_DIMENSION_TEXTURE2D,
@@ -122,43 +225,238 @@ impl Direct3D11Overlay {
             },
         };
 
-        let srv = unsafe { device.CreateShaderResourceView(&tex_2d, Some(&srv_desc))? };
+        let srv = unsafe { device.CreateShaderResourceView(&composited, Some(&srv_desc))? };
 
-        self.keyed_mutex = Some(tex_mtx);
-        self.texture = Some(tex_2d);
-        self.dimensions = Dimensions::new(tex_desc.Width, tex_desc.Height);
+        let context = {
+            let mut context = None;
+            unsafe { device.GetImmediateContext(&mut context) };
+            context
+        };
 
+        if self.has_frame.get() {
+            *self.retained.get_mut() = self
+                .shader_resource_view
+                .take()
+                .map(|srv| (srv, self.texels));
+        }
+
+        self.context = context;
+        self.shared = shared;
+        self.ready.set(None);
+        // Nothing has been copied into the new composited texture yet.
+        *self.damage.get_mut() = OverlayDamage::Full;
+        self.extent = (Dimensions::new(tex_desc.Width, tex_desc.Height), texel_size);
+        self.composited = Some(composited);
         self.shader_resource_view = Some(srv);
+        self.texels = texels;
+        self.has_frame.set(false);
         self.window = output_window;
 
-        eprintln!("[dx11] success on overlay");
-        Ok(())
+        Ok(Dimensions::new(tex_desc.Width, tex_desc.Height))
+    }
+}
+
+impl Default for Direct3D11OverlayBackend {
+    fn default() -> Self {
+        Direct3D11OverlayBackend::new()
     }
+}
 
-    #[must_use]
-    pub fn refresh(&mut self, params: OverlayTextureEventParams) -> Result<(), HandleError> {
-        let owning_pid = params.source_pid;
-        let handle = HANDLE(params.handle as isize);
+impl OverlayBackend for Direct3D11OverlayBackend {
+    const NAME: &'static str = "dx11";
+    const BACKEND: GraphicsBackends = GraphicsBackends::D3D11;
 
-        self.handle = {
-            let duped_handle = try_duplicate_handle(owning_pid as u32, handle)?;
+    type Handle = HANDLE;
+    type Target<'a> = (ID3D11Device1, HWND);
+    type SyncGuard<'a> = CompositedFrame;
 
-            // this doesn't do anything if its already null.
-            self.invalidate();
+    fn duplicate_handle(
+        &self,
+        params: &OverlayTextureEventParams,
+        _format: OverlayPixelFormat,
+    ) -> Result<HANDLE, HandleError> {
+        let duped_handle =
+            try_duplicate_handle(params.source_pid as u32, HANDLE(params.handle as isize))?;
+        eprintln!("[dx11] duped handle {:x?}", duped_handle);
+        Ok(duped_handle)
+    }
 
-            if self.ready_to_initialize() {
-                try_close_handle(self.handle)?;
-            }
+    fn close_handle(&self, handle: HANDLE) -> Result<(), HandleError> {
+        try_close_handle(handle)
+    }
+
+    fn capabilities(&self, _target: &(ID3D11Device1, HWND)) -> OverlayCapabilities {
+        OverlayCapabilities::WIN32_HANDLE
+            | OverlayCapabilities::KEYED_MUTEX
+            | OverlayCapabilities::TEXTURE_RING
+    }
+
+    fn formats(&self, _target: &(ID3D11Device1, HWND)) -> OverlayPixelFormats {
+        OverlayPixelFormats::ALL
+    }
+
+    fn adapter(&self, (device, _): &(ID3D11Device1, HWND)) -> Option<AdapterIdentity> {
+        let dxgi_device: IDXGIDevice = Interface::cast(device).ok()?;
+        let desc = unsafe {
+            dxgi_device
+                .GetAdapter()
+                .and_then(|adapter| adapter.GetDesc())
+        }
+        .ok()?;
+
+        // The bytes of the LUID as laid out in memory, which is how Vulkan reports it.
+        let mut luid = [0u8; 8];
+        luid[..4].copy_from_slice(&desc.AdapterLuid.LowPart.to_le_bytes());
+        luid[4..].copy_from_slice(&desc.AdapterLuid.HighPart.to_le_bytes());
+
+        Some(AdapterIdentity {
+            luid: Some(luid),
+            uuid: None,
+            vendor_id: desc.VendorId,
+            device_id: desc.DeviceId,
+        })
+    }
+
+    #[inline]
+    fn ready_to_paint(&self, (_, output_window): &(ID3D11Device1, HWND)) -> bool {
+        self.shader_resource_view.is_some()
+            && !self.shared.is_empty()
+            && self.composited.is_some()
+            && self.window == *output_window
+    }
+
+    fn import(
+        &mut self,
+        descriptor: &OverlayDescriptor<HANDLE>,
+        (device, output_window): (ID3D11Device1, HWND),
+    ) -> Result<Dimensions, RenderError> {
+        let dimensions =
+            self.import_shared(std::slice::from_ref(descriptor), &device, output_window)?;
+        self.ring = false;
+        self.ready.set(Some(0));
+        Ok(dimensions)
+    }
 
-            eprintln!("[dx11] duped handle {:x?}", duped_handle);
-            duped_handle
+    fn import_ring(
+        &mut self,
+        ring: &[OverlayDescriptor<HANDLE>],
+        (device, output_window): (ID3D11Device1, HWND),
+    ) -> Result<Dimensions, RenderError> {
+        let dimensions = self.import_shared(ring, &device, output_window)?;
+        self.ring = true;
+        Ok(dimensions)
+    }
+
+    fn select(&mut self, index: usize, damage: &OverlayDamage) {
+        if self.ring && index < self.shared.len() {
+            self.ready.set(Some(index));
+            self.damage.get_mut().add(damage);
+        }
+    }
+
+    fn invalidate(&mut self) {
+        self.shader_resource_view = None;
+        self.composited = None;
+        self.shared.clear();
+        self.ready.set(None);
+        *self.damage.get_mut() = OverlayDamage::Full;
+        self.context = None;
+        self.has_frame.set(false);
+        *self.retained.get_mut() = None;
+    }
+
+    fn acquire_sync(&self) -> Option<CompositedFrame> {
+        let (Some(context), Some(composited)) = (&self.context, &self.composited) else {
+            return None;
         };
-        Ok(())
+
+        let Some(shared) = self.ready.get().and_then(|index| self.shared.get(index)) else {
+            // The newest frame of the ring has already been copied.
+            return if self.has_frame.get() {
+                Some(CompositedFrame::Latest)
+            } else {
+                self.retained
+                    .borrow()
+                    .as_ref()
+                    .map(|_| CompositedFrame::Stale)
+            };
+        };
+
+        match KeyedMutexHandle::new(&shared.keyed_mutex, 0, OVERLAY_SYNC_TIMEOUT_MS) {
+            Some(_kmt) => {
+                // Every frame is copied whole out of a single shared texture, since it is
+                // not told what changed.
+                let damage = if self.ring {
+                    self.damage.replace(OverlayDamage::NONE)
+                } else {
+                    OverlayDamage::Full
+                };
+                let (dimensions, texel_size) = self.extent;
+                let rects = damage.rects(dimensions);
+
+                // The copy is queued before the mutex is released, which orders it
+                // before the orchestrator's next write.
+                match damage {
+                    OverlayDamage::Full => unsafe {
+                        context.CopyResource(composited, &shared.texture)
+                    },
+                    OverlayDamage::Rects(_) => {
+                        for rect in &rects {
+                            let source = D3D11_BOX {
+                                left: rect.x,
+                                top: rect.y,
+                                front: 0,
+                                right: rect.x + rect.width,
+                                bottom: rect.y + rect.height,
+                                back: 1,
+                            };
+                            unsafe {
+                                context.CopySubresourceRegion(
+                                    composited,
+                                    0,
+                                    rect.x,
+                                    rect.y,
+                                    0,
+                                    &shared.texture,
+                                    0,
+                                    Some(&source),
+                                )
+                            };
+                        }
+                    }
+                }
+                self.has_frame.set(true);
+                self.retained.replace(None);
+                if self.ring {
+                    self.ready.set(None);
+                }
+                Some(CompositedFrame::Copied(OverlayTransfer::new(
+                    &rects, dimensions, texel_size,
+                )))
+            }
+            None if self.has_frame.get() || self.retained.borrow().is_some() => {
+                Some(CompositedFrame::Stale)
+            }
+            None => None,
+        }
     }
 
-    pub fn paint<F: Sized + FnOnce(TextureId, Dimensions)>(&self, f: F) {
-        if let Some(srv_handle) = &self.shader_resource_view {
-            f(srv_handle.as_tex_id(), self.dimensions);
+    fn texture_id(&self) -> Option<TextureId> {
+        if !self.has_frame.get() {
+            if let Some((srv, _)) = self.retained.borrow().as_ref() {
+                return Some(srv.as_tex_id());
+            }
+        }
+        self.shader_resource_view
+            .as_ref()
+            .map(|srv| srv.as_tex_id())
+    }
+    fn texels(&self) -> OverlayTexels {
+        if !self.has_frame.get() {
+            if let Some((_, texels)) = self.retained.borrow().as_ref() {
+                return *texels;
+            }
         }
+        self.texels
     }
 }
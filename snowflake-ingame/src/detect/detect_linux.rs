@@ -0,0 +1,93 @@
+use std::error::Error;
+#[cfg(all(feature = "cdylib", not(test)))]
+use std::ffi::{c_char, c_int, c_void, CStr};
+#[cfg(all(feature = "cdylib", not(test)))]
+use std::sync::LazyLock;
+use std::sync::OnceLock;
+
+use crate::ipc::cmd::GraphicsBackends;
+
+static ON_LOAD: OnceLock<fn()> = OnceLock::new();
+
+const OPENGL_MODULES: &[&str] = &["libGL.so", "libGLX.so", "libEGL.so", "libOpenGL.so"];
+const VULKAN_MODULES: &[&str] = &["libvulkan.so"];
+
+/// Whether the file at `path` is one of `modules`, which may be followed by a version.
+fn is_module(path: &[u8], modules: &[&str]) -> bool {
+    let file = path.rsplit(|&c| c == b'/').next().unwrap_or(path);
+    modules
+        .iter()
+        .any(|module| file.starts_with(module.as_bytes()))
+}
+
+fn any_mapped(maps: &str, modules: &[&str]) -> bool {
+    maps.lines().any(|line| is_module(line.as_bytes(), modules))
+}
+
+/// The graphics APIs whose modules are currently mapped into the process.
+pub(super) fn loaded_backends() -> GraphicsBackends {
+    let maps = match std::fs::read_to_string("/proc/self/maps") {
+        Ok(maps) => maps,
+        Err(e) => {
+            eprintln!("[detect] unable to read /proc/self/maps ({})", e);
+            return GraphicsBackends::NONE;
+        }
+    };
+
+    let mut backends = GraphicsBackends::NONE;
+    if any_mapped(&maps, OPENGL_MODULES) {
+        backends |= GraphicsBackends::OPENGL;
+    }
+    if any_mapped(&maps, VULKAN_MODULES) {
+        backends |= GraphicsBackends::VULKAN;
+    }
+    backends
+}
+
+#[cfg(all(feature = "cdylib", not(test)))]
+type FnDlopen = unsafe extern "C" fn(*const c_char, c_int) -> *mut c_void;
+
+/// The `dlopen` this library interposes, resolved once. Nothing can be loaded without it, so the
+/// process is aborted if it is missing.
+#[cfg(all(feature = "cdylib", not(test)))]
+static REAL_DLOPEN: LazyLock<FnDlopen> = LazyLock::new(|| unsafe {
+    let dlopen = libc::dlsym(libc::RTLD_NEXT, b"dlopen\0".as_ptr() as *const c_char);
+    if dlopen.is_null() {
+        eprintln!("[detect] unable to resolve the next dlopen, aborting");
+        std::process::abort();
+    }
+    std::mem::transmute::<*mut c_void, FnDlopen>(dlopen)
+});
+
+/// Interposes `dlopen` for the rest of the process, to be notified of graphics modules being
+/// loaded by name.
+///
+/// This only takes effect when the library is preloaded, so that this definition is found
+/// before the one in libc. It is only exported from the cdylib build, so that programs linking
+/// the rlib keep their own `dlopen`.
+#[cfg(all(feature = "cdylib", not(test)))]
+#[no_mangle]
+pub unsafe extern "C" fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void {
+    let real_dlopen = *REAL_DLOPEN;
+
+    let handle = real_dlopen(filename, flags);
+    // Most modules loaded are not graphics modules, so only their names are checked here
+    // rather than everything mapped into the process.
+    if !handle.is_null() && !filename.is_null() {
+        let path = CStr::from_ptr(filename).to_bytes();
+        if is_module(path, OPENGL_MODULES) || is_module(path, VULKAN_MODULES) {
+            if let Some(on_load) = ON_LOAD.get() {
+                on_load();
+            }
+        }
+    }
+    handle
+}
+
+/// Call `on_load` after every successful `dlopen` of a graphics module.
+pub(super) fn install_load_hook(on_load: fn()) -> Result<(), Box<dyn Error>> {
+    ON_LOAD
+        .set(on_load)
+        .map_err(|_| "load hook already installed")?;
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use detour::static_detour;
+use std::error::Error;
+use std::sync::OnceLock;
+use windows::core::{PCSTR, PCWSTR};
+use windows::Win32::Foundation::{HANDLE, HINSTANCE};
+use windows::Win32::System::LibraryLoader::{GetModuleHandleA, GetProcAddress, LOAD_LIBRARY_FLAGS};
+
+use crate::ipc::cmd::GraphicsBackends;
+
+static_detour! {
+    static LOAD_LIBRARY_EX_W_DETOUR: extern "system" fn(
+        windows::core::PCWSTR,
+        windows::Win32::Foundation::HANDLE,
+        windows::Win32::System::LibraryLoader::LOAD_LIBRARY_FLAGS
+    ) -> windows::Win32::Foundation::HINSTANCE;
+}
+
+static ON_LOAD: OnceLock<fn()> = OnceLock::new();
+
+const D3D11_MODULES: &[&[u8]] = &[b"d3d11.dll\0"];
+const OPENGL_MODULES: &[&[u8]] = &[b"opengl32.dll\0"];
+const VULKAN_MODULES: &[&[u8]] = &[b"vulkan-1.dll\0"];
+
+fn any_loaded(modules: &[&[u8]]) -> bool {
+    modules
+        .iter()
+        .any(|module| unsafe { GetModuleHandleA(PCSTR(module.as_ptr())) }.is_ok())
+}
+
+/// The graphics APIs whose modules are currently loaded.
+pub(super) fn loaded_backends() -> GraphicsBackends {
+    let mut backends = GraphicsBackends::NONE;
+    if any_loaded(D3D11_MODULES) {
+        backends |= GraphicsBackends::D3D11;
+    }
+    if any_loaded(OPENGL_MODULES) {
+        backends |= GraphicsBackends::OPENGL;
+    }
+    if any_loaded(VULKAN_MODULES) {
+        backends |= GraphicsBackends::VULKAN;
+    }
+    backends
+}
+
+fn load_library_ex_w(name: PCWSTR, file: HANDLE, flags: LOAD_LIBRARY_FLAGS) -> HINSTANCE {
+    let module = LOAD_LIBRARY_EX_W_DETOUR.call(name, file, flags);
+    if !module.is_invalid() {
+        if let Some(on_load) = ON_LOAD.get() {
+            on_load();
+        }
+    }
+    module
+}
+
+/// Detour `LoadLibraryExW` to call `on_load` after every module load.
+///
+/// `LoadLibraryA`, `LoadLibraryW` and `LoadLibraryExA` are all implemented by
+/// `LoadLibraryExW` in kernelbase, so this catches every documented way to load a module.
+pub(super) fn install_load_hook(on_load: fn()) -> Result<(), Box<dyn Error>> {
+    ON_LOAD
+        .set(on_load)
+        .map_err(|_| "load hook already installed")?;
+
+    let kernelbase = unsafe { GetModuleHandleA(PCSTR(b"kernelbase.dll\0".as_ptr())) }
+        .or_else(|_| unsafe { GetModuleHandleA(PCSTR(b"kernel32.dll\0".as_ptr())) })?;
+
+    let target = unsafe { GetProcAddress(kernelbase, PCSTR(b"LoadLibraryExW\0".as_ptr())) }
+        .ok_or("LoadLibraryExW not found")?;
+
+    unsafe {
+        LOAD_LIBRARY_EX_W_DETOUR
+            .initialize(std::mem::transmute(target), load_library_ex_w)?
+            .enable()?;
+    }
+
+    Ok(())
+}
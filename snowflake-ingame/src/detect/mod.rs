@@ -0,0 +1,104 @@
+#[cfg(target_os = "linux")]
+mod detect_linux;
+#[cfg(windows)]
+mod detect_win32;
+
+#[cfg(target_os = "linux")]
+use detect_linux as sys;
+#[cfg(windows)]
+use detect_win32 as sys;
+
+use crossbeam_channel::{Receiver, Sender};
+use parking_lot::Mutex;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::OnceLock;
+
+use crate::ipc::cmd::GraphicsBackends;
+
+/// A graphics API that a kernel can be activated for.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum GraphicsApi {
+    Direct3D11,
+    OpenGL,
+    Vulkan,
+}
+
+impl GraphicsApi {
+    pub const ALL: [GraphicsApi; 3] = [
+        GraphicsApi::Direct3D11,
+        GraphicsApi::OpenGL,
+        GraphicsApi::Vulkan,
+    ];
+
+    pub const fn backend(self) -> GraphicsBackends {
+        match self {
+            GraphicsApi::Direct3D11 => GraphicsBackends::D3D11,
+            GraphicsApi::OpenGL => GraphicsBackends::OPENGL,
+            GraphicsApi::Vulkan => GraphicsBackends::VULKAN,
+        }
+    }
+}
+
+impl Display for GraphicsApi {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            GraphicsApi::Direct3D11 => "Direct3D 11",
+            GraphicsApi::OpenGL => "OpenGL",
+            GraphicsApi::Vulkan => "Vulkan",
+        })
+    }
+}
+
+/// Watches for graphics API modules being loaded into the process.
+///
+/// Every API is reported at most once, the first time one of its modules is seen.
+struct ModuleDetector {
+    seen: Mutex<GraphicsBackends>,
+    sender: Sender<GraphicsApi>,
+}
+
+static DETECTOR: OnceLock<ModuleDetector> = OnceLock::new();
+
+impl ModuleDetector {
+    fn rescan(&self) {
+        let loaded = sys::loaded_backends();
+        let mut seen = self.seen.lock();
+        for api in GraphicsApi::ALL {
+            if loaded.contains(api.backend()) && !seen.contains(api.backend()) {
+                eprintln!("[detect] {} loaded", api);
+                *seen |= api.backend();
+                self.sender.send(api).unwrap_or(());
+            }
+        }
+    }
+}
+
+/// Called by the platform load hooks whenever a module may have been loaded.
+fn on_module_loaded() {
+    if let Some(detector) = DETECTOR.get() {
+        detector.rescan();
+    }
+}
+
+/// Start watching for graphics APIs.
+///
+/// APIs whose modules are already loaded are sent immediately. Any API that is loaded
+/// afterwards is sent once its module appears. This may only be called once.
+#[must_use]
+pub fn watch() -> Result<Receiver<GraphicsApi>, Box<dyn Error>> {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let detector = ModuleDetector {
+        seen: Mutex::new(GraphicsBackends::NONE),
+        sender,
+    };
+
+    if DETECTOR.set(detector).is_err() {
+        return Err("module detection is already running".into());
+    }
+
+    // Install the hook before scanning, so that nothing loaded in between is missed.
+    sys::install_load_hook(on_module_loaded)?;
+    on_module_loaded();
+    Ok(receiver)
+}
@@ -0,0 +1,164 @@
+use std::cell::Cell;
+use std::error::Error;
+use std::ffi::{c_char, c_int, c_uint, c_ulong, c_void, CStr};
+
+use crate::glx::sys::{GlDrawable, EGL, GLX, SDL};
+use crate::hook::HookHandle;
+
+use crate::hook_define;
+use crate::hook_impl_fn;
+use crate::hook_key;
+use crate::hook_link_chain;
+
+pub(in crate::glx) struct GLXHookContext;
+
+struct GLXHookHandle {
+    swap_buffers_handle: usize,
+}
+
+pub type FnSwapBuffersHook = Box<dyn (Fn(GlDrawable, SwapBuffersContext) -> c_int) + Send + Sync>;
+hook_define!(chain SWAP_BUFFERS_CHAIN with FnSwapBuffersHook => SwapBuffersContext);
+
+/// Terminates the swap chain by calling the real definition of the interposed function.
+struct NextSwapBuffers;
+
+static SWAP_BUFFERS_NEXT: NextSwapBuffers = NextSwapBuffers;
+
+impl NextSwapBuffers {
+    fn call(&self, drawable: GlDrawable) -> c_int {
+        unsafe {
+            match drawable {
+                GlDrawable::Glx { display, drawable } => {
+                    if let Some(swap_buffers) = GLX.swap_buffers {
+                        swap_buffers(display, drawable);
+                    }
+                    0
+                }
+                GlDrawable::Egl { display, surface } => EGL
+                    .swap_buffers
+                    .map_or(0, |swap_buffers| swap_buffers(display, surface) as c_int),
+                GlDrawable::Sdl { window } => SDL
+                    .gl_swap_window
+                    .map_or(0, |gl_swap_window| gl_swap_window(window)),
+            }
+        }
+    }
+}
+
+thread_local! {
+    /// Whether this thread is already inside an interposed swap.
+    static SWAPPING: Cell<bool> = Cell::new(false);
+}
+
+impl GLXHookContext {
+    hook_impl_fn!(fn swap_buffers(drawable: GlDrawable) -> c_int =>
+        (SWAP_BUFFERS_CHAIN, SWAP_BUFFERS_NEXT, SwapBuffersContext)
+    );
+
+    /// Present `drawable` through the hook chain.
+    ///
+    /// SDL may swap through GLX or EGL, so a swap made while another is in progress on
+    /// the same thread skips the chain, and the overlay is only drawn once.
+    fn interpose(drawable: GlDrawable) -> c_int {
+        if SWAPPING.with(|swapping| swapping.replace(true)) {
+            return SWAP_BUFFERS_NEXT.call(drawable);
+        }
+        let result = GLXHookContext::swap_buffers(drawable);
+        SWAPPING.with(|swapping| swapping.set(false));
+        result
+    }
+
+    pub fn init() -> Result<GLXHookContext, Box<dyn Error>> {
+        // The swap functions are interposed as soon as the library is preloaded,
+        // so only the call chain termination has to be set up.
+        hook_link_chain! {
+            box link SWAP_BUFFERS_CHAIN with SWAP_BUFFERS_NEXT => drawable;
+        }
+
+        Ok(GLXHookContext)
+    }
+
+    pub fn new(&self, swap_buffers: FnSwapBuffersHook) -> Result<impl HookHandle, Box<dyn Error>> {
+        let key = hook_key!(box swap_buffers);
+        SWAP_BUFFERS_CHAIN.write()?.insert(key, swap_buffers);
+
+        Ok(GLXHookHandle {
+            swap_buffers_handle: key,
+        })
+    }
+}
+
+impl HookHandle for GLXHookHandle {}
+
+impl Drop for GLXHookHandle {
+    fn drop(&mut self) {
+        SWAP_BUFFERS_CHAIN
+            .write()
+            .unwrap()
+            .remove(&self.swap_buffers_handle);
+    }
+}
+
+/// The interposed function to return for `name` from `*GetProcAddress`, if there is one.
+///
+/// Games that resolve their swap function at runtime would otherwise bypass the interposed one.
+unsafe fn interposed_proc_address(name: *const c_char) -> Option<*const c_void> {
+    if name.is_null() {
+        return None;
+    }
+    let fp: *const c_void = match CStr::from_ptr(name).to_bytes() {
+        b"glXSwapBuffers" => glXSwapBuffers as *const c_void,
+        b"eglSwapBuffers" => eglSwapBuffers as *const c_void,
+        _ => return None,
+    };
+    Some(fp)
+}
+
+// These definitions only take effect when the library is preloaded, so that they are
+// found before the ones in the GL libraries.
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub unsafe extern "C" fn glXSwapBuffers(display: *mut c_void, drawable: c_ulong) {
+    GLXHookContext::interpose(GlDrawable::Glx { display, drawable });
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub unsafe extern "C" fn eglSwapBuffers(display: *mut c_void, surface: *mut c_void) -> c_uint {
+    GLXHookContext::interpose(GlDrawable::Egl { display, surface }) as c_uint
+}
+
+/// SDL 2 returns nothing and SDL 3 returns a bool, so the result is passed through as is.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub unsafe extern "C" fn SDL_GL_SwapWindow(window: *mut c_void) -> c_int {
+    GLXHookContext::interpose(GlDrawable::Sdl { window })
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub unsafe extern "C" fn glXGetProcAddress(name: *const c_char) -> *const c_void {
+    interposed_proc_address(name).unwrap_or_else(|| {
+        GLX.get_proc_address
+            .map_or(std::ptr::null(), |get_proc_address| get_proc_address(name))
+    })
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub unsafe extern "C" fn glXGetProcAddressARB(name: *const c_char) -> *const c_void {
+    interposed_proc_address(name).unwrap_or_else(|| {
+        GLX.get_proc_address_arb
+            .map_or(std::ptr::null(), |get_proc_address| get_proc_address(name))
+    })
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub unsafe extern "C" fn eglGetProcAddress(name: *const c_char) -> *const c_void {
+    interposed_proc_address(name).unwrap_or_else(|| {
+        EGL.get_proc_address
+            .map_or(std::ptr::null(), |get_proc_address| get_proc_address(name))
+    })
+}
@@ -0,0 +1,127 @@
+use crate::common::{Dimensions, RenderError};
+use crate::ogl::color_encoding;
+use crate::overlay::OverlayPaint;
+use imgui::{Context, DrawData};
+use imgui_renderer_ogl::{Composite, OpenGLImguiRenderer, RenderToken};
+use opengl_bindings::Gl;
+use parking_lot::RwLock;
+use std::sync::Arc;
+
+pub(in crate::glx) struct GLXImguiController {
+    imgui: Arc<RwLock<Context>>,
+    renderer: Option<OpenGLImguiRenderer>,
+    window: isize,
+    ctx: isize,
+}
+
+pub(in crate::glx) struct Render<'a> {
+    render: Option<&'a mut OpenGLImguiRenderer>,
+}
+
+impl Render<'_> {
+    pub fn render(self, draw_data: &DrawData) -> Result<RenderToken, RenderError> {
+        if let Some(renderer) = self.render {
+            Ok(renderer.render(draw_data))
+        } else {
+            Err(RenderError::RendererNotReady)
+        }
+    }
+
+    /// Composite the overlay texture onto the framebuffer, before the ImGui draw data.
+    pub fn composite(&mut self, paint: &OverlayPaint) -> Result<(), RenderError> {
+        let composite = Composite {
+            position: paint.placement.position.map(|p| p as f32),
+            size: paint.placement.size.into(),
+            framebuffer: paint.viewport.into(),
+            opacity: paint.placement.opacity,
+            bgra: paint.texels.bgra,
+            opaque: paint.texels.opaque,
+            premultiplied: paint.texels.premultiplied,
+            decode: color_encoding(paint.texels.encoding),
+            encode: color_encoding(paint.output.written()),
+        };
+
+        if let Some(renderer) = &mut self.render {
+            renderer.composite(paint.texture, &composite)?;
+            Ok(())
+        } else {
+            Err(RenderError::RendererNotReady)
+        }
+    }
+}
+
+impl GLXImguiController {
+    pub fn new(imgui: Arc<RwLock<Context>>) -> GLXImguiController {
+        GLXImguiController {
+            imgui,
+            renderer: None,
+            window: 0,
+            ctx: 0,
+        }
+    }
+
+    pub const fn renderer_ready(&self) -> bool {
+        self.renderer.is_some()
+    }
+
+    fn init_renderer(&mut self, gl: &Gl, window: isize) -> Result<(), RenderError> {
+        // Renderer owns its device.
+        self.renderer = Some(OpenGLImguiRenderer::new(&gl, &mut self.imgui.write())?);
+        self.window = window;
+        Ok(())
+    }
+
+    pub fn invalidate_renderer(&mut self) {
+        self.renderer = None;
+    }
+
+    /// Forget the renderer without deleting its GL objects, for when its context is not current.
+    pub fn abandon_renderer(&mut self) {
+        if let Some(renderer) = self.renderer.take() {
+            std::mem::forget(renderer);
+        }
+    }
+
+    pub fn frame<'a, F: FnOnce(&mut Context, Render) -> Result<RenderToken, RenderError>>(
+        &mut self,
+        f: F,
+    ) -> Result<RenderToken, RenderError> {
+        let mut imgui = self.imgui.write();
+        if let Some(renderer) = &self.renderer {
+            renderer.bind_fonts(&mut imgui);
+        }
+
+        let renderer = Render {
+            render: self.renderer.as_mut(),
+        };
+
+        f(&mut imgui, renderer)
+    }
+
+    #[must_use]
+    pub fn prepare_paint(
+        &mut self,
+        gl: &Gl,
+        window: isize,
+        ctx: isize,
+        screen_dim: Dimensions,
+    ) -> Result<(), RenderError> {
+        if window != self.window || ctx != self.ctx {
+            eprintln!("[glx] render context changed");
+            self.invalidate_renderer();
+        }
+
+        if !self.renderer_ready() {
+            self.init_renderer(gl, window)?;
+        }
+
+        // set screen size..
+        self.imgui.write().io_mut().display_size = screen_dim.into();
+        self.window = window;
+        self.ctx = ctx;
+        Ok(())
+    }
+}
+
+unsafe impl Send for GLXImguiController {}
+unsafe impl Sync for GLXImguiController {}
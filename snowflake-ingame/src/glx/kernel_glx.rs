@@ -0,0 +1,195 @@
+use crate::common::{Dimensions, RenderError};
+use crate::glx::hook::{FnSwapBuffersHook, GLXHookContext};
+use crate::glx::imgui::GLXImguiController;
+use crate::glx::overlay::{GLXOverlay, GLXOverlayBackend};
+use crate::glx::sys::GlDrawable;
+use crate::hook::{HookChain, HookHandle};
+use crate::ipc::cmd::GameWindowCommandType;
+use crate::overlay::{
+    claim_window, take_commands, OverlayBackend, OverlayStatus, OverlayTargetPolicy, Ownership,
+    SurfaceMap,
+};
+use imgui_renderer_ogl::RenderToken;
+use opengl_bindings::Gl;
+use parking_lot::{RwLock, RwLockWriteGuard};
+use std::error::Error;
+use std::ffi::CString;
+use std::mem::ManuallyDrop;
+use std::sync::Arc;
+
+use crate::kernel::common::{FrameKernel, KernelContext};
+use crate::ogl::{framebuffer_output, OwnedGl};
+
+/// Overlay and renderer state for a single drawable and GL context.
+struct GLXSurface {
+    gl: OwnedGl,
+    context: isize,
+    overlay: GLXOverlay,
+    imgui: GLXImguiController,
+}
+
+impl GLXSurface {
+    /// Forget any GL objects owned by this surface without deleting them.
+    ///
+    /// GL objects can only be deleted while their context is current, so
+    /// this must be used when a surface is evicted from a different context.
+    fn abandon(&mut self) {
+        for backend in self.overlay.backends_mut() {
+            backend.abandon();
+        }
+        self.imgui.abandon_renderer();
+    }
+}
+
+/// The kernel for OpenGL on Linux, presenting through GLX, EGL or SDL.
+pub struct GLXKernel {
+    hook: GLXHookContext,
+    context: KernelContext,
+    surfaces: Arc<RwLock<SurfaceMap<(isize, isize), GLXSurface>>>,
+    status: OverlayStatus,
+}
+
+impl FrameKernel for GLXKernel {
+    type Handle = impl HookHandle;
+
+    fn new(context: KernelContext) -> Result<Self, Box<dyn Error>> {
+        Ok(GLXKernel {
+            hook: GLXHookContext::init()?,
+            context,
+            surfaces: Arc::new(RwLock::new(SurfaceMap::new(OverlayTargetPolicy::Largest))),
+            status: OverlayStatus::new("glx"),
+        })
+    }
+
+    fn init(&mut self) -> Result<ManuallyDrop<Self::Handle>, Box<dyn Error>> {
+        println!("[glx] init");
+        let handle = self.hook.new(self.make_swap_buffers())?.persist();
+
+        Ok(handle)
+    }
+
+    fn status(&self) -> OverlayStatus {
+        self.status.clone()
+    }
+}
+
+impl GLXKernel {
+    fn swapbuffers_impl(
+        context: &KernelContext,
+        status: &OverlayStatus,
+        drawable: GlDrawable,
+        mut surfaces: RwLockWriteGuard<SurfaceMap<(isize, isize), GLXSurface>>,
+    ) -> Result<Option<RenderToken>, RenderError> {
+        let window = drawable.window();
+        let gl_context = unsafe { drawable.current_context() };
+        if gl_context == 0 {
+            return Ok(None);
+        }
+
+        let size = match unsafe { drawable.size() } {
+            Some((width, height)) => Dimensions { width, height },
+            None => return Ok(None),
+        };
+
+        let key = (window, gl_context);
+        surfaces.present(key, window, size, || GLXSurface {
+            gl: OwnedGl(Gl::load_with(|s| {
+                // The source of this string is a &str, so it is always valid UTF-8.
+                let proc_name = unsafe { CString::new(s).unwrap_unchecked() };
+                unsafe { drawable.get_proc_address(&proc_name) }
+            })),
+            context: gl_context,
+            overlay: GLXOverlay::with_status(GLXOverlayBackend::new(), status.clone()),
+            imgui: GLXImguiController::new(context.imgui.clone()),
+        });
+
+        // There is no portable way to tell whether a drawable still exists,
+        // so only stale surfaces are evicted.
+        for mut surface in surfaces.evict(|_| true) {
+            if surface.context != gl_context {
+                surface.abandon();
+            }
+        }
+
+        // Focus is not tracked for X11 or Wayland windows.
+        surfaces.select_target(0);
+
+        if !surfaces.is_target(&key) {
+            return Ok(None);
+        }
+
+        let ownership = claim_window(window, GLXOverlayBackend::NAME);
+
+        // Only the backend that owns the window the commands are routed to receives them,
+        // so that the overlay texture is handed to the overlay that paints it. Every command
+        // that arrived since the last frame is handled, so that frames the orchestrator
+        // announced are not painted late.
+        let cmds = if ownership.is_owner() {
+            take_commands(window, &context.ipc)
+        } else {
+            Vec::new()
+        };
+
+        for cmd in &cmds {
+            if cmd.ty == GameWindowCommandType::OVERLAY_TARGET {
+                surfaces.set_policy(unsafe { cmd.params.overlay_target_event }.into());
+            }
+        }
+
+        let GLXSurface {
+            gl, overlay, imgui, ..
+        } = match surfaces.get_mut(&key) {
+            Some(surface) => &mut surface.state,
+            None => return Ok(None),
+        };
+
+        match ownership {
+            Ownership::Owned | Ownership::Acquired => {}
+            Ownership::Revoked => {
+                overlay.reset();
+                return Ok(None);
+            }
+            Ownership::Denied => return Ok(None),
+        }
+
+        for cmd in &cmds {
+            overlay.handle_command(cmd);
+        }
+
+        let output = unsafe { framebuffer_output(gl) };
+        overlay.prepare_frame(&context.ipc, size, output, (gl, window, gl_context))?;
+
+        imgui
+            .prepare_paint(gl, window, gl_context, size)
+            .map_err(|e| RenderError::ImGuiNotReady(Box::new(e)))?;
+
+        imgui
+            .frame(|ctx, mut render| {
+                let ui = ctx.frame();
+                // The release semaphores may only be signalled once the textures have been
+                // sampled, so the guards are held until the frame has been rendered.
+                let layers = overlay.acquire_sync();
+                layers.paint(|paint| render.composite(paint))?;
+                let token = render.render(ui.render())?;
+                drop(layers);
+                Ok(token)
+            })
+            .map(Some)
+    }
+
+    fn make_swap_buffers(&self) -> FnSwapBuffersHook {
+        let context = self.context.clone();
+        let surfaces = self.surfaces.clone();
+        let status = self.status.clone();
+
+        Box::new(move |drawable, mut next| {
+            if let Err(e) =
+                GLXKernel::swapbuffers_impl(&context, &status, drawable, surfaces.write())
+            {
+                status.fail(&e);
+            }
+            let fp = next.fp_next();
+            fp(drawable, next)
+        })
+    }
+}
@@ -0,0 +1,11 @@
+mod hook_glx;
+mod imgui_glx;
+mod kernel_glx;
+mod overlay_glx;
+mod sys;
+
+use hook_glx as hook;
+use imgui_glx as imgui;
+use overlay_glx as overlay;
+
+pub use kernel_glx::GLXKernel;
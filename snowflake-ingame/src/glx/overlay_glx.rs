@@ -0,0 +1,536 @@
+use std::cell::{Cell, RefCell};
+use std::os::unix::io::{AsRawFd, IntoRawFd, OwnedFd};
+
+use imgui::TextureId;
+use imgui_renderer_ogl::ImguiTexture;
+use opengl_bindings as gl;
+use opengl_bindings::types::{GLenum, GLint, GLsizei, GLuint};
+use opengl_bindings::Gl;
+
+use crate::common::{AdapterIdentity, Dimensions, RenderError};
+use crate::ipc::cmd::{
+    GraphicsBackends, OverlayCapabilities, OverlayPixelFormat, OverlayPixelFormats,
+    OverlayTextureEventParams, OverlayTextureFdEventParams, DRM_FORMAT_ABGR16161616F,
+    DRM_FORMAT_ABGR2101010, DRM_FORMAT_ABGR8888, DRM_FORMAT_ARGB8888, DRM_FORMAT_XBGR16161616F,
+    DRM_FORMAT_XBGR2101010, DRM_FORMAT_XBGR8888, DRM_FORMAT_XRGB8888,
+};
+use crate::ogl::{adapter_identity, check_error, gl_filter, has_extension, UploadTexture};
+use crate::overlay::{
+    Overlay, OverlayBackend, OverlayDamage, OverlayDescriptor, OverlayEncoding, OverlayFilter,
+    OverlayFormat, OverlayTexels, OverlayTransfer, SharedFrame, SyncedFrame,
+};
+use crate::unix::handle::{HandleError, SharedFds};
+
+pub(in crate::glx) type GLXOverlay = Overlay<GLXOverlayBackend>;
+
+/// The overlay of a GLX, EGL or SDL window.
+///
+/// Shared textures are imported from opaque file descriptors, and synchronized with the
+/// semaphores sent alongside them, in which case each frame announced by the orchestrator
+/// is copied into a private texture. Frames written to shared memory are uploaded instead.
+pub(in crate::glx) struct GLXOverlayBackend {
+    window: isize,
+    context: isize,
+    texture: Option<GlSharedTexture>,
+    upload: Option<UploadTexture>,
+    filter: OverlayFilter,
+}
+
+struct GlSharedTexture {
+    gl: Gl,
+    texture: GLuint,
+    memory: GLuint,
+    acquire: GLuint,
+    release: GLuint,
+    /// A private copy of the last frame announced by the orchestrator, which is what is
+    /// painted if the texture is synchronized, so that the semaphores are only waited on
+    /// once the orchestrator has signalled them.
+    composited: GLuint,
+    dimensions: Dimensions,
+    /// The number of bytes of a texel of the texture.
+    texel_size: u32,
+    /// Whether the orchestrator announced a frame that has not been copied yet.
+    ready: Cell<bool>,
+    /// Whether a frame has been copied into `composited`.
+    has_frame: Cell<bool>,
+    /// The texels announced to have changed since the frame in `composited` was copied.
+    damage: RefCell<OverlayDamage>,
+    texels: OverlayTexels,
+}
+
+impl GlSharedTexture {
+    /// The semaphores are only waited on and signalled in lockstep, so both are needed.
+    #[inline]
+    fn is_synchronized(&self) -> bool {
+        self.acquire != 0 && self.release != 0
+    }
+
+    /// The texture that is painted.
+    #[inline]
+    fn painted(&self) -> GLuint {
+        if self.is_synchronized() {
+            self.composited
+        } else {
+            self.texture
+        }
+    }
+
+    /// Copy the newest frame announced by the orchestrator into the composited texture.
+    ///
+    /// The acquire semaphore is only waited on once the orchestrator announced that it
+    /// signalled it, so the wait is bounded by work it has already submitted.
+    fn composite(&self) -> Option<GLXSyncGuard> {
+        if !self.is_synchronized() {
+            return Some(GLXSyncGuard::Unsynchronized);
+        }
+        if !self.ready.get() {
+            // No frame was announced since the last copy.
+            return self.has_frame.get().then_some(GLXSyncGuard::Latest);
+        }
+
+        let gl = &self.gl;
+        let damage = self.damage.replace(OverlayDamage::NONE);
+        let rects = damage.rects(self.dimensions);
+        unsafe {
+            gl.WaitSemaphoreEXT(
+                self.acquire,
+                0,
+                std::ptr::null(),
+                1,
+                &self.texture,
+                &gl::LAYOUT_GENERAL_EXT,
+            );
+            for rect in &rects {
+                gl.CopyImageSubData(
+                    self.texture,
+                    gl::TEXTURE_2D,
+                    0,
+                    rect.x as GLint,
+                    rect.y as GLint,
+                    0,
+                    self.composited,
+                    gl::TEXTURE_2D,
+                    0,
+                    rect.x as GLint,
+                    rect.y as GLint,
+                    0,
+                    rect.width as GLsizei,
+                    rect.height as GLsizei,
+                    1,
+                );
+            }
+            gl.SignalSemaphoreEXT(
+                self.release,
+                0,
+                std::ptr::null(),
+                1,
+                &self.texture,
+                &gl::LAYOUT_GENERAL_EXT,
+            );
+            // The orchestrator waits on the release semaphore, so it must reach the driver.
+            gl.Flush();
+        }
+        self.ready.set(false);
+        self.has_frame.set(true);
+        Some(GLXSyncGuard::Copied(OverlayTransfer::new(
+            &rects,
+            self.dimensions,
+            self.texel_size,
+        )))
+    }
+}
+
+impl Drop for GlSharedTexture {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteTextures(1, &self.texture);
+            if self.composited != 0 {
+                self.gl.DeleteTextures(1, &self.composited);
+            }
+            self.gl.DeleteMemoryObjectsEXT(1, &self.memory);
+            for semaphore in [self.acquire, self.release] {
+                if semaphore != 0 {
+                    self.gl.DeleteSemaphoresEXT(1, &semaphore);
+                }
+            }
+        }
+    }
+}
+
+/// The frame in the texture the overlay is painted from.
+pub(in crate::glx) enum GLXSyncGuard {
+    /// The latest frame announced was copied out of the shared texture just now.
+    Copied(OverlayTransfer),
+    /// The latest frame announced was copied out of the shared texture.
+    Latest,
+    /// Without semaphores, the shared texture is sampled as it is.
+    Unsynchronized,
+    /// Uploaded textures are not shared, so there is nothing to synchronize.
+    Uploaded,
+}
+
+impl SyncedFrame for GLXSyncGuard {
+    fn transfer(&self) -> Option<OverlayTransfer> {
+        match self {
+            GLXSyncGuard::Copied(transfer) => Some(*transfer),
+            _ => None,
+        }
+    }
+}
+
+impl GLXOverlayBackend {
+    pub fn new() -> GLXOverlayBackend {
+        GLXOverlayBackend {
+            window: 0,
+            context: 0,
+            texture: None,
+            upload: None,
+            filter: OverlayFilter::Linear,
+        }
+    }
+
+    /// Forget the imported texture without deleting it, for when its context is not current.
+    pub fn abandon(&mut self) {
+        if let Some(texture) = self.texture.take() {
+            std::mem::forget(texture);
+        }
+        if let Some(upload) = self.upload.take() {
+            upload.abandon();
+        }
+    }
+}
+
+/// The ways a shared texture can be imported into the context `gl` was loaded from.
+///
+/// GLX and Mesa's EGL return functions for any name, so the extensions are checked as well.
+/// Frames can always be uploaded from shared memory.
+fn overlay_capabilities(gl: &Gl) -> OverlayCapabilities {
+    let mut capabilities = OverlayCapabilities::SHARED_MEMORY;
+    unsafe {
+        if has_extension(gl, "GL_EXT_memory_object_fd")
+            && gl.ImportMemoryFdEXT.is_loaded()
+            && gl.TextureStorageMem2DEXT.is_loaded()
+            && gl.CreateMemoryObjectsEXT.is_loaded()
+            && gl.DeleteMemoryObjectsEXT.is_loaded()
+            && gl.TextureParameteri.is_loaded()
+        {
+            capabilities |= OverlayCapabilities::OPAQUE_FD;
+        }
+        if has_extension(gl, "GL_EXT_semaphore_fd")
+            && gl.ImportSemaphoreFdEXT.is_loaded()
+            && gl.GenSemaphoresEXT.is_loaded()
+            && gl.DeleteSemaphoresEXT.is_loaded()
+            && gl.WaitSemaphoreEXT.is_loaded()
+            && gl.SignalSemaphoreEXT.is_loaded()
+            && gl.TextureStorage2D.is_loaded()
+            && gl.CopyImageSubData.is_loaded()
+        {
+            capabilities |= OverlayCapabilities::SEMAPHORE_FD;
+        }
+    }
+    capabilities
+}
+
+/// The format of a texture of the DRM format `fourcc`, the internal format it is imported as,
+/// and how its texels are read once imported.
+fn overlay_format(fourcc: u32) -> Result<(OverlayFormat, GLenum, OverlayTexels), RenderError> {
+    let opaque = OverlayTexels {
+        opaque: true,
+        ..OverlayTexels::RGBA
+    };
+    match fourcc {
+        DRM_FORMAT_ARGB8888 => Ok((OverlayFormat::Bgra8, gl::RGBA8, OverlayTexels::BGRA)),
+        DRM_FORMAT_XRGB8888 => Ok((
+            OverlayFormat::Bgra8,
+            gl::RGBA8,
+            OverlayTexels {
+                bgra: true,
+                ..opaque
+            },
+        )),
+        DRM_FORMAT_ABGR8888 => Ok((OverlayFormat::Rgba8, gl::RGBA8, OverlayTexels::RGBA)),
+        DRM_FORMAT_XBGR8888 => Ok((OverlayFormat::Rgba8, gl::RGBA8, opaque)),
+        DRM_FORMAT_ABGR2101010 => Ok((OverlayFormat::Rgb10A2, gl::RGB10_A2, OverlayTexels::RGBA)),
+        DRM_FORMAT_XBGR2101010 => Ok((OverlayFormat::Rgb10A2, gl::RGB10_A2, opaque)),
+        DRM_FORMAT_ABGR16161616F => {
+            Ok((OverlayFormat::Rgba16Float, gl::RGBA16F, OverlayTexels::RGBA))
+        }
+        DRM_FORMAT_XBGR16161616F => Ok((OverlayFormat::Rgba16Float, gl::RGBA16F, opaque)),
+        _ => Err(RenderError::OverlayFormatUnsupported(fourcc)),
+    }
+}
+
+/// Set how `texture` is sampled with `filter`, clamping to its edges.
+unsafe fn sample_parameters(gl: &Gl, texture: GLuint, filter: OverlayFilter) {
+    gl.TextureParameteri(texture, gl::TEXTURE_MIN_FILTER, gl_filter(filter));
+    gl.TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl_filter(filter));
+    gl.TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+    gl.TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+}
+
+/// Import a semaphore from a duplicate of `fd`.
+///
+/// The handle is kept by the overlay, so that it can be imported again into another context.
+unsafe fn import_semaphore(gl: &Gl, fd: &OwnedFd) -> Result<GLuint, RenderError> {
+    let fd = fd.try_clone().map_err(RenderError::OverlayFdError)?;
+    let mut semaphore = 0;
+    gl.GenSemaphoresEXT(1, &mut semaphore);
+    gl.ImportSemaphoreFdEXT(semaphore, gl::HANDLE_TYPE_OPAQUE_FD_EXT, fd.as_raw_fd());
+    if let Err(e) = check_error(gl) {
+        gl.DeleteSemaphoresEXT(1, &semaphore);
+        return Err(e);
+    }
+
+    // The semaphore owns the fd once it has been imported.
+    let _ = fd.into_raw_fd();
+    Ok(semaphore)
+}
+
+impl Default for GLXOverlayBackend {
+    fn default() -> Self {
+        GLXOverlayBackend::new()
+    }
+}
+
+impl OverlayBackend for GLXOverlayBackend {
+    const NAME: &'static str = "glx";
+    const BACKEND: GraphicsBackends = GraphicsBackends::OPENGL;
+
+    type Handle = SharedFds;
+    type Target<'a> = (&'a Gl, isize, isize);
+    type SyncGuard<'a> = GLXSyncGuard;
+
+    fn duplicate_handle(
+        &self,
+        params: &OverlayTextureEventParams,
+        format: OverlayPixelFormat,
+    ) -> Result<SharedFds, HandleError> {
+        let fds = SharedFds::duplicate(params, format)?;
+        eprintln!("[glx] duped fd {}", fds.memory.as_raw_fd());
+        Ok(fds)
+    }
+
+    fn adopt_fds(&self, params: &OverlayTextureFdEventParams) -> Result<SharedFds, HandleError> {
+        let fds = SharedFds::adopt(params)?;
+        eprintln!(
+            "[glx] received fd {} (fourcc {:#x}, semaphores: {})",
+            fds.memory.as_raw_fd(),
+            fds.fourcc,
+            fds.acquire.is_some() && fds.release.is_some()
+        );
+        Ok(fds)
+    }
+
+    fn close_handle(&self, handle: SharedFds) -> Result<(), HandleError> {
+        drop(handle);
+        Ok(())
+    }
+
+    fn capabilities(&self, (gl, _, _): &(&Gl, isize, isize)) -> OverlayCapabilities {
+        overlay_capabilities(gl)
+    }
+
+    fn formats(&self, (gl, _, _): &(&Gl, isize, isize)) -> OverlayPixelFormats {
+        // Frames in shared memory are always BGRA8.
+        if overlay_capabilities(gl).contains(OverlayCapabilities::OPAQUE_FD) {
+            OverlayPixelFormats::ALL
+        } else {
+            OverlayPixelFormats::BGRA8
+        }
+    }
+
+    fn adapter(&self, (gl, _, _): &(&Gl, isize, isize)) -> Option<AdapterIdentity> {
+        Some(unsafe { adapter_identity(gl, "glx") })
+    }
+
+    #[inline]
+    fn ready_to_paint(&self, (_, window, context): &(&Gl, isize, isize)) -> bool {
+        (self.texture.is_some() || self.upload.is_some())
+            && self.window == *window
+            && self.context == *context
+    }
+
+    fn import(
+        &mut self,
+        descriptor: &OverlayDescriptor<SharedFds>,
+        (gl, window, context): (&Gl, isize, isize),
+    ) -> Result<Dimensions, RenderError> {
+        let handle = &descriptor.handle;
+        let capabilities = overlay_capabilities(gl);
+
+        // DMA-BUFs are never advertised, they can only be imported through EGL images.
+        if handle.is_dma_buf() || !capabilities.contains(OverlayCapabilities::OPAQUE_FD) {
+            return Err(
+                imgui_renderer_ogl::RenderError::MissingExtensionError(Box::new(
+                    "GL_EXT_memory_object_fd, GL_ARB_direct_state_access",
+                ))
+                .into(),
+            );
+        }
+        let (format, internal_format, texels) = overlay_format(handle.fourcc)?;
+        let texels = OverlayTexels {
+            encoding: OverlayEncoding::announced(descriptor.colorspace, format),
+            ..texels
+        };
+
+        unsafe {
+            // Errors left behind by the game are not ours to report.
+            while gl.GetError() != gl::NO_ERROR {}
+
+            // Objects are owned by the texture as soon as they are created, so that
+            // everything is deleted if the import fails part way through.
+            let mut texture = GlSharedTexture {
+                gl: gl.clone(),
+                texture: 0,
+                memory: 0,
+                acquire: 0,
+                release: 0,
+                composited: 0,
+                dimensions: descriptor.dimensions,
+                texel_size: format.texel_size(),
+                ready: Cell::new(false),
+                has_frame: Cell::new(false),
+                damage: RefCell::new(OverlayDamage::Full),
+                texels,
+            };
+
+            gl.CreateTextures(gl::TEXTURE_2D, 1, &mut texture.texture);
+            gl.TextureParameteri(
+                texture.texture,
+                gl::TEXTURE_TILING_EXT,
+                gl::OPTIMAL_TILING_EXT as GLint,
+            );
+            sample_parameters(gl, texture.texture, self.filter);
+
+            let fd = handle
+                .memory
+                .try_clone()
+                .map_err(RenderError::OverlayFdError)?;
+            gl.CreateMemoryObjectsEXT(1, &mut texture.memory);
+            gl.ImportMemoryFdEXT(
+                texture.memory,
+                descriptor.size,
+                gl::HANDLE_TYPE_OPAQUE_FD_EXT,
+                fd.as_raw_fd(),
+            );
+            check_error(gl)?;
+            // The memory object owns the fd once it has been imported.
+            let _ = fd.into_raw_fd();
+
+            gl.TextureStorageMem2DEXT(
+                texture.texture,
+                1,
+                internal_format,
+                descriptor.dimensions.width as GLsizei,
+                descriptor.dimensions.height as GLsizei,
+                texture.memory,
+                handle.offset as u64,
+            );
+            check_error(gl)?;
+
+            match (&handle.acquire, &handle.release) {
+                (Some(acquire), Some(release))
+                    if capabilities.contains(OverlayCapabilities::SEMAPHORE_FD) =>
+                {
+                    texture.acquire = import_semaphore(gl, acquire)?;
+                    texture.release = import_semaphore(gl, release)?;
+
+                    gl.CreateTextures(gl::TEXTURE_2D, 1, &mut texture.composited);
+                    gl.TextureStorage2D(
+                        texture.composited,
+                        1,
+                        internal_format,
+                        descriptor.dimensions.width as GLsizei,
+                        descriptor.dimensions.height as GLsizei,
+                    );
+                    sample_parameters(gl, texture.composited, self.filter);
+                    check_error(gl)?;
+                }
+                (Some(_), Some(_)) => {
+                    eprintln!("[glx] semaphores can not be imported, painting unsynchronized")
+                }
+                _ => {}
+            }
+
+            self.texture = Some(texture);
+        }
+
+        self.window = window;
+        self.context = context;
+        Ok(descriptor.dimensions)
+    }
+
+    fn upload(
+        &mut self,
+        frame: &SharedFrame<'_>,
+        (gl, window, context): (&Gl, isize, isize),
+    ) -> Result<Dimensions, RenderError> {
+        let upload = self
+            .upload
+            .get_or_insert_with(|| unsafe { UploadTexture::new(gl, self.filter) });
+        let dimensions = unsafe { upload.upload(frame)? };
+
+        self.window = window;
+        self.context = context;
+        Ok(dimensions)
+    }
+
+    fn set_filter(&mut self, filter: OverlayFilter) {
+        self.filter = filter;
+        // Commands are handled while the context the texture was imported into is current.
+        if let Some(texture) = &self.texture {
+            let gl = &texture.gl;
+            unsafe {
+                let painted = texture.painted();
+                gl.TextureParameteri(painted, gl::TEXTURE_MIN_FILTER, gl_filter(filter));
+                gl.TextureParameteri(painted, gl::TEXTURE_MAG_FILTER, gl_filter(filter));
+            }
+        }
+        if let Some(upload) = &mut self.upload {
+            upload.set_filter(filter);
+        }
+    }
+
+    /// Mark the shared texture as holding a frame the orchestrator signalled the acquire
+    /// semaphore for, so that it is copied from the next time the texture is acquired.
+    fn select(&mut self, index: usize, damage: &OverlayDamage) {
+        if let Some(texture) = &mut self.texture {
+            if index == 0 && texture.is_synchronized() {
+                texture.ready.set(true);
+                texture.damage.get_mut().add(damage);
+            }
+        }
+    }
+
+    fn invalidate(&mut self) {
+        self.texture = None;
+        self.upload = None;
+    }
+
+    fn acquire_sync(&self) -> Option<GLXSyncGuard> {
+        match (&self.texture, &self.upload) {
+            (Some(texture), _) => texture.composite(),
+            (None, Some(_)) => Some(GLXSyncGuard::Uploaded),
+            (None, None) => None,
+        }
+    }
+
+    fn texture_id(&self) -> Option<TextureId> {
+        match (&self.texture, &self.upload) {
+            (Some(texture), _) => Some(texture.painted().as_tex_id()),
+            (None, Some(upload)) => Some(upload.texture().as_tex_id()),
+            (None, None) => None,
+        }
+    }
+
+    fn texels(&self) -> OverlayTexels {
+        // Uploads are converted from BGRA as they are uploaded.
+        match &self.texture {
+            Some(texture) => texture.texels,
+            None => OverlayTexels::RGBA,
+        }
+    }
+}
+
+unsafe impl Send for GLXOverlayBackend {}
+unsafe impl Sync for GLXOverlayBackend {}
@@ -0,0 +1,203 @@
+use std::ffi::{c_char, c_int, c_uint, c_ulong, c_void, CStr};
+use std::sync::LazyLock;
+
+/// The libraries the real definitions of interposed functions may be found in, when they
+/// were opened with `RTLD_LOCAL` and so can not be found through `RTLD_NEXT`.
+const GLX_LIBRARIES: &[&[u8]] = &[b"libGLX.so.0\0", b"libGL.so.1\0"];
+const EGL_LIBRARIES: &[&[u8]] = &[b"libEGL.so.1\0"];
+const SDL_LIBRARIES: &[&[u8]] = &[b"libSDL2-2.0.so.0\0", b"libSDL3.so.0\0"];
+
+const GLX_WIDTH: c_int = 0x801D;
+const GLX_HEIGHT: c_int = 0x801E;
+const EGL_HEIGHT: c_int = 0x3056;
+const EGL_WIDTH: c_int = 0x3057;
+
+pub(in crate::glx) type FnGlxSwapBuffers = unsafe extern "C" fn(*mut c_void, c_ulong);
+pub(in crate::glx) type FnEglSwapBuffers = unsafe extern "C" fn(*mut c_void, *mut c_void) -> c_uint;
+/// SDL 2 returns nothing and SDL 3 returns a bool, either of which fits in the return register.
+pub(in crate::glx) type FnSdlGlSwapWindow = unsafe extern "C" fn(*mut c_void) -> c_int;
+pub(in crate::glx) type FnGetProcAddress = unsafe extern "C" fn(*const c_char) -> *const c_void;
+
+type FnGetCurrentContext = unsafe extern "C" fn() -> *mut c_void;
+type FnGlxQueryDrawable = unsafe extern "C" fn(*mut c_void, c_ulong, c_int, *mut c_uint);
+type FnEglQuerySurface =
+    unsafe extern "C" fn(*mut c_void, *mut c_void, c_int, *mut c_int) -> c_uint;
+type FnSdlGetDrawableSize = unsafe extern "C" fn(*mut c_void, *mut c_int, *mut c_int);
+type FnSdlGetWindowSizeInPixels =
+    unsafe extern "C" fn(*mut c_void, *mut c_int, *mut c_int) -> c_int;
+
+/// Find the definition of `name` that follows this library, skipping the interposed one.
+unsafe fn next_symbol(name: &[u8], libraries: &[&[u8]]) -> *mut c_void {
+    let symbol = libc::dlsym(libc::RTLD_NEXT, name.as_ptr().cast());
+    if !symbol.is_null() {
+        return symbol;
+    }
+
+    for library in libraries {
+        let handle = libc::dlopen(library.as_ptr().cast(), libc::RTLD_LAZY | libc::RTLD_NOLOAD);
+        if handle.is_null() {
+            continue;
+        }
+        let symbol = libc::dlsym(handle, name.as_ptr().cast());
+        libc::dlclose(handle);
+        if !symbol.is_null() {
+            return symbol;
+        }
+    }
+    std::ptr::null_mut()
+}
+
+macro_rules! next_fn {
+    ($name:literal in $libraries:ident as $ty:ty) => {
+        unsafe {
+            let symbol = next_symbol(concat!($name, "\0").as_bytes(), $libraries);
+            (!symbol.is_null()).then(|| std::mem::transmute::<*mut c_void, $ty>(symbol))
+        }
+    };
+}
+
+/// The GLX functions hidden by, or used by, the interposed ones.
+pub(in crate::glx) struct Glx {
+    pub swap_buffers: Option<FnGlxSwapBuffers>,
+    pub get_proc_address: Option<FnGetProcAddress>,
+    pub get_proc_address_arb: Option<FnGetProcAddress>,
+    get_current_context: Option<FnGetCurrentContext>,
+    query_drawable: Option<FnGlxQueryDrawable>,
+}
+
+/// The EGL functions hidden by, or used by, the interposed ones.
+pub(in crate::glx) struct Egl {
+    pub swap_buffers: Option<FnEglSwapBuffers>,
+    pub get_proc_address: Option<FnGetProcAddress>,
+    get_current_context: Option<FnGetCurrentContext>,
+    query_surface: Option<FnEglQuerySurface>,
+}
+
+/// The SDL functions hidden by, or used by, the interposed ones.
+pub(in crate::glx) struct Sdl {
+    pub gl_swap_window: Option<FnSdlGlSwapWindow>,
+    gl_get_proc_address: Option<FnGetProcAddress>,
+    gl_get_current_context: Option<FnGetCurrentContext>,
+    // SDL 3 replaced SDL_GL_GetDrawableSize with SDL_GetWindowSizeInPixels.
+    gl_get_drawable_size: Option<FnSdlGetDrawableSize>,
+    get_window_size_in_pixels: Option<FnSdlGetWindowSizeInPixels>,
+}
+
+// Each API is resolved the first time one of its functions is called, by which time
+// its library has been loaded.
+pub(in crate::glx) static GLX: LazyLock<Glx> = LazyLock::new(|| Glx {
+    swap_buffers: next_fn!("glXSwapBuffers" in GLX_LIBRARIES as FnGlxSwapBuffers),
+    get_proc_address: next_fn!("glXGetProcAddress" in GLX_LIBRARIES as FnGetProcAddress),
+    get_proc_address_arb: next_fn!("glXGetProcAddressARB" in GLX_LIBRARIES as FnGetProcAddress),
+    get_current_context: next_fn!("glXGetCurrentContext" in GLX_LIBRARIES as FnGetCurrentContext),
+    query_drawable: next_fn!("glXQueryDrawable" in GLX_LIBRARIES as FnGlxQueryDrawable),
+});
+
+pub(in crate::glx) static EGL: LazyLock<Egl> = LazyLock::new(|| Egl {
+    swap_buffers: next_fn!("eglSwapBuffers" in EGL_LIBRARIES as FnEglSwapBuffers),
+    get_proc_address: next_fn!("eglGetProcAddress" in EGL_LIBRARIES as FnGetProcAddress),
+    get_current_context: next_fn!("eglGetCurrentContext" in EGL_LIBRARIES as FnGetCurrentContext),
+    query_surface: next_fn!("eglQuerySurface" in EGL_LIBRARIES as FnEglQuerySurface),
+});
+
+pub(in crate::glx) static SDL: LazyLock<Sdl> = LazyLock::new(|| Sdl {
+    gl_swap_window: next_fn!("SDL_GL_SwapWindow" in SDL_LIBRARIES as FnSdlGlSwapWindow),
+    gl_get_proc_address: next_fn!("SDL_GL_GetProcAddress" in SDL_LIBRARIES as FnGetProcAddress),
+    gl_get_current_context: next_fn!(
+        "SDL_GL_GetCurrentContext" in SDL_LIBRARIES as FnGetCurrentContext
+    ),
+    gl_get_drawable_size: next_fn!(
+        "SDL_GL_GetDrawableSize" in SDL_LIBRARIES as FnSdlGetDrawableSize
+    ),
+    get_window_size_in_pixels: next_fn!(
+        "SDL_GetWindowSizeInPixels" in SDL_LIBRARIES as FnSdlGetWindowSizeInPixels
+    ),
+});
+
+/// A drawable presented through one of the interposed swap functions.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GlDrawable {
+    Glx {
+        display: *mut c_void,
+        drawable: c_ulong,
+    },
+    Egl {
+        display: *mut c_void,
+        surface: *mut c_void,
+    },
+    Sdl {
+        window: *mut c_void,
+    },
+}
+
+impl GlDrawable {
+    /// An identifier for the window the drawable presents to.
+    pub fn window(&self) -> isize {
+        match *self {
+            GlDrawable::Glx { drawable, .. } => drawable as isize,
+            GlDrawable::Egl { surface, .. } => surface.addr() as isize,
+            GlDrawable::Sdl { window } => window.addr() as isize,
+        }
+    }
+
+    /// The context current on this thread, in the API of the drawable.
+    pub unsafe fn current_context(&self) -> isize {
+        let get_current_context = match self {
+            GlDrawable::Glx { .. } => GLX.get_current_context,
+            GlDrawable::Egl { .. } => EGL.get_current_context,
+            GlDrawable::Sdl { .. } => SDL.gl_get_current_context,
+        };
+        get_current_context.map_or(0, |f| f().addr() as isize)
+    }
+
+    /// The size of the drawable in pixels, or `None` if it can not be queried.
+    pub unsafe fn size(&self) -> Option<(u32, u32)> {
+        match *self {
+            GlDrawable::Glx { display, drawable } => {
+                let query_drawable = GLX.query_drawable?;
+                let (mut width, mut height) = (0, 0);
+                query_drawable(display, drawable, GLX_WIDTH, &mut width);
+                query_drawable(display, drawable, GLX_HEIGHT, &mut height);
+                Some((width, height))
+            }
+            GlDrawable::Egl { display, surface } => {
+                let query_surface = EGL.query_surface?;
+                let (mut width, mut height) = (0, 0);
+                if query_surface(display, surface, EGL_WIDTH, &mut width) == 0
+                    || query_surface(display, surface, EGL_HEIGHT, &mut height) == 0
+                {
+                    return None;
+                }
+                Some((width as u32, height as u32))
+            }
+            GlDrawable::Sdl { window } => {
+                let (mut width, mut height) = (0, 0);
+                if let Some(get_drawable_size) = SDL.gl_get_drawable_size {
+                    get_drawable_size(window, &mut width, &mut height);
+                } else {
+                    let get_window_size_in_pixels = SDL.get_window_size_in_pixels?;
+                    get_window_size_in_pixels(window, &mut width, &mut height);
+                }
+                Some((width as u32, height as u32))
+            }
+        }
+    }
+
+    /// Resolve a GL function through the loader of the drawable's API.
+    ///
+    /// Core functions are not always returned by `glXGetProcAddress` or `eglGetProcAddress`,
+    /// so those are looked up in the already loaded libraries as a fallback.
+    pub unsafe fn get_proc_address(&self, name: &CStr) -> *const c_void {
+        let get_proc_address = match self {
+            GlDrawable::Glx { .. } => GLX.get_proc_address_arb.or(GLX.get_proc_address),
+            GlDrawable::Egl { .. } => EGL.get_proc_address,
+            GlDrawable::Sdl { .. } => SDL.gl_get_proc_address,
+        };
+
+        let fp = get_proc_address.map_or(std::ptr::null(), |f| f(name.as_ptr()));
+        if !fp.is_null() {
+            return fp;
+        }
+        libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr())
+    }
+}
@@ -53,6 +53,7 @@ macro_rules! hook_define {
 
     (pub chain $chain_name:ident with $fn_hook:ty => $context_name:ident) => {
         // Make context
+        #[derive(Clone)]
         pub struct $context_name<'a> {
             chain: std::iter::Rev<indexmap::map::Iter<'a, std::primitive::usize, $fn_hook>>,
         }
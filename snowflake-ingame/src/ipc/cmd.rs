@@ -1,4 +1,5 @@
-use crate::common::Dimensions;
+use crate::common::{AdapterIdentity, Dimensions};
+use crate::overlay::OverlayDiagnostics;
 use std::fmt::{Debug, Formatter};
 use std::io::ErrorKind;
 use uuid::Uuid;
@@ -19,6 +20,66 @@ pub struct ModifierKey(u8);
 #[derive(Debug, Clone, Copy)]
 pub struct Cursor(u8);
 
+#[repr(transparent)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct OverlayFailureReason(u8);
+
+#[repr(transparent)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct OverlayTargetMode(u8);
+
+#[repr(transparent)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct GraphicsBackends(u8);
+
+#[repr(transparent)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct AdapterIdentityFlags(u8);
+
+#[repr(transparent)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct OverlayCapabilities(u8);
+
+#[repr(transparent)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct SwapchainPresentMode(u8);
+
+#[repr(transparent)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct SwapchainUsage(u8);
+
+#[repr(transparent)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct OverlayScaleMode(u8);
+
+#[repr(transparent)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct OverlayAnchor(u8);
+
+#[repr(transparent)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct OverlayFilterMode(u8);
+
+#[repr(transparent)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct OverlayPixelFormat(u8);
+
+#[repr(transparent)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct OverlayPixelFormats(u8);
+
+#[repr(transparent)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct OverlayColorspace(u8);
+
+#[repr(transparent)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Default)]
+pub struct OverlayLayerId(u8);
+
+#[repr(transparent)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct OverlayLayerFlags(u8);
+
 impl GameWindowCommandType {
     pub const HANDSHAKE: GameWindowCommandType = Self(1);
     pub const WINDOW_RESIZE: GameWindowCommandType = Self(2);
@@ -28,6 +89,273 @@ impl GameWindowCommandType {
     pub const OVERLAY_TEXTURE: GameWindowCommandType = Self(6);
     pub const OVERLAY_ACTIVE: GameWindowCommandType = Self(7);
     pub const SHUTDOWN: GameWindowCommandType = Self(8);
+    pub const OVERLAY_STATE: GameWindowCommandType = Self(9);
+    pub const OVERLAY_TARGET: GameWindowCommandType = Self(10);
+    pub const BACKENDS: GameWindowCommandType = Self(11);
+    pub const OVERLAY_TEXTURE_FD: GameWindowCommandType = Self(12);
+    pub const SWAPCHAIN_POLICY: GameWindowCommandType = Self(13);
+    pub const ADAPTER: GameWindowCommandType = Self(14);
+    pub const OVERLAY_CAPABILITIES: GameWindowCommandType = Self(15);
+    pub const OVERLAY_SHARED_MEMORY: GameWindowCommandType = Self(16);
+    pub const OVERLAY_TEXTURE_RING: GameWindowCommandType = Self(17);
+    pub const OVERLAY_FRAME_READY: GameWindowCommandType = Self(18);
+    pub const OVERLAY_PRESENTATION: GameWindowCommandType = Self(19);
+    pub const OVERLAY_PLACEMENT: GameWindowCommandType = Self(20);
+    pub const OVERLAY_TEXTURE_FORMAT: GameWindowCommandType = Self(21);
+    pub const OVERLAY_LAYER: GameWindowCommandType = Self(22);
+    pub const OVERLAY_TEXTURE_FD_LAYER: GameWindowCommandType = Self(23);
+    pub const OVERLAY_DIAGNOSTICS: GameWindowCommandType = Self(24);
+}
+
+impl OverlayFailureReason {
+    pub const NONE: OverlayFailureReason = Self(0);
+    pub const HANDLE_DUPLICATE: OverlayFailureReason = Self(1);
+    pub const IMPORT: OverlayFailureReason = Self(2);
+    pub const MISSING_EXTENSION: OverlayFailureReason = Self(3);
+    pub const SYNC: OverlayFailureReason = Self(4);
+    pub const DEVICE: OverlayFailureReason = Self(5);
+    pub const RENDERER: OverlayFailureReason = Self(6);
+    pub const IPC: OverlayFailureReason = Self(7);
+}
+
+impl OverlayTargetMode {
+    pub const LARGEST: OverlayTargetMode = Self(0);
+    pub const FOCUSED: OverlayTargetMode = Self(1);
+    pub const WINDOW: OverlayTargetMode = Self(2);
+}
+
+impl GraphicsBackends {
+    pub const NONE: GraphicsBackends = Self(0);
+    pub const D3D11: GraphicsBackends = Self(1 << 0);
+    pub const OPENGL: GraphicsBackends = Self(1 << 1);
+    pub const VULKAN: GraphicsBackends = Self(1 << 2);
+
+    #[inline]
+    pub const fn contains(self, other: GraphicsBackends) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    #[inline]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::ops::BitOr for GraphicsBackends {
+    type Output = GraphicsBackends;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for GraphicsBackends {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl AdapterIdentityFlags {
+    pub const NONE: AdapterIdentityFlags = Self(0);
+    pub const LUID: AdapterIdentityFlags = Self(1 << 0);
+    pub const UUID: AdapterIdentityFlags = Self(1 << 1);
+}
+
+impl std::ops::BitOrAssign for AdapterIdentityFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl OverlayCapabilities {
+    pub const NONE: OverlayCapabilities = Self(0);
+    /// A Direct3D 11 texture shared with `OVERLAY_TEXTURE`.
+    pub const WIN32_HANDLE: OverlayCapabilities = Self(1 << 0);
+    /// The keyed mutex of a shared Direct3D 11 texture.
+    pub const KEYED_MUTEX: OverlayCapabilities = Self(1 << 1);
+    /// Memory exported as an opaque fd.
+    pub const OPAQUE_FD: OverlayCapabilities = Self(1 << 2);
+    /// Memory exported as a dma-buf with an explicit DRM format modifier.
+    pub const DMA_BUF: OverlayCapabilities = Self(1 << 3);
+    /// Acquire and release semaphores exported as opaque fds.
+    pub const SEMAPHORE_FD: OverlayCapabilities = Self(1 << 4);
+    /// Frames written to a ring in shared memory with `OVERLAY_SHARED_MEMORY`, and uploaded
+    /// into a texture every frame.
+    pub const SHARED_MEMORY: OverlayCapabilities = Self(1 << 5);
+    /// Several Direct3D 11 textures shared with `OVERLAY_TEXTURE_RING`, of which the newest
+    /// announced with `OVERLAY_FRAME_READY` is painted.
+    pub const TEXTURE_RING: OverlayCapabilities = Self(1 << 6);
+
+    #[inline]
+    pub const fn contains(self, other: OverlayCapabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for OverlayCapabilities {
+    type Output = OverlayCapabilities;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for OverlayCapabilities {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl SwapchainPresentMode {
+    /// Keep the present mode requested by the application.
+    pub const APPLICATION: SwapchainPresentMode = Self(0);
+    pub const FIFO: SwapchainPresentMode = Self(1);
+    pub const MAILBOX: SwapchainPresentMode = Self(2);
+    pub const IMMEDIATE: SwapchainPresentMode = Self(3);
+}
+
+impl SwapchainUsage {
+    pub const NONE: SwapchainUsage = Self(0);
+    pub const COLOR_ATTACHMENT: SwapchainUsage = Self(1 << 0);
+    pub const TRANSFER_SRC: SwapchainUsage = Self(1 << 1);
+    pub const TRANSFER_DST: SwapchainUsage = Self(1 << 2);
+
+    #[inline]
+    pub const fn contains(self, other: SwapchainUsage) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for SwapchainUsage {
+    type Output = SwapchainUsage;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl OverlayScaleMode {
+    /// Stretch the texture over the whole viewport.
+    pub const STRETCH: OverlayScaleMode = Self(0);
+    /// Scale the texture to fit the viewport, keeping its aspect ratio.
+    pub const ASPECT_FIT: OverlayScaleMode = Self(1);
+    /// Scale the texture by the largest whole factor that fits the viewport, or 1 if none does.
+    pub const INTEGER: OverlayScaleMode = Self(2);
+    /// Paint the texture at its own size.
+    pub const CENTERED: OverlayScaleMode = Self(3);
+}
+
+impl OverlayAnchor {
+    pub const TOP_LEFT: OverlayAnchor = Self(0);
+    pub const TOP: OverlayAnchor = Self(1);
+    pub const TOP_RIGHT: OverlayAnchor = Self(2);
+    pub const LEFT: OverlayAnchor = Self(3);
+    pub const CENTER: OverlayAnchor = Self(4);
+    pub const RIGHT: OverlayAnchor = Self(5);
+    pub const BOTTOM_LEFT: OverlayAnchor = Self(6);
+    pub const BOTTOM: OverlayAnchor = Self(7);
+    pub const BOTTOM_RIGHT: OverlayAnchor = Self(8);
+}
+
+impl OverlayFilterMode {
+    pub const LINEAR: OverlayFilterMode = Self(0);
+    pub const NEAREST: OverlayFilterMode = Self(1);
+}
+
+impl OverlayPixelFormat {
+    /// Not announced, which is taken to be `BGRA8` for a texture.
+    pub const UNSPECIFIED: OverlayPixelFormat = Self(0);
+    pub const BGRA8: OverlayPixelFormat = Self(1);
+    pub const RGBA8: OverlayPixelFormat = Self(2);
+    pub const RGBA16F: OverlayPixelFormat = Self(3);
+    pub const RGB10A2: OverlayPixelFormat = Self(4);
+
+    #[inline]
+    pub const fn code(self) -> u8 {
+        self.0
+    }
+
+    /// The DRM fourcc code with the same memory layout, if the format is known.
+    pub const fn fourcc(self) -> Option<u32> {
+        match self {
+            Self::UNSPECIFIED | Self::BGRA8 => Some(DRM_FORMAT_ARGB8888),
+            Self::RGBA8 => Some(DRM_FORMAT_ABGR8888),
+            Self::RGBA16F => Some(DRM_FORMAT_ABGR16161616F),
+            Self::RGB10A2 => Some(DRM_FORMAT_ABGR2101010),
+            _ => None,
+        }
+    }
+}
+
+impl OverlayPixelFormats {
+    pub const NONE: OverlayPixelFormats = Self(0);
+    pub const BGRA8: OverlayPixelFormats = Self(1 << 0);
+    pub const RGBA8: OverlayPixelFormats = Self(1 << 1);
+    pub const RGBA16F: OverlayPixelFormats = Self(1 << 2);
+    pub const RGB10A2: OverlayPixelFormats = Self(1 << 3);
+    pub const ALL: OverlayPixelFormats = Self(0b1111);
+
+    #[inline]
+    pub const fn contains(self, other: OverlayPixelFormats) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for OverlayPixelFormats {
+    type Output = OverlayPixelFormats;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for OverlayPixelFormats {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl OverlayColorspace {
+    /// Not announced, which is taken to be `LINEAR` for `RGBA16F`, and `SRGB` otherwise.
+    pub const UNSPECIFIED: OverlayColorspace = Self(0);
+    /// sRGB primaries and transfer function.
+    pub const SRGB: OverlayColorspace = Self(1);
+    /// sRGB primaries with linear values, where 1.0 is SDR white, as in scRGB.
+    pub const LINEAR: OverlayColorspace = Self(2);
+    /// BT.2020 primaries with the ST 2084 (PQ) transfer function.
+    pub const HDR10: OverlayColorspace = Self(3);
+}
+
+impl OverlayLayerId {
+    /// The layer textures are announced in by an orchestrator that does not know of layers,
+    /// which is sized to the viewport.
+    pub const MAIN: OverlayLayerId = Self(0);
+
+    #[cfg(test)]
+    pub const fn new(id: u8) -> OverlayLayerId {
+        Self(id)
+    }
+}
+
+impl OverlayLayerFlags {
+    pub const NONE: OverlayLayerFlags = Self(0);
+    /// The layer is composited.
+    pub const VISIBLE: OverlayLayerFlags = Self(1 << 0);
+    /// The layer is removed along with its textures. The main layer is only hidden.
+    pub const REMOVE: OverlayLayerFlags = Self(1 << 1);
+
+    #[inline]
+    pub const fn contains(self, other: OverlayLayerFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for OverlayLayerFlags {
+    type Output = OverlayLayerFlags;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -65,6 +393,100 @@ pub struct OverlayTextureEventParams {
     pub sync_handle: usize,
 }
 
+// Every other command fits within the size of the packet the orchestrator sends.
+#[cfg(target_pointer_width = "64")]
+static_assertions::const_assert_eq!(std::mem::size_of::<OverlayTextureEventParams>(), 44);
+
+/// The format and layer of the texture of the next `OVERLAY_TEXTURE`, which is sent right
+/// before it.
+///
+/// `OVERLAY_TEXTURE` has no room for these, so a texture announced without this command is
+/// of an unspecified format and colorspace, and replaces the texture of the main layer.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OverlayTextureFormatEventParams {
+    /// The pixel format of the texture, which is one of the formats the backend reported.
+    pub format: OverlayPixelFormat,
+    pub colorspace: OverlayColorspace,
+    /// The layer the texture replaces the texture of.
+    pub layer: OverlayLayerId,
+}
+
+static_assertions::const_assert!(
+    std::mem::size_of::<OverlayTextureFormatEventParams>()
+        <= std::mem::size_of::<OverlayTextureEventParams>()
+);
+
+/// The DRM format modifier that marks a texture as having a driver-private layout.
+pub const DRM_FORMAT_MOD_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+
+const fn fourcc(code: &[u8; 4]) -> u32 {
+    u32::from_le_bytes(*code)
+}
+
+/// DRM fourcc codes of the texture formats the orchestrator may export.
+pub const DRM_FORMAT_ARGB8888: u32 = fourcc(b"AR24");
+pub const DRM_FORMAT_XRGB8888: u32 = fourcc(b"XR24");
+pub const DRM_FORMAT_ABGR8888: u32 = fourcc(b"AB24");
+pub const DRM_FORMAT_XBGR8888: u32 = fourcc(b"XB24");
+pub const DRM_FORMAT_ABGR2101010: u32 = fourcc(b"AB30");
+pub const DRM_FORMAT_XBGR2101010: u32 = fourcc(b"XB30");
+pub const DRM_FORMAT_ABGR16161616F: u32 = fourcc(b"AB4H");
+pub const DRM_FORMAT_XBGR16161616F: u32 = fourcc(b"XB4H");
+
+/// An overlay texture exported as file descriptors, sent over a Unix socket with `SCM_RIGHTS`.
+///
+/// The file descriptors are sent alongside the command in the order memory, acquire semaphore,
+/// release semaphore. The values in the command itself are replaced by the descriptors received
+/// in this process, or -1 if fewer were sent.
+///
+/// If `modifier` is [`DRM_FORMAT_MOD_INVALID`], the memory is an opaque fd of `size` bytes.
+/// Otherwise it is a dma-buf with a single plane at `offset`, laid out with `modifier`.
+///
+/// The command has no room for a layer, so the texture replaces that of the main layer, unless
+/// it follows an `OVERLAY_TEXTURE_FD_LAYER` naming another layer.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct OverlayTextureFdEventParams {
+    pub memory_fd: i32,
+    pub acquire_fd: i32,
+    pub release_fd: i32,
+    pub width: u32,
+    pub height: u32,
+    /// The DRM fourcc code of the texture format.
+    pub fourcc: u32,
+    pub modifier: u64,
+    pub size: u32,
+    pub offset: u32,
+    pub row_pitch: u32,
+}
+
+// Adding a command must not change the size of the packet the orchestrator sends.
+static_assertions::const_assert!(
+    std::mem::size_of::<OverlayTextureFdEventParams>()
+        <= std::mem::size_of::<OverlayTextureEventParams>()
+);
+
+impl OverlayTextureFdEventParams {
+    #[inline]
+    pub const fn is_dma_buf(&self) -> bool {
+        self.modifier != DRM_FORMAT_MOD_INVALID
+    }
+}
+
+/// The layer the texture of the next `OVERLAY_TEXTURE_FD` replaces, which is sent right
+/// before it.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct OverlayTextureFdLayerEventParams {
+    pub layer: OverlayLayerId,
+}
+
+static_assertions::const_assert!(
+    std::mem::size_of::<OverlayTextureFdLayerEventParams>()
+        <= std::mem::size_of::<OverlayTextureEventParams>()
+);
+
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
 pub struct WindowMessageEventParams {
@@ -87,6 +509,308 @@ pub struct OverlayActiveEventParams {
     pub active: u8,
 }
 
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct OverlayStateEventParams {
+    pub state: u8,
+    pub reason: OverlayFailureReason,
+}
+
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct OverlayTargetEventParams {
+    pub mode: OverlayTargetMode,
+    pub window: u64,
+}
+
+static_assertions::const_assert!(
+    std::mem::size_of::<OverlayTargetEventParams>()
+        <= std::mem::size_of::<OverlayTextureEventParams>()
+);
+
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct BackendsEventParams {
+    pub backends: GraphicsBackends,
+}
+
+static_assertions::const_assert!(
+    std::mem::size_of::<BackendsEventParams>() <= std::mem::size_of::<OverlayTextureEventParams>()
+);
+
+/// The adapter a backend renders the game with, sent before the overlay texture is requested.
+///
+/// `flags` marks which of `luid` and `uuid` are valid.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct AdapterEventParams {
+    pub backend: GraphicsBackends,
+    pub flags: AdapterIdentityFlags,
+    pub luid: [u8; 8],
+    pub uuid: [u8; 16],
+    pub vendor_id: u32,
+    pub device_id: u32,
+}
+
+static_assertions::const_assert!(
+    std::mem::size_of::<AdapterEventParams>() <= std::mem::size_of::<OverlayTextureEventParams>()
+);
+
+/// The ways a backend can import the overlay texture, sent before the texture is requested,
+/// and again whenever the framebuffer it is composited onto changes format.
+///
+/// The orchestrator should export the texture in a way the backend supports, and falls back to
+/// `OVERLAY_TEXTURE` on Windows or an opaque fd without semaphores on Linux if it can not.
+/// The texture should be in one of `formats`, preferably one that can represent
+/// `output_colorspace`, the colorspace of the framebuffer.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct OverlayCapabilitiesEventParams {
+    pub backend: GraphicsBackends,
+    pub capabilities: OverlayCapabilities,
+    pub formats: OverlayPixelFormats,
+    /// The format of the framebuffer, or `UNSPECIFIED` if it is none of the texture formats.
+    pub output_format: OverlayPixelFormat,
+    pub output_colorspace: OverlayColorspace,
+}
+
+static_assertions::const_assert!(
+    std::mem::size_of::<OverlayCapabilitiesEventParams>()
+        <= std::mem::size_of::<OverlayTextureEventParams>()
+);
+
+/// A ring of BGRA frames in shared memory, for backends that can not import a shared texture.
+///
+/// On Windows, `handle` is a file mapping in the process `source_pid`. On Linux, the memfd is
+/// sent with the command, and `fd` is set when it is received. If no fd was sent, `handle` is
+/// duplicated from the process `source_pid` instead.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct OverlaySharedMemoryEventParams {
+    pub handle: usize,
+    pub source_pid: i32,
+    pub fd: i32,
+    pub width: u32,
+    pub height: u32,
+    /// The size of the mapping, including the header of the ring.
+    pub size: u64,
+    pub layer: OverlayLayerId,
+}
+
+static_assertions::const_assert!(
+    std::mem::size_of::<OverlaySharedMemoryEventParams>()
+        <= std::mem::size_of::<OverlayTextureEventParams>()
+);
+
+/// One of `count` shared textures the orchestrator renders overlay frames into in turn.
+///
+/// The textures are announced in order of `index`, and index 0 starts a new ring. Each is
+/// synchronized with its own keyed mutex, so the orchestrator can write one texture while
+/// another is painted.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct OverlayTextureRingEventParams {
+    pub handle: usize,
+    pub source_pid: i32,
+    pub width: u32,
+    pub height: u32,
+    pub size: u64,
+    pub index: u8,
+    pub count: u8,
+    pub format: OverlayPixelFormat,
+    pub colorspace: OverlayColorspace,
+    pub layer: OverlayLayerId,
+}
+
+static_assertions::const_assert!(
+    std::mem::size_of::<OverlayTextureRingEventParams>()
+        <= std::mem::size_of::<OverlayTextureEventParams>()
+);
+
+impl OverlayTextureRingEventParams {
+    /// The parameters of the texture alone, as it would be announced with `OVERLAY_TEXTURE`.
+    pub const fn texture(&self) -> OverlayTextureEventParams {
+        OverlayTextureEventParams {
+            handle: self.handle,
+            source_pid: self.source_pid,
+            width: self.width,
+            height: self.height,
+            size: self.size,
+            alignment: 0,
+            sync_handle: 0,
+        }
+    }
+
+    /// The format and layer of the texture, as they would be announced with
+    /// `OVERLAY_TEXTURE_FORMAT`.
+    pub const fn format(&self) -> OverlayTextureFormatEventParams {
+        OverlayTextureFormatEventParams {
+            format: self.format,
+            colorspace: self.colorspace,
+            layer: self.layer,
+        }
+    }
+}
+
+/// The most damage rectangles announced with a frame, as many as fit in a command.
+///
+/// A slot of a shared frame ring has room for one more in its header.
+pub const OVERLAY_DAMAGE_RECTS: usize = 4;
+
+/// A rectangle of a frame that changed since the frame before it, in texels from the top
+/// left corner of the texture.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OverlayDamageRect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// Frame `frame` has been written into texture `index` of the ring, and its keyed mutex released.
+///
+/// Frames are numbered from 1, and a frame older than the newest one announced is ignored.
+/// The first `damage_count` rectangles of `damage` cover every texel that changed since the
+/// frame before it. A count of 0, or of more than [`OVERLAY_DAMAGE_RECTS`], marks the whole
+/// frame as changed.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct OverlayFrameReadyEventParams {
+    pub index: u8,
+    pub frame: u64,
+    pub layer: OverlayLayerId,
+    pub damage_count: u8,
+    pub damage: [OverlayDamageRect; OVERLAY_DAMAGE_RECTS],
+}
+
+static_assertions::const_assert!(
+    std::mem::size_of::<OverlayFrameReadyEventParams>()
+        <= std::mem::size_of::<OverlayTextureEventParams>()
+);
+
+impl OverlayFrameReadyEventParams {
+    /// The rectangles that changed since the frame before, or `None` if the whole frame did.
+    pub fn damage(&self) -> Option<&[OverlayDamageRect]> {
+        let count = self.damage_count as usize;
+        let damage = &self.damage;
+        (1..=OVERLAY_DAMAGE_RECTS)
+            .contains(&count)
+            .then(|| &damage[..count])
+    }
+}
+
+/// How the overlay texture is placed on the viewport, which applies from the next frame.
+///
+/// `anchor` positions a texture that does not cover the viewport, and `opacity` is multiplied
+/// with the alpha of every texel, from 0 for transparent to 255 for opaque.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct OverlayPresentationEventParams {
+    pub mode: OverlayScaleMode,
+    pub anchor: OverlayAnchor,
+    pub filter: OverlayFilterMode,
+    pub opacity: u8,
+    pub layer: OverlayLayerId,
+}
+
+static_assertions::const_assert!(
+    std::mem::size_of::<OverlayPresentationEventParams>()
+        <= std::mem::size_of::<OverlayTextureEventParams>()
+);
+
+/// The rectangle of the viewport the texture of `layer` is painted over, in pixels from the
+/// top left corner of the viewport, sent whenever it changes.
+///
+/// The orchestrator maps input into the texture with it, so that a point in the window hits
+/// the texel painted under it. Parts of the rectangle may lie outside of the viewport.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct OverlayPlacementEventParams {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub layer: OverlayLayerId,
+}
+
+static_assertions::const_assert!(
+    std::mem::size_of::<OverlayPlacementEventParams>()
+        <= std::mem::size_of::<OverlayTextureEventParams>()
+);
+
+/// How a layer of the overlay is stacked onto the viewport, which applies from the next frame.
+///
+/// The texture of the layer is placed within the rectangle at `x` and `y` from the top left
+/// of the viewport, as `OVERLAY_PRESENTATION` asks for the layer. A rectangle of zero width
+/// or height covers the whole viewport. Layers are composited in order of increasing `z`,
+/// and of increasing id for layers of the same `z`. `opacity` is multiplied with that of the
+/// layer's presentation, from 0 for transparent to 255 for opaque.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct OverlayLayerEventParams {
+    pub layer: OverlayLayerId,
+    pub flags: OverlayLayerFlags,
+    pub opacity: u8,
+    pub z: i32,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+static_assertions::const_assert!(
+    std::mem::size_of::<OverlayLayerEventParams>()
+        <= std::mem::size_of::<OverlayTextureEventParams>()
+);
+
+/// The state of the overlay pipeline of `backend`, along with counters of the frames it painted
+/// since it was activated, sent periodically while any of them changes.
+///
+/// `state` and `reason` are as in `OVERLAY_STATE`. Counters that do not fit saturate.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct OverlayDiagnosticsEventParams {
+    pub backend: GraphicsBackends,
+    pub state: u8,
+    pub reason: OverlayFailureReason,
+    /// Frames that painted the last composited frame again, as the orchestrator held the latest.
+    pub stale_frames: u32,
+    /// Frames that painted no overlay, as the orchestrator held the texture before any frame
+    /// was composited.
+    pub skipped_frames: u32,
+    /// Bytes of frames copied or uploaded into the textures that are painted.
+    pub transferred_bytes: u64,
+    /// Bytes of those frames left out, as they were outside of the damage announced.
+    pub saved_bytes: u64,
+}
+
+static_assertions::const_assert!(
+    std::mem::size_of::<OverlayDiagnosticsEventParams>()
+        <= std::mem::size_of::<OverlayTextureEventParams>()
+);
+
+/// Overrides applied to swapchains the game creates after the command is received.
+///
+/// Each override is checked against the capabilities of the surface, and is ignored
+/// if the surface does not support it.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct SwapchainPolicyEventParams {
+    pub present_mode: SwapchainPresentMode,
+    /// Image usage added to the usage requested by the application.
+    pub usage: SwapchainUsage,
+    /// The least number of images to request, or 0 to keep the application's request.
+    pub min_image_count: u32,
+    /// The most number of images to request, or 0 to keep the application's request.
+    pub max_image_count: u32,
+}
+
+static_assertions::const_assert!(
+    std::mem::size_of::<SwapchainPolicyEventParams>()
+        <= std::mem::size_of::<OverlayTextureEventParams>()
+);
+
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
 pub struct MouseEventParams {
@@ -109,6 +833,22 @@ pub union GameWindowCommandParams {
     pub mouse_event: MouseEventParams,
     pub cursor_event: CursorEventParams,
     pub overlay_event: OverlayTextureEventParams,
+    pub overlay_fd_event: OverlayTextureFdEventParams,
+    pub overlay_format_event: OverlayTextureFormatEventParams,
+    pub overlay_fd_layer_event: OverlayTextureFdLayerEventParams,
+    pub overlay_state_event: OverlayStateEventParams,
+    pub overlay_target_event: OverlayTargetEventParams,
+    pub backends_event: BackendsEventParams,
+    pub swapchain_policy_event: SwapchainPolicyEventParams,
+    pub adapter_event: AdapterEventParams,
+    pub overlay_capabilities_event: OverlayCapabilitiesEventParams,
+    pub overlay_shared_memory_event: OverlaySharedMemoryEventParams,
+    pub overlay_ring_event: OverlayTextureRingEventParams,
+    pub overlay_frame_ready_event: OverlayFrameReadyEventParams,
+    pub overlay_presentation_event: OverlayPresentationEventParams,
+    pub overlay_placement_event: OverlayPlacementEventParams,
+    pub overlay_layer_event: OverlayLayerEventParams,
+    pub overlay_diagnostics_event: OverlayDiagnosticsEventParams,
 }
 
 #[repr(C, packed)]
@@ -149,6 +889,115 @@ impl GameWindowCommand {
             },
         }
     }
+
+    pub const fn overlay_state(state: u8, reason: OverlayFailureReason) -> GameWindowCommand {
+        GameWindowCommand {
+            magic: GameWindowMagic::MAGIC,
+            ty: GameWindowCommandType::OVERLAY_STATE,
+            params: GameWindowCommandParams {
+                overlay_state_event: OverlayStateEventParams { state, reason },
+            },
+        }
+    }
+
+    pub const fn backends(backends: GraphicsBackends) -> GameWindowCommand {
+        GameWindowCommand {
+            magic: GameWindowMagic::MAGIC,
+            ty: GameWindowCommandType::BACKENDS,
+            params: GameWindowCommandParams {
+                backends_event: BackendsEventParams { backends },
+            },
+        }
+    }
+
+    pub const fn overlay_capabilities(
+        backend: GraphicsBackends,
+        capabilities: OverlayCapabilities,
+        formats: OverlayPixelFormats,
+        output_format: OverlayPixelFormat,
+        output_colorspace: OverlayColorspace,
+    ) -> GameWindowCommand {
+        GameWindowCommand {
+            magic: GameWindowMagic::MAGIC,
+            ty: GameWindowCommandType::OVERLAY_CAPABILITIES,
+            params: GameWindowCommandParams {
+                overlay_capabilities_event: OverlayCapabilitiesEventParams {
+                    backend,
+                    capabilities,
+                    formats,
+                    output_format,
+                    output_colorspace,
+                },
+            },
+        }
+    }
+
+    pub const fn overlay_placement(
+        layer: OverlayLayerId,
+        x: i32,
+        y: i32,
+        size: &Dimensions,
+    ) -> GameWindowCommand {
+        GameWindowCommand {
+            magic: GameWindowMagic::MAGIC,
+            ty: GameWindowCommandType::OVERLAY_PLACEMENT,
+            params: GameWindowCommandParams {
+                overlay_placement_event: OverlayPlacementEventParams {
+                    x,
+                    y,
+                    width: size.width,
+                    height: size.height,
+                    layer,
+                },
+            },
+        }
+    }
+
+    pub fn overlay_diagnostics(
+        backend: GraphicsBackends,
+        diagnostics: &OverlayDiagnostics,
+    ) -> GameWindowCommand {
+        GameWindowCommand {
+            magic: GameWindowMagic::MAGIC,
+            ty: GameWindowCommandType::OVERLAY_DIAGNOSTICS,
+            params: GameWindowCommandParams {
+                overlay_diagnostics_event: OverlayDiagnosticsEventParams {
+                    backend,
+                    state: diagnostics.state as u8,
+                    reason: diagnostics.failure,
+                    stale_frames: diagnostics.stale_frames.try_into().unwrap_or(u32::MAX),
+                    skipped_frames: diagnostics.skipped_frames.try_into().unwrap_or(u32::MAX),
+                    transferred_bytes: diagnostics.transferred_bytes,
+                    saved_bytes: diagnostics.saved_bytes,
+                },
+            },
+        }
+    }
+
+    pub fn adapter(backend: GraphicsBackends, identity: &AdapterIdentity) -> GameWindowCommand {
+        let mut flags = AdapterIdentityFlags::NONE;
+        if identity.luid.is_some() {
+            flags |= AdapterIdentityFlags::LUID;
+        }
+        if identity.uuid.is_some() {
+            flags |= AdapterIdentityFlags::UUID;
+        }
+
+        GameWindowCommand {
+            magic: GameWindowMagic::MAGIC,
+            ty: GameWindowCommandType::ADAPTER,
+            params: GameWindowCommandParams {
+                adapter_event: AdapterEventParams {
+                    backend,
+                    flags,
+                    luid: identity.luid.unwrap_or_default(),
+                    uuid: identity.uuid.unwrap_or_default(),
+                    vendor_id: identity.vendor_id,
+                    device_id: identity.device_id,
+                },
+            },
+        }
+    }
 }
 
 unsafe fn any_as_u8_slice<T: Sized>(p: &T) -> &[u8] {
@@ -0,0 +1,91 @@
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+
+use tokio::io::Interest;
+use tokio::net::UnixStream;
+
+use crate::ipc::cmd::{GameWindowCommand, GameWindowCommandType};
+
+/// The most file descriptors sent with a single command.
+const MAX_FDS: usize = 3;
+
+/// Read a command from `stream`, along with any file descriptors sent with it.
+pub(super) fn try_read_with_fds(
+    stream: &UnixStream,
+    data: &mut [u8],
+) -> io::Result<(usize, Vec<OwnedFd>)> {
+    stream.try_io(Interest::READABLE, || {
+        recv_with_fds(stream.as_raw_fd(), data)
+    })
+}
+
+/// Move the file descriptors received with `cmd` into its parameters.
+///
+/// Descriptors received with a command that does not carry any are closed.
+/// Otherwise, whoever handles the command owns the descriptors.
+pub(super) fn attach_fds(cmd: &mut GameWindowCommand, fds: Vec<OwnedFd>) {
+    if cmd.ty == GameWindowCommandType::OVERLAY_SHARED_MEMORY {
+        // Only the memfd is expected, any other descriptors are closed.
+        let mut params = unsafe { cmd.params.overlay_shared_memory_event };
+        params.fd = fds.into_iter().next().map_or(-1, IntoRawFd::into_raw_fd);
+        cmd.params.overlay_shared_memory_event = params;
+        return;
+    }
+
+    if cmd.ty != GameWindowCommandType::OVERLAY_TEXTURE_FD {
+        if !fds.is_empty() {
+            eprintln!("[ipc] closing {} unexpected file descriptors", fds.len());
+        }
+        return;
+    }
+
+    let mut fds = fds.into_iter().map(IntoRawFd::into_raw_fd);
+    let mut params = unsafe { cmd.params.overlay_fd_event };
+    params.memory_fd = fds.next().unwrap_or(-1);
+    params.acquire_fd = fds.next().unwrap_or(-1);
+    params.release_fd = fds.next().unwrap_or(-1);
+    cmd.params.overlay_fd_event = params;
+}
+
+fn recv_with_fds(fd: RawFd, data: &mut [u8]) -> io::Result<(usize, Vec<OwnedFd>)> {
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr().cast(),
+        iov_len: data.len(),
+    };
+
+    // Aligned for cmsghdr, and large enough for MAX_FDS descriptors.
+    let mut control = [0u64; 4 + MAX_FDS];
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    let read = unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if read < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut fds = Vec::new();
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+                let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize)
+                    / mem::size_of::<RawFd>();
+                for i in 0..count {
+                    fds.push(OwnedFd::from_raw_fd(data.add(i).read_unaligned()));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        eprintln!("[ipc] file descriptors were truncated");
+    }
+    Ok((read as usize, fds))
+}
@@ -1,4 +1,6 @@
 pub mod cmd;
+#[cfg(target_os = "linux")]
+mod fd;
 
 use std::error::Error;
 use std::fmt::{Display, Formatter};
@@ -6,7 +8,10 @@ use std::mem;
 use std::time::Duration;
 
 use tokio::io::{AsyncReadExt, AsyncWriteExt, Interest};
+#[cfg(windows)]
 use tokio::net::windows::named_pipe::{ClientOptions, NamedPipeClient};
+#[cfg(target_os = "linux")]
+use tokio::net::UnixStream;
 use tokio::runtime::Runtime;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::{io, time};
@@ -14,8 +19,15 @@ use uuid::Uuid;
 
 use crate::ipc::cmd::{GameWindowCommand, GameWindowCommandType};
 use crate::ipc::IpcConnectError::InvalidHandshake;
+#[cfg(windows)]
 use windows::Win32::Foundation::ERROR_PIPE_BUSY;
 
+/// The stream commands are exchanged with the orchestrator over.
+#[cfg(windows)]
+type IpcStream = NamedPipeClient;
+#[cfg(target_os = "linux")]
+type IpcStream = UnixStream;
+
 #[derive(Debug)]
 pub enum IpcConnectError {
     InvalidHandshake,
@@ -32,7 +44,8 @@ impl Display for IpcConnectError {
 
 impl std::error::Error for IpcConnectError {}
 
-pub async fn connect(pipeid: &Uuid) -> Result<NamedPipeClient, Box<dyn Error>> {
+#[cfg(windows)]
+pub async fn connect(pipeid: &Uuid) -> Result<IpcStream, Box<dyn Error>> {
     let pipe_name = format!(
         r"\\.\pipe\Snowflake.Orchestration.Renderer-{}",
         pipeid.to_simple()
@@ -50,6 +63,17 @@ pub async fn connect(pipeid: &Uuid) -> Result<NamedPipeClient, Box<dyn Error>> {
     Ok(client)
 }
 
+/// Connect to the orchestrator's socket in `$XDG_RUNTIME_DIR`, or `/tmp` if it is not set.
+#[cfg(target_os = "linux")]
+pub async fn connect(pipeid: &Uuid) -> Result<IpcStream, Box<dyn Error>> {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").unwrap_or_else(|| "/tmp".into());
+    let socket_path = std::path::Path::new(&runtime_dir).join(format!(
+        "Snowflake.Orchestration.Renderer-{}.sock",
+        pipeid.to_simple()
+    ));
+    Ok(UnixStream::connect(socket_path).await?)
+}
+
 pub struct IpcConnectionBuilder {
     ctx: Runtime,
     uuid: Uuid,
@@ -61,9 +85,16 @@ pub struct IpcHandle {
     events: crossbeam_channel::Receiver<GameWindowCommand>,
 }
 
+/// The end of a pair made by `IpcConnectionBuilder::connect_pair` that stands in for the
+/// orchestrator.
+#[cfg(all(test, windows))]
+pub(crate) type IpcRemote = tokio::net::windows::named_pipe::NamedPipeServer;
+#[cfg(all(test, target_os = "linux"))]
+pub(crate) type IpcRemote = std::os::unix::net::UnixStream;
+
 pub struct IpcConnection {
     ctx: Runtime,
-    pipe: NamedPipeClient,
+    pipe: IpcStream,
     remote_tx: tokio::sync::mpsc::UnboundedSender<GameWindowCommand>,
     local_rx: crossbeam_channel::Receiver<GameWindowCommand>,
     remote_rx: tokio::sync::mpsc::UnboundedReceiver<GameWindowCommand>,
@@ -101,18 +132,50 @@ impl IpcConnectionBuilder {
             Ok::<_, Box<dyn Error>>(pipe)
         })?;
 
-        let (client_tx, rx) = tokio::sync::mpsc::unbounded_channel();
-        let (tx, client_rx) = crossbeam_channel::unbounded();
+        Ok(IpcConnection::new(self.ctx, pipe, kill_rx))
+    }
 
-        Ok(IpcConnection {
-            ctx: self.ctx,
-            pipe,
-            remote_rx: rx,
-            local_tx: tx,
-            remote_tx: client_tx,
-            local_rx: client_rx,
-            kill_rx,
-        })
+    /// Connect over a pipe served by the returned end instead of the orchestrator's pipe,
+    /// without a handshake. The returned end must be kept open while the connection listens.
+    #[cfg(all(test, windows))]
+    pub(crate) fn connect_pair(
+        self,
+        kill_rx: Option<tokio::sync::oneshot::Receiver<()>>,
+    ) -> Result<(IpcConnection, IpcRemote), Box<dyn Error>> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        static PAIRS: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            r"\\.\pipe\Snowflake.Test-{}-{}",
+            std::process::id(),
+            PAIRS.fetch_add(1, Ordering::Relaxed)
+        );
+        let (pipe, remote) = self.ctx.block_on(async {
+            let remote = ServerOptions::new()
+                .first_pipe_instance(true)
+                .create(&name)?;
+            let pipe = ClientOptions::new().open(&name)?;
+            remote.connect().await?;
+            Ok::<_, io::Error>((pipe, remote))
+        })?;
+        Ok((IpcConnection::new(self.ctx, pipe, kill_rx), remote))
+    }
+
+    /// Connect over one end of a socket pair instead of the orchestrator's socket, without a
+    /// handshake. The other end is returned, and must be kept open while the connection listens.
+    #[cfg(all(test, target_os = "linux"))]
+    pub(crate) fn connect_pair(
+        self,
+        kill_rx: Option<tokio::sync::oneshot::Receiver<()>>,
+    ) -> Result<(IpcConnection, IpcRemote), Box<dyn Error>> {
+        let (local, remote) = std::os::unix::net::UnixStream::pair()?;
+        local.set_nonblocking(true)?;
+        let pipe = {
+            let _guard = self.ctx.enter();
+            UnixStream::from_std(local)?
+        };
+        Ok((IpcConnection::new(self.ctx, pipe, kill_rx), remote))
     }
 
     pub fn new(uuid: Uuid) -> Self {
@@ -124,6 +187,25 @@ impl IpcConnectionBuilder {
 }
 
 impl IpcConnection {
+    fn new(
+        ctx: Runtime,
+        pipe: IpcStream,
+        kill_rx: Option<tokio::sync::oneshot::Receiver<()>>,
+    ) -> IpcConnection {
+        let (client_tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let (tx, client_rx) = crossbeam_channel::unbounded();
+
+        IpcConnection {
+            ctx,
+            pipe,
+            remote_rx: rx,
+            local_tx: tx,
+            remote_tx: client_tx,
+            local_rx: client_rx,
+            kill_rx,
+        }
+    }
+
     pub fn handle(&self) -> IpcHandle {
         IpcHandle {
             sender: UnboundedSender::clone(&self.remote_tx),
@@ -147,22 +229,38 @@ impl IpcConnection {
         self.ctx
             .block_on(async move {
                 loop {
-                    if let Some(Ok(())) = kill_rx.as_mut().map(|r| r.try_recv()) {
-                        println!("[ipc] kill signal received");
-                        break Ok(());
-                    }
-
-                    let ready = client
-                        .ready(Interest::READABLE | Interest::WRITABLE)
-                        .await?;
+                    let ready = match kill_rx.as_mut() {
+                        Some(kill) => tokio::select! {
+                            _ = kill => {
+                                println!("[ipc] kill signal received");
+                                break Ok(());
+                            }
+                            ready = client.ready(Interest::READABLE | Interest::WRITABLE) => ready?,
+                        },
+                        None => {
+                            client
+                                .ready(Interest::READABLE | Interest::WRITABLE)
+                                .await?
+                        }
+                    };
 
                     if ready.is_readable() {
                         let mut data = vec![0u8; mem::size_of::<GameWindowCommand>()];
-                        match client.try_read(&mut data) {
-                            Ok(_n) => {
+                        #[cfg(windows)]
+                        let read = client.try_read(&mut data).map(|n| (n, ()));
+                        #[cfg(target_os = "linux")]
+                        let read = fd::try_read_with_fds(&client, &mut data);
+
+                        match read {
+                            Ok((_n, _fds)) => {
                                 match TryInto::<&GameWindowCommand>::try_into(data.as_slice()) {
                                     Ok(cmd) => {
-                                        match local_tx.send(*cmd) {
+                                        #[allow(unused_mut)]
+                                        let mut cmd = *cmd;
+                                        #[cfg(target_os = "linux")]
+                                        fd::attach_fds(&mut cmd, _fds);
+
+                                        match local_tx.send(cmd) {
                                             Ok(()) => {
                                                 //  println!("[ipc] Recv cmd {}", cmd.ty.0)
                                             }
@@ -210,6 +308,19 @@ impl IpcConnection {
 }
 
 impl IpcHandle {
+    /// A handle whose commands are sent to the returned receiver instead of the orchestrator,
+    /// and which receives the commands sent into the returned sender.
+    #[cfg(test)]
+    pub(crate) fn loopback() -> (
+        IpcHandle,
+        tokio::sync::mpsc::UnboundedReceiver<GameWindowCommand>,
+        crossbeam_channel::Sender<GameWindowCommand>,
+    ) {
+        let (sender, sent) = tokio::sync::mpsc::unbounded_channel();
+        let (received, events) = crossbeam_channel::unbounded();
+        (IpcHandle { sender, events }, sent, received)
+    }
+
     pub fn send(
         &self,
         cmd: GameWindowCommand,
@@ -222,7 +333,13 @@ impl IpcHandle {
         self.events.recv()
     }
 
+    #[allow(dead_code)]
     pub fn try_recv(&self) -> Result<GameWindowCommand, crossbeam_channel::TryRecvError> {
         self.events.try_recv()
     }
+
+    /// Receive every command that has arrived, without blocking.
+    pub fn drain(&self) -> Vec<GameWindowCommand> {
+        self.events.try_iter().collect()
+    }
 }
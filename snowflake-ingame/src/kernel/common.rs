@@ -1,4 +1,5 @@
 use crate::ipc::IpcHandle;
+use crate::overlay::OverlayStatus;
 use crate::HookHandle;
 use parking_lot::RwLock;
 use std::error::Error;
@@ -28,4 +29,7 @@ where
     /// Initialize the kernel hook. The hook should deactivate when the returned
     /// `ManuallyDrop<Self::Handle>`is dropped.
     fn init(&mut self) -> Result<ManuallyDrop<Self::Handle>, Box<dyn Error>>;
+
+    /// The state of the overlay pipeline driven by this kernel.
+    fn status(&self) -> OverlayStatus;
 }
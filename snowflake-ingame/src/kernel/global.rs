@@ -2,86 +2,389 @@ use crate::common::RenderError;
 use crate::ipc::IpcConnection;
 use crate::{IpcConnectionBuilder, KernelContext};
 use imgui::Context;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use std::error::Error;
-use std::sync::Arc;
-use std::sync::OnceLock;
+use std::sync::{Arc, LazyLock};
 use tokio::sync::oneshot::*;
 use uuid::Uuid;
 
-static mut KERNEL_CONTEXT: OnceLock<KernelContext> = OnceLock::new();
-static mut IPC_CONNECTION: OnceLock<IpcConnection> = OnceLock::new();
-static mut KILL_HANDLE: OnceLock<Sender<()>> = OnceLock::new();
-static mut IMGUI_CONTEXT: OnceLock<Arc<RwLock<Context>>> = OnceLock::new();
+/// Connects to the orchestrator. The connection must stop listening once the receiver is signalled.
+pub type KernelConnector = fn(Receiver<()>) -> Result<IpcConnection, Box<dyn Error>>;
 
-/// Acquire a handle to the global kernel.
-/// This will initialize the IPC connection and ImGui context
-/// the first time it is called. This function is safe to call multiple times,
-/// if and only if it is not called before `kernel::start`.
+enum KernelPhase {
+    /// No IPC connection exists.
+    Idle,
+    /// The IPC connection has been made, but no thread is listening on it yet.
+    Acquired {
+        context: KernelContext,
+        connection: IpcConnection,
+        kill: Sender<()>,
+    },
+    /// A thread is listening on the IPC connection.
+    Running {
+        context: KernelContext,
+        kill: Sender<()>,
+    },
+}
+
+struct KernelState {
+    phase: KernelPhase,
+    /// Incremented every time a new connection is made, so that a listener that
+    /// outlives its connection does not reset the state of a newer one.
+    generation: u64,
+    /// The number of acquired handles that have not yet been released with `kill`.
+    references: usize,
+    /// The ImGui context outlives any connection, since only one may exist per process.
+    imgui: Option<Arc<RwLock<Context>>>,
+}
+
+// SAFETY: Every field but `imgui` is Send on its own: the IPC connection, the kill sender, and
+// KernelContext, which is declared Send. `imgui` is not, only because ImGui keeps a single
+// current context per process rather than one per thread. That context is created once here and
+// is never touched by KernelState other than to clone the Arc, while every use of it goes
+// through its RwLock, so it is never accessed from two threads at once.
+unsafe impl Send for KernelState {}
+
+/// The lifecycle of the kernel.
 ///
-/// SAFETY: Calling `kernel::acquire` after `kernel::start` is undefined behaviour.
-pub unsafe fn acquire() -> Result<&'static KernelContext, Box<dyn Error>> {
-    if let Some(context) = KERNEL_CONTEXT.get() {
-        println!("[krnl] reusing existing context");
-        return Ok(context);
+/// The kernel may be acquired any number of times, by any number of callers. The first
+/// acquisition connects to the orchestrator, and the connection is kept alive until every
+/// acquisition has been released with `kill`. Once released, the kernel can be acquired and
+/// started again.
+pub struct KernelLifecycle {
+    connector: KernelConnector,
+    /// Held while the connector runs, so that only one caller connects at a time without
+    /// holding `state` while the connector blocks.
+    connecting: Mutex<()>,
+    state: Mutex<KernelState>,
+}
+
+fn connect_orchestrator(kill_rx: Receiver<()>) -> Result<IpcConnection, Box<dyn Error>> {
+    IpcConnectionBuilder::new(Uuid::nil()).connect(Some(kill_rx))
+}
+
+static KERNEL: LazyLock<KernelLifecycle> =
+    LazyLock::new(|| KernelLifecycle::new(connect_orchestrator));
+
+impl KernelLifecycle {
+    pub fn new(connector: KernelConnector) -> KernelLifecycle {
+        KernelLifecycle {
+            connector,
+            connecting: Mutex::new(()),
+            state: Mutex::new(KernelState {
+                phase: KernelPhase::Idle,
+                generation: 0,
+                references: 0,
+                imgui: None,
+            }),
+        }
     }
 
-    println!("[krnl] initializing IPC connnection");
-    let ipc = unsafe {
-        IPC_CONNECTION.get_or_try_init::<_, Box<dyn Error>>(move || {
-            let (kill_tx, kill_rx) = channel();
-            let ipc = IpcConnectionBuilder::new(Uuid::nil()).connect(Some(kill_rx))?;
-            KILL_HANDLE.get_or_init(move || kill_tx);
-            Ok(ipc)
-        })?
-    };
-
-    let imgui = IMGUI_CONTEXT.get_or_init(|| {
-        eprintln!("[krnl] initializing imgui context");
-        Arc::new(RwLock::new(Context::create()))
-    });
-
-    let context = KERNEL_CONTEXT.get_or_init(|| {
-        let handle = ipc.handle();
-        KernelContext {
-            imgui: imgui.clone(),
-            ipc: handle.clone(),
+    /// Acquire a handle to the kernel.
+    ///
+    /// This will initialize the IPC connection if the kernel is not already acquired or running.
+    /// Callers that acquire the kernel while it connects wait for that connection.
+    /// Every successful call must be paired with a call to `kill`.
+    pub fn acquire(&self) -> Result<KernelContext, Box<dyn Error>> {
+        let _connecting = self.connecting.lock();
+        {
+            let mut state = self.state.lock();
+            if let KernelPhase::Acquired { context, .. } | KernelPhase::Running { context, .. } =
+                &state.phase
+            {
+                println!("[krnl] reusing existing context");
+                let context = context.clone();
+                state.references += 1;
+                return Ok(context);
+            }
         }
-    });
 
-    Ok(context)
-}
+        // The state is not locked while connecting, so that `start` and `kill` do not block on
+        // the orchestrator. It stays idle until the connection is made, since only this caller
+        // may connect.
+        println!("[krnl] initializing IPC connnection");
+        let (kill_tx, kill_rx) = channel();
+        let connection = (self.connector)(kill_rx)?;
 
-/// Kill a running IPC thread from a different thread.
-///
-/// This method is idempotent if and only if the IPC connection can be killed or is already
-/// dead. If the IPC connection was acquired but not started when this method is called,
-/// this will panic and kill the calling thread.
-pub fn kill() {
-    unsafe {
-        if let Some(handle) = KILL_HANDLE.take() {
-            handle
-                .send(())
-                .expect("Could not send kill signal to receiving thread.");
+        let mut state = self.state.lock();
+        let imgui = state
+            .imgui
+            .get_or_insert_with(|| {
+                eprintln!("[krnl] initializing imgui context");
+                Arc::new(RwLock::new(Context::create()))
+            })
+            .clone();
+
+        let context = KernelContext {
+            imgui,
+            ipc: connection.handle(),
+        };
+
+        state.generation += 1;
+        state.phase = KernelPhase::Acquired {
+            context: context.clone(),
+            connection,
+            kill: kill_tx,
+        };
+        state.references += 1;
+        Ok(context)
+    }
+
+    /// Start the kernel. This blocks the calling thread until the kernel is killed.
+    ///
+    /// If the kernel is already running, this returns immediately.
+    pub fn start(&self) -> Result<(), Box<dyn Error>> {
+        let (connection, generation) = {
+            let mut state = self.state.lock();
+            match std::mem::replace(&mut state.phase, KernelPhase::Idle) {
+                KernelPhase::Idle => return Err(RenderError::KernelNotReady.into()),
+                running @ KernelPhase::Running { .. } => {
+                    eprintln!("[krnl] ipc already consumed.");
+                    state.phase = running;
+                    return Ok(());
+                }
+                KernelPhase::Acquired {
+                    context,
+                    connection,
+                    kill,
+                } => {
+                    state.phase = KernelPhase::Running { context, kill };
+                    (connection, state.generation)
+                }
+            }
+        };
+
+        eprintln!("[krnl] starting main ipc loop");
+        let result = connection.listen();
+        eprintln!("[krnl] stopping main loop");
+
+        let mut state = self.state.lock();
+        if state.generation == generation {
+            if let KernelPhase::Running { .. } = state.phase {
+                // The connection ended without being killed, the next acquire must reconnect.
+                state.phase = KernelPhase::Idle;
+            }
         }
+        result
+    }
 
-        // SAFETY: This should return None cause it should only be called after start.
-        assert!(IPC_CONNECTION.take().is_none());
+    /// Release a handle acquired with `acquire`.
+    ///
+    /// Once every handle has been released, a running kernel is signalled to stop, and a kernel
+    /// that was acquired but never started drops its connection. Releasing more handles than
+    /// were acquired does nothing.
+    pub fn kill(&self) {
+        let mut state = self.state.lock();
+        state.references = state.references.saturating_sub(1);
+        if state.references > 0 {
+            return;
+        }
 
-        // Drop the existing kernel context.
-        drop(KERNEL_CONTEXT.take());
+        match std::mem::replace(&mut state.phase, KernelPhase::Idle) {
+            KernelPhase::Idle => {}
+            KernelPhase::Acquired { connection, .. } => {
+                eprintln!("[krnl] dropping unstarted ipc connection");
+                drop(connection);
+            }
+            KernelPhase::Running { kill, .. } => {
+                // The listener may have already stopped, in which case there's nothing to kill.
+                kill.send(()).unwrap_or(());
+            }
+        }
     }
 }
 
-/// Start the kernel. Once this function returns, the calling thread must halt.
+/// Acquire a handle to the global kernel.
+/// This will initialize the IPC connection and ImGui context
+/// if the kernel is not already acquired or running.
+pub fn acquire() -> Result<KernelContext, Box<dyn Error>> {
+    KERNEL.acquire()
+}
+
+/// Release a handle to the global kernel, killing the IPC thread once the last handle is released.
+pub fn kill() {
+    KERNEL.kill()
+}
+
+/// Start the global kernel. Once this function returns, the kernel has been killed.
 pub fn start() -> Result<(), Box<dyn Error>> {
-    eprintln!("[krnl] starting main ipc loop");
-    if unsafe { IPC_CONNECTION.get() }.is_none() {
-        eprintln!("[krnl] ipc already consumed.");
-        return Ok(());
+    KERNEL.start()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::IpcRemote;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    /// ImGui allows only one context per process, which every lifecycle creates on acquire.
+    static IMGUI: Mutex<()> = parking_lot::const_mutex(());
+
+    /// The remote ends of the pairs made by the connectors, kept open so that the listeners
+    /// only stop when killed.
+    static REMOTES: Mutex<Vec<IpcRemote>> = parking_lot::const_mutex(Vec::new());
+
+    fn connect_pair(kill_rx: Receiver<()>) -> Result<IpcConnection, Box<dyn Error>> {
+        let (connection, remote) =
+            IpcConnectionBuilder::new(Uuid::nil()).connect_pair(Some(kill_rx))?;
+        REMOTES.lock().push(remote);
+        Ok(connection)
+    }
+
+    fn is_running(kernel: &KernelLifecycle) -> bool {
+        matches!(kernel.state.lock().phase, KernelPhase::Running { .. })
+    }
+
+    fn is_idle(kernel: &KernelLifecycle) -> bool {
+        matches!(kernel.state.lock().phase, KernelPhase::Idle)
+    }
+
+    #[test]
+    fn reacquire_after_kill() {
+        static CONNECTS: AtomicUsize = AtomicUsize::new(0);
+        fn connector(kill_rx: Receiver<()>) -> Result<IpcConnection, Box<dyn Error>> {
+            CONNECTS.fetch_add(1, Ordering::SeqCst);
+            connect_pair(kill_rx)
+        }
+
+        let _imgui = IMGUI.lock();
+        let kernel = KernelLifecycle::new(connector);
+
+        for generation in 1..=2 {
+            kernel.acquire().unwrap();
+            assert_eq!(CONNECTS.load(Ordering::SeqCst), generation);
+            assert_eq!(kernel.state.lock().generation, generation as u64);
+
+            std::thread::scope(|scope| {
+                let listener = scope.spawn(|| kernel.start());
+                while !is_running(&kernel) {
+                    std::thread::yield_now();
+                }
+
+                // Starting a running kernel returns at once.
+                kernel.start().unwrap();
+
+                kernel.kill();
+                listener.join().unwrap().unwrap();
+            });
+            assert!(is_idle(&kernel));
+        }
+    }
+
+    #[test]
+    fn start_requires_acquire() {
+        let _imgui = IMGUI.lock();
+        let kernel = KernelLifecycle::new(connect_pair);
+        assert!(kernel.start().is_err());
+
+        kernel.acquire().unwrap();
+        kernel.kill();
+        assert!(kernel.start().is_err());
+    }
+
+    #[test]
+    fn devices_share_connection() {
+        static CONNECTS: AtomicUsize = AtomicUsize::new(0);
+        fn connector(kill_rx: Receiver<()>) -> Result<IpcConnection, Box<dyn Error>> {
+            CONNECTS.fetch_add(1, Ordering::SeqCst);
+            connect_pair(kill_rx)
+        }
+
+        let _imgui = IMGUI.lock();
+        let kernel = KernelLifecycle::new(connector);
+        let first = kernel.acquire().unwrap();
+        let second = kernel.acquire().unwrap();
+        assert_eq!(CONNECTS.load(Ordering::SeqCst), 1);
+        assert!(Arc::ptr_eq(&first.imgui, &second.imgui));
+
+        std::thread::scope(|scope| {
+            let listener = scope.spawn(|| kernel.start());
+            while !is_running(&kernel) {
+                std::thread::yield_now();
+            }
+
+            // The connection outlives the first device to be destroyed.
+            kernel.kill();
+            assert!(is_running(&kernel));
+
+            kernel.kill();
+            listener.join().unwrap().unwrap();
+        });
+        assert!(is_idle(&kernel));
+
+        // Releasing more handles than were acquired does nothing.
+        kernel.kill();
+        assert_eq!(kernel.state.lock().references, 0);
+
+        // The ImGui context outlives the connection.
+        let third = kernel.acquire().unwrap();
+        assert_eq!(CONNECTS.load(Ordering::SeqCst), 2);
+        assert!(Arc::ptr_eq(&first.imgui, &third.imgui));
+        kernel.kill();
+    }
+
+    #[test]
+    fn concurrent_acquire() {
+        const DEVICES: usize = 8;
+        static CONNECTS: AtomicUsize = AtomicUsize::new(0);
+        fn connector(kill_rx: Receiver<()>) -> Result<IpcConnection, Box<dyn Error>> {
+            CONNECTS.fetch_add(1, Ordering::SeqCst);
+            connect_pair(kill_rx)
+        }
+
+        let _imgui = IMGUI.lock();
+        let kernel = KernelLifecycle::new(connector);
+        std::thread::scope(|scope| {
+            let devices: Vec<_> = (0..DEVICES)
+                .map(|_| scope.spawn(|| kernel.acquire()))
+                .collect();
+            for device in devices {
+                device.join().unwrap().unwrap();
+            }
+        });
+        assert_eq!(CONNECTS.load(Ordering::SeqCst), 1);
+        assert_eq!(kernel.state.lock().references, DEVICES);
+
+        std::thread::scope(|scope| {
+            for _ in 0..DEVICES {
+                scope.spawn(|| kernel.kill());
+            }
+        });
+        assert_eq!(kernel.state.lock().references, 0);
+        assert!(is_idle(&kernel));
+    }
+
+    #[test]
+    fn connect_releases_state() {
+        static CONNECTING: AtomicBool = AtomicBool::new(false);
+        static CONNECT: AtomicBool = AtomicBool::new(false);
+        fn connector(kill_rx: Receiver<()>) -> Result<IpcConnection, Box<dyn Error>> {
+            CONNECTING.store(true, Ordering::SeqCst);
+            while !CONNECT.load(Ordering::SeqCst) {
+                std::thread::yield_now();
+            }
+            connect_pair(kill_rx)
+        }
+
+        let _imgui = IMGUI.lock();
+        let kernel = KernelLifecycle::new(connector);
+        std::thread::scope(|scope| {
+            let device = scope.spawn(|| kernel.acquire());
+            while !CONNECTING.load(Ordering::SeqCst) {
+                std::thread::yield_now();
+            }
+
+            // The kernel can be started and killed while the connector blocks.
+            assert!(is_idle(&kernel));
+            assert!(kernel.start().is_err());
+            kernel.kill();
+
+            CONNECT.store(true, Ordering::SeqCst);
+            device.join().unwrap().unwrap();
+        });
+        assert_eq!(kernel.state.lock().references, 1);
+
+        kernel.kill();
+        assert!(is_idle(&kernel));
     }
-    let ipc = unsafe { IPC_CONNECTION.take().ok_or(RenderError::KernelNotReady)? };
-    ipc.listen()?;
-    eprintln!("[krnl] stopping main loop");
-    Ok(())
 }
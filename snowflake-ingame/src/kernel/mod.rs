@@ -2,5 +2,6 @@ pub(crate) mod common;
 mod global;
 
 pub use global::acquire;
+pub use global::KernelLifecycle;
 pub use global::kill;
 pub use global::start;
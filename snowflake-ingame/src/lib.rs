@@ -2,41 +2,71 @@
 #![feature(type_alias_impl_trait)]
 #![feature(associated_type_defaults)]
 #![feature(strict_provenance)]
+#![feature(generic_associated_types)]
 
 use std::error::Error;
-use std::ffi::c_void;
 use std::panic::catch_unwind;
+use std::time::Duration;
+
+#[cfg(windows)]
+use std::ffi::c_void;
+#[cfg(windows)]
 use windows::Win32::Foundation::{BOOL, HINSTANCE};
+#[cfg(windows)]
 use windows::Win32::System::Console::AllocConsole;
+#[cfg(windows)]
 use windows::Win32::System::LibraryLoader::DisableThreadLibraryCalls;
+#[cfg(windows)]
 use windows::Win32::System::SystemServices::DLL_PROCESS_ATTACH;
 
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use parking_lot::{const_mutex, Mutex};
+
+#[cfg(windows)]
 use crate::d3d11::Direct3D11Kernel;
+use crate::detect::GraphicsApi;
+#[cfg(target_os = "linux")]
+use crate::glx::GLXKernel;
 use crate::hook::*;
+use crate::ipc::cmd::{GameWindowCommand, GraphicsBackends};
 use crate::ipc::IpcConnectionBuilder;
 use crate::kernel::common::{FrameKernel, KernelContext};
+use crate::overlay::OverlayStatus;
+#[cfg(windows)]
 use crate::wgl::WGLKernel;
 
 mod common;
+#[cfg(windows)]
 mod d3d11;
+mod detect;
+#[cfg(target_os = "linux")]
+mod glx;
 mod hook;
 mod ipc;
 mod kernel;
+mod ogl;
+mod overlay;
+#[cfg(target_os = "linux")]
+mod unix;
 mod vk;
+#[cfg(windows)]
 mod wgl;
+#[cfg(windows)]
 mod win32;
 
+#[cfg(target_os = "linux")]
+use unix as platform;
+#[cfg(windows)]
+use win32 as platform;
+
 unsafe fn main() -> Result<(), Box<dyn Error>> {
     println!("[ingame] reached main");
     let context = kernel::acquire()?;
     println!("[ingame] kernel acquired");
-    let mut dx11 = Direct3D11Kernel::new(context.clone())?;
-    dx11.init()?;
-    println!("[dx11] init finish");
 
-    let mut wgl = WGLKernel::new(context.clone())?;
-    wgl.init()?;
-    println!("[wgl] init finish");
+    let apis = detect::watch()?;
+    let activation_context = context.clone();
+    std::thread::spawn(move || activate_kernels(activation_context, apis));
 
     if !vk::entry::is_vk_loaded() {
         println!("[init] starting kernel.");
@@ -47,6 +77,106 @@ unsafe fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// How often the diagnostics of the overlays are reported to the orchestrator.
+const DIAGNOSTICS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The state of the overlays of every kernel activated so far, and the API each one hooks.
+static ACTIVE_KERNELS: Mutex<Vec<(GraphicsApi, OverlayStatus)>> = const_mutex(Vec::new());
+
+/// Activate the kernel `K` for `api`.
+///
+/// The kernel is kept alive for the rest of the process, as the hooks it installs are, and the
+/// state of its overlays is kept in [`ACTIVE_KERNELS`]. The backends of every active kernel are
+/// then reported to the orchestrator.
+fn activate<K: FrameKernel + 'static>(
+    context: &KernelContext,
+    api: GraphicsApi,
+) -> Result<(), Box<dyn Error>> {
+    let mut kernel = K::new(context.clone())?;
+    kernel.init()?;
+    let kernel: &'static K = Box::leak(Box::new(kernel));
+
+    // The lock is held while reporting, so that the backends are reported in order.
+    let mut kernels = ACTIVE_KERNELS.lock();
+    kernels.push((api, kernel.status()));
+    let active = kernels
+        .iter()
+        .fold(GraphicsBackends::NONE, |active, (api, _)| {
+            active | api.backend()
+        });
+    context
+        .ipc
+        .send(GameWindowCommand::backends(active))
+        .unwrap_or(());
+    Ok(())
+}
+
+/// Initialize the kernel for each graphics API as it is loaded into the process.
+///
+/// While waiting for an API, the diagnostics of the overlays of every active kernel are
+/// reported every [`DIAGNOSTICS_INTERVAL`].
+fn activate_kernels(context: KernelContext, apis: Receiver<GraphicsApi>) {
+    loop {
+        let api = match apis.recv_timeout(DIAGNOSTICS_INTERVAL) {
+            Ok(api) => api,
+            Err(RecvTimeoutError::Timeout) => {
+                for (api, status) in ACTIVE_KERNELS.lock().iter() {
+                    status
+                        .report_diagnostics(&context.ipc, api.backend())
+                        .unwrap_or_else(|e| {
+                            eprintln!("[init] unable to report {} diagnostics: {}", api, e)
+                        });
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        let result = match api {
+            #[cfg(windows)]
+            GraphicsApi::Direct3D11 => activate::<Direct3D11Kernel>(&context, api),
+            #[cfg(windows)]
+            GraphicsApi::OpenGL => activate::<WGLKernel>(&context, api),
+            #[cfg(target_os = "linux")]
+            GraphicsApi::OpenGL => activate::<GLXKernel>(&context, api),
+            // The Vulkan kernel is driven by the layer, which is loaded by the Vulkan loader,
+            // and is activated by the first device created.
+            GraphicsApi::Vulkan => continue,
+            #[allow(unreachable_patterns)]
+            _ => Err(format!("{} is not supported on this platform", api).into()),
+        };
+
+        match result {
+            Ok(()) => println!("[init] {} kernel active", api),
+            Err(e) => eprintln!("[init] unable to activate {} kernel: {}", api, e),
+        }
+    }
+}
+
+/// Run `main` on a new thread, so that the loader lock is not held while connecting to the orchestrator.
+#[cfg_attr(
+    not(any(windows, all(feature = "cdylib", not(test)))),
+    allow(dead_code)
+)]
+fn spawn_main() {
+    std::thread::spawn(|| unsafe {
+        println!(
+            "[init] {:?}",
+            catch_unwind(|| {
+                match crate::main() {
+                    Ok(()) => 0 as u32,
+                    Err(e) => {
+                        println!("Error occurred when injecting: {}", e);
+                        1
+                    }
+                }
+            })
+        );
+        println!("[init] main over");
+    });
+}
+
+#[cfg(windows)]
 #[no_mangle]
 #[allow(non_snake_case)]
 pub extern "system" fn DllMain(
@@ -65,21 +195,22 @@ pub extern "system" fn DllMain(
         }
 
         println!("[init] DllMain");
-        std::thread::spawn(|| unsafe {
-            println!(
-                "[init] {:?}",
-                catch_unwind(|| {
-                    match crate::main() {
-                        Ok(()) => 0 as u32,
-                        Err(e) => {
-                            println!("Error occurred when injecting: {}", e);
-                            1
-                        }
-                    }
-                })
-            );
-            println!("[init] DllMain over");
-        });
+        spawn_main();
     }
     true.into()
 }
+
+/// Runs when the shared object is loaded, either by the Vulkan loader or through `LD_PRELOAD`.
+///
+/// Test executables, and programs that link the rlib without the `cdylib` feature, do not
+/// start the kernel as they are loaded.
+#[cfg(all(target_os = "linux", feature = "cdylib", not(test)))]
+#[used]
+#[link_section = ".init_array"]
+static INIT_ARRAY: extern "C" fn() = {
+    extern "C" fn init() {
+        println!("[init] init_array");
+        spawn_main();
+    }
+    init
+};
@@ -0,0 +1,303 @@
+use std::ffi::CStr;
+use std::ops::Deref;
+
+use imgui_renderer_ogl::ColorEncoding;
+use opengl_bindings as gl;
+use opengl_bindings::types::{GLenum, GLint, GLsizei, GLuint};
+use opengl_bindings::Gl;
+
+use crate::common::{AdapterIdentity, Dimensions, RenderError};
+use crate::overlay::{OverlayEncoding, OverlayFilter, OverlayFormat, OverlayOutput, SharedFrame};
+
+/// GL function pointers, shared between the hooks of every thread that presents.
+///
+/// The pointers are only ever called on the thread whose context is current.
+pub(crate) struct OwnedGl(pub Gl);
+unsafe impl Send for OwnedGl {}
+unsafe impl Sync for OwnedGl {}
+
+impl Deref for OwnedGl {
+    type Target = Gl;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// A string queried with `glGetString` or `glGetStringi`, or an empty string if there is none.
+pub(crate) unsafe fn gl_string(s: *const u8) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(s.cast()).to_string_lossy().into_owned()
+}
+
+pub(crate) unsafe fn has_extension(gl: &Gl, name: &str) -> bool {
+    let mut count = 0;
+    gl.GetIntegerv(gl::NUM_EXTENSIONS, &mut count);
+    (0..count as GLuint).any(|i| gl_string(gl.GetStringi(gl::EXTENSIONS, i)) == name)
+}
+
+/// The PCI vendor id of the vendor named by `GL_VENDOR`, or 0 if it is not recognized.
+fn pci_vendor_id(vendor: &str) -> u32 {
+    let vendor = vendor.to_ascii_lowercase();
+    if vendor.contains("nvidia") {
+        0x10de
+    } else if vendor.contains("amd") || vendor.contains("ati technologies") {
+        0x1002
+    } else if vendor.contains("intel") {
+        0x8086
+    } else {
+        0
+    }
+}
+
+/// The adapter the current context renders on.
+///
+/// OpenGL only identifies the adapter with `GL_EXT_memory_object`, otherwise the vendor
+/// is taken from the renderer strings.
+pub(crate) unsafe fn adapter_identity(gl: &Gl, log_prefix: &str) -> AdapterIdentity {
+    let vendor = gl_string(gl.GetString(gl::VENDOR));
+    let renderer = gl_string(gl.GetString(gl::RENDERER));
+    eprintln!("[{}] renderer {} ({})", log_prefix, renderer, vendor);
+
+    let mut adapter = AdapterIdentity {
+        vendor_id: pci_vendor_id(&vendor),
+        ..Default::default()
+    };
+
+    if has_extension(gl, "GL_EXT_memory_object") && gl.GetUnsignedBytei_vEXT.is_loaded() {
+        let mut uuid = [0u8; gl::UUID_SIZE_EXT as usize];
+        gl.GetUnsignedBytei_vEXT(gl::DEVICE_UUID_EXT, 0, uuid.as_mut_ptr());
+        adapter.uuid = Some(uuid);
+    }
+
+    if has_extension(gl, "GL_EXT_memory_object_win32") && gl.GetUnsignedBytevEXT.is_loaded() {
+        let mut luid = [0u8; gl::LUID_SIZE_EXT as usize];
+        gl.GetUnsignedBytevEXT(gl::DEVICE_LUID_EXT, luid.as_mut_ptr());
+        adapter.luid = Some(luid);
+    }
+
+    adapter
+}
+
+/// Fail with the first GL error raised since the last call, if any.
+pub(crate) unsafe fn check_error(gl: &Gl) -> Result<(), RenderError> {
+    match gl.GetError() {
+        gl::NO_ERROR => Ok(()),
+        error => Err(RenderError::OpenGLError(error)),
+    }
+}
+
+/// The framebuffer bound for drawing, which the overlay is composited onto.
+///
+/// A floating point framebuffer holds linear colour, as in scRGB, and any other holds sRGB,
+/// which it may encode itself if `GL_FRAMEBUFFER_SRGB` is enabled.
+pub(crate) unsafe fn framebuffer_output(gl: &Gl) -> OverlayOutput {
+    if !gl.GetFramebufferAttachmentParameteriv.is_loaded() {
+        return OverlayOutput::default();
+    }
+
+    // Errors left behind by the game are not ours to report.
+    while gl.GetError() != gl::NO_ERROR {}
+
+    let mut framebuffer = 0;
+    gl.GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut framebuffer);
+    let attachment = if framebuffer == 0 {
+        gl::BACK_LEFT
+    } else {
+        gl::COLOR_ATTACHMENT0
+    };
+    let attachment_parameter = |pname| {
+        let mut value = 0;
+        gl.GetFramebufferAttachmentParameteriv(gl::DRAW_FRAMEBUFFER, attachment, pname, &mut value);
+        value as GLenum
+    };
+
+    let encoding = attachment_parameter(gl::FRAMEBUFFER_ATTACHMENT_COLOR_ENCODING);
+    let component_type = attachment_parameter(gl::FRAMEBUFFER_ATTACHMENT_COMPONENT_TYPE);
+    let red_size = attachment_parameter(gl::FRAMEBUFFER_ATTACHMENT_RED_SIZE);
+    if gl.GetError() != gl::NO_ERROR {
+        return OverlayOutput::default();
+    }
+
+    let output = match (component_type, red_size) {
+        (gl::FLOAT, 16) => OverlayOutput {
+            format: Some(OverlayFormat::Rgba16Float),
+            encoding: OverlayEncoding::Linear,
+            srgb_writes: false,
+        },
+        (gl::FLOAT, _) => OverlayOutput {
+            encoding: OverlayEncoding::Linear,
+            ..OverlayOutput::default()
+        },
+        (_, 10) => OverlayOutput {
+            format: Some(OverlayFormat::Rgb10A2),
+            ..OverlayOutput::default()
+        },
+        (_, 8) => OverlayOutput {
+            format: Some(OverlayFormat::Rgba8),
+            ..OverlayOutput::default()
+        },
+        _ => OverlayOutput::default(),
+    };
+
+    OverlayOutput {
+        srgb_writes: encoding == gl::SRGB && gl.IsEnabled(gl::FRAMEBUFFER_SRGB) == gl::TRUE,
+        ..output
+    }
+}
+
+/// The pixel unpack state an upload changes, which is restored for the game afterwards.
+const UNPACK_STATE: [GLenum; 4] = [
+    gl::UNPACK_ROW_LENGTH,
+    gl::UNPACK_SKIP_ROWS,
+    gl::UNPACK_SKIP_PIXELS,
+    gl::UNPACK_ALIGNMENT,
+];
+
+/// The GL filter an overlay texture is sampled with.
+pub(crate) const fn gl_filter(filter: OverlayFilter) -> GLint {
+    match filter {
+        OverlayFilter::Linear => gl::LINEAR as GLint,
+        OverlayFilter::Nearest => gl::NEAREST as GLint,
+    }
+}
+
+/// The encoding the composite pass of the renderer converts colour from or to.
+pub(crate) const fn color_encoding(encoding: OverlayEncoding) -> ColorEncoding {
+    match encoding {
+        OverlayEncoding::Srgb => ColorEncoding::Srgb,
+        OverlayEncoding::Linear => ColorEncoding::Linear,
+        OverlayEncoding::Hdr10 => ColorEncoding::Hdr10,
+    }
+}
+
+/// A texture overlay frames are uploaded into, when they are written to shared memory
+/// instead of being shared with the context.
+///
+/// Only core OpenGL is used, so that frames can be uploaded into any context.
+pub(crate) struct UploadTexture {
+    gl: Gl,
+    texture: GLuint,
+    dimensions: Dimensions,
+    filter: OverlayFilter,
+}
+
+impl UploadTexture {
+    pub unsafe fn new(gl: &Gl, filter: OverlayFilter) -> UploadTexture {
+        let mut texture = 0;
+        gl.GenTextures(1, &mut texture);
+        UploadTexture {
+            gl: gl.clone(),
+            texture,
+            dimensions: Dimensions::new(0, 0),
+            filter,
+        }
+    }
+
+    #[inline]
+    pub fn texture(&self) -> GLuint {
+        self.texture
+    }
+
+    /// Sample the texture with `filter` from the next frame uploaded.
+    #[inline]
+    pub fn set_filter(&mut self, filter: OverlayFilter) {
+        self.filter = filter;
+    }
+
+    /// Upload the damage of `frame` into the texture, or all of it if the size of the frame
+    /// changed and the texture is reallocated.
+    pub unsafe fn upload(&mut self, frame: &SharedFrame<'_>) -> Result<Dimensions, RenderError> {
+        let gl = &self.gl;
+
+        // Errors left behind by the game are not ours to report.
+        while gl.GetError() != gl::NO_ERROR {}
+
+        let (mut texture, mut unpack_buffer) = (0, 0);
+        gl.GetIntegerv(gl::TEXTURE_BINDING_2D, &mut texture);
+        gl.GetIntegerv(gl::PIXEL_UNPACK_BUFFER_BINDING, &mut unpack_buffer);
+        let unpack_state = UNPACK_STATE.map(|pname| {
+            let mut value = 0;
+            gl.GetIntegerv(pname, &mut value);
+            value
+        });
+
+        gl.BindTexture(gl::TEXTURE_2D, self.texture);
+        gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+        for (pname, value) in UNPACK_STATE
+            .into_iter()
+            .zip([frame.row_pitch as GLint / 4, 0, 0, 4])
+        {
+            gl.PixelStorei(pname, value);
+        }
+
+        let Dimensions { width, height } = frame.dimensions;
+        let filter = gl_filter(self.filter);
+        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter);
+        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter);
+        if self.dimensions != frame.dimensions {
+            gl.TexParameteri(
+                gl::TEXTURE_2D,
+                gl::TEXTURE_WRAP_S,
+                gl::CLAMP_TO_EDGE as GLint,
+            );
+            gl.TexParameteri(
+                gl::TEXTURE_2D,
+                gl::TEXTURE_WRAP_T,
+                gl::CLAMP_TO_EDGE as GLint,
+            );
+            gl.TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::RGBA8 as GLint,
+                width as GLsizei,
+                height as GLsizei,
+                0,
+                gl::BGRA,
+                gl::UNSIGNED_BYTE,
+                frame.pixels.as_ptr().cast(),
+            );
+        } else {
+            // Only the damaged rectangles are uploaded, skipping to each within the frame.
+            for rect in frame.damage.rects(frame.dimensions) {
+                gl.PixelStorei(gl::UNPACK_SKIP_PIXELS, rect.x as GLint);
+                gl.PixelStorei(gl::UNPACK_SKIP_ROWS, rect.y as GLint);
+                gl.TexSubImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    rect.x as GLint,
+                    rect.y as GLint,
+                    rect.width as GLsizei,
+                    rect.height as GLsizei,
+                    gl::BGRA,
+                    gl::UNSIGNED_BYTE,
+                    frame.pixels.as_ptr().cast(),
+                );
+            }
+        }
+        let result = check_error(gl);
+
+        for (pname, value) in UNPACK_STATE.into_iter().zip(unpack_state) {
+            gl.PixelStorei(pname, value);
+        }
+        gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, unpack_buffer as GLuint);
+        gl.BindTexture(gl::TEXTURE_2D, texture as GLuint);
+
+        result?;
+        self.dimensions = frame.dimensions;
+        Ok(frame.dimensions)
+    }
+
+    /// Forget the texture without deleting it, for when its context is not current.
+    pub fn abandon(self) {
+        std::mem::forget(self)
+    }
+}
+
+impl Drop for UploadTexture {
+    fn drop(&mut self) {
+        unsafe { self.gl.DeleteTextures(1, &self.texture) };
+    }
+}
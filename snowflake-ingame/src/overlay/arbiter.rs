@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::ipc::cmd::{GameWindowCommand, GameWindowCommandType, OverlayTargetMode};
+use crate::ipc::IpcHandle;
+
+/// An owner that has not presented to its window for this long can be replaced.
+const HANDOVER_AFTER: Duration = Duration::from_millis(250);
+
+/// Windows that have not been presented to for this long are forgotten.
+const FORGET_AFTER: Duration = Duration::from_secs(5);
+
+/// The result of a backend claiming a window for the current frame.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Ownership {
+    /// The backend already owned the window.
+    Owned,
+    /// The backend has just taken ownership of the window.
+    Acquired,
+    /// The backend used to own the window, but it was handed over to another backend.
+    /// The backend should drop any overlay state it had for the window.
+    Revoked,
+    /// Another backend owns the window.
+    Denied,
+}
+
+impl Ownership {
+    /// Whether the backend may paint the overlay this frame.
+    #[inline]
+    pub fn is_owner(self) -> bool {
+        matches!(self, Ownership::Owned | Ownership::Acquired)
+    }
+}
+
+struct WindowOwner {
+    backend: &'static str,
+    last_present: Instant,
+    /// Backends that owned this window, but have not yet been told they no longer do.
+    revoked: Vec<&'static str>,
+}
+
+/// The window the commands of the orchestrator are handed to.
+struct CommandRoute {
+    /// The window commands are routed to, if any window has taken them yet.
+    window: Option<isize>,
+    /// When commands were last taken, or when the arbiter was created if they never were.
+    taken: Instant,
+    /// Commands received for the window that its owner has not taken yet.
+    pending: Vec<GameWindowCommand>,
+}
+
+/// Decides which backend paints the overlay onto a window.
+///
+/// Games can trigger more than one of our hooks for the same window, such as when a
+/// translation layer implements one API on top of another. Only one backend may paint
+/// the overlay for a given window, and whichever presents first keeps it for as long as
+/// it keeps presenting. Because hooks run before calling through to the next function,
+/// this is the outermost API when one API is layered on another.
+///
+/// Once the owner stops presenting, the next backend to present takes over.
+///
+/// The orchestrator sends its commands for a single window, which the kernels of every API
+/// receive from the same queue. They are routed to the window the overlay is painted on, so
+/// that the owner of another window does not take them.
+pub struct PresentArbiter {
+    windows: Mutex<HashMap<isize, WindowOwner>>,
+    route: Mutex<CommandRoute>,
+}
+
+static ARBITER: LazyLock<PresentArbiter> = LazyLock::new(PresentArbiter::new);
+
+impl PresentArbiter {
+    pub fn new() -> PresentArbiter {
+        PresentArbiter {
+            windows: Mutex::new(HashMap::new()),
+            route: Mutex::new(CommandRoute {
+                window: None,
+                taken: Instant::now(),
+                pending: Vec::new(),
+            }),
+        }
+    }
+
+    /// Claim `window` for `backend`, as it is about to be presented to.
+    pub fn claim(&self, window: isize, backend: &'static str) -> Ownership {
+        self.claim_at(window, backend, Instant::now())
+    }
+
+    fn claim_at(&self, window: isize, backend: &'static str, now: Instant) -> Ownership {
+        let mut windows = self.windows.lock();
+        windows.retain(|_, owner| now.duration_since(owner.last_present) < FORGET_AFTER);
+
+        let owner = match windows.get_mut(&window) {
+            Some(owner) => owner,
+            None => {
+                eprintln!("[ovl] {} owns window {:x}", backend, window);
+                windows.insert(
+                    window,
+                    WindowOwner {
+                        backend,
+                        last_present: now,
+                        revoked: Vec::new(),
+                    },
+                );
+                return Ownership::Acquired;
+            }
+        };
+
+        if owner.backend == backend {
+            owner.last_present = now;
+            return Ownership::Owned;
+        }
+
+        if let Some(index) = owner.revoked.iter().position(|revoked| *revoked == backend) {
+            owner.revoked.swap_remove(index);
+            return Ownership::Revoked;
+        }
+
+        if now.duration_since(owner.last_present) < HANDOVER_AFTER {
+            return Ownership::Denied;
+        }
+
+        eprintln!(
+            "[ovl] window {:x} handed over from {} to {}",
+            window, owner.backend, backend
+        );
+        owner.revoked.push(owner.backend);
+        owner.backend = backend;
+        owner.last_present = now;
+        Ownership::Acquired
+    }
+
+    /// Take the commands received from `ipc` for `window`, whose owner is about to paint it.
+    ///
+    /// Commands are routed to the first window whose owner takes them, until the orchestrator
+    /// targets another window with `OVERLAY_TARGET`. Other windows receive none, unless the
+    /// commands are not taken for as long as it takes to hand a window over to another owner.
+    pub fn take_commands(&self, window: isize, ipc: &IpcHandle) -> Vec<GameWindowCommand> {
+        self.take_commands_at(window, ipc, Instant::now())
+    }
+
+    fn take_commands_at(
+        &self,
+        window: isize,
+        ipc: &IpcHandle,
+        now: Instant,
+    ) -> Vec<GameWindowCommand> {
+        let mut route = self.route.lock();
+        route.pending.extend(ipc.drain());
+
+        // The newest target the orchestrator asked for decides, if it names a window.
+        let targeted = route
+            .pending
+            .iter()
+            .rev()
+            .find(|cmd| cmd.ty == GameWindowCommandType::OVERLAY_TARGET)
+            .map(|cmd| unsafe { cmd.params.overlay_target_event })
+            .filter(|target| target.mode == OverlayTargetMode::WINDOW)
+            .map(|target| target.window as isize);
+
+        let routed = match targeted.or(route.window) {
+            Some(routed) => routed == window || now.duration_since(route.taken) >= HANDOVER_AFTER,
+            None => true,
+        };
+        if !routed {
+            return Vec::new();
+        }
+
+        if route.window != Some(window) {
+            eprintln!("[ovl] routing commands to window {:x}", window);
+        }
+        route.window = Some(window);
+        route.taken = now;
+        std::mem::take(&mut route.pending)
+    }
+}
+
+/// Claim `window` for `backend` with the global arbiter.
+pub fn claim_window(window: isize, backend: &'static str) -> Ownership {
+    ARBITER.claim(window, backend)
+}
+
+/// Take the commands routed to `window` by the global arbiter.
+pub fn take_commands(window: isize, ipc: &IpcHandle) -> Vec<GameWindowCommand> {
+    ARBITER.take_commands(window, ipc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::cmd::{GameWindowCommandParams, GameWindowMagic, OverlayTargetEventParams};
+
+    const WINDOW: isize = 0x10;
+    const OTHER_WINDOW: isize = 0x20;
+
+    fn target_command(mode: OverlayTargetMode, window: isize) -> GameWindowCommand {
+        GameWindowCommand {
+            magic: GameWindowMagic::MAGIC,
+            ty: GameWindowCommandType::OVERLAY_TARGET,
+            params: GameWindowCommandParams {
+                overlay_target_event: OverlayTargetEventParams {
+                    mode,
+                    window: window as u64,
+                },
+            },
+        }
+    }
+
+    fn shutdown_command() -> GameWindowCommand {
+        let mut cmd = target_command(OverlayTargetMode::LARGEST, 0);
+        cmd.ty = GameWindowCommandType::SHUTDOWN;
+        cmd
+    }
+
+    #[test]
+    fn first_to_present_owns() {
+        let arbiter = PresentArbiter::new();
+        let now = Instant::now();
+        assert_eq!(arbiter.claim_at(WINDOW, "vk", now), Ownership::Acquired);
+        assert_eq!(arbiter.claim_at(WINDOW, "vk", now), Ownership::Owned);
+        assert_eq!(arbiter.claim_at(WINDOW, "d3d11", now), Ownership::Denied);
+
+        // Every window has an owner of its own.
+        assert_eq!(
+            arbiter.claim_at(OTHER_WINDOW, "d3d11", now),
+            Ownership::Acquired
+        );
+    }
+
+    #[test]
+    fn handover() {
+        let arbiter = PresentArbiter::new();
+        let start = Instant::now();
+        assert_eq!(arbiter.claim_at(WINDOW, "vk", start), Ownership::Acquired);
+
+        // The owner keeps the window for as long as it keeps presenting.
+        let presenting = start + HANDOVER_AFTER / 2;
+        assert_eq!(arbiter.claim_at(WINDOW, "vk", presenting), Ownership::Owned);
+        let now = start + HANDOVER_AFTER;
+        assert_eq!(arbiter.claim_at(WINDOW, "d3d11", now), Ownership::Denied);
+
+        let now = presenting + HANDOVER_AFTER;
+        assert_eq!(arbiter.claim_at(WINDOW, "d3d11", now), Ownership::Acquired);
+        assert_eq!(arbiter.claim_at(WINDOW, "d3d11", now), Ownership::Owned);
+    }
+
+    #[test]
+    fn revocation() {
+        let arbiter = PresentArbiter::new();
+        let start = Instant::now();
+        arbiter.claim_at(WINDOW, "vk", start);
+
+        let now = start + HANDOVER_AFTER;
+        assert_eq!(arbiter.claim_at(WINDOW, "d3d11", now), Ownership::Acquired);
+
+        // The previous owner is told once that it lost the window, so that it drops its overlay.
+        assert_eq!(arbiter.claim_at(WINDOW, "vk", now), Ownership::Revoked);
+        assert_eq!(arbiter.claim_at(WINDOW, "vk", now), Ownership::Denied);
+
+        // It takes the window back once the new owner stops presenting, and the new owner
+        // is revoked in turn.
+        let now = now + HANDOVER_AFTER;
+        assert_eq!(arbiter.claim_at(WINDOW, "vk", now), Ownership::Acquired);
+        assert_eq!(arbiter.claim_at(WINDOW, "d3d11", now), Ownership::Revoked);
+    }
+
+    #[test]
+    fn forget() {
+        let arbiter = PresentArbiter::new();
+        let start = Instant::now();
+        arbiter.claim_at(WINDOW, "vk", start);
+        arbiter.claim_at(WINDOW, "d3d11", start + HANDOVER_AFTER);
+
+        // A window that is no longer presented to is forgotten along with its revoked owners.
+        let now = start + HANDOVER_AFTER + FORGET_AFTER;
+        assert_eq!(arbiter.claim_at(WINDOW, "vk", now), Ownership::Acquired);
+    }
+
+    #[test]
+    fn commands_routed_to_window() {
+        let arbiter = PresentArbiter::new();
+        let (ipc, _, events) = IpcHandle::loopback();
+        let start = Instant::now();
+
+        events.send(shutdown_command()).unwrap();
+        assert_eq!(arbiter.take_commands_at(WINDOW, &ipc, start).len(), 1);
+
+        // Commands for the window are kept for it, and not taken by another.
+        events.send(shutdown_command()).unwrap();
+        let now = start + HANDOVER_AFTER / 2;
+        assert!(arbiter.take_commands_at(OTHER_WINDOW, &ipc, now).is_empty());
+        events.send(shutdown_command()).unwrap();
+        assert_eq!(arbiter.take_commands_at(WINDOW, &ipc, now).len(), 2);
+
+        // Once the window stops taking commands, another window takes them over.
+        events.send(shutdown_command()).unwrap();
+        let now = now + HANDOVER_AFTER;
+        assert_eq!(arbiter.take_commands_at(OTHER_WINDOW, &ipc, now).len(), 1);
+        events.send(shutdown_command()).unwrap();
+        assert!(arbiter.take_commands_at(WINDOW, &ipc, now).is_empty());
+    }
+
+    #[test]
+    fn commands_follow_target() {
+        let arbiter = PresentArbiter::new();
+        let (ipc, _, events) = IpcHandle::loopback();
+        let now = Instant::now();
+        arbiter.take_commands_at(WINDOW, &ipc, now);
+
+        // The orchestrator targeting another window routes commands to it right away.
+        events
+            .send(target_command(OverlayTargetMode::WINDOW, OTHER_WINDOW))
+            .unwrap();
+        events.send(shutdown_command()).unwrap();
+        assert!(arbiter.take_commands_at(WINDOW, &ipc, now).is_empty());
+        assert_eq!(arbiter.take_commands_at(OTHER_WINDOW, &ipc, now).len(), 2);
+
+        // Targeting no window in particular keeps commands on the window they are routed to.
+        events
+            .send(target_command(OverlayTargetMode::LARGEST, 0))
+            .unwrap();
+        assert!(arbiter.take_commands_at(WINDOW, &ipc, now).is_empty());
+        assert_eq!(arbiter.take_commands_at(OTHER_WINDOW, &ipc, now).len(), 1);
+    }
+}
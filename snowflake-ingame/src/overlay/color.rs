@@ -0,0 +1,214 @@
+use crate::common::RenderError;
+use crate::ipc::cmd::{OverlayColorspace, OverlayPixelFormat, OverlayPixelFormats};
+
+/// A pixel format the overlay texture can be exported in.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum OverlayFormat {
+    Bgra8,
+    Rgba8,
+    Rgba16Float,
+    Rgb10A2,
+}
+
+impl OverlayFormat {
+    /// The format a texture was announced in.
+    ///
+    /// A texture announced without a format is BGRA8, which is what the orchestrator
+    /// exported before formats were negotiated.
+    pub fn announced(format: OverlayPixelFormat) -> Result<OverlayFormat, RenderError> {
+        match format {
+            OverlayPixelFormat::UNSPECIFIED | OverlayPixelFormat::BGRA8 => Ok(OverlayFormat::Bgra8),
+            OverlayPixelFormat::RGBA8 => Ok(OverlayFormat::Rgba8),
+            OverlayPixelFormat::RGBA16F => Ok(OverlayFormat::Rgba16Float),
+            OverlayPixelFormat::RGB10A2 => Ok(OverlayFormat::Rgb10A2),
+            _ => Err(RenderError::OverlayFormatUnsupported(format.code() as u32)),
+        }
+    }
+
+    /// The number of bytes of a texel.
+    pub const fn texel_size(self) -> u32 {
+        match self {
+            OverlayFormat::Bgra8 | OverlayFormat::Rgba8 | OverlayFormat::Rgb10A2 => 4,
+            OverlayFormat::Rgba16Float => 8,
+        }
+    }
+}
+
+impl From<OverlayFormat> for OverlayPixelFormat {
+    fn from(format: OverlayFormat) -> Self {
+        match format {
+            OverlayFormat::Bgra8 => OverlayPixelFormat::BGRA8,
+            OverlayFormat::Rgba8 => OverlayPixelFormat::RGBA8,
+            OverlayFormat::Rgba16Float => OverlayPixelFormat::RGBA16F,
+            OverlayFormat::Rgb10A2 => OverlayPixelFormat::RGB10A2,
+        }
+    }
+}
+
+impl From<OverlayFormat> for OverlayPixelFormats {
+    fn from(format: OverlayFormat) -> Self {
+        match format {
+            OverlayFormat::Bgra8 => OverlayPixelFormats::BGRA8,
+            OverlayFormat::Rgba8 => OverlayPixelFormats::RGBA8,
+            OverlayFormat::Rgba16Float => OverlayPixelFormats::RGBA16F,
+            OverlayFormat::Rgb10A2 => OverlayPixelFormats::RGB10A2,
+        }
+    }
+}
+
+/// How colour is encoded in a texture or framebuffer.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum OverlayEncoding {
+    /// sRGB primaries and transfer function, as SDR content is.
+    Srgb,
+    /// Linear values with sRGB primaries, where 1.0 is SDR white, as in scRGB.
+    Linear,
+    /// BT.2020 primaries with the PQ transfer function, as HDR10 is.
+    Hdr10,
+}
+
+impl OverlayEncoding {
+    /// The encoding a texture in `format` was announced in.
+    ///
+    /// A texture announced without a colorspace is linear if it holds floating point
+    /// values, and sRGB otherwise.
+    pub const fn announced(
+        colorspace: OverlayColorspace,
+        format: OverlayFormat,
+    ) -> OverlayEncoding {
+        match (colorspace, format) {
+            (OverlayColorspace::SRGB, _) => OverlayEncoding::Srgb,
+            (OverlayColorspace::LINEAR, _) => OverlayEncoding::Linear,
+            (OverlayColorspace::HDR10, _) => OverlayEncoding::Hdr10,
+            (_, OverlayFormat::Rgba16Float) => OverlayEncoding::Linear,
+            _ => OverlayEncoding::Srgb,
+        }
+    }
+}
+
+impl From<OverlayEncoding> for OverlayColorspace {
+    fn from(encoding: OverlayEncoding) -> Self {
+        match encoding {
+            OverlayEncoding::Srgb => OverlayColorspace::SRGB,
+            OverlayEncoding::Linear => OverlayColorspace::LINEAR,
+            OverlayEncoding::Hdr10 => OverlayColorspace::HDR10,
+        }
+    }
+}
+
+/// The framebuffer the overlay is composited onto.
+///
+/// It is reported to the orchestrator along with the formats the texture can be imported in,
+/// so that the texture can be rendered in a matching format and colorspace.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct OverlayOutput {
+    /// The format of the framebuffer, if it is one the texture could be exported in.
+    pub format: Option<OverlayFormat>,
+    pub encoding: OverlayEncoding,
+    /// Whether the framebuffer encodes linear colour to sRGB as it is written.
+    pub srgb_writes: bool,
+}
+
+impl Default for OverlayOutput {
+    fn default() -> Self {
+        OverlayOutput {
+            format: None,
+            encoding: OverlayEncoding::Srgb,
+            srgb_writes: false,
+        }
+    }
+}
+
+impl OverlayOutput {
+    /// The encoding the composite pass writes colour in.
+    #[inline]
+    pub const fn written(&self) -> OverlayEncoding {
+        match self.encoding {
+            OverlayEncoding::Srgb if self.srgb_writes => OverlayEncoding::Linear,
+            encoding => encoding,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [OverlayFormat; 4] = [
+        OverlayFormat::Bgra8,
+        OverlayFormat::Rgba8,
+        OverlayFormat::Rgba16Float,
+        OverlayFormat::Rgb10A2,
+    ];
+
+    #[test]
+    fn formats_round_trip() {
+        for format in FORMATS {
+            let announced = OverlayFormat::announced(format.into()).unwrap();
+            assert_eq!(announced, format);
+            assert!(OverlayPixelFormats::ALL.contains(format.into()));
+        }
+        assert_eq!(
+            OverlayFormat::announced(OverlayPixelFormat::UNSPECIFIED).unwrap(),
+            OverlayFormat::Bgra8
+        );
+        assert_eq!(OverlayFormat::Rgba16Float.texel_size(), 8);
+        assert_eq!(OverlayFormat::Rgb10A2.texel_size(), 4);
+    }
+
+    #[test]
+    fn encodings_round_trip() {
+        for encoding in [
+            OverlayEncoding::Srgb,
+            OverlayEncoding::Linear,
+            OverlayEncoding::Hdr10,
+        ] {
+            for format in FORMATS {
+                assert_eq!(
+                    OverlayEncoding::announced(encoding.into(), format),
+                    encoding
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unspecified_encoding_follows_format() {
+        let unspecified =
+            |format| OverlayEncoding::announced(OverlayColorspace::UNSPECIFIED, format);
+        assert_eq!(
+            unspecified(OverlayFormat::Rgba16Float),
+            OverlayEncoding::Linear
+        );
+        assert_eq!(unspecified(OverlayFormat::Bgra8), OverlayEncoding::Srgb);
+        assert_eq!(unspecified(OverlayFormat::Rgba8), OverlayEncoding::Srgb);
+        assert_eq!(unspecified(OverlayFormat::Rgb10A2), OverlayEncoding::Srgb);
+    }
+
+    #[test]
+    fn srgb_writes_are_linear() {
+        let output = |encoding, srgb_writes| OverlayOutput {
+            format: Some(OverlayFormat::Bgra8),
+            encoding,
+            srgb_writes,
+        };
+        assert_eq!(OverlayOutput::default().written(), OverlayEncoding::Srgb);
+        assert_eq!(
+            output(OverlayEncoding::Srgb, true).written(),
+            OverlayEncoding::Linear
+        );
+        assert_eq!(
+            output(OverlayEncoding::Srgb, false).written(),
+            OverlayEncoding::Srgb
+        );
+        // Only sRGB framebuffers encode colour as it is written.
+        assert_eq!(
+            output(OverlayEncoding::Hdr10, true).written(),
+            OverlayEncoding::Hdr10
+        );
+        assert_eq!(
+            output(OverlayEncoding::Linear, true).written(),
+            OverlayEncoding::Linear
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use crate::common::Dimensions;
+use crate::ipc::cmd::OverlayDamageRect;
+
+/// The most rectangles damage is tracked in, beyond which they are merged into the
+/// rectangle bounding them.
+const MAX_DAMAGE_RECTS: usize = 16;
+
+/// A rectangle of a texture, in texels from its top left corner.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct DamageRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl DamageRect {
+    /// The rectangle covering the whole of a texture of `dimensions` size.
+    pub fn full(dimensions: Dimensions) -> DamageRect {
+        DamageRect {
+            x: 0,
+            y: 0,
+            width: dimensions.width,
+            height: dimensions.height,
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// The number of texels in the rectangle.
+    #[inline]
+    pub fn area(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    /// The part of the rectangle within a texture of `dimensions` size.
+    fn clip(&self, dimensions: Dimensions) -> DamageRect {
+        let x = self.x.min(dimensions.width);
+        let y = self.y.min(dimensions.height);
+        DamageRect {
+            x,
+            y,
+            width: self.width.min(dimensions.width - x),
+            height: self.height.min(dimensions.height - y),
+        }
+    }
+
+    fn bounds(&self, other: &DamageRect) -> DamageRect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        DamageRect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+impl From<&OverlayDamageRect> for DamageRect {
+    fn from(rect: &OverlayDamageRect) -> Self {
+        DamageRect {
+            x: rect.x as u32,
+            y: rect.y as u32,
+            width: rect.width as u32,
+            height: rect.height as u32,
+        }
+    }
+}
+
+/// The texels of a texture that changed since the frame last transferred out of it.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum OverlayDamage {
+    /// Any texel may have changed, so the whole texture is transferred.
+    Full,
+    /// Only the texels within these rectangles changed.
+    Rects(Vec<DamageRect>),
+}
+
+impl OverlayDamage {
+    /// Nothing changed.
+    pub const NONE: OverlayDamage = OverlayDamage::Rects(Vec::new());
+
+    /// The damage announced by the orchestrator, where `None` marks the whole frame.
+    pub fn announced(rects: Option<&[OverlayDamageRect]>) -> OverlayDamage {
+        let Some(rects) = rects else {
+            return OverlayDamage::Full;
+        };
+        let mut damage = OverlayDamage::NONE;
+        for rect in rects {
+            damage.add_rect(rect.into());
+        }
+        damage
+    }
+
+    /// Add the texels changed by a later frame.
+    pub fn add(&mut self, other: &OverlayDamage) {
+        match other {
+            OverlayDamage::Full => *self = OverlayDamage::Full,
+            OverlayDamage::Rects(rects) => {
+                for rect in rects {
+                    self.add_rect(*rect);
+                }
+            }
+        }
+    }
+
+    fn add_rect(&mut self, rect: DamageRect) {
+        let OverlayDamage::Rects(rects) = self else {
+            return;
+        };
+        if rect.is_empty() {
+            return;
+        }
+        if rects.len() == MAX_DAMAGE_RECTS {
+            let bounds = rects
+                .drain(..)
+                .fold(rect, |bounds, rect| bounds.bounds(&rect));
+            rects.push(bounds);
+        } else {
+            rects.push(rect);
+        }
+    }
+
+    /// The rectangles to transfer out of a texture of `dimensions` size, clipped to it.
+    pub fn rects(&self, dimensions: Dimensions) -> Vec<DamageRect> {
+        match self {
+            OverlayDamage::Full => vec![DamageRect::full(dimensions)],
+            OverlayDamage::Rects(rects) => rects
+                .iter()
+                .map(|rect| rect.clip(dimensions))
+                .filter(|rect| !rect.is_empty())
+                .collect(),
+        }
+    }
+}
+
+/// The bytes of a frame transferred into the texture that is painted, out of the bytes
+/// the whole frame would have taken.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub struct OverlayTransfer {
+    pub transferred: u64,
+    pub full: u64,
+}
+
+impl OverlayTransfer {
+    /// The transfer of the `rects` of a frame of `dimensions` size, in texels of
+    /// `texel_size` bytes.
+    pub fn new(rects: &[DamageRect], dimensions: Dimensions, texel_size: u32) -> OverlayTransfer {
+        let texels: u64 = rects.iter().map(DamageRect::area).sum();
+        OverlayTransfer {
+            transferred: texels * texel_size as u64,
+            full: DamageRect::full(dimensions).area() * texel_size as u64,
+        }
+    }
+
+    /// The bytes left out of the transfer, because they did not change.
+    #[inline]
+    pub fn saved(&self) -> u64 {
+        self.full.saturating_sub(self.transferred)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> DamageRect {
+        DamageRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn rects_are_kept_until_full() {
+        let mut damage = OverlayDamage::NONE;
+        for i in 0..MAX_DAMAGE_RECTS as u32 {
+            damage.add(&OverlayDamage::Rects(vec![rect(i * 10, i, 2, 2)]));
+        }
+        let dimensions = Dimensions::new(1000, 1000);
+        assert_eq!(damage.rects(dimensions).len(), MAX_DAMAGE_RECTS);
+        assert_eq!(damage.rects(dimensions)[3], rect(30, 3, 2, 2));
+    }
+
+    #[test]
+    fn overflow_merges_into_bounds() {
+        let mut damage = OverlayDamage::NONE;
+        for i in 0..MAX_DAMAGE_RECTS as u32 {
+            damage.add(&OverlayDamage::Rects(vec![rect(10 + i * 10, 20 + i, 2, 2)]));
+        }
+        damage.add(&OverlayDamage::Rects(vec![rect(5, 500, 1, 1)]));
+
+        let last = 10 + (MAX_DAMAGE_RECTS as u32 - 1) * 10;
+        assert_eq!(
+            damage,
+            OverlayDamage::Rects(vec![rect(5, 20, last + 2 - 5, 481)])
+        );
+
+        // The merged rectangle makes room for the rectangles added after it.
+        damage.add(&OverlayDamage::Rects(vec![rect(0, 0, 1, 1)]));
+        assert_eq!(damage.rects(Dimensions::new(1000, 1000)).len(), 2);
+    }
+
+    #[test]
+    fn full_absorbs_rects() {
+        let mut damage = OverlayDamage::Rects(vec![rect(1, 1, 1, 1)]);
+        damage.add(&OverlayDamage::Full);
+        assert_eq!(damage, OverlayDamage::Full);
+
+        damage.add(&OverlayDamage::Rects(vec![rect(2, 2, 2, 2)]));
+        assert_eq!(damage, OverlayDamage::Full);
+        assert_eq!(
+            damage.rects(Dimensions::new(64, 32)),
+            vec![rect(0, 0, 64, 32)]
+        );
+    }
+
+    #[test]
+    fn announced_damage() {
+        assert_eq!(OverlayDamage::announced(None), OverlayDamage::Full);
+
+        let announced = [
+            OverlayDamageRect {
+                x: 1,
+                y: 2,
+                width: 3,
+                height: 4,
+            },
+            OverlayDamageRect::default(),
+        ];
+        assert_eq!(
+            OverlayDamage::announced(Some(&announced)),
+            OverlayDamage::Rects(vec![rect(1, 2, 3, 4)])
+        );
+    }
+
+    #[test]
+    fn rects_are_clipped() {
+        let damage = OverlayDamage::Rects(vec![
+            rect(60, 30, 10, 10),
+            rect(100, 0, 5, 5),
+            rect(0, 0, 4, 4),
+        ]);
+        assert_eq!(
+            damage.rects(Dimensions::new(64, 32)),
+            vec![rect(60, 30, 4, 2), rect(0, 0, 4, 4)]
+        );
+    }
+
+    #[test]
+    fn transfer_counts_saved_bytes() {
+        let dimensions = Dimensions::new(64, 32);
+        let transfer = OverlayTransfer::new(&[rect(0, 0, 4, 4), rect(8, 8, 2, 2)], dimensions, 4);
+        assert_eq!(transfer.transferred, 20 * 4);
+        assert_eq!(transfer.full, 64 * 32 * 4);
+        assert_eq!(transfer.saved(), (64 * 32 - 20) * 4);
+
+        let whole = OverlayTransfer::new(&OverlayDamage::Full.rects(dimensions), dimensions, 4);
+        assert_eq!(whole.saved(), 0);
+    }
+}
@@ -0,0 +1,555 @@
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+
+use crate::common::Dimensions;
+use crate::ipc::cmd::{OverlayDamageRect, OverlayFailureReason, OverlaySharedMemoryEventParams};
+use crate::overlay::OverlayDamage;
+use crate::platform::handle::HandleError;
+use crate::platform::shm::SharedMapping;
+
+/// Identifies the start of a shared frame ring, `SNFR` in little endian.
+pub const SHARED_FRAMES_MAGIC: u32 = u32::from_le_bytes(*b"SNFR");
+pub const SHARED_FRAMES_VERSION: u32 = 2;
+
+/// The first version whose slots carry the damage of their frame.
+const DAMAGE_VERSION: u32 = 2;
+
+/// The size of the ring header, which the first slot follows.
+const HEADER_SIZE: usize = 64;
+
+/// The size of the header at the start of every slot, which its pixels follow.
+const SLOT_HEADER_SIZE: usize = 64;
+
+/// How many times a frame is read again after it was overwritten while being read.
+const READ_ATTEMPTS: usize = 4;
+
+/// The most damage rectangles a slot carries, as many as fit in the rest of the slot header.
+///
+/// This is one more than [`OVERLAY_DAMAGE_RECTS`](crate::ipc::cmd::OVERLAY_DAMAGE_RECTS),
+/// which is limited by the size of a command rather than of a slot header.
+const SLOT_DAMAGE_RECTS: usize = 5;
+
+/// The header at the start of the shared memory.
+#[repr(C)]
+struct RingHeader {
+    magic: u32,
+    version: u32,
+    width: u32,
+    height: u32,
+    /// The number of bytes between rows of pixels, at least `width * 4`.
+    row_pitch: u32,
+    slot_count: u32,
+    /// The number of bytes between slots, including the slot header.
+    slot_size: u64,
+    /// The number of the latest complete frame, or 0 if no frame has been written.
+    latest: AtomicU64,
+}
+
+/// The header at the start of every slot.
+#[repr(C)]
+struct SlotHeader {
+    /// Incremented before and after the slot is written, so that it is odd while it is.
+    sequence: AtomicU64,
+    /// The number of the frame written to the slot.
+    frame: AtomicU64,
+    /// The number of rectangles in `damage` that cover every pixel that changed since
+    /// the frame before, or 0 if the whole frame did.
+    damage_count: u32,
+    reserved: u32,
+    damage: [OverlayDamageRect; SLOT_DAMAGE_RECTS],
+}
+
+static_assertions::const_assert!(std::mem::size_of::<RingHeader>() <= HEADER_SIZE);
+static_assertions::const_assert!(std::mem::size_of::<SlotHeader>() <= SLOT_HEADER_SIZE);
+static_assertions::const_assert!(
+    std::mem::size_of::<SlotHeader>() + std::mem::size_of::<OverlayDamageRect>()
+        > SLOT_HEADER_SIZE
+);
+
+#[derive(thiserror::Error, Debug)]
+pub enum SharedFramesError {
+    #[error("{0}")]
+    Handle(#[from] HandleError),
+    #[error("Invalid shared frame ring: {0}")]
+    InvalidLayout(&'static str),
+}
+
+impl SharedFramesError {
+    pub fn reason(&self) -> OverlayFailureReason {
+        match self {
+            SharedFramesError::Handle(_) => OverlayFailureReason::HANDLE_DUPLICATE,
+            SharedFramesError::InvalidLayout(_) => OverlayFailureReason::IMPORT,
+        }
+    }
+}
+
+/// A complete frame read from the ring, in BGRA.
+pub struct SharedFrame<'a> {
+    pub pixels: &'a [u8],
+    pub dimensions: Dimensions,
+    pub row_pitch: u32,
+    /// The pixels that changed since the frame read before this one.
+    pub damage: OverlayDamage,
+}
+
+/// A ring of frames the orchestrator writes into shared memory, when the overlay texture
+/// can not be shared with the graphics API.
+///
+/// Frame `n` is written to slot `n % slot_count`, guarded by the slot's sequence in the
+/// manner of a seqlock, after which `latest` is set to `n`. Only the latest frame is read,
+/// and a frame that was overwritten while it was read is discarded.
+///
+/// Since version 2, a slot also carries the rectangles of its frame that changed since the
+/// frame before. As long as the slots of the frames since the last frame read have not been
+/// overwritten, only the rectangles those frames changed are copied out of the latest slot.
+pub struct SharedFrames {
+    mapping: SharedMapping,
+    dimensions: Dimensions,
+    row_pitch: u32,
+    slot_count: u64,
+    slot_size: u64,
+    /// Whether slots carry the damage of their frame.
+    damage: bool,
+    /// The number of the frame in `pixels`, or 0 if none has been read.
+    frame: u64,
+    pixels: Vec<u8>,
+}
+
+impl SharedFrames {
+    /// Map the ring announced with `params`, and check that it is laid out as announced.
+    pub fn open(
+        params: &OverlaySharedMemoryEventParams,
+    ) -> Result<SharedFrames, SharedFramesError> {
+        let mapping = SharedMapping::open(params)?;
+        if mapping.size() < HEADER_SIZE {
+            return Err(SharedFramesError::InvalidLayout("the mapping is too small"));
+        }
+
+        let header = unsafe { &*(mapping.as_ptr() as *const RingHeader) };
+        if header.magic != SHARED_FRAMES_MAGIC
+            || !(1..=SHARED_FRAMES_VERSION).contains(&header.version)
+        {
+            return Err(SharedFramesError::InvalidLayout("unknown magic or version"));
+        }
+        if header.width != params.width || header.height != params.height {
+            return Err(SharedFramesError::InvalidLayout(
+                "the frame size does not match the announced size",
+            ));
+        }
+        if header.row_pitch % 4 != 0 || header.row_pitch / 4 < header.width {
+            return Err(SharedFramesError::InvalidLayout("invalid row pitch"));
+        }
+
+        let frame_size = header.row_pitch as u64 * header.height as u64;
+        let ring_size = (header.slot_count as u64)
+            .checked_mul(header.slot_size)
+            .and_then(|size| size.checked_add(HEADER_SIZE as u64));
+        if header.slot_count == 0
+            || header.slot_size % 8 != 0
+            || header.slot_size < SLOT_HEADER_SIZE as u64 + frame_size
+            || ring_size.map_or(true, |size| size > mapping.size() as u64)
+        {
+            return Err(SharedFramesError::InvalidLayout(
+                "the slots do not fit in the mapping",
+            ));
+        }
+
+        Ok(SharedFrames {
+            dimensions: Dimensions::new(header.width, header.height),
+            row_pitch: header.row_pitch,
+            slot_count: header.slot_count as u64,
+            slot_size: header.slot_size,
+            damage: header.version >= DAMAGE_VERSION,
+            frame: 0,
+            pixels: vec![0; frame_size as usize],
+            mapping,
+        })
+    }
+
+    #[inline]
+    pub fn dimensions(&self) -> Dimensions {
+        self.dimensions
+    }
+
+    fn header(&self) -> &RingHeader {
+        unsafe { &*(self.mapping.as_ptr() as *const RingHeader) }
+    }
+
+    /// The header and pixels of the slot frame `n` is written to.
+    fn slot(&self, n: u64) -> (*const SlotHeader, *const u8) {
+        let offset = HEADER_SIZE as u64 + (n % self.slot_count) * self.slot_size;
+        unsafe {
+            let slot = self.mapping.as_ptr().add(offset as usize);
+            (slot as *const SlotHeader, slot.add(SLOT_HEADER_SIZE))
+        }
+    }
+
+    /// The pixels the frames after the last frame read changed up to frame `latest`, which
+    /// is all of them unless the slots of those frames still hold them.
+    fn damage_since(&self, latest: u64) -> OverlayDamage {
+        if !self.damage
+            || self.frame == 0
+            || latest <= self.frame
+            || latest - self.frame > self.slot_count
+        {
+            return OverlayDamage::Full;
+        }
+
+        let mut damage = OverlayDamage::NONE;
+        for n in self.frame + 1..=latest {
+            // The slot is mapped for as long as the ring is.
+            let slot = unsafe { &*self.slot(n).0 };
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            let (count, rects) = (slot.damage_count as usize, slot.damage);
+            fence(Ordering::Acquire);
+            if sequence % 2 != 0
+                || slot.frame.load(Ordering::Relaxed) != n
+                || slot.sequence.load(Ordering::Relaxed) != sequence
+            {
+                return OverlayDamage::Full;
+            }
+            let rects = (1..=SLOT_DAMAGE_RECTS)
+                .contains(&count)
+                .then(|| &rects[..count]);
+            damage.add(&OverlayDamage::announced(rects));
+        }
+        damage
+    }
+
+    /// Read the latest complete frame, if it has not been read already.
+    ///
+    /// If `reread` is set, the last frame read is returned again when there is no newer one,
+    /// for when the texture it was uploaded into was lost, and the frame returned is damaged
+    /// whole.
+    pub fn read(&mut self, reread: bool) -> Option<SharedFrame<'_>> {
+        for _ in 0..READ_ATTEMPTS {
+            let latest = self.header().latest.load(Ordering::Acquire);
+            if latest == 0 {
+                return None;
+            }
+            if latest == self.frame {
+                break;
+            }
+
+            let (slot, pixels) = self.slot(latest);
+            // The slot is mapped for as long as the ring is.
+            let slot = unsafe { &*slot };
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            if sequence % 2 != 0 || slot.frame.load(Ordering::Relaxed) != latest {
+                // The slot is being overwritten with a newer frame.
+                continue;
+            }
+
+            let damage = self.damage_since(latest);
+            match &damage {
+                OverlayDamage::Full => unsafe {
+                    std::ptr::copy_nonoverlapping(
+                        pixels,
+                        self.pixels.as_mut_ptr(),
+                        self.pixels.len(),
+                    );
+                },
+                OverlayDamage::Rects(_) => {
+                    for rect in damage.rects(self.dimensions) {
+                        let row = rect.width as usize * 4;
+                        for y in rect.y..rect.y + rect.height {
+                            let offset = y as usize * self.row_pitch as usize + rect.x as usize * 4;
+                            unsafe {
+                                std::ptr::copy_nonoverlapping(
+                                    pixels.add(offset),
+                                    self.pixels.as_mut_ptr().add(offset),
+                                    row,
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+            fence(Ordering::Acquire);
+            if slot.sequence.load(Ordering::Relaxed) != sequence {
+                // The pixels no longer hold a complete frame.
+                self.frame = 0;
+                continue;
+            }
+
+            self.frame = latest;
+            return Some(self.current(if reread { OverlayDamage::Full } else { damage }));
+        }
+
+        (reread && self.frame != 0).then(|| self.current(OverlayDamage::Full))
+    }
+
+    fn current(&self, damage: OverlayDamage) -> SharedFrame<'_> {
+        SharedFrame {
+            pixels: &self.pixels,
+            dimensions: self.dimensions,
+            row_pitch: self.row_pitch,
+            damage,
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+    use super::*;
+    use crate::ipc::cmd::OverlayLayerId;
+    use crate::overlay::DamageRect;
+
+    const WIDTH: u32 = 8;
+    const HEIGHT: u32 = 4;
+    const ROW_PITCH: u32 = WIDTH * 4;
+    const FRAME_SIZE: usize = (ROW_PITCH * HEIGHT) as usize;
+    const SLOTS: u64 = 3;
+    const SLOT_SIZE: u64 = (SLOT_HEADER_SIZE + FRAME_SIZE) as u64;
+    const SIZE: usize = HEADER_SIZE + (SLOTS * SLOT_SIZE) as usize;
+
+    /// The orchestrator's side of a ring, mapped writable.
+    struct Writer {
+        ptr: *mut u8,
+    }
+
+    unsafe impl Send for Writer {}
+    unsafe impl Sync for Writer {}
+
+    impl Writer {
+        /// Create a ring with a header of `magic`, and open it as the overlay would.
+        fn open(magic: u32) -> (Writer, Result<SharedFrames, SharedFramesError>) {
+            let name = b"snowflake-frames\0";
+            let fd = unsafe { libc::memfd_create(name.as_ptr() as *const libc::c_char, 0) };
+            assert!(fd >= 0, "memfd_create failed");
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            assert_eq!(
+                unsafe { libc::ftruncate(fd.as_raw_fd(), SIZE as libc::off_t) },
+                0
+            );
+
+            let ptr = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    SIZE,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    fd.as_raw_fd(),
+                    0,
+                )
+            };
+            assert_ne!(ptr, libc::MAP_FAILED);
+            unsafe {
+                std::ptr::write(
+                    ptr as *mut RingHeader,
+                    RingHeader {
+                        magic,
+                        version: SHARED_FRAMES_VERSION,
+                        width: WIDTH,
+                        height: HEIGHT,
+                        row_pitch: ROW_PITCH,
+                        slot_count: SLOTS as u32,
+                        slot_size: SLOT_SIZE,
+                        latest: AtomicU64::new(0),
+                    },
+                );
+            }
+
+            let params = OverlaySharedMemoryEventParams {
+                handle: 0,
+                source_pid: 0,
+                fd: unsafe { libc::dup(fd.as_raw_fd()) },
+                width: WIDTH,
+                height: HEIGHT,
+                size: SIZE as u64,
+                layer: OverlayLayerId::MAIN,
+            };
+            let writer = Writer {
+                ptr: ptr as *mut u8,
+            };
+            (writer, SharedFrames::open(&params))
+        }
+
+        fn ring() -> (Writer, SharedFrames) {
+            let (writer, frames) = Writer::open(SHARED_FRAMES_MAGIC);
+            (writer, frames.expect("the ring is laid out as announced"))
+        }
+
+        fn slot(&self, n: u64) -> *mut SlotHeader {
+            let offset = HEADER_SIZE + ((n % SLOTS) * SLOT_SIZE) as usize;
+            unsafe { self.ptr.add(offset) as *mut SlotHeader }
+        }
+
+        /// Start writing frame `n`, which changed the `damage` rectangles, or all of the
+        /// frame if `None`, and fill its pixels with `value`.
+        fn begin(&self, n: u64, value: u8, damage: Option<&[OverlayDamageRect]>) {
+            let slot = self.slot(n);
+            let damage = damage.unwrap_or_default();
+            unsafe {
+                (*slot).sequence.fetch_add(1, Ordering::Relaxed);
+                fence(Ordering::Release);
+                (*slot).frame.store(n, Ordering::Relaxed);
+                (*slot).damage_count = damage.len() as u32;
+                (*slot).damage[..damage.len()].copy_from_slice(damage);
+                std::ptr::write_bytes((slot as *mut u8).add(SLOT_HEADER_SIZE), value, FRAME_SIZE);
+            }
+        }
+
+        /// Complete frame `n`, and make it the latest.
+        fn finish(&self, n: u64) {
+            unsafe { (*self.slot(n)).sequence.fetch_add(1, Ordering::Release) };
+            let header = unsafe { &*(self.ptr as *const RingHeader) };
+            header.latest.store(n, Ordering::Release);
+        }
+
+        fn write(&self, n: u64, value: u8, damage: Option<&[OverlayDamageRect]>) {
+            self.begin(n, value, damage);
+            self.finish(n);
+        }
+    }
+
+    impl Drop for Writer {
+        fn drop(&mut self) {
+            unsafe { libc::munmap(self.ptr as *mut libc::c_void, SIZE) };
+        }
+    }
+
+    fn rect(x: u16, y: u16, width: u16, height: u16) -> OverlayDamageRect {
+        OverlayDamageRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The value every byte of a frame read was filled with.
+    fn filled(frame: &SharedFrame) -> Option<u8> {
+        let first = frame.pixels[0];
+        frame.pixels.iter().all(|&b| b == first).then_some(first)
+    }
+
+    #[test]
+    fn rejects_unknown_magic() {
+        let (_writer, frames) = Writer::open(u32::from_le_bytes(*b"NOPE"));
+        assert!(matches!(frames, Err(SharedFramesError::InvalidLayout(_))));
+    }
+
+    #[test]
+    fn reads_latest_frame() {
+        let (writer, mut frames) = Writer::ring();
+        assert!(frames.read(false).is_none());
+        assert!(frames.read(true).is_none());
+
+        writer.write(1, 1, None);
+        let frame = frames.read(false).expect("frame 1 is complete");
+        assert_eq!(filled(&frame), Some(1));
+        assert_eq!(frame.damage, OverlayDamage::Full);
+        assert!(frames.read(false).is_none());
+
+        // A frame read again is damaged whole.
+        let frame = frames.read(true).expect("frame 1 is read again");
+        assert_eq!(filled(&frame), Some(1));
+        assert_eq!(frame.damage, OverlayDamage::Full);
+
+        // Only the latest of the frames written since is read.
+        writer.write(2, 2, None);
+        writer.write(3, 3, None);
+        assert_eq!(filled(&frames.read(false).unwrap()), Some(3));
+        assert_eq!(frames.frame, 3);
+    }
+
+    #[test]
+    fn frame_being_overwritten_is_skipped() {
+        let (writer, mut frames) = Writer::ring();
+        writer.write(1, 1, None);
+        writer.begin(1 + SLOTS, 4, None);
+        assert!(frames.read(false).is_none());
+        assert_eq!(frames.frame, 0);
+
+        writer.finish(1 + SLOTS);
+        assert_eq!(filled(&frames.read(false).unwrap()), Some(4));
+
+        // While the slot of the latest frame is being overwritten, the frame read before
+        // is kept.
+        writer.write(5, 5, None);
+        writer.begin(5 + SLOTS, 8, None);
+        assert!(frames.read(false).is_none());
+        let frame = frames.read(true).expect("frame 4 is read again");
+        assert_eq!(filled(&frame), Some(4));
+        assert_eq!(frames.frame, 1 + SLOTS);
+    }
+
+    #[test]
+    fn copies_damage_since_last_read() {
+        let (writer, mut frames) = Writer::ring();
+        writer.write(1, 1, None);
+        frames.read(false).unwrap();
+
+        let (a, b) = (rect(0, 0, 2, 1), rect(4, 2, 3, 2));
+        writer.write(2, 2, Some(&[a]));
+        writer.write(3, 3, Some(&[b]));
+        let frame = frames.read(false).unwrap();
+        assert_eq!(
+            frame.damage,
+            OverlayDamage::Rects(vec![DamageRect::from(&a), DamageRect::from(&b)])
+        );
+
+        // Only the damaged texels are copied out of the slot of frame 3.
+        let damaged = |x: u16, y: u16, r: &OverlayDamageRect| {
+            (r.x..r.x + r.width).contains(&x) && (r.y..r.y + r.height).contains(&y)
+        };
+        for y in 0..HEIGHT as u16 {
+            for x in 0..WIDTH as u16 {
+                let texel = y as usize * ROW_PITCH as usize + x as usize * 4;
+                let expected = if damaged(x, y, &a) || damaged(x, y, &b) {
+                    3
+                } else {
+                    1
+                };
+                assert_eq!(frame.pixels[texel..texel + 4], [expected; 4], "at {x}, {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn wraparound_damages_whole_frame() {
+        let (writer, mut frames) = Writer::ring();
+        writer.write(1, 1, None);
+        frames.read(false).unwrap();
+
+        // More frames than slots were written since the last read.
+        for n in 2..=2 + SLOTS {
+            writer.write(n, n as u8, Some(&[rect(0, 0, 1, 1)]));
+        }
+        let frame = frames.read(false).unwrap();
+        assert_eq!(frame.damage, OverlayDamage::Full);
+        assert_eq!(filled(&frame), Some(2 + SLOTS as u8));
+
+        // The slot of a frame since the last read is being overwritten.
+        let last = 2 + SLOTS;
+        for n in last + 1..=last + SLOTS {
+            writer.write(n, n as u8, Some(&[rect(0, 0, 1, 1)]));
+        }
+        writer.begin(last + 1 + SLOTS, 0, Some(&[rect(0, 0, 1, 1)]));
+        let frame = frames.read(false).unwrap();
+        assert_eq!(frame.damage, OverlayDamage::Full);
+        assert_eq!(filled(&frame), Some((last + SLOTS) as u8));
+    }
+
+    #[test]
+    fn torn_reads_are_discarded() {
+        const FRAMES: u64 = 20_000;
+        let (writer, mut frames) = Writer::ring();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for n in 1..=FRAMES {
+                    writer.write(n, n as u8, None);
+                }
+            });
+
+            while frames.frame < FRAMES {
+                if let Some(frame) = frames.read(false) {
+                    let value = filled(&frame).expect("a frame read is never torn");
+                    assert_eq!(value, frames.frame as u8);
+                }
+            }
+        });
+    }
+}
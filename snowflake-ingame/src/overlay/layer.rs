@@ -0,0 +1,414 @@
+use crate::common::{Dimensions, RenderError};
+#[cfg(target_os = "linux")]
+use crate::ipc::cmd::OverlayTextureFdEventParams;
+use crate::ipc::cmd::OverlayTextureFormatEventParams;
+use crate::ipc::cmd::OverlayTextureRingEventParams;
+use crate::ipc::cmd::{GameWindowCommand, OverlayFailureReason, OverlayFrameReadyEventParams};
+use crate::ipc::cmd::{OverlayColorspace, OverlayLayerId, OverlayPixelFormat};
+use crate::ipc::cmd::{OverlaySharedMemoryEventParams, OverlayTextureEventParams};
+use crate::ipc::IpcHandle;
+use crate::overlay::presentation::{OverlayPresentation, OverlayStacking};
+use crate::overlay::state::{OverlayState, OverlayStatus};
+use crate::overlay::{
+    OverlayBackend, OverlayDamage, OverlayDescriptor, OverlayFormat, OverlayPlacement,
+    OverlayTransfer, SharedFrames, SharedFramesError,
+};
+use crate::platform::handle::HandleError;
+
+/// A texture of the overlay, and how it is stacked onto the viewport.
+///
+/// Every layer imports its texture with a backend of its own. Only the main layer drives
+/// the state of the overlay. Other layers keep a state of their own, so that a layer that
+/// keeps failing is logged once, and does not change the state reported to the orchestrator.
+pub(super) struct OverlayLayer<B: OverlayBackend> {
+    pub id: OverlayLayerId,
+    pub backend: B,
+    descriptor: Option<OverlayDescriptor<B::Handle>>,
+    /// The textures of the ring announced so far, in order of their index.
+    ring: Vec<OverlayDescriptor<B::Handle>>,
+    /// The number of textures in the ring, or 0 if no ring was announced.
+    ring_count: usize,
+    /// The texture of the ring holding the newest frame, and the number of that frame.
+    ready: Option<(usize, u64)>,
+    /// Whether the texture in `descriptor`, or the textures in `ring`, have been imported.
+    imported: bool,
+    /// Whether importing the texture in `descriptor`, or the textures in `ring`, failed, in
+    /// which case they are not imported again until the orchestrator announces others.
+    failed: bool,
+    frames: Option<SharedFrames>,
+    /// The dimensions of the newest texture announced.
+    pub dimensions: Dimensions,
+    /// The dimensions of the texture that is painted, which lag `dimensions` until
+    /// the newest texture has been imported.
+    painted: Dimensions,
+    /// How the orchestrator asked for the texture to be placed on the layer.
+    pub presentation: OverlayPresentation,
+    pub stacking: OverlayStacking,
+    /// Where the texture is painted, as last reported to the orchestrator.
+    pub placement: Option<OverlayPlacement>,
+    /// The state of the layer's pipeline, which is the state of the overlay for the main layer.
+    pub state: OverlayStatus,
+    /// The status of the overlay, which frames transferred into the layer are counted in.
+    status: OverlayStatus,
+}
+
+impl<B: OverlayBackend> OverlayLayer<B> {
+    pub fn new(id: OverlayLayerId, backend: B, status: OverlayStatus) -> OverlayLayer<B> {
+        OverlayLayer {
+            id,
+            backend,
+            descriptor: None,
+            ring: Vec::new(),
+            ring_count: 0,
+            ready: None,
+            imported: false,
+            failed: false,
+            frames: None,
+            dimensions: Dimensions::new(0, 0),
+            painted: Dimensions::new(0, 0),
+            presentation: OverlayPresentation::default(),
+            stacking: OverlayStacking::default(),
+            placement: None,
+            state: if id == OverlayLayerId::MAIN {
+                status.clone()
+            } else {
+                OverlayStatus::new(B::NAME)
+            },
+            status,
+        }
+    }
+
+    #[inline]
+    pub fn ready_to_initialize(&self) -> bool {
+        self.descriptor.is_some() || self.frames.is_some() || self.ring_ready()
+    }
+
+    /// Whether every texture of the ring has been announced.
+    #[inline]
+    fn ring_ready(&self) -> bool {
+        self.ring_count != 0 && self.ring.len() == self.ring_count
+    }
+
+    /// Whether the layer has a texture placed on the viewport that is composited.
+    #[inline]
+    pub fn paintable(&self) -> bool {
+        self.stacking.visible && self.placement.is_some()
+    }
+
+    /// Replace the texture with one announced in `format`.
+    #[must_use]
+    pub fn refresh(
+        &mut self,
+        params: OverlayTextureEventParams,
+        format: OverlayTextureFormatEventParams,
+    ) -> Result<(), HandleError> {
+        let duped_handle = self
+            .backend
+            .duplicate_handle(&params, format.format)
+            .map_err(|e| {
+                self.state.lost(OverlayFailureReason::HANDLE_DUPLICATE, &e);
+                e
+            })?;
+
+        self.replace_descriptor(OverlayDescriptor {
+            handle: duped_handle,
+            dimensions: Dimensions::new(params.width, params.height),
+            size: params.size,
+            format: format.format,
+            colorspace: format.colorspace,
+        })
+    }
+
+    /// Replace the texture with one exported as file descriptors.
+    #[cfg(target_os = "linux")]
+    #[must_use]
+    pub fn refresh_fd(&mut self, params: OverlayTextureFdEventParams) -> Result<(), HandleError> {
+        let handle = self.backend.adopt_fds(&params).map_err(|e| {
+            self.state.lost(OverlayFailureReason::HANDLE_DUPLICATE, &e);
+            e
+        })?;
+
+        self.replace_descriptor(OverlayDescriptor {
+            handle,
+            dimensions: Dimensions::new(params.width, params.height),
+            size: params.size as u64,
+            format: OverlayPixelFormat::UNSPECIFIED,
+            colorspace: OverlayColorspace::UNSPECIFIED,
+        })
+    }
+
+    /// Add a texture to the ring of textures, replacing the texture with a new ring
+    /// if it is the first.
+    #[must_use]
+    pub fn refresh_ring(
+        &mut self,
+        params: OverlayTextureRingEventParams,
+    ) -> Result<(), HandleError> {
+        let duped_handle = self
+            .backend
+            .duplicate_handle(&params.texture(), params.format)
+            .map_err(|e| {
+                self.state.lost(OverlayFailureReason::HANDLE_DUPLICATE, &e);
+                e
+            })?;
+
+        let (index, count) = (params.index as usize, params.count as usize);
+        if index == 0 {
+            // The texture on screen is kept until the new ring has been imported.
+            self.frames = None;
+            self.release_ring();
+            self.ring_count = count;
+            self.dimensions = Dimensions::new(params.width, params.height);
+        }
+
+        if count == 0
+            || count != self.ring_count
+            || index != self.ring.len()
+            || self.dimensions != Dimensions::new(params.width, params.height)
+            || self.ring.first().map_or(false, |first| {
+                (first.format, first.colorspace) != (params.format, params.colorspace)
+            })
+        {
+            self.state.lost(
+                OverlayFailureReason::IMPORT,
+                format_args!("texture {} of {} does not belong to the ring", index, count),
+            );
+            return self.backend.close_handle(duped_handle);
+        }
+
+        self.ring.push(OverlayDescriptor {
+            handle: duped_handle,
+            dimensions: Dimensions::new(params.width, params.height),
+            size: params.size,
+            format: params.format,
+            colorspace: params.colorspace,
+        });
+        if self.ring_ready() {
+            self.imported = false;
+            self.failed = false;
+            self.state.transition(OverlayState::HandleDuplicated);
+            if let Some(descriptor) = self.descriptor.take() {
+                return self.backend.close_handle(descriptor.handle);
+            }
+        }
+        Ok(())
+    }
+
+    /// Mark the texture of the ring a frame was written into as the one to paint,
+    /// unless a newer frame is already ready.
+    ///
+    /// A single texture is announced as texture 0 of a ring of one, so that backends which
+    /// synchronize with semaphores only wait for frames the orchestrator has signalled.
+    ///
+    /// The damage of a frame is relative to the frame before it, so a frame that does not
+    /// follow the newest one damages the whole texture.
+    pub fn frame_ready(&mut self, params: OverlayFrameReadyEventParams) {
+        let (index, frame) = (params.index as usize, params.frame);
+        let count = match (self.ring_count, &self.descriptor) {
+            (0, Some(_)) => 1,
+            (count, _) => count,
+        };
+        if index >= count || self.ready.map_or(false, |(_, newest)| frame <= newest) {
+            return;
+        }
+        let damage = match self.ready {
+            Some((_, newest)) if frame == newest + 1 => OverlayDamage::announced(params.damage()),
+            _ => OverlayDamage::Full,
+        };
+        self.ready = Some((index, frame));
+        self.backend.select(index, &damage);
+    }
+
+    /// Replace the texture with frames uploaded from a ring in shared memory.
+    ///
+    /// Unlike a texture, this drops the imported texture right away, since backends paint
+    /// an imported texture in favour of uploaded frames.
+    #[must_use]
+    pub fn refresh_shared_memory(
+        &mut self,
+        params: OverlaySharedMemoryEventParams,
+    ) -> Result<(), SharedFramesError> {
+        let frames = SharedFrames::open(&params).map_err(|e| {
+            self.state.lost(e.reason(), &e);
+            e
+        })?;
+
+        self.release();
+        self.dimensions = frames.dimensions();
+        self.frames = Some(frames);
+        self.state.transition(OverlayState::HandleDuplicated);
+        Ok(())
+    }
+
+    fn replace_descriptor(
+        &mut self,
+        descriptor: OverlayDescriptor<B::Handle>,
+    ) -> Result<(), HandleError> {
+        // The texture on screen is kept until the new one has been imported, which does not
+        // need the handle of the old one.
+        self.frames = None;
+        self.release_ring();
+
+        if let Some(descriptor) = self.descriptor.take() {
+            self.backend.close_handle(descriptor.handle)?;
+        }
+
+        self.dimensions = descriptor.dimensions;
+        self.descriptor = Some(descriptor);
+        self.imported = false;
+        self.failed = false;
+        self.state.transition(OverlayState::HandleDuplicated);
+        Ok(())
+    }
+
+    pub fn release(&mut self) {
+        self.backend.invalidate();
+        self.frames = None;
+        self.release_ring();
+        self.imported = false;
+        self.failed = false;
+        self.placement = None;
+        if let Some(descriptor) = self.descriptor.take() {
+            self.backend
+                .close_handle(descriptor.handle)
+                .unwrap_or_else(|e| eprintln!("[{}] handle error: {}", B::NAME, e));
+        }
+    }
+
+    fn release_ring(&mut self) {
+        for descriptor in self.ring.drain(..) {
+            self.backend
+                .close_handle(descriptor.handle)
+                .unwrap_or_else(|e| eprintln!("[{}] handle error: {}", B::NAME, e));
+        }
+        self.ring_count = 0;
+        self.ready = None;
+    }
+
+    #[must_use]
+    pub fn prepare_paint(&mut self, target: B::Target<'_>) -> Result<(), RenderError> {
+        if let Some(frames) = &mut self.frames {
+            // A new frame is uploaded whenever there is one, and the last frame is uploaded
+            // again if the texture it was uploaded into can not be painted onto `target`.
+            let lost = !self.backend.ready_to_paint(&target);
+            if lost {
+                self.backend.invalidate();
+            }
+
+            match frames.read(lost) {
+                Some(frame) => {
+                    let rects = frame.damage.rects(frame.dimensions);
+                    self.dimensions = self.backend.upload(&frame, target).map_err(|e| {
+                        // The next frame has to be uploaded whole.
+                        self.backend.invalidate();
+                        e
+                    })?;
+                    self.painted = self.dimensions;
+                    // Frames in shared memory are always BGRA8.
+                    let texel_size = OverlayFormat::Bgra8.texel_size();
+                    self.status.frame_transferred(OverlayTransfer::new(
+                        &rects,
+                        frame.dimensions,
+                        texel_size,
+                    ));
+                }
+                None if lost => return Err(RenderError::OverlayFrameNotReady),
+                None => return Ok(()),
+            }
+            if lost {
+                self.state.transition(OverlayState::Imported);
+            }
+            return Ok(());
+        }
+
+        if !self.ring_ready() && self.descriptor.is_none() {
+            return Err(RenderError::OverlayHandleNotReady);
+        }
+
+        let paintable = self.backend.ready_to_paint(&target);
+        if paintable && self.imported {
+            return Ok(());
+        }
+        if self.failed {
+            // The failure was recorded when the import failed, and the previous texture
+            // is painted until the orchestrator sends another.
+            return if paintable {
+                Ok(())
+            } else {
+                Err(RenderError::OverlayHandleNotReady)
+            };
+        }
+        if !paintable {
+            // Nothing that was imported can be painted onto `target`, so there is nothing
+            // to keep on screen.
+            self.backend.invalidate();
+        }
+
+        let imported = match &self.descriptor {
+            Some(descriptor) if !self.ring_ready() => self.backend.import(descriptor, target),
+            _ => self.backend.import_ring(&self.ring, target),
+        };
+
+        match imported {
+            Ok(dimensions) => {
+                self.dimensions = dimensions;
+                self.painted = dimensions;
+                self.imported = true;
+                // A single texture only holds a frame once the next one is announced, since
+                // the one announced before may already have been taken.
+                if let (true, Some((index, _))) = (self.ring_ready(), self.ready) {
+                    self.backend.select(index, &OverlayDamage::Full);
+                }
+                self.state.transition(OverlayState::Imported);
+                Ok(())
+            }
+            Err(e) => {
+                // The texture painted is still the previous one, so the texture that failed
+                // is requested again if the viewport does not match it.
+                self.failed = true;
+                self.dimensions = self.painted;
+                if paintable {
+                    self.state
+                        .fail(&RenderError::OverlayPaintNotReady(Box::new(e)));
+                    Ok(())
+                } else {
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    /// Apply the presentation the orchestrator asked for, from the next frame.
+    pub fn present(&mut self, presentation: OverlayPresentation) {
+        if presentation.filter != self.presentation.filter {
+            self.backend.set_filter(presentation.filter);
+        }
+        self.presentation = presentation;
+    }
+
+    /// Place the painted texture within the layer on a viewport of `viewport` size, reporting
+    /// the rectangle it covers to the orchestrator if it moved, so that input keeps being
+    /// mapped to what is on screen.
+    pub fn place(&mut self, ipc: &IpcHandle, viewport: Dimensions) -> Result<(), RenderError> {
+        let placement = self
+            .stacking
+            .place(&self.presentation, self.painted, viewport);
+        if !matches!(&self.placement, Some(placed) if placed.same_rect(&placement)) {
+            let [x, y] = placement.position;
+            ipc.send(GameWindowCommand::overlay_placement(
+                self.id,
+                x,
+                y,
+                &placement.size,
+            ))?;
+        }
+        self.placement = Some(placement);
+        Ok(())
+    }
+}
+
+impl<B: OverlayBackend> Drop for OverlayLayer<B> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
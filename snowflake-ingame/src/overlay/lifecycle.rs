@@ -0,0 +1,650 @@
+use std::time::{Duration, Instant};
+
+use crate::common::{AdapterIdentity, Dimensions, RenderError};
+use crate::ipc::cmd::{GameWindowCommand, GameWindowCommandType, OverlayCapabilities};
+use crate::ipc::cmd::{OverlayLayerEventParams, OverlayLayerFlags, OverlayLayerId};
+use crate::ipc::cmd::{OverlayPixelFormat, OverlayTextureFormatEventParams};
+use crate::ipc::IpcHandle;
+use crate::overlay::layer::OverlayLayer;
+use crate::overlay::presentation::OverlayScaling;
+use crate::overlay::state::{OverlayState, OverlayStatus};
+use crate::overlay::{OverlayBackend, OverlayOutput, OverlayPaint, SyncedFrame};
+
+/// How long the viewport has to keep its size before a texture of that size is requested,
+/// so that a window being resized does not request a texture every frame.
+const RESIZE_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// How long to wait for a texture of the requested size before requesting it again.
+const RESIZE_RETRY: Duration = Duration::from_secs(1);
+
+/// Drives the lifecycle of an overlay for a single backend.
+///
+/// The overlay is a stack of layers, each owning the handle announced by the orchestrator
+/// for it, which is handed to the layer's backend for import when a frame is about to be
+/// painted. If the orchestrator renders a layer into a ring of textures, the whole ring is
+/// imported once it has been announced, and the backend is told which texture holds the
+/// newest frame. If the orchestrator writes frames to shared memory instead, the latest frame
+/// is handed to the backend to upload. Every step of the pipeline of the main layer is recorded
+/// in the overlay's [`OverlayStatus`], while other layers record theirs in a state of their own.
+///
+/// A texture that replaces one already imported is imported over it, so the previous texture
+/// is painted, placed on the viewport, until its replacement has been imported.
+///
+/// The main layer always exists and is sized to the viewport. Other layers are created when
+/// the orchestrator first announces them, and composited over or under it by their z-order.
+pub struct Overlay<B: OverlayBackend> {
+    /// The layers of the overlay, of which the first is the main layer.
+    layers: Vec<OverlayLayer<B>>,
+    /// The size of the viewport the overlay is painted onto, and when it last changed.
+    viewport: (Dimensions, Instant),
+    /// The size last requested from the orchestrator, and when.
+    requested: Option<(Dimensions, Instant)>,
+    /// The adapter last reported to the orchestrator.
+    adapter: Option<AdapterIdentity>,
+    /// The import capabilities last reported to the orchestrator.
+    capabilities: Option<OverlayCapabilities>,
+    /// The framebuffer last reported to the orchestrator along with the capabilities.
+    output: Option<OverlayOutput>,
+    /// The format and layer of the texture of the next `OVERLAY_TEXTURE`.
+    texture_format: OverlayTextureFormatEventParams,
+    /// The layer the texture of the next `OVERLAY_TEXTURE_FD` replaces.
+    #[cfg(target_os = "linux")]
+    fd_layer: OverlayLayerId,
+    status: OverlayStatus,
+}
+
+impl<B: OverlayBackend> Overlay<B> {
+    pub fn new(backend: B) -> Overlay<B> {
+        Overlay::with_status(backend, OverlayStatus::new(B::NAME))
+    }
+
+    /// Create an overlay that records its state into an existing status,
+    /// shared by every overlay a kernel owns.
+    ///
+    /// `backend` imports the texture of the main layer.
+    pub fn with_status(backend: B, status: OverlayStatus) -> Overlay<B> {
+        Overlay {
+            layers: vec![OverlayLayer::new(
+                OverlayLayerId::MAIN,
+                backend,
+                status.clone(),
+            )],
+            viewport: (Dimensions::new(0, 0), Instant::now()),
+            requested: None,
+            adapter: None,
+            capabilities: None,
+            output: None,
+            texture_format: OverlayTextureFormatEventParams::default(),
+            #[cfg(target_os = "linux")]
+            fd_layer: OverlayLayerId::MAIN,
+            status,
+        }
+    }
+
+    /// A shared handle to the state of this overlay.
+    pub fn status(&self) -> OverlayStatus {
+        self.status.clone()
+    }
+
+    /// The backends of every layer.
+    pub fn backends_mut(&mut self) -> impl Iterator<Item = &mut B> {
+        self.layers.iter_mut().map(|layer| &mut layer.backend)
+    }
+
+    #[inline]
+    fn main(&self) -> &OverlayLayer<B> {
+        &self.layers[0]
+    }
+
+    /// The layer `id`, which is created if it does not exist yet.
+    fn layer_mut(&mut self, id: OverlayLayerId) -> &mut OverlayLayer<B> {
+        let index = match self.layers.iter().position(|layer| layer.id == id) {
+            Some(index) => index,
+            None => {
+                eprintln!("[{}] creating overlay layer {:?}", B::NAME, id);
+                self.layers
+                    .push(OverlayLayer::new(id, B::default(), self.status.clone()));
+                self.layers.len() - 1
+            }
+        };
+        &mut self.layers[index]
+    }
+
+    /// Whether any layer has a texture to import or upload.
+    #[inline]
+    pub fn ready_to_initialize(&self) -> bool {
+        self.layers.iter().any(OverlayLayer::ready_to_initialize)
+    }
+
+    #[inline]
+    pub fn size_matches_viewpoint(&self, size: &Dimensions) -> bool {
+        self.main().dimensions == *size
+    }
+
+    /// Drop the imported textures and their handles, and every layer but the main one, so that
+    /// a new texture is requested from the orchestrator the next time the overlay is prepared.
+    pub fn reset(&mut self) {
+        self.layers.truncate(1);
+        self.layers[0].release();
+        self.layers[0].dimensions = Dimensions::new(0, 0);
+        self.requested = None;
+        // Another backend may have reported its adapter and capabilities while this one
+        // did not own the window.
+        self.adapter = None;
+        self.capabilities = None;
+        self.output = None;
+        self.status.transition(OverlayState::AwaitingTexture);
+    }
+
+    /// Handle an overlay event received from the orchestrator.
+    pub fn handle_command(&mut self, cmd: &GameWindowCommand) {
+        match cmd.ty {
+            GameWindowCommandType::OVERLAY_TEXTURE => {
+                let params = unsafe { cmd.params.overlay_event };
+                eprintln!("[{}] received overlay texture event", B::NAME);
+                let format = std::mem::take(&mut self.texture_format);
+                // Failures are also recorded in the overlay status.
+                if let Err(e) = self.layer_mut(format.layer).refresh(params, format) {
+                    eprintln!("[{}] handle error: {}", B::NAME, e);
+                }
+            }
+            GameWindowCommandType::OVERLAY_TEXTURE_FORMAT => {
+                self.texture_format = unsafe { cmd.params.overlay_format_event };
+            }
+            #[cfg(target_os = "linux")]
+            GameWindowCommandType::OVERLAY_TEXTURE_FD_LAYER => {
+                self.fd_layer = unsafe { cmd.params.overlay_fd_layer_event }.layer;
+            }
+            #[cfg(target_os = "linux")]
+            GameWindowCommandType::OVERLAY_TEXTURE_FD => {
+                eprintln!("[{}] received overlay texture fd event", B::NAME);
+                let params = unsafe { cmd.params.overlay_fd_event };
+                let layer = std::mem::replace(&mut self.fd_layer, OverlayLayerId::MAIN);
+                if let Err(e) = self.layer_mut(layer).refresh_fd(params) {
+                    eprintln!("[{}] handle error: {}", B::NAME, e);
+                }
+            }
+            GameWindowCommandType::OVERLAY_TEXTURE_RING => {
+                let params = unsafe { cmd.params.overlay_ring_event };
+                eprintln!("[{}] received overlay texture ring event", B::NAME);
+                if let Err(e) = self.layer_mut(params.layer).refresh_ring(params) {
+                    eprintln!("[{}] handle error: {}", B::NAME, e);
+                }
+            }
+            GameWindowCommandType::OVERLAY_FRAME_READY => {
+                let params = unsafe { cmd.params.overlay_frame_ready_event };
+                let layer = params.layer;
+                if let Some(layer) = self.layers.iter_mut().find(|l| l.id == layer) {
+                    layer.frame_ready(params);
+                }
+            }
+            GameWindowCommandType::OVERLAY_PRESENTATION => {
+                let params = unsafe { cmd.params.overlay_presentation_event };
+                self.layer_mut(params.layer).present(params.into());
+            }
+            GameWindowCommandType::OVERLAY_SHARED_MEMORY => {
+                let params = unsafe { cmd.params.overlay_shared_memory_event };
+                eprintln!("[{}] received overlay shared memory event", B::NAME);
+                if let Err(e) = self.layer_mut(params.layer).refresh_shared_memory(params) {
+                    eprintln!("[{}] handle error: {}", B::NAME, e);
+                }
+            }
+            GameWindowCommandType::OVERLAY_LAYER => {
+                self.stack(unsafe { cmd.params.overlay_layer_event });
+            }
+            _ => {}
+        }
+    }
+
+    /// Stack a layer onto the viewport as the orchestrator asked for, or remove it.
+    fn stack(&mut self, params: OverlayLayerEventParams) {
+        let id = params.layer;
+        if params.flags.contains(OverlayLayerFlags::REMOVE) && id != OverlayLayerId::MAIN {
+            eprintln!("[{}] removing overlay layer {:?}", B::NAME, id);
+            self.layers.retain(|layer| layer.id != id);
+            return;
+        }
+        self.layer_mut(id).stacking = params.into();
+    }
+
+    /// Notify the orchestrator of the current viewport size, and prepare the overlay
+    /// to be painted onto `target`, whose framebuffer is described by `output`.
+    ///
+    /// Every layer with a texture is prepared, and a layer that fails is not painted. This
+    /// fails only if no layer can be painted.
+    ///
+    /// This is called by a kernel once per frame before the overlay is painted.
+    pub fn prepare_frame(
+        &mut self,
+        ipc: &IpcHandle,
+        size: Dimensions,
+        output: OverlayOutput,
+        target: B::Target<'_>,
+    ) -> Result<(), RenderError> {
+        self.status.flush(ipc)?;
+
+        let backend = &self.layers[0].backend;
+
+        // The adapter and capabilities are reported before the texture is requested,
+        // so that it is allocated on that adapter, and exported in a way it can be imported.
+        if self.adapter.is_none() {
+            if let Some(adapter) = backend.adapter(&target) {
+                ipc.send(GameWindowCommand::adapter(B::BACKEND, &adapter))?;
+                self.adapter = Some(adapter);
+            }
+        }
+
+        // The orchestrator is told when the framebuffer changes format, such as when
+        // the game switches to HDR, so that it can render the texture to match.
+        if self.capabilities.is_none() || self.output != Some(output) {
+            let capabilities = backend.capabilities(&target);
+            ipc.send(GameWindowCommand::overlay_capabilities(
+                B::BACKEND,
+                capabilities,
+                backend.formats(&target),
+                output
+                    .format
+                    .map_or(OverlayPixelFormat::UNSPECIFIED, Into::into),
+                output.encoding.into(),
+            ))?;
+            self.capabilities = Some(capabilities);
+            self.output = Some(output);
+        }
+
+        let now = Instant::now();
+        if self.viewport.0 != size {
+            self.viewport = (size, now);
+        }
+
+        if !self.size_matches_viewpoint(&size) && self.should_request(&size, now) {
+            // if the main layer is not ready to initialize then the orchestrator needs
+            // to send a handle.
+            ipc.send(GameWindowCommand::window_resize(
+                &size,
+                !self.main().ready_to_initialize(),
+            ))?;
+            self.requested = Some((size, now));
+        }
+
+        if !self.ready_to_initialize() {
+            return Err(RenderError::OverlayHandleNotReady);
+        }
+
+        let mut prepared = Ok(());
+        let mut painted = false;
+        for layer in self.layers.iter_mut() {
+            if !layer.ready_to_initialize() {
+                continue;
+            }
+            match layer.prepare_paint(target.clone()) {
+                Ok(()) => {
+                    layer.place(ipc, self.viewport.0)?;
+                    painted = true;
+                }
+                Err(e) => {
+                    let e = RenderError::OverlayPaintNotReady(Box::new(e));
+                    layer.state.fail(&e);
+                    if prepared.is_ok() {
+                        prepared = Err(e);
+                    }
+                }
+            }
+        }
+
+        if painted {
+            Ok(())
+        } else {
+            prepared
+        }
+    }
+
+    /// Whether a texture of `size` should be requested from the orchestrator for the main layer.
+    ///
+    /// Without a texture there is nothing on screen to scale, so one is requested right away.
+    /// Otherwise the viewport has to keep its size for [`RESIZE_DEBOUNCE`] first. A request
+    /// that was not answered is repeated after [`RESIZE_RETRY`], unless the texture is not
+    /// stretched over the viewport, in which case the orchestrator may keep its own size.
+    fn should_request(&self, size: &Dimensions, now: Instant) -> bool {
+        let main = self.main();
+        if main.ready_to_initialize() && now.duration_since(self.viewport.1) < RESIZE_DEBOUNCE {
+            return false;
+        }
+        match self.requested {
+            Some((requested, at)) => {
+                requested != *size
+                    || (main.presentation.scaling == OverlayScaling::Stretch
+                        && now.duration_since(at) >= RESIZE_RETRY)
+            }
+            None => true,
+        }
+    }
+
+    /// Acquire the texture of every visible layer for painting, without waiting on
+    /// the orchestrator.
+    ///
+    /// A frame the orchestrator is still writing is not a failure: the last composited frame
+    /// is painted again if the backend has one, and the layer is not painted if it does not.
+    pub fn acquire_sync(&self) -> AcquiredLayers<'_, B> {
+        let mut layers: Vec<_> = self
+            .layers
+            .iter()
+            .filter(|layer| layer.paintable())
+            .collect();
+        layers.sort_by_key(|layer| (layer.stacking.z, layer.id));
+
+        let layers = layers
+            .into_iter()
+            .filter_map(|layer| {
+                let guard = layer.backend.acquire_sync();
+                if let Some(transfer) = guard.as_ref().and_then(SyncedFrame::transfer) {
+                    self.status.frame_transferred(transfer);
+                }
+                match &guard {
+                    Some(guard) if guard.is_stale() => self.status.frame_stale(),
+                    Some(_) => layer.state.transition(OverlayState::Paintable),
+                    None if layer.backend.texture_id().is_some() => self.status.frame_skipped(),
+                    None => layer.state.fail(&RenderError::OverlayMutexNotReady),
+                }
+                Some((layer, guard?))
+            })
+            .collect();
+
+        AcquiredLayers {
+            layers,
+            viewport: self.viewport.0,
+            output: self.output.unwrap_or_default(),
+        }
+    }
+}
+
+/// The layers of an overlay acquired for painting, in the order they are composited.
+///
+/// The texture of a layer may only be sampled while this is held.
+pub struct AcquiredLayers<'a, B: OverlayBackend + 'a> {
+    layers: Vec<(&'a OverlayLayer<B>, B::SyncGuard<'a>)>,
+    viewport: Dimensions,
+    output: OverlayOutput,
+}
+
+impl<'a, B: OverlayBackend> AcquiredLayers<'a, B> {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// The backend of each acquired layer, and the guard it was acquired with.
+    pub fn iter(&self) -> impl Iterator<Item = (&B, &B::SyncGuard<'a>)> {
+        self.layers
+            .iter()
+            .map(|(layer, guard)| (&layer.backend, guard))
+    }
+
+    /// Composite the texture of each layer with `f` at its placement on the viewport,
+    /// from the bottom layer up.
+    pub fn paint<F>(&self, mut f: F) -> Result<(), RenderError>
+    where
+        F: FnMut(&OverlayPaint) -> Result<(), RenderError>,
+    {
+        for (layer, _) in &self.layers {
+            if let (Some(texture), Some(placement)) = (layer.backend.texture_id(), layer.placement)
+            {
+                f(&OverlayPaint {
+                    texture,
+                    texels: layer.backend.texels(),
+                    placement,
+                    filter: layer.presentation.filter,
+                    viewport: self.viewport,
+                    output: self.output,
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::cmd::{OverlayFailureReason, OverlayStateEventParams};
+    use crate::overlay::mock::{texture_commands, MockBackend, FAILING_HANDLE};
+    use crate::overlay::OverlayPlacement;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    const SIZE: Dimensions = Dimensions {
+        width: 640,
+        height: 480,
+    };
+
+    fn sent(
+        rx: &mut UnboundedReceiver<GameWindowCommand>,
+        ty: GameWindowCommandType,
+    ) -> Vec<GameWindowCommand> {
+        std::iter::from_fn(|| rx.try_recv().ok())
+            .filter(|cmd| cmd.ty == ty)
+            .collect()
+    }
+
+    fn announce(overlay: &mut Overlay<MockBackend>, handle: usize, size: Dimensions) {
+        let commands = texture_commands(OverlayLayerId::MAIN, handle, 0, size.width, size.height);
+        for cmd in &commands {
+            overlay.handle_command(cmd);
+        }
+    }
+
+    fn painted(overlay: &Overlay<MockBackend>) -> Vec<OverlayPlacement> {
+        let mut placements = Vec::new();
+        overlay
+            .acquire_sync()
+            .paint(|paint| {
+                placements.push(paint.placement);
+                Ok(())
+            })
+            .unwrap();
+        placements
+    }
+
+    #[test]
+    fn import() {
+        let (ipc, mut rx, _) = IpcHandle::loopback();
+        let mut overlay = Overlay::new(MockBackend::default());
+
+        // Without a texture, one is requested right away.
+        let prepared = overlay.prepare_frame(&ipc, SIZE, OverlayOutput::default(), 1);
+        assert!(matches!(prepared, Err(RenderError::OverlayHandleNotReady)));
+        let resize = sent(&mut rx, GameWindowCommandType::WINDOW_RESIZE);
+        assert_eq!(resize.len(), 1);
+        let resize = unsafe { resize[0].params.resize_event };
+        assert_eq!((resize.width, resize.height, resize.force), (640, 480, 1));
+
+        announce(&mut overlay, 1, SIZE);
+        assert_eq!(
+            overlay.status().diagnostics().state,
+            OverlayState::HandleDuplicated
+        );
+
+        overlay
+            .prepare_frame(&ipc, SIZE, OverlayOutput::default(), 1)
+            .unwrap();
+        assert_eq!(overlay.status().diagnostics().state, OverlayState::Imported);
+        assert_eq!(overlay.main().backend.imports, 1);
+
+        let placed = sent(&mut rx, GameWindowCommandType::OVERLAY_PLACEMENT);
+        assert_eq!(placed.len(), 1);
+        let placed = unsafe { placed[0].params.overlay_placement_event };
+        assert_eq!((placed.width, placed.height), (640, 480));
+
+        let placements = painted(&overlay);
+        assert_eq!(placements.len(), 1);
+        assert_eq!(placements[0].size, SIZE);
+        assert_eq!(
+            overlay.status().diagnostics().state,
+            OverlayState::Paintable
+        );
+
+        // The texture is imported once, and the placement reported once.
+        overlay
+            .prepare_frame(&ipc, SIZE, OverlayOutput::default(), 1)
+            .unwrap();
+        assert_eq!(overlay.main().backend.imports, 1);
+        assert!(sent(&mut rx, GameWindowCommandType::OVERLAY_PLACEMENT).is_empty());
+    }
+
+    #[test]
+    fn texture_format_selects_layer() {
+        let mut overlay = Overlay::new(MockBackend::default());
+        let layer = OverlayLayerId::new(1);
+
+        for cmd in &texture_commands(layer, 1, 0, 320, 200) {
+            overlay.handle_command(cmd);
+        }
+        assert_eq!(overlay.layers.len(), 2);
+        assert_eq!(overlay.layers[1].id, layer);
+        assert!(overlay.layers[1].ready_to_initialize());
+        assert!(!overlay.main().ready_to_initialize());
+
+        // A texture announced without its format replaces that of the main layer.
+        let [_, texture] = texture_commands(layer, 2, 0, 640, 480);
+        overlay.handle_command(&texture);
+        assert_eq!(overlay.layers.len(), 2);
+        assert!(overlay.main().ready_to_initialize());
+    }
+
+    #[test]
+    fn lost() {
+        let (ipc, mut rx, _) = IpcHandle::loopback();
+        let mut overlay = Overlay::new(MockBackend::default());
+
+        // A handle that can not be duplicated loses the overlay.
+        for cmd in &texture_commands(OverlayLayerId::MAIN, 1, -1, 640, 480) {
+            overlay.handle_command(cmd);
+        }
+        assert_eq!(overlay.status().diagnostics().state, OverlayState::Lost);
+        assert!(!overlay.ready_to_initialize());
+
+        // The failure is reported once.
+        overlay
+            .prepare_frame(&ipc, SIZE, OverlayOutput::default(), 1)
+            .unwrap_err();
+        overlay
+            .prepare_frame(&ipc, SIZE, OverlayOutput::default(), 1)
+            .unwrap_err();
+        let states = sent(&mut rx, GameWindowCommandType::OVERLAY_STATE);
+        assert_eq!(states.len(), 1);
+        let OverlayStateEventParams { state, reason } =
+            unsafe { states[0].params.overlay_state_event };
+        assert_eq!(state, OverlayState::Lost as u8);
+        assert_eq!(reason, OverlayFailureReason::HANDLE_DUPLICATE);
+
+        // A texture that fails to import is not imported again until another is announced.
+        announce(&mut overlay, FAILING_HANDLE, SIZE);
+        overlay
+            .prepare_frame(&ipc, SIZE, OverlayOutput::default(), 1)
+            .unwrap_err();
+        assert_eq!(overlay.status().diagnostics().state, OverlayState::Lost);
+        assert_eq!(
+            overlay.status().diagnostics().failure,
+            OverlayFailureReason::IMPORT
+        );
+        overlay
+            .prepare_frame(&ipc, SIZE, OverlayOutput::default(), 1)
+            .unwrap_err();
+        assert_eq!(overlay.main().backend.imports, 1);
+        assert!(painted(&overlay).is_empty());
+
+        announce(&mut overlay, 2, SIZE);
+        overlay
+            .prepare_frame(&ipc, SIZE, OverlayOutput::default(), 1)
+            .unwrap();
+        assert_eq!(overlay.main().backend.imports, 2);
+        assert_eq!(overlay.status().diagnostics().state, OverlayState::Imported);
+        // The handle of the texture that failed was closed once it was replaced.
+        assert_eq!(overlay.main().backend.closed.get(), 1);
+    }
+
+    #[test]
+    fn reacquire() {
+        let (ipc, mut rx, _) = IpcHandle::loopback();
+        let mut overlay = Overlay::new(MockBackend::default());
+        announce(&mut overlay, 1, SIZE);
+        overlay
+            .prepare_frame(&ipc, SIZE, OverlayOutput::default(), 1)
+            .unwrap();
+
+        // The texture is imported again into a new device.
+        overlay
+            .prepare_frame(&ipc, SIZE, OverlayOutput::default(), 2)
+            .unwrap();
+        assert_eq!(overlay.main().backend.imports, 2);
+        assert_eq!(overlay.main().backend.imported, Some((2, 1)));
+        assert_eq!(painted(&overlay).len(), 1);
+
+        // Once reset, a texture has to be sent again.
+        overlay.reset();
+        assert_eq!(
+            overlay.status().diagnostics().state,
+            OverlayState::AwaitingTexture
+        );
+        assert_eq!(overlay.main().backend.closed.get(), 1);
+        assert!(painted(&overlay).is_empty());
+
+        sent(&mut rx, GameWindowCommandType::WINDOW_RESIZE);
+        let prepared = overlay.prepare_frame(&ipc, SIZE, OverlayOutput::default(), 2);
+        assert!(matches!(prepared, Err(RenderError::OverlayHandleNotReady)));
+        assert_eq!(sent(&mut rx, GameWindowCommandType::WINDOW_RESIZE).len(), 1);
+
+        announce(&mut overlay, 3, SIZE);
+        overlay
+            .prepare_frame(&ipc, SIZE, OverlayOutput::default(), 2)
+            .unwrap();
+        assert_eq!(overlay.main().backend.imported, Some((2, 3)));
+    }
+
+    #[test]
+    fn resize() {
+        let resized = Dimensions::new(800, 600);
+        let (ipc, mut rx, _) = IpcHandle::loopback();
+        let mut overlay = Overlay::new(MockBackend::default());
+        announce(&mut overlay, 1, SIZE);
+        overlay
+            .prepare_frame(&ipc, SIZE, OverlayOutput::default(), 1)
+            .unwrap();
+        sent(&mut rx, GameWindowCommandType::WINDOW_RESIZE);
+
+        // The texture on screen is stretched over the viewport until it keeps its size.
+        overlay
+            .prepare_frame(&ipc, resized, OverlayOutput::default(), 1)
+            .unwrap();
+        assert!(sent(&mut rx, GameWindowCommandType::WINDOW_RESIZE).is_empty());
+        assert_eq!(painted(&overlay)[0].size, resized);
+
+        overlay.viewport.1 = Instant::now() - RESIZE_DEBOUNCE;
+        overlay
+            .prepare_frame(&ipc, resized, OverlayOutput::default(), 1)
+            .unwrap();
+        let resize = sent(&mut rx, GameWindowCommandType::WINDOW_RESIZE);
+        assert_eq!(resize.len(), 1);
+        let resize = unsafe { resize[0].params.resize_event };
+        assert_eq!((resize.width, resize.height, resize.force), (800, 600, 0));
+
+        // The request is not repeated while it is answered.
+        overlay
+            .prepare_frame(&ipc, resized, OverlayOutput::default(), 1)
+            .unwrap();
+        assert!(sent(&mut rx, GameWindowCommandType::WINDOW_RESIZE).is_empty());
+
+        // A request that is not answered is repeated.
+        overlay.requested = Some((resized, Instant::now() - RESIZE_RETRY));
+        overlay
+            .prepare_frame(&ipc, resized, OverlayOutput::default(), 1)
+            .unwrap();
+        assert_eq!(sent(&mut rx, GameWindowCommandType::WINDOW_RESIZE).len(), 1);
+
+        announce(&mut overlay, 2, resized);
+        overlay
+            .prepare_frame(&ipc, resized, OverlayOutput::default(), 1)
+            .unwrap();
+        assert!(overlay.size_matches_viewpoint(&resized));
+        assert_eq!(overlay.main().backend.imported, Some((1, 2)));
+        assert_eq!(painted(&overlay)[0].size, resized);
+    }
+}
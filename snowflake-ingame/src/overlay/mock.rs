@@ -0,0 +1,129 @@
+use std::cell::Cell;
+
+use imgui::TextureId;
+
+use crate::common::{Dimensions, RenderError};
+use crate::ipc::cmd::OverlayTextureFormatEventParams;
+use crate::ipc::cmd::{GameWindowCommand, GameWindowCommandParams, GameWindowCommandType};
+use crate::ipc::cmd::{GameWindowMagic, OverlayCapabilities, OverlayTextureEventParams};
+use crate::ipc::cmd::{GraphicsBackends, OverlayColorspace, OverlayLayerId, OverlayPixelFormat};
+use crate::overlay::{OverlayBackend, OverlayDescriptor, SyncedFrame};
+use crate::platform::handle::HandleError;
+
+/// The handle of a texture that the mock backend fails to import.
+pub const FAILING_HANDLE: usize = 0xbad;
+
+/// A backend that imports textures without a graphics API.
+///
+/// The target is the generation of the device the texture is imported into, so a target of
+/// another generation stands in for a lost device that the texture has to be imported into again.
+#[derive(Default)]
+pub struct MockBackend {
+    /// The device the texture was imported into, and the handle it was imported from.
+    pub imported: Option<(u32, usize)>,
+    /// The number of times a texture was imported.
+    pub imports: usize,
+    /// The number of handles closed.
+    pub closed: Cell<usize>,
+}
+
+pub struct MockSyncGuard;
+
+impl SyncedFrame for MockSyncGuard {}
+
+impl OverlayBackend for MockBackend {
+    const NAME: &'static str = "mock";
+    const BACKEND: GraphicsBackends = GraphicsBackends::NONE;
+    type Handle = usize;
+    type Target<'a> = u32;
+    type SyncGuard<'a> = MockSyncGuard;
+
+    /// Handles announced from a negative process id can not be duplicated.
+    fn duplicate_handle(
+        &self,
+        params: &OverlayTextureEventParams,
+        _format: OverlayPixelFormat,
+    ) -> Result<Self::Handle, HandleError> {
+        if params.source_pid < 0 {
+            return Err(HandleError::InvalidProcess);
+        }
+        Ok(params.handle)
+    }
+
+    fn capabilities(&self, _target: &Self::Target<'_>) -> OverlayCapabilities {
+        OverlayCapabilities::NONE
+    }
+
+    fn close_handle(&self, _handle: Self::Handle) -> Result<(), HandleError> {
+        self.closed.set(self.closed.get() + 1);
+        Ok(())
+    }
+
+    fn ready_to_paint(&self, target: &Self::Target<'_>) -> bool {
+        matches!(self.imported, Some((device, _)) if device == *target)
+    }
+
+    fn import(
+        &mut self,
+        descriptor: &OverlayDescriptor<Self::Handle>,
+        target: Self::Target<'_>,
+    ) -> Result<Dimensions, RenderError> {
+        self.imports += 1;
+        if descriptor.handle == FAILING_HANDLE {
+            let fourcc = descriptor.format.fourcc().unwrap_or_default();
+            return Err(RenderError::OverlayFormatUnsupported(fourcc));
+        }
+        self.imported = Some((target, descriptor.handle));
+        Ok(descriptor.dimensions)
+    }
+
+    fn invalidate(&mut self) {
+        self.imported = None;
+    }
+
+    fn acquire_sync(&self) -> Option<Self::SyncGuard<'_>> {
+        self.imported.map(|_| MockSyncGuard)
+    }
+
+    fn texture_id(&self) -> Option<TextureId> {
+        self.imported.map(|(_, handle)| TextureId::new(handle))
+    }
+}
+
+/// The `OVERLAY_TEXTURE_FORMAT` and `OVERLAY_TEXTURE` commands announcing texture `handle`
+/// of `width` by `height` for `layer`.
+pub fn texture_commands(
+    layer: OverlayLayerId,
+    handle: usize,
+    source_pid: i32,
+    width: u32,
+    height: u32,
+) -> [GameWindowCommand; 2] {
+    let format = GameWindowCommand {
+        magic: GameWindowMagic::MAGIC,
+        ty: GameWindowCommandType::OVERLAY_TEXTURE_FORMAT,
+        params: GameWindowCommandParams {
+            overlay_format_event: OverlayTextureFormatEventParams {
+                format: OverlayPixelFormat::BGRA8,
+                colorspace: OverlayColorspace::SRGB,
+                layer,
+            },
+        },
+    };
+    let texture = GameWindowCommand {
+        magic: GameWindowMagic::MAGIC,
+        ty: GameWindowCommandType::OVERLAY_TEXTURE,
+        params: GameWindowCommandParams {
+            overlay_event: OverlayTextureEventParams {
+                handle,
+                source_pid,
+                width,
+                height,
+                size: width as u64 * height as u64 * 4,
+                alignment: 0,
+                sync_handle: 0,
+            },
+        },
+    };
+    [format, texture]
+}
@@ -0,0 +1,198 @@
+mod arbiter;
+mod color;
+mod damage;
+mod frames;
+mod layer;
+mod lifecycle;
+#[cfg(test)]
+mod mock;
+mod presentation;
+mod state;
+mod surface;
+
+use imgui::TextureId;
+
+use crate::common::{AdapterIdentity, Dimensions, RenderError};
+#[cfg(target_os = "linux")]
+use crate::ipc::cmd::OverlayTextureFdEventParams;
+use crate::ipc::cmd::{GraphicsBackends, OverlayCapabilities, OverlayTextureEventParams};
+use crate::ipc::cmd::{OverlayColorspace, OverlayPixelFormat, OverlayPixelFormats};
+use crate::platform::handle::HandleError;
+
+pub use arbiter::{claim_window, take_commands, Ownership, PresentArbiter};
+pub use color::{OverlayEncoding, OverlayFormat, OverlayOutput};
+pub use damage::{DamageRect, OverlayDamage, OverlayTransfer};
+pub use frames::{SharedFrame, SharedFrames, SharedFramesError};
+pub use lifecycle::{AcquiredLayers, Overlay};
+pub use presentation::{OverlayFilter, OverlayPaint, OverlayPlacement, OverlayTexels};
+pub use state::{OverlayDiagnostics, OverlayState, OverlayStatus, OverlayTransition};
+pub use surface::{OverlayTargetPolicy, Surface, SurfaceMap};
+
+/// A shared overlay texture handle that has been received from the orchestrator,
+/// along with the parameters it was announced with.
+pub struct OverlayDescriptor<H> {
+    pub handle: H,
+    pub dimensions: Dimensions,
+    pub size: u64,
+    /// The format the texture was announced in, if it was not announced with a DRM fourcc.
+    pub format: OverlayPixelFormat,
+    pub colorspace: OverlayColorspace,
+}
+
+impl<H> OverlayDescriptor<H> {
+    /// The format and encoding the texture was announced in.
+    pub fn announced(&self) -> Result<(OverlayFormat, OverlayEncoding), RenderError> {
+        let format = OverlayFormat::announced(self.format)?;
+        Ok((format, OverlayEncoding::announced(self.colorspace, format)))
+    }
+}
+
+/// How long a backend waits for the orchestrator to release the shared texture, in milliseconds.
+///
+/// The wait happens on the game's render thread, so a backend never blocks on the orchestrator,
+/// and paints the last frame it composited again instead.
+pub const OVERLAY_SYNC_TIMEOUT_MS: u32 = 0;
+
+/// The frame of the overlay texture an [`OverlayBackend::SyncGuard`] allows to be painted.
+pub trait SyncedFrame {
+    /// Whether the latest frame was still held by the orchestrator, so that the last frame
+    /// composited is painted again.
+    fn is_stale(&self) -> bool {
+        false
+    }
+
+    /// How much of the frame was copied into the texture that is painted, if a frame was.
+    fn transfer(&self) -> Option<OverlayTransfer> {
+        None
+    }
+}
+
+/// The graphics API specific half of an overlay.
+///
+/// A backend only has to know how to take ownership of a shared handle, import it into
+/// its API as a texture, and synchronize access to it. The lifecycle of the overlay
+/// (refreshing handles, resize notifications, readiness) is driven by [`Overlay`].
+///
+/// Every layer of the overlay imports its texture with a backend of its own, which is
+/// created with [`Default::default`].
+pub trait OverlayBackend: Default {
+    /// The name of the backend used in log messages.
+    const NAME: &'static str;
+
+    /// The graphics API the backend imports the texture into.
+    const BACKEND: GraphicsBackends;
+
+    /// The owned handle type to the shared texture.
+    type Handle;
+
+    /// The device state the texture must be imported into, which is cloned for every layer.
+    type Target<'a>: Clone;
+
+    /// RAII guard for the shared texture. The texture may only be sampled while this is held.
+    type SyncGuard<'a>: SyncedFrame
+    where
+        Self: 'a;
+
+    /// Take ownership of the handle described by `params`, of a texture in `format`.
+    fn duplicate_handle(
+        &self,
+        params: &OverlayTextureEventParams,
+        format: OverlayPixelFormat,
+    ) -> Result<Self::Handle, HandleError>;
+
+    /// Take ownership of the file descriptors sent with `params`.
+    ///
+    /// The descriptors are owned by the backend once this is called, whether or not it succeeds.
+    #[cfg(target_os = "linux")]
+    fn adopt_fds(&self, params: &OverlayTextureFdEventParams) -> Result<Self::Handle, HandleError> {
+        for fd in [params.memory_fd, params.acquire_fd, params.release_fd] {
+            if fd >= 0 {
+                crate::platform::handle::try_close_handle(fd)?;
+            }
+        }
+        Err(HandleError::Unsupported)
+    }
+
+    /// The adapter `target` renders on, if the graphics API can identify it.
+    fn adapter(&self, _target: &Self::Target<'_>) -> Option<AdapterIdentity> {
+        None
+    }
+
+    /// The ways the texture can be imported into `target`, reported to the orchestrator
+    /// so that it exports the texture in one of them.
+    fn capabilities(&self, target: &Self::Target<'_>) -> OverlayCapabilities;
+
+    /// The pixel formats a texture can be imported into `target` in, reported to the
+    /// orchestrator along with the capabilities.
+    fn formats(&self, _target: &Self::Target<'_>) -> OverlayPixelFormats {
+        OverlayPixelFormats::BGRA8
+    }
+
+    /// Release a handle previously returned by `duplicate_handle`.
+    fn close_handle(&self, handle: Self::Handle) -> Result<(), HandleError>;
+
+    /// Whether the imported texture is valid to paint onto `target`.
+    fn ready_to_paint(&self, target: &Self::Target<'_>) -> bool;
+
+    /// Import the shared texture into `target`, returning the dimensions of the imported texture.
+    fn import(
+        &mut self,
+        descriptor: &OverlayDescriptor<Self::Handle>,
+        target: Self::Target<'_>,
+    ) -> Result<Dimensions, RenderError>;
+
+    /// Import a ring of shared textures into `target`, returning the dimensions of the
+    /// texture that is painted.
+    ///
+    /// This is only used by backends that report [`OverlayCapabilities::TEXTURE_RING`].
+    fn import_ring(
+        &mut self,
+        _ring: &[OverlayDescriptor<Self::Handle>],
+        _target: Self::Target<'_>,
+    ) -> Result<Dimensions, RenderError> {
+        Err(RenderError::OverlayRingUnsupported)
+    }
+
+    /// Mark texture `index` of the imported ring as holding the newest frame, so that it is
+    /// painted from the next time the texture is acquired. A single imported texture is
+    /// texture 0, which is announced for every frame written into it.
+    ///
+    /// `damage` covers the texels that changed since the frame selected before it, so that
+    /// a backend that copies frames out of the ring only has to copy those.
+    fn select(&mut self, _index: usize, _damage: &OverlayDamage) {}
+
+    /// Upload a frame read from shared memory into a texture on `target`, returning
+    /// the dimensions of the texture.
+    ///
+    /// This is only used by backends that report [`OverlayCapabilities::SHARED_MEMORY`].
+    fn upload(
+        &mut self,
+        _frame: &SharedFrame<'_>,
+        _target: Self::Target<'_>,
+    ) -> Result<Dimensions, RenderError> {
+        Err(RenderError::OverlaySharedMemoryUnsupported)
+    }
+
+    /// Sample the texture with `filter` from the next time it is painted.
+    ///
+    /// Backends whose renderer chooses the filter for every texture leave this to the kernel.
+    fn set_filter(&mut self, _filter: OverlayFilter) {}
+
+    /// Drop any imported or uploaded resources.
+    fn invalidate(&mut self);
+
+    /// Acquire the synchronization primitive for the imported texture.
+    ///
+    /// This must not wait longer than [`OVERLAY_SYNC_TIMEOUT_MS`]. A backend that keeps a copy
+    /// of the last frame returns a stale guard when the orchestrator still holds the texture,
+    /// and `None` only if nothing can be painted.
+    fn acquire_sync(&self) -> Option<Self::SyncGuard<'_>>;
+
+    /// The texture id of the imported texture, if it has been imported.
+    fn texture_id(&self) -> Option<TextureId>;
+
+    /// How the texels of the texture returned by `texture_id` are read when it is composited.
+    fn texels(&self) -> OverlayTexels {
+        OverlayTexels::RGBA
+    }
+}
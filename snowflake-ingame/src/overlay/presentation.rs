@@ -0,0 +1,417 @@
+use imgui::TextureId;
+
+use crate::common::Dimensions;
+use crate::ipc::cmd::{
+    OverlayAnchor, OverlayFilterMode, OverlayLayerEventParams, OverlayLayerFlags,
+    OverlayPresentationEventParams, OverlayScaleMode,
+};
+use crate::overlay::{OverlayEncoding, OverlayOutput};
+
+/// How the overlay texture is scaled onto the viewport.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum OverlayScaling {
+    Stretch,
+    AspectFit,
+    Integer,
+    Centered,
+}
+
+/// The filter the overlay texture is sampled with when it is scaled.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum OverlayFilter {
+    Linear,
+    Nearest,
+}
+
+/// How the orchestrator asked for the overlay texture to be placed on the viewport.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct OverlayPresentation {
+    pub scaling: OverlayScaling,
+    /// The point of the texture that is aligned with the same point of the viewport,
+    /// as a fraction of their sizes.
+    pub anchor: [f32; 2],
+    pub filter: OverlayFilter,
+    pub opacity: f32,
+}
+
+impl Default for OverlayPresentation {
+    fn default() -> Self {
+        OverlayPresentation {
+            scaling: OverlayScaling::Stretch,
+            anchor: [0.5, 0.5],
+            filter: OverlayFilter::Linear,
+            opacity: 1.0,
+        }
+    }
+}
+
+impl From<OverlayPresentationEventParams> for OverlayPresentation {
+    fn from(params: OverlayPresentationEventParams) -> Self {
+        let scaling = match params.mode {
+            OverlayScaleMode::ASPECT_FIT => OverlayScaling::AspectFit,
+            OverlayScaleMode::INTEGER => OverlayScaling::Integer,
+            OverlayScaleMode::CENTERED => OverlayScaling::Centered,
+            _ => OverlayScaling::Stretch,
+        };
+
+        let anchor = match params.anchor {
+            OverlayAnchor::TOP_LEFT => [0.0, 0.0],
+            OverlayAnchor::TOP => [0.5, 0.0],
+            OverlayAnchor::TOP_RIGHT => [1.0, 0.0],
+            OverlayAnchor::LEFT => [0.0, 0.5],
+            OverlayAnchor::RIGHT => [1.0, 0.5],
+            OverlayAnchor::BOTTOM_LEFT => [0.0, 1.0],
+            OverlayAnchor::BOTTOM => [0.5, 1.0],
+            OverlayAnchor::BOTTOM_RIGHT => [1.0, 1.0],
+            _ => [0.5, 0.5],
+        };
+
+        let filter = match params.filter {
+            OverlayFilterMode::NEAREST => OverlayFilter::Nearest,
+            _ => OverlayFilter::Linear,
+        };
+
+        OverlayPresentation {
+            scaling,
+            anchor,
+            filter,
+            opacity: params.opacity as f32 / 255.0,
+        }
+    }
+}
+
+impl OverlayPresentation {
+    /// Place a texture of `texture` size onto a viewport of `viewport` size.
+    pub fn place(&self, texture: Dimensions, viewport: Dimensions) -> OverlayPlacement {
+        if self.scaling == OverlayScaling::Stretch || texture.width == 0 || texture.height == 0 {
+            return OverlayPlacement {
+                position: [0, 0],
+                size: viewport,
+                opacity: self.opacity,
+            };
+        }
+
+        let fit = f32::min(
+            viewport.width as f32 / texture.width as f32,
+            viewport.height as f32 / texture.height as f32,
+        );
+        let scale = match self.scaling {
+            OverlayScaling::AspectFit => fit,
+            OverlayScaling::Integer => fit.floor().max(1.0),
+            _ => 1.0,
+        };
+
+        let size = Dimensions::new(
+            (texture.width as f32 * scale).round() as u32,
+            (texture.height as f32 * scale).round() as u32,
+        );
+
+        // Positions are kept to whole pixels, so that an unscaled texture is not resampled.
+        let offset = |viewport: u32, size: u32, anchor: f32| {
+            ((viewport as f32 - size as f32) * anchor).round() as i32
+        };
+
+        OverlayPlacement {
+            position: [
+                offset(viewport.width, size.width, self.anchor[0]),
+                offset(viewport.height, size.height, self.anchor[1]),
+            ],
+            size,
+            opacity: self.opacity,
+        }
+    }
+}
+
+/// How the orchestrator asked for a layer of the overlay to be stacked onto the viewport.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct OverlayStacking {
+    /// The top left corner of the rectangle the layer is placed within, in pixels from
+    /// the top left of the viewport.
+    pub position: [i32; 2],
+    /// The size of the rectangle, or `None` if the layer covers the whole viewport.
+    pub size: Option<Dimensions>,
+    pub z: i32,
+    pub opacity: f32,
+    pub visible: bool,
+}
+
+impl Default for OverlayStacking {
+    fn default() -> Self {
+        OverlayStacking {
+            position: [0, 0],
+            size: None,
+            z: 0,
+            opacity: 1.0,
+            visible: true,
+        }
+    }
+}
+
+impl From<OverlayLayerEventParams> for OverlayStacking {
+    fn from(params: OverlayLayerEventParams) -> Self {
+        let size = (params.width != 0 && params.height != 0)
+            .then(|| Dimensions::new(params.width, params.height));
+        OverlayStacking {
+            position: [params.x, params.y],
+            size,
+            z: params.z,
+            opacity: params.opacity as f32 / 255.0,
+            visible: params.flags.contains(OverlayLayerFlags::VISIBLE),
+        }
+    }
+}
+
+impl OverlayStacking {
+    /// Place a texture of `texture` size within the rectangle of the layer on a viewport
+    /// of `viewport` size.
+    pub fn place(
+        &self,
+        presentation: &OverlayPresentation,
+        texture: Dimensions,
+        viewport: Dimensions,
+    ) -> OverlayPlacement {
+        let placement = presentation.place(texture, self.size.unwrap_or(viewport));
+        OverlayPlacement {
+            position: [
+                self.position[0] + placement.position[0],
+                self.position[1] + placement.position[1],
+            ],
+            opacity: placement.opacity * self.opacity,
+            ..placement
+        }
+    }
+}
+
+/// Where the overlay texture is painted on the viewport.
+///
+/// Input is mapped into the texture through the same rectangle, which is reported to
+/// the orchestrator whenever it changes.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct OverlayPlacement {
+    /// The top left corner of the texture, in pixels from the top left of the viewport.
+    pub position: [i32; 2],
+    pub size: Dimensions,
+    pub opacity: f32,
+}
+
+impl OverlayPlacement {
+    /// Whether the placement covers the same rectangle as `other`.
+    #[inline]
+    pub fn same_rect(&self, other: &OverlayPlacement) -> bool {
+        self.position == other.position && self.size == other.size
+    }
+}
+
+/// How the texels of the painted texture are read when it is composited.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct OverlayTexels {
+    /// The texture holds BGRA texels but is sampled as RGBA, so they are swizzled.
+    pub bgra: bool,
+    /// The alpha channel of the texture is undefined, so it is composited as opaque.
+    pub opaque: bool,
+    /// The colour of the texture is premultiplied by its alpha, as the browser renders it.
+    pub premultiplied: bool,
+    /// How the colour of the texture is encoded, which is converted to the encoding
+    /// of the framebuffer.
+    pub encoding: OverlayEncoding,
+}
+
+impl OverlayTexels {
+    /// sRGB texels sampled in the order they are stored, with premultiplied alpha.
+    pub const RGBA: OverlayTexels = OverlayTexels {
+        bgra: false,
+        opaque: false,
+        premultiplied: true,
+        encoding: OverlayEncoding::Srgb,
+    };
+
+    /// sRGB BGRA texels sampled as RGBA, with premultiplied alpha.
+    pub const BGRA: OverlayTexels = OverlayTexels {
+        bgra: true,
+        ..OverlayTexels::RGBA
+    };
+}
+
+/// The texture a kernel composites onto its framebuffer, and where.
+#[derive(Copy, Clone, Debug)]
+pub struct OverlayPaint {
+    pub texture: TextureId,
+    pub texels: OverlayTexels,
+    pub placement: OverlayPlacement,
+    pub filter: OverlayFilter,
+    /// The size of the framebuffer the texture is composited onto.
+    pub viewport: Dimensions,
+    pub output: OverlayOutput,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::cmd::OverlayLayerId;
+
+    const MODES: [(OverlayScaleMode, OverlayScaling); 4] = [
+        (OverlayScaleMode::STRETCH, OverlayScaling::Stretch),
+        (OverlayScaleMode::ASPECT_FIT, OverlayScaling::AspectFit),
+        (OverlayScaleMode::INTEGER, OverlayScaling::Integer),
+        (OverlayScaleMode::CENTERED, OverlayScaling::Centered),
+    ];
+
+    const ANCHORS: [(OverlayAnchor, [f32; 2]); 9] = [
+        (OverlayAnchor::TOP_LEFT, [0.0, 0.0]),
+        (OverlayAnchor::TOP, [0.5, 0.0]),
+        (OverlayAnchor::TOP_RIGHT, [1.0, 0.0]),
+        (OverlayAnchor::LEFT, [0.0, 0.5]),
+        (OverlayAnchor::CENTER, [0.5, 0.5]),
+        (OverlayAnchor::RIGHT, [1.0, 0.5]),
+        (OverlayAnchor::BOTTOM_LEFT, [0.0, 1.0]),
+        (OverlayAnchor::BOTTOM, [0.5, 1.0]),
+        (OverlayAnchor::BOTTOM_RIGHT, [1.0, 1.0]),
+    ];
+
+    /// Textures placed onto viewports larger, smaller, and of the same size as they are.
+    fn sizes() -> [(Dimensions, Dimensions); 5] {
+        let size = Dimensions::new;
+        [
+            (size(640, 480), size(1920, 1080)),
+            (size(1920, 1080), size(1280, 720)),
+            (size(300, 200), size(301, 999)),
+            (size(801, 601), size(800, 600)),
+            (size(800, 600), size(800, 600)),
+        ]
+    }
+
+    fn presentation(mode: OverlayScaleMode, anchor: OverlayAnchor) -> OverlayPresentation {
+        OverlayPresentationEventParams {
+            mode,
+            anchor,
+            filter: OverlayFilterMode::LINEAR,
+            opacity: 255,
+            layer: OverlayLayerId::MAIN,
+        }
+        .into()
+    }
+
+    /// Map a point on the viewport into a texture of `texture` size painted at `placement`,
+    /// as the orchestrator maps input.
+    fn to_texture(placement: &OverlayPlacement, point: [f32; 2], texture: Dimensions) -> [f32; 2] {
+        [
+            (point[0] - placement.position[0] as f32) * texture.width as f32
+                / placement.size.width as f32,
+            (point[1] - placement.position[1] as f32) * texture.height as f32
+                / placement.size.height as f32,
+        ]
+    }
+
+    fn assert_near(actual: [f32; 2], expected: [f32; 2], tolerance: f32, context: &str) {
+        for axis in 0..2 {
+            assert!(
+                (actual[axis] - expected[axis]).abs() <= tolerance,
+                "{context}: mapped to {actual:?}, expected {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn params_select_scaling_and_anchor() {
+        for (mode, scaling) in MODES {
+            for (anchor, point) in ANCHORS {
+                let presentation = presentation(mode, anchor);
+                assert_eq!(presentation.scaling, scaling);
+                assert_eq!(presentation.anchor, point);
+                assert_eq!(presentation.opacity, 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn placement_round_trips() {
+        for (texture, viewport) in sizes() {
+            for (mode, scaling) in MODES {
+                for (anchor, [ax, ay]) in ANCHORS {
+                    let placement = presentation(mode, anchor).place(texture, viewport);
+                    let context = format!("{scaling:?} {anchor:?} {texture:?} on {viewport:?}");
+                    let [x, y] = placement.position.map(|p| p as f32);
+                    let size = placement.size;
+                    let (width, height) = (size.width as f32, size.height as f32);
+
+                    match scaling {
+                        OverlayScaling::Stretch => assert_eq!(size, viewport, "{context}"),
+                        OverlayScaling::AspectFit => {
+                            assert!(size.width <= viewport.width, "{context}");
+                            assert!(size.height <= viewport.height, "{context}");
+                            assert!(
+                                size.width == viewport.width || size.height == viewport.height,
+                                "{context}"
+                            );
+                        }
+                        OverlayScaling::Integer => {
+                            let factor = size.width / texture.width;
+                            assert!(factor >= 1, "{context}");
+                            assert_eq!(size.width, texture.width * factor, "{context}");
+                            assert_eq!(size.height, texture.height * factor, "{context}");
+                            let larger = Dimensions::new(
+                                texture.width * (factor + 1),
+                                texture.height * (factor + 1),
+                            );
+                            assert!(
+                                larger.width > viewport.width || larger.height > viewport.height,
+                                "{context}"
+                            );
+                        }
+                        OverlayScaling::Centered => assert_eq!(size, texture, "{context}"),
+                    }
+
+                    // The corners of the placement map to the corners of the texture.
+                    let (tw, th) = (texture.width as f32, texture.height as f32);
+                    assert_near(
+                        to_texture(&placement, [x, y], texture),
+                        [0.0, 0.0],
+                        1e-3,
+                        &context,
+                    );
+                    assert_near(
+                        to_texture(&placement, [x + width, y + height], texture),
+                        [tw, th],
+                        1e-3,
+                        &context,
+                    );
+
+                    // The anchor of the viewport maps to the anchor of the texture, to within
+                    // the half pixel the position is rounded by.
+                    let anchor = [ax * viewport.width as f32, ay * viewport.height as f32];
+                    let tolerance = 0.5 * f32::max(tw / width, th / height) + 1e-3;
+                    assert_near(
+                        to_texture(&placement, anchor, texture),
+                        [ax * tw, ay * th],
+                        tolerance,
+                        &context,
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stacking_offsets_placement() {
+        let stacking = OverlayStacking {
+            position: [100, 50],
+            size: Some(Dimensions::new(400, 300)),
+            opacity: 0.5,
+            ..OverlayStacking::default()
+        };
+        let texture = Dimensions::new(200, 100);
+        let presentation = presentation(OverlayScaleMode::CENTERED, OverlayAnchor::BOTTOM_RIGHT);
+        let placement = stacking.place(&presentation, texture, Dimensions::new(1920, 1080));
+        assert_eq!(placement.position, [300, 250]);
+        assert_eq!(placement.size, texture);
+        assert_eq!(placement.opacity, 0.5);
+
+        // The bottom right corner of the layer's rectangle is that of the texture.
+        assert_near(
+            to_texture(&placement, [500.0, 350.0], texture),
+            [200.0, 100.0],
+            1e-3,
+            "",
+        );
+    }
+}
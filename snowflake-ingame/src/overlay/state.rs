@@ -0,0 +1,375 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::Instant;
+
+use parking_lot::Mutex;
+
+use crate::common::RenderError;
+use crate::ipc::cmd::{GameWindowCommand, GraphicsBackends, OverlayFailureReason};
+use crate::ipc::IpcHandle;
+use crate::overlay::OverlayTransfer;
+
+/// The number of transitions kept for diagnostics.
+const HISTORY_LEN: usize = 32;
+
+/// The state of the overlay pipeline.
+///
+/// ```text
+/// AwaitingTexture -> HandleDuplicated -> Imported -> Paintable
+///                                  \          \          \
+///                                   +----------+----------+--> Lost
+/// ```
+///
+/// `Lost` is left once a new handle is duplicated, or the existing handle is
+/// imported successfully again.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[repr(u8)]
+pub enum OverlayState {
+    AwaitingTexture = 0,
+    HandleDuplicated = 1,
+    Imported = 2,
+    Paintable = 3,
+    Lost = 4,
+}
+
+impl Display for OverlayState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            OverlayState::AwaitingTexture => "AwaitingTexture",
+            OverlayState::HandleDuplicated => "HandleDuplicated",
+            OverlayState::Imported => "Imported",
+            OverlayState::Paintable => "Paintable",
+            OverlayState::Lost => "Lost",
+        })
+    }
+}
+
+impl From<&RenderError> for OverlayFailureReason {
+    fn from(err: &RenderError) -> Self {
+        match err {
+            RenderError::IpcError(_) => OverlayFailureReason::IPC,
+            RenderError::OpenGLInternalError(
+                imgui_renderer_ogl::RenderError::MissingExtensionError(_),
+            ) => OverlayFailureReason::MISSING_EXTENSION,
+            RenderError::VulkanMissingExtension(_) => OverlayFailureReason::MISSING_EXTENSION,
+            RenderError::OpenGLInternalError(_) | RenderError::VulkanInternalError(_) => {
+                OverlayFailureReason::RENDERER
+            }
+            #[cfg(windows)]
+            RenderError::Direct3D11InternalError(_) => OverlayFailureReason::RENDERER,
+            #[cfg(windows)]
+            RenderError::DXGIInternalError(_) => OverlayFailureReason::DEVICE,
+            RenderError::VulkanError(_) => OverlayFailureReason::DEVICE,
+            // GL errors are only checked for while importing the overlay texture.
+            RenderError::OpenGLError(_) => OverlayFailureReason::IMPORT,
+            RenderError::RendererNotReady | RenderError::ImGuiNotReady(_) => {
+                OverlayFailureReason::RENDERER
+            }
+            #[cfg(windows)]
+            RenderError::OverlayHandleError(_, _) => OverlayFailureReason::IMPORT,
+            #[cfg(target_os = "linux")]
+            RenderError::OverlayFdError(_) => OverlayFailureReason::IMPORT,
+            RenderError::OverlayFormatUnsupported(_) => OverlayFailureReason::IMPORT,
+            RenderError::OverlayFormatMismatch(..) => OverlayFailureReason::IMPORT,
+            RenderError::OverlayHandleNotReady => OverlayFailureReason::NONE,
+            RenderError::OverlayMutexNotReady => OverlayFailureReason::SYNC,
+            RenderError::OverlayFrameNotReady => OverlayFailureReason::NONE,
+            RenderError::OverlaySharedMemoryUnsupported => OverlayFailureReason::IMPORT,
+            RenderError::OverlayRingUnsupported => OverlayFailureReason::IMPORT,
+            RenderError::OverlayPaintNotReady(inner) => inner.as_ref().into(),
+            RenderError::KernelNotReady => OverlayFailureReason::DEVICE,
+        }
+    }
+}
+
+/// A recorded change of [`OverlayState`].
+#[derive(Clone, Debug)]
+pub struct OverlayTransition {
+    pub from: OverlayState,
+    pub to: OverlayState,
+    pub reason: Option<String>,
+    pub at: Instant,
+}
+
+/// A snapshot of the overlay pipeline.
+#[derive(Clone, Debug)]
+pub struct OverlayDiagnostics {
+    pub state: OverlayState,
+    pub failure: OverlayFailureReason,
+    pub reason: Option<String>,
+    pub transitions: Vec<OverlayTransition>,
+    /// The number of frames the last composited frame was painted again, because the
+    /// orchestrator still held the latest one.
+    pub stale_frames: u64,
+    /// The number of frames no overlay was painted, because the orchestrator held the
+    /// texture before any frame was composited.
+    pub skipped_frames: u64,
+    /// The number of bytes of frames copied or uploaded into the textures that are painted.
+    pub transferred_bytes: u64,
+    /// The number of bytes of those frames that were not copied or uploaded, because they
+    /// were outside of the regions the orchestrator marked as damaged.
+    pub saved_bytes: u64,
+}
+
+struct OverlayStatusInner {
+    state: OverlayState,
+    failure: OverlayFailureReason,
+    reason: Option<String>,
+    history: VecDeque<OverlayTransition>,
+    unreported: bool,
+    /// Whether the diagnostics changed since they were last reported.
+    diagnostics_unreported: bool,
+    stale_frames: u64,
+    skipped_frames: u64,
+    transferred_bytes: u64,
+    saved_bytes: u64,
+}
+
+/// Shared handle to the state of an overlay pipeline.
+///
+/// Transitions are logged and queued for the orchestrator only when the state or
+/// the failure reason changes, so a persistent failure is only reported once.
+#[derive(Clone)]
+pub struct OverlayStatus {
+    name: &'static str,
+    inner: Arc<Mutex<OverlayStatusInner>>,
+}
+
+impl OverlayStatus {
+    pub fn new(name: &'static str) -> OverlayStatus {
+        OverlayStatus {
+            name,
+            inner: Arc::new(Mutex::new(OverlayStatusInner {
+                state: OverlayState::AwaitingTexture,
+                failure: OverlayFailureReason::NONE,
+                reason: None,
+                history: VecDeque::with_capacity(HISTORY_LEN),
+                unreported: false,
+                diagnostics_unreported: false,
+                stale_frames: 0,
+                skipped_frames: 0,
+                transferred_bytes: 0,
+                saved_bytes: 0,
+            })),
+        }
+    }
+
+    pub fn diagnostics(&self) -> OverlayDiagnostics {
+        let inner = self.inner.lock();
+        OverlayDiagnostics {
+            state: inner.state,
+            failure: inner.failure,
+            reason: inner.reason.clone(),
+            transitions: inner.history.iter().cloned().collect(),
+            stale_frames: inner.stale_frames,
+            skipped_frames: inner.skipped_frames,
+            transferred_bytes: inner.transferred_bytes,
+            saved_bytes: inner.saved_bytes,
+        }
+    }
+
+    fn record(&self, to: OverlayState, failure: OverlayFailureReason, reason: Option<String>) {
+        let mut inner = self.inner.lock();
+        if inner.state == to && inner.reason == reason {
+            return;
+        }
+
+        match &reason {
+            Some(reason) => eprintln!(
+                "[{}] overlay {} -> {}: {}",
+                self.name, inner.state, to, reason
+            ),
+            None => eprintln!("[{}] overlay {} -> {}", self.name, inner.state, to),
+        }
+
+        if inner.history.len() == HISTORY_LEN {
+            inner.history.pop_front();
+        }
+
+        let from = inner.state;
+        inner.history.push_back(OverlayTransition {
+            from,
+            to,
+            reason: reason.clone(),
+            at: Instant::now(),
+        });
+
+        inner.state = to;
+        inner.failure = failure;
+        inner.reason = reason;
+        inner.unreported = true;
+        inner.diagnostics_unreported = true;
+    }
+
+    /// Transition to `to` after a successful step of the pipeline.
+    pub fn transition(&self, to: OverlayState) {
+        self.record(to, OverlayFailureReason::NONE, None)
+    }
+
+    /// Record a failure of the pipeline.
+    ///
+    /// The overlay is considered lost, unless it is still waiting for a texture.
+    pub fn fail(&self, err: &RenderError) {
+        let failure = OverlayFailureReason::from(err);
+        if failure == OverlayFailureReason::NONE {
+            return;
+        }
+        self.lost(failure, err)
+    }
+
+    /// Mark the overlay as lost for the given reason.
+    pub fn lost(&self, failure: OverlayFailureReason, reason: impl Display) {
+        self.record(OverlayState::Lost, failure, Some(reason.to_string()))
+    }
+
+    /// Count a frame that painted the last composited frame again.
+    pub fn frame_stale(&self) {
+        let mut inner = self.inner.lock();
+        inner.stale_frames += 1;
+        inner.diagnostics_unreported = true;
+    }
+
+    /// Count a frame that painted no overlay because none had been composited.
+    pub fn frame_skipped(&self) {
+        let mut inner = self.inner.lock();
+        inner.skipped_frames += 1;
+        inner.diagnostics_unreported = true;
+    }
+
+    /// Count the bytes of a frame copied or uploaded into a texture that is painted.
+    pub fn frame_transferred(&self, transfer: OverlayTransfer) {
+        let mut inner = self.inner.lock();
+        inner.transferred_bytes += transfer.transferred;
+        inner.saved_bytes += transfer.saved();
+        inner.diagnostics_unreported = true;
+    }
+
+    /// Report the current state to the orchestrator if it changed since it was last reported.
+    pub fn flush(&self, ipc: &IpcHandle) -> Result<(), RenderError> {
+        let mut inner = self.inner.lock();
+        if !inner.unreported {
+            return Ok(());
+        }
+        ipc.send(GameWindowCommand::overlay_state(
+            inner.state as u8,
+            inner.failure,
+        ))?;
+        inner.unreported = false;
+        Ok(())
+    }
+
+    /// Report the diagnostics of the overlay, driven by `backend`, to the orchestrator if they
+    /// changed since they were last reported.
+    pub fn report_diagnostics(
+        &self,
+        ipc: &IpcHandle,
+        backend: GraphicsBackends,
+    ) -> Result<(), RenderError> {
+        if !std::mem::take(&mut self.inner.lock().diagnostics_unreported) {
+            return Ok(());
+        }
+        ipc.send(GameWindowCommand::overlay_diagnostics(
+            backend,
+            &self.diagnostics(),
+        ))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::cmd::GameWindowCommandType;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    fn reported(rx: &mut UnboundedReceiver<GameWindowCommand>) -> Vec<(u8, OverlayFailureReason)> {
+        std::iter::from_fn(|| rx.try_recv().ok())
+            .filter(|cmd| cmd.ty == GameWindowCommandType::OVERLAY_STATE)
+            .map(|cmd| {
+                let params = unsafe { cmd.params.overlay_state_event };
+                (params.state, params.reason)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn transitions() {
+        let (ipc, mut rx, _) = IpcHandle::loopback();
+        let status = OverlayStatus::new("test");
+
+        // Nothing is reported until the state changes.
+        status.flush(&ipc).unwrap();
+        assert!(reported(&mut rx).is_empty());
+
+        // Only the latest state is reported, once.
+        status.transition(OverlayState::HandleDuplicated);
+        status.transition(OverlayState::Imported);
+        status.transition(OverlayState::Imported);
+        status.flush(&ipc).unwrap();
+        status.flush(&ipc).unwrap();
+        assert_eq!(
+            reported(&mut rx),
+            vec![(OverlayState::Imported as u8, OverlayFailureReason::NONE)]
+        );
+
+        // A failure that persists is reported once.
+        for _ in 0..3 {
+            status.fail(&RenderError::OverlayMutexNotReady);
+            status.flush(&ipc).unwrap();
+        }
+        assert_eq!(
+            reported(&mut rx),
+            vec![(OverlayState::Lost as u8, OverlayFailureReason::SYNC)]
+        );
+
+        // Waiting for a texture is not a failure.
+        status.fail(&RenderError::OverlayHandleNotReady);
+        status.flush(&ipc).unwrap();
+        assert!(reported(&mut rx).is_empty());
+
+        // A failure for another reason is reported again.
+        status.lost(OverlayFailureReason::IMPORT, "import failed");
+        status.flush(&ipc).unwrap();
+        assert_eq!(
+            reported(&mut rx),
+            vec![(OverlayState::Lost as u8, OverlayFailureReason::IMPORT)]
+        );
+
+        let diagnostics = status.diagnostics();
+        assert_eq!(diagnostics.state, OverlayState::Lost);
+        assert_eq!(diagnostics.failure, OverlayFailureReason::IMPORT);
+        assert_eq!(diagnostics.reason.as_deref(), Some("import failed"));
+        let transitions: Vec<_> = diagnostics
+            .transitions
+            .iter()
+            .map(|transition| (transition.from, transition.to))
+            .collect();
+        assert_eq!(
+            transitions,
+            vec![
+                (
+                    OverlayState::AwaitingTexture,
+                    OverlayState::HandleDuplicated
+                ),
+                (OverlayState::HandleDuplicated, OverlayState::Imported),
+                (OverlayState::Imported, OverlayState::Lost),
+                (OverlayState::Lost, OverlayState::Lost),
+            ]
+        );
+    }
+
+    #[test]
+    fn history() {
+        let status = OverlayStatus::new("test");
+        for _ in 0..HISTORY_LEN {
+            status.transition(OverlayState::Imported);
+            status.transition(OverlayState::Paintable);
+        }
+
+        // Only the latest transitions are kept.
+        let transitions = status.diagnostics().transitions;
+        assert_eq!(transitions.len(), HISTORY_LEN);
+        assert_eq!(transitions.last().unwrap().to, OverlayState::Paintable);
+    }
+}
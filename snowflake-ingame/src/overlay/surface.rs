@@ -0,0 +1,319 @@
+use std::convert::Infallible;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use indexmap::map::Entry;
+use indexmap::IndexMap;
+
+use crate::common::Dimensions;
+use crate::ipc::cmd::{OverlayTargetEventParams, OverlayTargetMode};
+
+/// Surfaces that have not been presented to for this long are evicted, and are not chosen
+/// as the target until they are presented to again.
+const STALE_AFTER: Duration = Duration::from_secs(5);
+
+/// How the surface the overlay is drawn on is chosen, when a kernel presents to more than one.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum OverlayTargetPolicy {
+    /// The surface with the largest area.
+    Largest,
+    /// The surface presenting to the focused window, or the previous target if none are focused.
+    ///
+    /// Focus is only tracked on Windows. Elsewhere the focused mode is taken as [`Largest`].
+    ///
+    /// [`Largest`]: OverlayTargetPolicy::Largest
+    #[cfg_attr(not(any(windows, test)), allow(dead_code))]
+    Focused,
+    /// The surface presenting to the given window, as chosen by the orchestrator.
+    Window(isize),
+}
+
+impl From<OverlayTargetEventParams> for OverlayTargetPolicy {
+    fn from(params: OverlayTargetEventParams) -> Self {
+        match params.mode {
+            #[cfg(windows)]
+            OverlayTargetMode::FOCUSED => OverlayTargetPolicy::Focused,
+            OverlayTargetMode::WINDOW => OverlayTargetPolicy::Window(params.window as isize),
+            _ => OverlayTargetPolicy::Largest,
+        }
+    }
+}
+
+/// The state kept for a single swapchain or drawable.
+pub struct Surface<S> {
+    pub state: S,
+    pub window: isize,
+    pub size: Dimensions,
+    last_present: Instant,
+}
+
+/// Per-surface state for a kernel, keyed by the identity of the swapchain or drawable.
+///
+/// Only the target surface has the overlay drawn onto it. Other surfaces keep their state,
+/// so that presenting to several surfaces in a frame does not rebuild the overlay.
+pub struct SurfaceMap<K, S> {
+    surfaces: IndexMap<K, Surface<S>>,
+    policy: OverlayTargetPolicy,
+    target: Option<K>,
+}
+
+impl<K: Hash + Eq + Clone, S> SurfaceMap<K, S> {
+    pub fn new(policy: OverlayTargetPolicy) -> SurfaceMap<K, S> {
+        SurfaceMap {
+            surfaces: IndexMap::new(),
+            policy,
+            target: None,
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: OverlayTargetPolicy) {
+        self.policy = policy;
+    }
+
+    /// Record a present to the surface `key`, creating its state if it has not been seen before.
+    pub fn present<F: FnOnce() -> S>(
+        &mut self,
+        key: K,
+        window: isize,
+        size: Dimensions,
+        init: F,
+    ) -> &mut Surface<S> {
+        match self.try_present(key, window, size, || Ok::<_, Infallible>(init())) {
+            Ok(surface) => surface,
+            Err(infallible) => match infallible {},
+        }
+    }
+
+    /// Record a present to the surface `key` as with [`present`](Self::present), with state
+    /// that may fail to be created, in which case the present is not recorded.
+    pub fn try_present<E, F: FnOnce() -> Result<S, E>>(
+        &mut self,
+        key: K,
+        window: isize,
+        size: Dimensions,
+        init: F,
+    ) -> Result<&mut Surface<S>, E> {
+        let surface = match self.surfaces.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(Surface {
+                state: init()?,
+                window,
+                size,
+                last_present: Instant::now(),
+            }),
+        };
+
+        surface.window = window;
+        surface.size = size;
+        surface.last_present = Instant::now();
+        Ok(surface)
+    }
+
+    /// Choose the target surface according to the policy, given the currently focused window.
+    ///
+    /// Stale surfaces are not chosen, whether or not they have been evicted.
+    pub fn select_target(&mut self, focused: isize) -> Option<&K> {
+        let now = Instant::now();
+        let live = || {
+            self.surfaces
+                .iter()
+                .filter(move |(_, surface)| now.duration_since(surface.last_present) < STALE_AFTER)
+        };
+
+        let by_window = |window: isize| {
+            live()
+                .find(|(_, surface)| surface.window == window)
+                .map(|(key, _)| key.clone())
+        };
+
+        let largest = || {
+            live()
+                .max_by_key(|(_, s)| s.size.width as u64 * s.size.height as u64)
+                .map(|(key, _)| key.clone())
+        };
+
+        let current = self
+            .target
+            .as_ref()
+            .filter(|key| live().any(|(other, _)| other == *key))
+            .cloned();
+
+        let target = match self.policy {
+            OverlayTargetPolicy::Largest => largest(),
+            OverlayTargetPolicy::Focused => by_window(focused).or(current).or_else(largest),
+            OverlayTargetPolicy::Window(window) => by_window(window).or_else(largest),
+        };
+
+        if target.is_some() && target != self.target {
+            eprintln!("[ovl] overlay target changed");
+        }
+        self.target = target;
+        self.target.as_ref()
+    }
+
+    #[inline]
+    pub fn is_target(&self, key: &K) -> bool {
+        self.target.as_ref() == Some(key)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut Surface<S>> {
+        self.surfaces.get_mut(key)
+    }
+
+    /// Remove the surface `key`, returning its state.
+    pub fn remove(&mut self, key: &K) -> Option<S> {
+        if self.target.as_ref() == Some(key) {
+            self.target = None;
+        }
+        self.surfaces.swap_remove(key).map(|surface| surface.state)
+    }
+
+    /// Move the state of the surface `from` to the surface `to`, which replaces it.
+    ///
+    /// The replacing surface stays the target if the replaced one was.
+    pub fn replace(&mut self, from: &K, to: K) -> Option<&mut Surface<S>> {
+        let surface = self.surfaces.swap_remove(from)?;
+        if self.target.as_ref() == Some(from) {
+            self.target = Some(to.clone());
+        }
+        let (index, _) = self.surfaces.insert_full(to, surface);
+        self.surfaces
+            .get_index_mut(index)
+            .map(|(_, surface)| surface)
+    }
+
+    /// Remove every surface whose state matches `f`, returning their state.
+    pub fn remove_matching<F: Fn(&S) -> bool>(&mut self, f: F) -> Vec<S> {
+        let keys: Vec<K> = self
+            .surfaces
+            .iter()
+            .filter(|(_, surface)| f(&surface.state))
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.iter().filter_map(|key| self.remove(key)).collect()
+    }
+
+    /// Remove surfaces whose window is no longer alive, or that have not been presented
+    /// to recently, returning their state.
+    pub fn evict<F: Fn(isize) -> bool>(&mut self, alive: F) -> Vec<S> {
+        let now = Instant::now();
+        let mut evicted = Vec::new();
+
+        let mut i = 0;
+        while i < self.surfaces.len() {
+            let (_, surface) = self.surfaces.get_index(i).unwrap();
+            if alive(surface.window) && now.duration_since(surface.last_present) < STALE_AFTER {
+                i += 1;
+                continue;
+            }
+
+            let (key, surface) = self.surfaces.swap_remove_index(i).unwrap();
+            if self.target.as_ref() == Some(&key) {
+                self.target = None;
+            }
+            evicted.push(surface.state);
+        }
+
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn three_surfaces() -> SurfaceMap<u32, ()> {
+        let mut surfaces = SurfaceMap::new(OverlayTargetPolicy::Largest);
+        surfaces.present(1, 10, Dimensions::new(800, 600), || ());
+        surfaces.present(2, 20, Dimensions::new(1920, 1080), || ());
+        surfaces.present(3, 30, Dimensions::new(640, 480), || ());
+        surfaces
+    }
+
+    #[test]
+    fn largest() {
+        let mut surfaces = three_surfaces();
+        assert_eq!(surfaces.select_target(10), Some(&2));
+        assert!(surfaces.is_target(&2));
+
+        // The target moves to a surface that grows larger.
+        surfaces.present(3, 30, Dimensions::new(2560, 1440), || ());
+        assert_eq!(surfaces.select_target(10), Some(&3));
+        assert!(!surfaces.is_target(&2));
+    }
+
+    #[test]
+    fn focused() {
+        let mut surfaces = three_surfaces();
+        surfaces.set_policy(OverlayTargetPolicy::Focused);
+        assert_eq!(surfaces.select_target(30), Some(&3));
+
+        // Without a focused surface, the previous target is kept.
+        assert_eq!(surfaces.select_target(0), Some(&3));
+        assert_eq!(surfaces.select_target(10), Some(&1));
+
+        // Without a previous target either, the largest surface is chosen.
+        let mut surfaces = three_surfaces();
+        surfaces.set_policy(OverlayTargetPolicy::Focused);
+        assert_eq!(surfaces.select_target(0), Some(&2));
+    }
+
+    #[test]
+    fn focused_mode() {
+        let policy = OverlayTargetPolicy::from(OverlayTargetEventParams {
+            mode: OverlayTargetMode::FOCUSED,
+            window: 0,
+        });
+        if cfg!(windows) {
+            assert_eq!(policy, OverlayTargetPolicy::Focused);
+        } else {
+            assert_eq!(policy, OverlayTargetPolicy::Largest);
+        }
+    }
+
+    #[test]
+    fn window() {
+        let mut surfaces = three_surfaces();
+        surfaces.set_policy(OverlayTargetPolicy::from(OverlayTargetEventParams {
+            mode: OverlayTargetMode::WINDOW,
+            window: 10,
+        }));
+        assert_eq!(surfaces.select_target(30), Some(&1));
+
+        // A window without a surface falls back to the largest surface.
+        surfaces.set_policy(OverlayTargetPolicy::Window(40));
+        assert_eq!(surfaces.select_target(30), Some(&2));
+    }
+
+    #[test]
+    fn evict() {
+        let mut surfaces = SurfaceMap::new(OverlayTargetPolicy::Largest);
+        surfaces.present(1, 10, Dimensions::new(800, 600), || "first");
+        surfaces.present(2, 20, Dimensions::new(1920, 1080), || "second");
+
+        // The state of a surface is kept across presents.
+        surfaces.present(2, 20, Dimensions::new(1920, 1080), || "replaced");
+        assert_eq!(surfaces.select_target(0), Some(&2));
+
+        // The surface of a window that is gone is evicted, and is no longer the target.
+        assert_eq!(surfaces.evict(|window| window != 20), vec!["second"]);
+        assert!(!surfaces.is_target(&2));
+        assert!(surfaces.get_mut(&2).is_none());
+        assert_eq!(surfaces.select_target(0), Some(&1));
+        assert!(surfaces.evict(|_| true).is_empty());
+    }
+
+    #[test]
+    fn stale() {
+        let mut surfaces = three_surfaces();
+        surfaces.get_mut(&2).unwrap().last_present = Instant::now() - STALE_AFTER;
+
+        // A stale surface is kept until it is evicted, but is not the target.
+        assert_eq!(surfaces.select_target(20), Some(&1));
+        assert!(surfaces.get_mut(&2).is_some());
+
+        // Presenting to it again makes it a candidate again.
+        surfaces.present(2, 20, Dimensions::new(1920, 1080), || ());
+        assert_eq!(surfaces.select_target(20), Some(&2));
+    }
+}
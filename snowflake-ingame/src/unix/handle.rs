@@ -0,0 +1,116 @@
+use std::io;
+use std::os::unix::io::{FromRawFd, OwnedFd, RawFd};
+
+use crate::ipc::cmd::{
+    OverlayPixelFormat, OverlayTextureEventParams, OverlayTextureFdEventParams,
+    DRM_FORMAT_MOD_INVALID,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum HandleError {
+    #[error("Unable to open source process")]
+    InvalidProcess,
+    #[error("Unable to duplicate handle: {0}")]
+    CannotDuplicate(io::Error),
+    #[error("Unable to close handle {0}")]
+    CannotClose(io::Error),
+    #[error("File descriptors can not be imported by this backend")]
+    Unsupported,
+    #[error("Unable to map shared memory: {0}")]
+    CannotMap(io::Error),
+}
+
+/// Duplicate the file descriptor `fd` of the process `source_pid` into this process.
+///
+/// This requires Linux 5.6 or later, and ptrace access to the source process.
+pub fn try_duplicate_handle(source_pid: u32, fd: RawFd) -> Result<RawFd, HandleError> {
+    let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, source_pid as libc::pid_t, 0) };
+    if pidfd < 0 {
+        return Err(HandleError::InvalidProcess);
+    }
+    let pidfd = pidfd as RawFd;
+
+    let duped_fd = unsafe { libc::syscall(libc::SYS_pidfd_getfd, pidfd, fd, 0) };
+    let error = io::Error::last_os_error();
+    unsafe { libc::close(pidfd) };
+
+    if duped_fd < 0 {
+        return Err(HandleError::CannotDuplicate(error));
+    }
+    Ok(duped_fd as RawFd)
+}
+
+pub fn try_close_handle(fd: RawFd) -> Result<(), HandleError> {
+    if unsafe { libc::close(fd) } != 0 {
+        return Err(HandleError::CannotClose(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+/// The file descriptors of a shared texture exported by the orchestrator, and how its
+/// memory is laid out.
+pub struct SharedFds {
+    pub memory: OwnedFd,
+    pub acquire: Option<OwnedFd>,
+    pub release: Option<OwnedFd>,
+    pub fourcc: u32,
+    pub modifier: u64,
+    pub offset: u32,
+    pub row_pitch: u32,
+}
+
+impl SharedFds {
+    /// Textures announced without file descriptors are opaque fds in the orchestrator's
+    /// process, in the `format` they were announced in and with no semaphores.
+    ///
+    /// A format without a DRM fourcc code is left as 0, which no backend imports.
+    pub fn duplicate(
+        params: &OverlayTextureEventParams,
+        format: OverlayPixelFormat,
+    ) -> Result<SharedFds, HandleError> {
+        let duped_fd = try_duplicate_handle(params.source_pid as u32, params.handle as RawFd)?;
+        Ok(SharedFds {
+            memory: unsafe { OwnedFd::from_raw_fd(duped_fd) },
+            acquire: None,
+            release: None,
+            fourcc: format.fourcc().unwrap_or_default(),
+            modifier: DRM_FORMAT_MOD_INVALID,
+            offset: 0,
+            row_pitch: 0,
+        })
+    }
+
+    /// Take ownership of the file descriptors received with `params`.
+    ///
+    /// The descriptors are owned by the returned value, or closed if no memory fd was received.
+    pub fn adopt(params: &OverlayTextureFdEventParams) -> Result<SharedFds, HandleError> {
+        let adopt = |fd: i32| (fd >= 0).then(|| unsafe { OwnedFd::from_raw_fd(fd) });
+        let (memory, acquire, release) = (
+            adopt(params.memory_fd),
+            adopt(params.acquire_fd),
+            adopt(params.release_fd),
+        );
+
+        let memory = memory.ok_or_else(|| {
+            HandleError::CannotDuplicate(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no memory fd was received",
+            ))
+        })?;
+
+        Ok(SharedFds {
+            memory,
+            acquire,
+            release,
+            fourcc: params.fourcc,
+            modifier: params.modifier,
+            offset: params.offset,
+            row_pitch: params.row_pitch,
+        })
+    }
+
+    #[inline]
+    pub fn is_dma_buf(&self) -> bool {
+        self.modifier != DRM_FORMAT_MOD_INVALID
+    }
+}
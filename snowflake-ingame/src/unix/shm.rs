@@ -0,0 +1,75 @@
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use crate::ipc::cmd::OverlaySharedMemoryEventParams;
+use crate::unix::handle::{try_duplicate_handle, HandleError};
+
+/// A read-only mapping of the shared memory the orchestrator writes overlay frames into.
+pub struct SharedMapping {
+    ptr: *const u8,
+    len: usize,
+}
+
+unsafe impl Send for SharedMapping {}
+unsafe impl Sync for SharedMapping {}
+
+impl SharedMapping {
+    /// Map the memfd received with `params`, or duplicated from the orchestrator if none was.
+    ///
+    /// The fd is closed once it has been mapped.
+    pub fn open(params: &OverlaySharedMemoryEventParams) -> Result<SharedMapping, HandleError> {
+        let fd = if params.fd >= 0 {
+            params.fd
+        } else {
+            try_duplicate_handle(params.source_pid as u32, params.handle as RawFd)?
+        };
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let len = params.size as usize;
+
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } != 0 {
+            return Err(HandleError::CannotMap(io::Error::last_os_error()));
+        }
+        if (stat.st_size as u64) < params.size || len == 0 {
+            return Err(HandleError::CannotMap(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the memfd is smaller than the announced size",
+            )));
+        }
+
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(HandleError::CannotMap(io::Error::last_os_error()));
+        }
+
+        Ok(SharedMapping {
+            ptr: ptr as *const u8,
+            len,
+        })
+    }
+
+    #[inline]
+    pub fn as_ptr(&self) -> *const u8 {
+        self.ptr
+    }
+
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.len
+    }
+}
+
+impl Drop for SharedMapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+}
@@ -1,10 +1,27 @@
-use ash::vk;
-use ash::vk::{Handle, Result};
-use windows::core::PCSTR;
-use windows::Win32::System::LibraryLoader::GetModuleHandleA;
-
 /// Get whether or not Vulkan is loaded.
+#[cfg(windows)]
 pub fn is_vk_loaded() -> bool {
+    use windows::core::PCSTR;
+    use windows::Win32::System::LibraryLoader::GetModuleHandleA;
+
     let vk_instance = unsafe { GetModuleHandleA(PCSTR(b"vulkan-1\0".as_ptr())) };
     return !vk_instance.is_err();
 }
+
+/// Get whether or not Vulkan is loaded.
+#[cfg(target_os = "linux")]
+pub fn is_vk_loaded() -> bool {
+    let vk_instance = unsafe {
+        libc::dlopen(
+            b"libvulkan.so.1\0".as_ptr().cast(),
+            libc::RTLD_LAZY | libc::RTLD_NOLOAD,
+        )
+    };
+    if vk_instance.is_null() {
+        return false;
+    }
+
+    // RTLD_NOLOAD still increments the reference count.
+    unsafe { libc::dlclose(vk_instance) };
+    true
+}
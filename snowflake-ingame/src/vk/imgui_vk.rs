@@ -0,0 +1,142 @@
+use crate::common::{Dimensions, RenderError};
+use crate::overlay::{OverlayEncoding, OverlayPaint};
+use ash::vk;
+use imgui::{Context, DrawData};
+use imgui_renderer_vk::{
+    ColorEncoding, Composite, RenderToken, RendererDevice, VulkanImguiRenderer,
+};
+use parking_lot::RwLock;
+use std::sync::Arc;
+
+/// The encoding the composite pass of the renderer converts colour from or to.
+const fn color_encoding(encoding: OverlayEncoding) -> ColorEncoding {
+    match encoding {
+        OverlayEncoding::Srgb => ColorEncoding::Srgb,
+        OverlayEncoding::Linear => ColorEncoding::Linear,
+        OverlayEncoding::Hdr10 => ColorEncoding::Hdr10,
+    }
+}
+
+pub(in crate::vk) struct VulkanImguiController {
+    imgui: Arc<RwLock<Context>>,
+    renderer: Option<VulkanImguiRenderer>,
+    render_pass: vk::RenderPass,
+}
+
+pub(in crate::vk) struct Render<'a> {
+    render: Option<&'a mut VulkanImguiRenderer>,
+    command_buffer: vk::CommandBuffer,
+}
+
+impl Render<'_> {
+    pub fn render(self, draw_data: &DrawData) -> Result<RenderToken, RenderError> {
+        if let Some(renderer) = self.render {
+            Ok(renderer.render(self.command_buffer, draw_data)?)
+        } else {
+            Err(RenderError::RendererNotReady)
+        }
+    }
+
+    /// Composite the overlay texture onto the framebuffer, before the ImGui draw data.
+    pub fn composite(&mut self, paint: &OverlayPaint) -> Result<(), RenderError> {
+        let composite = Composite {
+            position: paint.placement.position.map(|p| p as f32),
+            size: paint.placement.size.into(),
+            framebuffer: paint.viewport.into(),
+            opacity: paint.placement.opacity,
+            bgra: paint.texels.bgra,
+            opaque: paint.texels.opaque,
+            premultiplied: paint.texels.premultiplied,
+            decode: color_encoding(paint.texels.encoding),
+            encode: color_encoding(paint.output.written()),
+        };
+
+        if let Some(renderer) = &mut self.render {
+            renderer.composite(self.command_buffer, paint.texture, &composite)?;
+            Ok(())
+        } else {
+            Err(RenderError::RendererNotReady)
+        }
+    }
+}
+
+impl VulkanImguiController {
+    pub fn new(imgui: Arc<RwLock<Context>>) -> VulkanImguiController {
+        VulkanImguiController {
+            imgui,
+            renderer: None,
+            render_pass: vk::RenderPass::null(),
+        }
+    }
+
+    pub const fn renderer_ready(&self) -> bool {
+        self.renderer.is_some()
+    }
+
+    fn init_renderer(
+        &mut self,
+        device: &RendererDevice,
+        render_pass: vk::RenderPass,
+        frames_in_flight: usize,
+    ) -> Result<(), RenderError> {
+        self.renderer = Some(VulkanImguiRenderer::new(
+            device,
+            render_pass,
+            frames_in_flight,
+            &mut self.imgui.write(),
+        )?);
+        self.render_pass = render_pass;
+        Ok(())
+    }
+
+    pub fn invalidate_renderer(&mut self) {
+        self.renderer = None;
+    }
+
+    pub fn frame<'a, F: FnOnce(&mut Context, Render) -> Result<RenderToken, RenderError>>(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        f: F,
+    ) -> Result<RenderToken, RenderError> {
+        let mut imgui = self.imgui.write();
+        if let Some(renderer) = &self.renderer {
+            renderer.bind_fonts(&mut imgui);
+        }
+
+        let renderer = Render {
+            render: self.renderer.as_mut(),
+            command_buffer,
+        };
+
+        f(&mut imgui, renderer)
+    }
+
+    /// Ready the renderer to draw into subpass 0 of `render_pass`.
+    ///
+    /// `frames_in_flight` must be at least the number of frames the kernel
+    /// records before waiting on the first of them.
+    #[must_use]
+    pub fn prepare_paint(
+        &mut self,
+        device: &RendererDevice,
+        render_pass: vk::RenderPass,
+        frames_in_flight: usize,
+        screen_dim: Dimensions,
+    ) -> Result<(), RenderError> {
+        if render_pass != self.render_pass {
+            eprintln!("[vk] render pass changed");
+            self.invalidate_renderer();
+        }
+
+        if !self.renderer_ready() {
+            self.init_renderer(device, render_pass, frames_in_flight)?;
+        }
+
+        // set screen size..
+        self.imgui.write().io_mut().display_size = screen_dim.into();
+        Ok(())
+    }
+}
+
+unsafe impl Send for VulkanImguiController {}
+unsafe impl Sync for VulkanImguiController {}
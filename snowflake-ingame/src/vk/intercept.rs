@@ -0,0 +1,134 @@
+use ash::vk;
+
+/// The value an intercepted command returns when the next layer's implementation
+/// can not be found for the handle it was called with.
+pub trait InterceptReturn {
+    fn lost() -> Self;
+}
+
+impl InterceptReturn for vk::Result {
+    fn lost() -> Self {
+        vk::Result::ERROR_DEVICE_LOST
+    }
+}
+
+impl InterceptReturn for () {
+    fn lost() -> Self {}
+}
+
+/// Generate the intercepts for a list of device level Vulkan commands.
+///
+/// Each command is declared as `module_name: vkCommandName(handle: Type, args..) -> Ret;`,
+/// where the first parameter is the dispatchable handle the command is called on.
+/// For every command, a module is generated containing
+///
+/// * `Hook`, the boxed hook type, which receives the arguments of the command and a `Context`
+///   to call the next hook in the chain with,
+/// * `CHAIN`, the hook chain for the command,
+/// * `intercept`, the function returned to the application by `vkGetDeviceProcAddr`,
+/// * `call_next`, which calls the next layer's implementation of the command.
+///
+/// Along with these, `NextDeviceFns` holds the next layer's implementation of every command,
+/// loaded when the device is created, `proc_addr` returns the intercept for a command by name,
+/// and `link` inserts the terminator of every chain.
+macro_rules! vk_intercepts {
+    ($(
+        $(#[$meta:meta])*
+        $name:ident: $vk_name:ident($handle:ident: $handle_t:ty $(, $arg:ident: $arg_t:ty)* $(,)?) $(-> $ret:ty)?;
+    )*) => {
+        /// The next layer's implementation of each intercepted command.
+        #[derive(Clone, Copy, Default)]
+        pub struct NextDeviceFns {
+            $(pub $name: Option<unsafe extern "system" fn($handle_t $(, $arg_t)*) $(-> $ret)?>,)*
+        }
+
+        impl NextDeviceFns {
+            pub unsafe fn load(
+                device: ash::vk::Device,
+                get_device_proc_addr: ash::vk::PFN_vkGetDeviceProcAddr,
+            ) -> NextDeviceFns {
+                NextDeviceFns {
+                    $($name: std::mem::transmute(get_device_proc_addr(
+                        device,
+                        concat!(stringify!($vk_name), "\0").as_ptr().cast(),
+                    )),)*
+                }
+            }
+        }
+
+        /// The intercept for the command `name`, if it is intercepted and the next layer
+        /// implements it for `device`.
+        pub unsafe fn proc_addr(
+            device: ash::vk::Device,
+            name: &std::ffi::CStr,
+        ) -> ash::vk::PFN_vkVoidFunction {
+            let next = crate::vk::sys::DispatchableHandle::get_next_fns(&device)?;
+            $(
+                if name.to_bytes() == stringify!($vk_name).as_bytes() {
+                    let intercept: unsafe extern "system" fn($handle_t $(, $arg_t)*) $(-> $ret)? =
+                        $name::intercept;
+                    return next.$name.map(|_| std::mem::transmute(intercept));
+                }
+            )*
+            None
+        }
+
+        /// Insert the terminator of every chain, which calls the next layer.
+        pub fn link() -> Result<(), Box<dyn std::error::Error>> {
+            $(
+                $name::CHAIN.write()?.insert(
+                    0,
+                    Box::new(|$handle $(, $arg)*, _next| unsafe { $name::call_next($handle $(, $arg)*) }),
+                );
+            )*
+            Ok(())
+        }
+
+        $(
+            $(#[$meta])*
+            pub mod $name {
+                #[allow(unused_imports)]
+                use super::*;
+
+                pub type Hook = Box<dyn (Fn($handle_t $(, $arg_t)*, Context) $(-> $ret)?) + Send + Sync>;
+
+                crate::hook_define!(pub chain CHAIN with Hook => Context);
+
+                pub(super) unsafe extern "system" fn intercept($handle: $handle_t $(, $arg: $arg_t)*) $(-> $ret)? {
+                    if let Ok(chain) = CHAIN.read() {
+                        if let Some((_, next)) = chain.last() {
+                            let mut iter = chain.iter().rev();
+                            // Advance the chain to the next call.
+                            iter.next();
+                            return next($handle $(, $arg)*, Context { chain: iter });
+                        }
+                    }
+                    call_next($handle $(, $arg)*)
+                }
+
+                /// Call the next layer's implementation of the command.
+                pub unsafe fn call_next($handle: $handle_t $(, $arg: $arg_t)*) $(-> $ret)? {
+                    let next = crate::vk::sys::DispatchableHandle::get_next_fns(&$handle)
+                        .and_then(|next| next.$name);
+                    match next {
+                        Some(fp) => fp($handle $(, $arg)*),
+                        None => crate::vk::intercept::InterceptReturn::lost(),
+                    }
+                }
+
+                /// Add `hook` to the chain, returning the key to remove it with.
+                pub fn register(hook: Hook) -> Result<usize, Box<dyn std::error::Error>> {
+                    let key = crate::hook_key!(box hook);
+                    CHAIN.write()?.insert(key, hook);
+                    Ok(key)
+                }
+
+                pub fn unregister(key: usize) {
+                    CHAIN.write().unwrap().remove(&key);
+                }
+            }
+        )*
+    };
+}
+
+pub(crate) use vk_intercepts;
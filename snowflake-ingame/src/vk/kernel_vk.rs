@@ -0,0 +1,763 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::mem::ManuallyDrop;
+use std::sync::{Arc, OnceLock};
+
+use ash::vk;
+use ash::vk::Handle;
+use imgui_renderer_vk::RendererDevice;
+use parking_lot::{RwLock, RwLockWriteGuard};
+
+use crate::common::{Dimensions, RenderError};
+use crate::detect::GraphicsApi;
+use crate::hook::{HookChain, HookHandle};
+use crate::ipc::cmd::GameWindowCommandType;
+use crate::kernel::common::{FrameKernel, KernelContext};
+use crate::overlay::{
+    claim_window, take_commands, OverlayBackend, OverlayEncoding, OverlayFormat, OverlayOutput,
+    OverlayStatus, OverlayTargetPolicy, Ownership, SurfaceMap,
+};
+use crate::vk::hook::{
+    create_swapchain_khr, destroy_swapchain_khr, queue_present_khr, VkHookContext,
+};
+use crate::vk::imgui::VulkanImguiController;
+use crate::vk::overlay::{VulkanOverlay, VulkanOverlayBackend};
+use crate::vk::swapchain::SwapchainPolicy;
+use crate::vk::sys::{
+    DeviceDispatchTable, HookedVulkanDeviceHandle, HookedVulkanQueueHandle,
+    HookedVulkanSurfaceHandle,
+};
+
+/// The swapchain parameters needed to draw onto its images.
+#[derive(Clone, Copy)]
+struct SwapchainInfo {
+    device: vk::Device,
+    window: isize,
+    format: vk::Format,
+    color_space: vk::ColorSpaceKHR,
+    extent: vk::Extent2D,
+    /// Whether the swapchain was passed as `oldSwapchain` when creating another swapchain.
+    /// Images already acquired from it can still be presented, but the overlay is not drawn on them.
+    retired: bool,
+}
+
+impl SwapchainInfo {
+    /// The framebuffer the overlay is composited onto.
+    fn output(&self) -> OverlayOutput {
+        let (format, srgb_writes) = match self.format {
+            vk::Format::B8G8R8A8_UNORM => (Some(OverlayFormat::Bgra8), false),
+            vk::Format::B8G8R8A8_SRGB => (Some(OverlayFormat::Bgra8), true),
+            vk::Format::R8G8B8A8_UNORM => (Some(OverlayFormat::Rgba8), false),
+            vk::Format::R8G8B8A8_SRGB => (Some(OverlayFormat::Rgba8), true),
+            vk::Format::A2B10G10R10_UNORM_PACK32 => (Some(OverlayFormat::Rgb10A2), false),
+            vk::Format::R16G16B16A16_SFLOAT => (Some(OverlayFormat::Rgba16Float), false),
+            _ => (None, false),
+        };
+        let encoding = match self.color_space {
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => OverlayEncoding::Hdr10,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => OverlayEncoding::Linear,
+            _ => OverlayEncoding::Srgb,
+        };
+        OverlayOutput {
+            format,
+            encoding,
+            srgb_writes,
+        }
+    }
+}
+
+/// The command buffer and synchronization objects for a single frame in flight.
+struct FrameSync {
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    semaphore: vk::Semaphore,
+}
+
+/// The objects used to draw onto the images of a single swapchain.
+struct SwapchainFrames {
+    device: ash::Device,
+    queue_family_index: u32,
+    render_pass: vk::RenderPass,
+    views: Vec<vk::ImageView>,
+    framebuffers: Vec<vk::Framebuffer>,
+    command_pool: vk::CommandPool,
+    frames: Vec<FrameSync>,
+    frame_index: usize,
+}
+
+impl SwapchainFrames {
+    fn new(
+        dispatch: &DeviceDispatchTable,
+        swapchain: vk::SwapchainKHR,
+        info: &SwapchainInfo,
+        queue_family_index: u32,
+    ) -> Result<SwapchainFrames, vk::Result> {
+        // Every object is put into this as it is created, so that a failure part way
+        // through releases everything created before it.
+        let mut frames = SwapchainFrames {
+            device: dispatch.device_vtable.clone(),
+            queue_family_index,
+            render_pass: vk::RenderPass::null(),
+            views: Vec::new(),
+            framebuffers: Vec::new(),
+            command_pool: vk::CommandPool::null(),
+            frames: Vec::new(),
+            frame_index: 0,
+        };
+
+        let images = unsafe { dispatch.swapchain_vtable.get_swapchain_images(swapchain)? };
+        unsafe { frames.create(&images, info)? };
+        Ok(frames)
+    }
+
+    unsafe fn create(
+        &mut self,
+        images: &[vk::Image],
+        info: &SwapchainInfo,
+    ) -> Result<(), vk::Result> {
+        let device = &self.device;
+
+        // The overlay is drawn over whatever the application presented.
+        let attachments = [vk::AttachmentDescription::builder()
+            .format(info.format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .build()];
+        let color_attachments = [vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+        let subpasses = [vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachments)
+            .build()];
+        let dependencies = [vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            )
+            .build()];
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+        self.render_pass = device.create_render_pass(&render_pass_info, None)?;
+
+        for image in images {
+            let view_info = vk::ImageViewCreateInfo::builder()
+                .image(*image)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(info.format)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                });
+            let view = device.create_image_view(&view_info, None)?;
+            self.views.push(view);
+
+            let views = [view];
+            let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(self.render_pass)
+                .attachments(&views)
+                .width(info.extent.width)
+                .height(info.extent.height)
+                .layers(1);
+            self.framebuffers
+                .push(device.create_framebuffer(&framebuffer_info, None)?);
+        }
+
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(self.queue_family_index);
+        self.command_pool = device.create_command_pool(&pool_info, None)?;
+
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(self.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(images.len() as u32);
+        let command_buffers = device.allocate_command_buffers(&alloc_info)?;
+
+        for command_buffer in command_buffers {
+            let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+            let fence = device.create_fence(&fence_info, None)?;
+            let semaphore = device
+                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+                .map_err(|e| {
+                    device.destroy_fence(fence, None);
+                    e
+                })?;
+            self.frames.push(FrameSync {
+                command_buffer,
+                fence,
+                semaphore,
+            });
+        }
+        Ok(())
+    }
+
+    /// The number of frames that can be in flight at once.
+    fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Wait for the next frame to be free, and begin recording its command buffer.
+    ///
+    /// A fence only signals once every batch submitted before it has completed, so waiting
+    /// on the frame recorded `len()` frames ago also frees every draw buffer the imgui
+    /// renderer rotated through since. The fence is only reset when the frame is submitted,
+    /// so that it stays signalled if the frame fails before then.
+    fn begin_frame(&mut self) -> Result<&FrameSync, vk::Result> {
+        let index = self.frame_index;
+        self.frame_index = (self.frame_index + 1) % self.frames.len();
+        let frame = &self.frames[index];
+
+        unsafe {
+            self.device
+                .wait_for_fences(&[frame.fence], true, u64::MAX)?;
+            self.device
+                .reset_command_buffer(frame.command_buffer, vk::CommandBufferResetFlags::empty())?;
+
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            self.device
+                .begin_command_buffer(frame.command_buffer, &begin_info)?;
+        }
+        Ok(frame)
+    }
+}
+
+impl Drop for SwapchainFrames {
+    fn drop(&mut self) {
+        unsafe {
+            let fences: Vec<vk::Fence> = self.frames.iter().map(|frame| frame.fence).collect();
+            if !fences.is_empty() {
+                self.device
+                    .wait_for_fences(&fences, true, u64::MAX)
+                    .unwrap_or(());
+            }
+
+            for frame in self.frames.drain(..) {
+                self.device.destroy_semaphore(frame.semaphore, None);
+                self.device.destroy_fence(frame.fence, None);
+            }
+
+            // Destroying the pool frees its command buffers.
+            self.device.destroy_command_pool(self.command_pool, None);
+            for framebuffer in self.framebuffers.drain(..) {
+                self.device.destroy_framebuffer(framebuffer, None);
+            }
+            for view in self.views.drain(..) {
+                self.device.destroy_image_view(view, None);
+            }
+            self.device.destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
+
+/// Overlay and renderer state for a single swapchain.
+struct VulkanSurface {
+    device: vk::Device,
+    imgui: VulkanImguiController,
+    overlay: VulkanOverlay,
+    // Dropped last, the renderer and overlay may be in use by frames in flight.
+    frames: Option<SwapchainFrames>,
+}
+
+impl VulkanSurface {
+    /// Release everything built for the images of the swapchain, to be rebuilt for the swapchain
+    /// replacing it on the next present. The overlay texture is kept.
+    fn retire_frames(&mut self) {
+        // The frames wait for their submissions to complete before they are released.
+        self.frames = None;
+        // The renderer was built for the render pass and image count of the old swapchain.
+        self.imgui.invalidate_renderer();
+    }
+}
+
+impl Drop for VulkanSurface {
+    fn drop(&mut self) {
+        // The imgui renderer and overlay texture do not track their use by the device.
+        if let Some(device) = unsafe { self.device.get_device_vtable() } {
+            unsafe { device.device_wait_idle() }.unwrap_or(());
+        }
+    }
+}
+
+pub struct VulkanKernel {
+    hook: VkHookContext,
+    context: KernelContext,
+    surfaces: Arc<RwLock<SurfaceMap<u64, VulkanSurface>>>,
+    swapchains: Arc<RwLock<HashMap<vk::SwapchainKHR, SwapchainInfo>>>,
+    swapchain_policy: Arc<RwLock<SwapchainPolicy>>,
+    lost_devices: Arc<RwLock<HashSet<vk::Device>>>,
+    status: OverlayStatus,
+}
+
+impl FrameKernel for VulkanKernel {
+    type Handle = impl HookHandle;
+
+    fn new(context: KernelContext) -> Result<Self, Box<dyn Error>> {
+        Ok(VulkanKernel {
+            hook: VkHookContext::init()?,
+            context,
+            surfaces: Arc::new(RwLock::new(SurfaceMap::new(OverlayTargetPolicy::Largest))),
+            swapchains: Arc::new(RwLock::new(HashMap::new())),
+            swapchain_policy: Arc::new(RwLock::new(SwapchainPolicy::default())),
+            lost_devices: Arc::new(RwLock::new(HashSet::new())),
+            status: OverlayStatus::new("vk"),
+        })
+    }
+
+    fn init(&mut self) -> Result<ManuallyDrop<Self::Handle>, Box<dyn Error>> {
+        println!("[vk] init");
+        let handle = self
+            .hook
+            .new(
+                self.make_create_swapchain(),
+                self.make_destroy_swapchain(),
+                self.make_queue_present(),
+            )?
+            .persist();
+
+        Ok(handle)
+    }
+
+    fn status(&self) -> OverlayStatus {
+        self.status.clone()
+    }
+}
+
+/// The window that currently has focus.
+#[cfg(windows)]
+fn focused_window() -> isize {
+    unsafe { windows::Win32::UI::WindowsAndMessaging::GetForegroundWindow() }.0
+}
+
+/// Focus is not tracked for X11 or Wayland windows, so the focused policy is never chosen here.
+#[cfg(not(windows))]
+fn focused_window() -> isize {
+    0
+}
+
+impl VulkanKernel {
+    /// Activate the Vulkan kernel. The kernel is only activated once, by the first
+    /// device created after the layer is loaded.
+    pub fn activate(context: KernelContext) {
+        static ACTIVATED: OnceLock<()> = OnceLock::new();
+        ACTIVATED.get_or_init(|| {
+            match crate::activate::<VulkanKernel>(&context, GraphicsApi::Vulkan) {
+                Ok(()) => println!("[init] Vulkan kernel active"),
+                Err(e) => eprintln!("[init] unable to activate Vulkan kernel: {}", e),
+            }
+        });
+    }
+
+    fn present_impl(
+        context: &KernelContext,
+        status: &OverlayStatus,
+        queue: vk::Queue,
+        present_info: &vk::PresentInfoKHR,
+        mut surfaces: RwLockWriteGuard<SurfaceMap<u64, VulkanSurface>>,
+        swapchains: &HashMap<vk::SwapchainKHR, SwapchainInfo>,
+        swapchain_policy: &RwLock<SwapchainPolicy>,
+    ) -> Result<Option<vk::Semaphore>, RenderError> {
+        let (device, queue_family_index) = match unsafe { queue.get_queue_device() } {
+            Some(queue_device) => queue_device,
+            None => return Ok(None),
+        };
+
+        let dispatch = match unsafe { device.get_dispatch() } {
+            Some(dispatch) => dispatch,
+            None => return Ok(None),
+        };
+
+        let (presented, image_indices) = unsafe {
+            let count = present_info.swapchain_count as usize;
+            (
+                std::slice::from_raw_parts(present_info.p_swapchains, count),
+                std::slice::from_raw_parts(present_info.p_image_indices, count),
+            )
+        };
+
+        for swapchain in presented {
+            if let Some(info) = swapchains.get(swapchain).filter(|info| !info.retired) {
+                let size = Dimensions {
+                    width: info.extent.width,
+                    height: info.extent.height,
+                };
+                surfaces.present(swapchain.as_raw(), info.window, size, || VulkanSurface {
+                    device: info.device,
+                    imgui: VulkanImguiController::new(context.imgui.clone()),
+                    overlay: VulkanOverlay::with_status(
+                        VulkanOverlayBackend::new(),
+                        status.clone(),
+                    ),
+                    frames: None,
+                });
+            }
+        }
+
+        // Surfaces are not evicted here, since releasing one waits for the device to idle.
+        // They are removed when their swapchain is destroyed, and stale ones are not targeted.
+        surfaces.select_target(focused_window());
+
+        let (swapchain, image_index) = match presented
+            .iter()
+            .zip(image_indices)
+            .find(|(swapchain, _)| surfaces.is_target(&swapchain.as_raw()))
+        {
+            Some((swapchain, image_index)) => (*swapchain, *image_index as usize),
+            None => return Ok(None),
+        };
+
+        let info = match swapchains.get(&swapchain).filter(|info| !info.retired) {
+            Some(info) => *info,
+            None => return Ok(None),
+        };
+
+        let ownership = claim_window(info.window, VulkanOverlayBackend::NAME);
+
+        // Only the backend that owns the window the commands are routed to receives them,
+        // so that the overlay texture is handed to the overlay that paints it. Every command
+        // that arrived since the last frame is handled, so that frames the orchestrator
+        // announced are not painted late.
+        let cmds = if ownership.is_owner() {
+            take_commands(info.window, &context.ipc)
+        } else {
+            Vec::new()
+        };
+
+        for cmd in &cmds {
+            if cmd.ty == GameWindowCommandType::OVERLAY_TARGET {
+                surfaces.set_policy(unsafe { cmd.params.overlay_target_event }.into());
+            }
+            if cmd.ty == GameWindowCommandType::SWAPCHAIN_POLICY {
+                *swapchain_policy.write() = unsafe { cmd.params.swapchain_policy_event }.into();
+            }
+        }
+
+        let VulkanSurface {
+            imgui,
+            overlay,
+            frames,
+            ..
+        } = match surfaces.get_mut(&swapchain.as_raw()) {
+            Some(surface) => &mut surface.state,
+            None => return Ok(None),
+        };
+
+        match ownership {
+            Ownership::Owned | Ownership::Acquired => {}
+            Ownership::Revoked => {
+                overlay.reset();
+                return Ok(None);
+            }
+            Ownership::Denied => return Ok(None),
+        }
+
+        // Handle update of any overlay here.
+        for cmd in &cmds {
+            overlay.handle_command(cmd);
+        }
+        for backend in overlay.backends_mut() {
+            backend.set_queue(queue, queue_family_index);
+        }
+
+        let size = Dimensions {
+            width: info.extent.width,
+            height: info.extent.height,
+        };
+        overlay.prepare_frame(&context.ipc, size, info.output(), &dispatch)?;
+
+        // Command buffers can only be submitted to queues of the family their pool was created for.
+        if frames.as_ref().map_or(true, |frames| {
+            frames.queue_family_index != queue_family_index
+        }) {
+            *frames = None;
+            *frames = Some(SwapchainFrames::new(
+                &dispatch,
+                swapchain,
+                &info,
+                queue_family_index,
+            )?);
+        }
+
+        // Checked above.
+        let frames = unsafe { frames.as_mut().unwrap_unchecked() };
+
+        let renderer_device = RendererDevice {
+            device: dispatch.device_vtable.clone(),
+            memory_properties: unsafe {
+                dispatch
+                    .instance_vtable
+                    .get_physical_device_memory_properties(dispatch.physical_device)
+            },
+            queue,
+            queue_family_index,
+        };
+
+        imgui
+            .prepare_paint(&renderer_device, frames.render_pass, frames.len(), size)
+            .map_err(|e| RenderError::ImGuiNotReady(Box::new(e)))?;
+
+        let render_pass = frames.render_pass;
+        let framebuffer = frames.framebuffers[image_index];
+        let device = frames.device.clone();
+        let frame = frames.begin_frame()?;
+        let (command_buffer, fence, semaphore) =
+            (frame.command_buffer, frame.fence, frame.semaphore);
+
+        // New frames are copied out of the shared textures by submissions of their own,
+        // which are ordered before this frame's on the queue.
+        let layers = overlay.acquire_sync();
+
+        let begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(render_pass)
+            .framebuffer(framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: info.extent,
+            });
+
+        let result = unsafe {
+            device.cmd_begin_render_pass(command_buffer, &begin_info, vk::SubpassContents::INLINE);
+            let result = imgui.frame(command_buffer, |ctx, mut render| {
+                let ui = ctx.frame();
+                layers.paint(|paint| render.composite(paint))?;
+                ui.show_metrics_window(&mut false);
+                ui.show_demo_window(&mut false);
+                let token = render.render(ui.render())?;
+                Ok(token)
+            });
+            device.cmd_end_render_pass(command_buffer);
+            result
+        };
+
+        unsafe { device.end_command_buffer(command_buffer)? };
+
+        // The command buffer is submitted even if imgui failed to render, so that the fence
+        // for this frame is signalled.
+        Self::submit(
+            &device,
+            queue,
+            present_info,
+            command_buffer,
+            fence,
+            semaphore,
+        )?;
+        drop(layers);
+
+        // The application's semaphores have been waited on by the submission, so the
+        // present has to wait on ours whether or not imgui rendered.
+        if let Err(e) = result {
+            status.fail(&e);
+        }
+        Ok(Some(semaphore))
+    }
+
+    /// Stop drawing on a lost device, releasing the state of every surface created on it.
+    ///
+    /// The device stays lost until a swapchain is created on it again.
+    fn device_lost(
+        status: &OverlayStatus,
+        surfaces: &RwLock<SurfaceMap<u64, VulkanSurface>>,
+        lost_devices: &RwLock<HashSet<vk::Device>>,
+        device: vk::Device,
+    ) {
+        if !lost_devices.write().insert(device) {
+            return;
+        }
+
+        eprintln!("[vk] device lost, releasing overlay");
+        // Work on a lost device completes immediately, so releasing the surfaces does not block.
+        drop(
+            surfaces
+                .write()
+                .remove_matching(|surface| surface.device == device),
+        );
+        status.fail(&RenderError::VulkanError(vk::Result::ERROR_DEVICE_LOST));
+    }
+
+    /// Submit the overlay, waiting on the semaphores the application presents with,
+    /// and signalling `semaphore` for the present to wait on instead.
+    fn submit(
+        device: &ash::Device,
+        queue: vk::Queue,
+        present_info: &vk::PresentInfoKHR,
+        command_buffer: vk::CommandBuffer,
+        fence: vk::Fence,
+        semaphore: vk::Semaphore,
+    ) -> Result<(), RenderError> {
+        let wait_semaphores = unsafe {
+            std::slice::from_raw_parts(
+                present_info.p_wait_semaphores,
+                present_info.wait_semaphore_count as usize,
+            )
+        };
+        let wait_stages =
+            vec![vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT; wait_semaphores.len()];
+        let command_buffers = [command_buffer];
+        let signal_semaphores = [semaphore];
+
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores);
+
+        unsafe { device.reset_fences(&[fence])? };
+        if let Err(e) = unsafe { device.queue_submit(queue, &[submit_info.build()], fence) } {
+            // A failed submission leaves the fence unsignalled, and the frame waits on it
+            // before it is recorded again, so it is signalled by an empty submission instead.
+            unsafe { device.queue_submit(queue, &[], fence) }.unwrap_or(());
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    fn make_create_swapchain(&self) -> create_swapchain_khr::Hook {
+        let surfaces = self.surfaces.clone();
+        let swapchains = self.swapchains.clone();
+        let swapchain_policy = self.swapchain_policy.clone();
+        let lost_devices = self.lost_devices.clone();
+
+        Box::new(move |device, p_info, p_allocator, p_swapchain, mut next| {
+            // The policy only overrides what the surface supports, so the swapchain is not
+            // created again as the application requested if this fails.
+            let policy = *swapchain_policy.read();
+            let create_info = match unsafe { device.get_dispatch() } {
+                Some(dispatch) => unsafe { policy.apply(&dispatch, &*p_info) },
+                None => unsafe { *p_info },
+            };
+
+            let fp = next.fp_next();
+            let result = fp(device, &create_info, p_allocator, p_swapchain, next);
+
+            // The old swapchain is retired even if creating the new one failed.
+            let old_swapchain = create_info.old_swapchain;
+            if let Some(old) = swapchains.write().get_mut(&old_swapchain) {
+                old.retired = true;
+            }
+
+            if result != vk::Result::SUCCESS {
+                return result;
+            }
+
+            let swapchain = unsafe { *p_swapchain };
+            let window = unsafe { create_info.surface.get_window() }
+                .unwrap_or(create_info.surface.as_raw() as isize);
+
+            lost_devices.write().remove(&device);
+            swapchains.write().insert(
+                swapchain,
+                SwapchainInfo {
+                    device,
+                    window,
+                    format: create_info.image_format,
+                    color_space: create_info.image_color_space,
+                    extent: create_info.image_extent,
+                    retired: false,
+                },
+            );
+
+            // Hand the overlay over to the new swapchain, rebuilding the per-image state lazily.
+            if old_swapchain != vk::SwapchainKHR::null() {
+                if let Some(surface) = surfaces
+                    .write()
+                    .replace(&old_swapchain.as_raw(), swapchain.as_raw())
+                {
+                    surface.state.retire_frames();
+                }
+            }
+            result
+        })
+    }
+
+    fn make_destroy_swapchain(&self) -> destroy_swapchain_khr::Hook {
+        let surfaces = self.surfaces.clone();
+        let swapchains = self.swapchains.clone();
+
+        Box::new(move |device, swapchain, p_allocator, mut next| {
+            // The surface state has to be dropped before the swapchain images are destroyed.
+            drop(surfaces.write().remove(&swapchain.as_raw()));
+            swapchains.write().remove(&swapchain);
+
+            let fp = next.fp_next();
+            fp(device, swapchain, p_allocator, next)
+        })
+    }
+
+    fn make_queue_present(&self) -> queue_present_khr::Hook {
+        let context = self.context.clone();
+        let surfaces = self.surfaces.clone();
+        let swapchains = self.swapchains.clone();
+        let swapchain_policy = self.swapchain_policy.clone();
+        let lost_devices = self.lost_devices.clone();
+        let status = self.status.clone();
+
+        Box::new(move |queue, p_present_info, mut next| {
+            let present_info = unsafe { &*p_present_info };
+            let device = unsafe { queue.get_queue_device() }.map(|(device, _)| device);
+            let lost = device.map_or(true, |device| lost_devices.read().contains(&device));
+
+            // Nothing is drawn on a lost device until the application recreates its swapchains.
+            let drawn = if lost {
+                Ok(None)
+            } else {
+                VulkanKernel::present_impl(
+                    &context,
+                    &status,
+                    queue,
+                    present_info,
+                    surfaces.write(),
+                    &swapchains.read(),
+                    &swapchain_policy,
+                )
+            };
+
+            let semaphore = match drawn {
+                Ok(semaphore) => semaphore,
+                Err(RenderError::VulkanError(vk::Result::ERROR_DEVICE_LOST)) => {
+                    if let Some(device) = device {
+                        VulkanKernel::device_lost(&status, &surfaces, &lost_devices, device);
+                    }
+                    None
+                }
+                Err(e) => {
+                    status.fail(&e);
+                    None
+                }
+            };
+
+            // The result of the present is always the application's own.
+            let fp = next.fp_next();
+            let result = match semaphore {
+                // The overlay submission waited on the application's semaphores.
+                Some(semaphore) => {
+                    let wait_semaphores = [semaphore];
+                    let mut present_info = *present_info;
+                    present_info.wait_semaphore_count = 1;
+                    present_info.p_wait_semaphores = wait_semaphores.as_ptr();
+                    fp(queue, &present_info, next)
+                }
+                None => fp(queue, present_info, next),
+            };
+
+            if let (vk::Result::ERROR_DEVICE_LOST, Some(device)) = (result, device) {
+                VulkanKernel::device_lost(&status, &surfaces, &lost_devices, device);
+            }
+            result
+        })
+    }
+}
@@ -1,3 +1,15 @@
 pub mod entry;
 mod hook_vk;
-mod sys;
\ No newline at end of file
+mod imgui_vk;
+mod intercept;
+mod kernel_vk;
+mod overlay_vk;
+mod swapchain_vk;
+mod sys;
+
+use hook_vk as hook;
+use imgui_vk as imgui;
+use overlay_vk as overlay;
+use swapchain_vk as swapchain;
+
+pub use kernel_vk::VulkanKernel;
@@ -0,0 +1,1026 @@
+use std::cell::Cell;
+#[cfg(target_os = "linux")]
+use std::cell::RefCell;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::{AsRawFd, IntoRawFd, OwnedFd};
+
+use ash::vk;
+use ash::vk::Handle;
+use imgui::TextureId;
+#[cfg(windows)]
+use windows::Win32::Foundation::HANDLE;
+
+use imgui_renderer_vk::{find_memory_type, ImguiTexture};
+
+use crate::common::{AdapterIdentity, Dimensions, RenderError};
+use crate::ipc::cmd::{
+    GraphicsBackends, OverlayCapabilities, OverlayPixelFormat, OverlayPixelFormats,
+    OverlayTextureEventParams,
+};
+#[cfg(target_os = "linux")]
+use crate::ipc::cmd::{
+    OverlayTextureFdEventParams, DRM_FORMAT_ABGR16161616F, DRM_FORMAT_ABGR2101010,
+    DRM_FORMAT_ABGR8888, DRM_FORMAT_ARGB8888, DRM_FORMAT_XBGR16161616F, DRM_FORMAT_XBGR2101010,
+    DRM_FORMAT_XBGR8888, DRM_FORMAT_XRGB8888,
+};
+#[cfg(target_os = "linux")]
+use crate::overlay::OverlayEncoding;
+#[cfg(windows)]
+use crate::overlay::OVERLAY_SYNC_TIMEOUT_MS;
+use crate::overlay::{
+    DamageRect, Overlay, OverlayBackend, OverlayDamage, OverlayDescriptor, OverlayFilter,
+    OverlayFormat, OverlayTexels, OverlayTransfer, SyncedFrame,
+};
+use crate::platform::handle::HandleError;
+#[cfg(target_os = "linux")]
+use crate::platform::handle::SharedFds;
+#[cfg(windows)]
+use crate::platform::handle::{try_close_handle, try_duplicate_handle};
+use crate::vk::sys::DeviceDispatchTable;
+
+pub(in crate::vk) type VulkanOverlay = Overlay<VulkanOverlayBackend>;
+
+/// The handle to the shared texture sent by the orchestrator.
+#[cfg(windows)]
+type SharedHandle = HANDLE;
+#[cfg(target_os = "linux")]
+type SharedHandle = SharedFds;
+
+/// The type of the handle to the shared texture.
+#[cfg(windows)]
+const SHARED_HANDLE_TYPE: vk::ExternalMemoryHandleTypeFlags =
+    vk::ExternalMemoryHandleTypeFlags::D3D11_TEXTURE;
+
+/// The extensions required to import the shared texture.
+#[cfg(windows)]
+const OVERLAY_EXTENSION_NAMES: &str = "VK_KHR_external_memory_win32, VK_KHR_win32_keyed_mutex";
+#[cfg(target_os = "linux")]
+const OVERLAY_EXTENSION_NAMES: &str = "VK_KHR_external_memory_fd, VK_KHR_external_semaphore_fd";
+
+/// The Vulkan format of a shared texture created by the orchestrator in `format`.
+#[cfg(windows)]
+const fn vk_format(format: OverlayFormat) -> vk::Format {
+    match format {
+        OverlayFormat::Bgra8 => vk::Format::B8G8R8A8_UNORM,
+        OverlayFormat::Rgba8 => vk::Format::R8G8B8A8_UNORM,
+        OverlayFormat::Rgba16Float => vk::Format::R16G16B16A16_SFLOAT,
+        OverlayFormat::Rgb10A2 => vk::Format::A2B10G10R10_UNORM_PACK32,
+    }
+}
+
+/// The Vulkan format with the same memory layout as the DRM format `fourcc`.
+#[cfg(target_os = "linux")]
+const fn drm_format(fourcc: u32) -> Option<(OverlayFormat, vk::Format)> {
+    match fourcc {
+        DRM_FORMAT_ARGB8888 | DRM_FORMAT_XRGB8888 => {
+            Some((OverlayFormat::Bgra8, vk::Format::B8G8R8A8_UNORM))
+        }
+        DRM_FORMAT_ABGR8888 | DRM_FORMAT_XBGR8888 => {
+            Some((OverlayFormat::Rgba8, vk::Format::R8G8B8A8_UNORM))
+        }
+        DRM_FORMAT_ABGR2101010 | DRM_FORMAT_XBGR2101010 => {
+            Some((OverlayFormat::Rgb10A2, vk::Format::A2B10G10R10_UNORM_PACK32))
+        }
+        DRM_FORMAT_ABGR16161616F | DRM_FORMAT_XBGR16161616F => {
+            Some((OverlayFormat::Rgba16Float, vk::Format::R16G16B16A16_SFLOAT))
+        }
+        _ => None,
+    }
+}
+
+/// The format of the shared texture, and how its texels are composited.
+///
+/// The format already orders the channels, so the texels are never swizzled.
+#[cfg(windows)]
+fn overlay_format(
+    descriptor: &OverlayDescriptor<SharedHandle>,
+) -> Result<(OverlayFormat, vk::Format, OverlayTexels), RenderError> {
+    let (format, encoding) = descriptor.announced()?;
+    Ok((
+        format,
+        vk_format(format),
+        OverlayTexels {
+            encoding,
+            ..OverlayTexels::RGBA
+        },
+    ))
+}
+
+/// The format of the shared texture, and how its texels are composited.
+///
+/// The format already orders the channels, but formats without alpha leave it undefined.
+#[cfg(target_os = "linux")]
+fn overlay_format(
+    descriptor: &OverlayDescriptor<SharedHandle>,
+) -> Result<(OverlayFormat, vk::Format, OverlayTexels), RenderError> {
+    let fourcc = descriptor.handle.fourcc;
+    let (format, vk_format) =
+        drm_format(fourcc).ok_or(RenderError::OverlayFormatUnsupported(fourcc))?;
+    Ok((
+        format,
+        vk_format,
+        OverlayTexels {
+            opaque: matches!(
+                fourcc,
+                DRM_FORMAT_XRGB8888
+                    | DRM_FORMAT_XBGR8888
+                    | DRM_FORMAT_XBGR2101010
+                    | DRM_FORMAT_XBGR16161616F
+            ),
+            encoding: OverlayEncoding::announced(descriptor.colorspace, format),
+            ..OverlayTexels::RGBA
+        },
+    ))
+}
+
+#[cfg(windows)]
+fn memory_handle_type(_handle: &SharedHandle) -> vk::ExternalMemoryHandleTypeFlags {
+    SHARED_HANDLE_TYPE
+}
+
+#[cfg(target_os = "linux")]
+fn memory_handle_type(handle: &SharedHandle) -> vk::ExternalMemoryHandleTypeFlags {
+    if handle.is_dma_buf() {
+        vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT
+    } else {
+        vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD
+    }
+}
+
+const COLOR_SUBRESOURCE_RANGE: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
+    aspect_mask: vk::ImageAspectFlags::COLOR,
+    base_mip_level: 0,
+    level_count: 1,
+    base_array_layer: 0,
+    layer_count: 1,
+};
+
+const COLOR_SUBRESOURCE_LAYERS: vk::ImageSubresourceLayers = vk::ImageSubresourceLayers {
+    aspect_mask: vk::ImageAspectFlags::COLOR,
+    mip_level: 0,
+    base_array_layer: 0,
+    layer_count: 1,
+};
+
+/// The filters the overlay texture can be sampled with, in the order of its descriptor sets.
+const OVERLAY_FILTERS: [OverlayFilter; 2] = [OverlayFilter::Linear, OverlayFilter::Nearest];
+
+pub(in crate::vk) struct VulkanOverlayBackend {
+    device: vk::Device,
+    /// The queue the overlay is presented on, and its family, which frames are copied on.
+    queue: (vk::Queue, u32),
+    texture: Option<VkSharedTexture>,
+    filter: OverlayFilter,
+}
+
+/// The imported overlay texture, the private image its frames are copied into, and the
+/// descriptor sets that image is sampled through.
+///
+/// The descriptor set layout is identical to the one used by the imgui renderer,
+/// so the sets can be bound with the renderer's pipeline layout. There is a set for each
+/// of [`OVERLAY_FILTERS`], so that the filter can change while a frame is in flight.
+struct VkSharedTexture {
+    device: ash::Device,
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    /// A private copy of the last frame copied out of `image`, which is what is painted,
+    /// so that it can be painted again while the orchestrator holds the shared texture.
+    composited: vk::Image,
+    composited_memory: vk::DeviceMemory,
+    view: vk::ImageView,
+    samplers: [vk::Sampler; OVERLAY_FILTERS.len()],
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_sets: [vk::DescriptorSet; OVERLAY_FILTERS.len()],
+    /// The family of the queue frames are copied on, which owns `composited`.
+    queue_family_index: u32,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    // Signalled once the last copy has completed, so that the command buffer can be reused.
+    fence: vk::Fence,
+    // Signalled by the orchestrator when the texture may be read, if it sent one.
+    acquire_semaphore: vk::Semaphore,
+    // Signalled once the texture has been read, if the orchestrator sent one.
+    release_semaphore: vk::Semaphore,
+    /// Whether the orchestrator announced a frame that has not been copied yet.
+    #[cfg(target_os = "linux")]
+    ready: Cell<bool>,
+    // The image is in an undefined layout until the first time it is acquired.
+    initialized: Cell<bool>,
+    /// Whether a frame has been copied into `composited`.
+    has_frame: Cell<bool>,
+    /// The texels announced to have changed since the frame in `composited` was copied.
+    #[cfg(target_os = "linux")]
+    damage: RefCell<OverlayDamage>,
+    dimensions: Dimensions,
+    /// The number of bytes of a texel of the texture.
+    texel_size: u32,
+    texels: OverlayTexels,
+}
+
+impl Drop for VkSharedTexture {
+    fn drop(&mut self) {
+        unsafe {
+            // The texture is only replaced when the orchestrator sends a new handle,
+            // so waiting for the device here is not on the frame path.
+            self.device.device_wait_idle().unwrap_or(());
+            // Destroying the pool frees its command buffer.
+            self.device.destroy_command_pool(self.command_pool, None);
+            self.device.destroy_fence(self.fence, None);
+            self.device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            for sampler in self.samplers {
+                self.device.destroy_sampler(sampler, None);
+            }
+            self.device.destroy_image_view(self.view, None);
+            self.device.destroy_image(self.composited, None);
+            self.device.free_memory(self.composited_memory, None);
+            self.device.destroy_image(self.image, None);
+            self.device.free_memory(self.memory, None);
+            self.device.destroy_semaphore(self.acquire_semaphore, None);
+            self.device.destroy_semaphore(self.release_semaphore, None);
+        }
+    }
+}
+
+/// The frame in the composited image, which is painted instead of the shared texture.
+pub(in crate::vk) enum CompositedFrame {
+    /// The latest frame was copied out of the shared texture just now.
+    Copied(OverlayTransfer),
+    /// The latest frame was copied out of the shared texture.
+    Latest,
+    /// The orchestrator held the shared texture, so the last frame copied is painted again.
+    Stale,
+}
+
+impl SyncedFrame for CompositedFrame {
+    fn is_stale(&self) -> bool {
+        matches!(self, CompositedFrame::Stale)
+    }
+
+    fn transfer(&self) -> Option<OverlayTransfer> {
+        match self {
+            CompositedFrame::Copied(transfer) => Some(*transfer),
+            _ => None,
+        }
+    }
+}
+
+/// A barrier on the color of `image` that does not transfer it between queue families.
+fn image_barrier<'a>(image: vk::Image) -> vk::ImageMemoryBarrierBuilder<'a> {
+    vk::ImageMemoryBarrier::builder()
+        .image(image)
+        .subresource_range(COLOR_SUBRESOURCE_RANGE)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+}
+
+impl VkSharedTexture {
+    /// Import the shared texture, and create the image its frames are copied into on
+    /// queues of `queue_family_index`.
+    fn create(
+        dispatch: &DeviceDispatchTable,
+        descriptor: &OverlayDescriptor<SharedHandle>,
+        queue_family_index: u32,
+    ) -> Result<VkSharedTexture, RenderError> {
+        let device = &dispatch.device_vtable;
+
+        let (overlay_format, format, texels) = overlay_format(descriptor)?;
+        let extent = vk::Extent3D {
+            width: descriptor.dimensions.width,
+            height: descriptor.dimensions.height,
+            depth: 1,
+        };
+
+        // Every object is put into this as it is created, so that a failure part way
+        // through releases everything created before it.
+        let mut texture = VkSharedTexture {
+            device: device.clone(),
+            image: vk::Image::null(),
+            memory: vk::DeviceMemory::null(),
+            composited: vk::Image::null(),
+            composited_memory: vk::DeviceMemory::null(),
+            view: vk::ImageView::null(),
+            samplers: [vk::Sampler::null(); OVERLAY_FILTERS.len()],
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_sets: [vk::DescriptorSet::null(); OVERLAY_FILTERS.len()],
+            queue_family_index,
+            command_pool: vk::CommandPool::null(),
+            command_buffer: vk::CommandBuffer::null(),
+            fence: vk::Fence::null(),
+            acquire_semaphore: vk::Semaphore::null(),
+            release_semaphore: vk::Semaphore::null(),
+            #[cfg(target_os = "linux")]
+            ready: Cell::new(false),
+            initialized: Cell::new(false),
+            has_frame: Cell::new(false),
+            #[cfg(target_os = "linux")]
+            damage: RefCell::new(OverlayDamage::Full),
+            dimensions: descriptor.dimensions,
+            texel_size: overlay_format.texel_size(),
+            texels,
+        };
+
+        unsafe {
+            let mut external_info = vk::ExternalMemoryImageCreateInfo::builder()
+                .handle_types(memory_handle_type(&descriptor.handle));
+            #[allow(unused_mut)]
+            let mut image_info = vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(format)
+                .extent(extent)
+                .mip_levels(1)
+                .array_layers(1)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(vk::ImageUsageFlags::TRANSFER_SRC)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .push_next(&mut external_info);
+
+            // A dma-buf is laid out by the exporter, so the image is created with its layout.
+            #[cfg(target_os = "linux")]
+            let plane_layouts = [vk::SubresourceLayout {
+                offset: descriptor.handle.offset as vk::DeviceSize,
+                size: 0,
+                row_pitch: descriptor.handle.row_pitch as vk::DeviceSize,
+                array_pitch: 0,
+                depth_pitch: 0,
+            }];
+            #[cfg(target_os = "linux")]
+            let mut modifier_info = vk::ImageDrmFormatModifierExplicitCreateInfoEXT::builder()
+                .drm_format_modifier(descriptor.handle.modifier)
+                .plane_layouts(&plane_layouts);
+            #[cfg(target_os = "linux")]
+            if descriptor.handle.is_dma_buf() {
+                image_info = image_info
+                    .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
+                    .push_next(&mut modifier_info);
+            }
+
+            texture.image = device.create_image(&image_info, None)?;
+
+            let requirements = device.get_image_memory_requirements(texture.image);
+            texture.memory = import_memory(dispatch, descriptor, texture.image, requirements)?;
+            device.bind_image_memory(texture.image, texture.memory, 0)?;
+
+            #[cfg(target_os = "linux")]
+            {
+                texture.acquire_semaphore =
+                    import_semaphore(dispatch, descriptor.handle.acquire.as_ref())?;
+                texture.release_semaphore =
+                    import_semaphore(dispatch, descriptor.handle.release.as_ref())?;
+            }
+
+            let composited_info = vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(format)
+                .extent(extent)
+                .mip_levels(1)
+                .array_layers(1)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .initial_layout(vk::ImageLayout::UNDEFINED);
+            texture.composited = device.create_image(&composited_info, None)?;
+
+            let requirements = device.get_image_memory_requirements(texture.composited);
+            let alloc_info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(requirements.size)
+                .memory_type_index(overlay_memory_type(
+                    dispatch,
+                    requirements.memory_type_bits,
+                )?);
+            texture.composited_memory = device.allocate_memory(&alloc_info, None)?;
+            device.bind_image_memory(texture.composited, texture.composited_memory, 0)?;
+
+            let pool_info = vk::CommandPoolCreateInfo::builder()
+                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                .queue_family_index(queue_family_index);
+            texture.command_pool = device.create_command_pool(&pool_info, None)?;
+            let alloc_info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(texture.command_pool)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_buffer_count(1);
+            texture.command_buffer = device.allocate_command_buffers(&alloc_info)?[0];
+            let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+            texture.fence = device.create_fence(&fence_info, None)?;
+
+            let view_info = vk::ImageViewCreateInfo::builder()
+                .image(texture.composited)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(format)
+                .subresource_range(COLOR_SUBRESOURCE_RANGE);
+            texture.view = device.create_image_view(&view_info, None)?;
+
+            for (sampler, filter) in texture.samplers.iter_mut().zip(OVERLAY_FILTERS) {
+                let filter = match filter {
+                    OverlayFilter::Linear => vk::Filter::LINEAR,
+                    OverlayFilter::Nearest => vk::Filter::NEAREST,
+                };
+                let sampler_info = vk::SamplerCreateInfo::builder()
+                    .mag_filter(filter)
+                    .min_filter(filter)
+                    .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .max_anisotropy(1.0);
+                *sampler = device.create_sampler(&sampler_info, None)?;
+            }
+
+            let bindings = [vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()];
+            let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+            texture.descriptor_set_layout =
+                device.create_descriptor_set_layout(&layout_info, None)?;
+
+            let pool_sizes = [vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: OVERLAY_FILTERS.len() as u32,
+            }];
+            let pool_info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(OVERLAY_FILTERS.len() as u32)
+                .pool_sizes(&pool_sizes);
+            texture.descriptor_pool = device.create_descriptor_pool(&pool_info, None)?;
+
+            let set_layouts = [texture.descriptor_set_layout; OVERLAY_FILTERS.len()];
+            let set_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(texture.descriptor_pool)
+                .set_layouts(&set_layouts);
+            let sets = device.allocate_descriptor_sets(&set_info)?;
+            texture.descriptor_sets.copy_from_slice(&sets);
+
+            let image_infos = texture.samplers.map(|sampler| {
+                [vk::DescriptorImageInfo {
+                    sampler,
+                    image_view: texture.view,
+                    image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                }]
+            });
+            let writes = texture
+                .descriptor_sets
+                .iter()
+                .zip(&image_infos)
+                .map(|(set, image_info)| {
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(*set)
+                        .dst_binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(image_info)
+                        .build()
+                })
+                .collect::<Vec<_>>();
+            device.update_descriptor_sets(&writes, &[]);
+        }
+
+        Ok(texture)
+    }
+
+    /// Whether the orchestrator sent semaphores, which are only waited on and signalled in
+    /// lockstep, so that it announces every frame written into the texture.
+    #[cfg(target_os = "linux")]
+    #[inline]
+    fn is_synchronized(&self) -> bool {
+        self.acquire_semaphore != vk::Semaphore::null()
+            && self.release_semaphore != vk::Semaphore::null()
+    }
+
+    /// Record the copy of `rects` out of the shared texture into the composited image,
+    /// transferring the shared texture from the orchestrator and back around it.
+    unsafe fn record_copy(&self, rects: &[DamageRect]) -> Result<(), vk::Result> {
+        let device = &self.device;
+        let command_buffer = self.command_buffer;
+        device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.begin_command_buffer(command_buffer, &begin_info)?;
+
+        let shared_layout = if self.initialized.get() {
+            vk::ImageLayout::GENERAL
+        } else {
+            vk::ImageLayout::UNDEFINED
+        };
+        let composited_layout = if self.has_frame.get() {
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        } else {
+            vk::ImageLayout::UNDEFINED
+        };
+
+        // Frames submitted before this one may still be sampling the composited image.
+        let acquire = [
+            image_barrier(self.image)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .old_layout(shared_layout)
+                .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_EXTERNAL)
+                .dst_queue_family_index(self.queue_family_index)
+                .build(),
+            image_barrier(self.composited)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .old_layout(composited_layout)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .build(),
+        ];
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &acquire,
+        );
+
+        let regions: Vec<vk::ImageCopy> = rects
+            .iter()
+            .map(|rect| {
+                let offset = vk::Offset3D {
+                    x: rect.x as i32,
+                    y: rect.y as i32,
+                    z: 0,
+                };
+                vk::ImageCopy {
+                    src_subresource: COLOR_SUBRESOURCE_LAYERS,
+                    src_offset: offset,
+                    dst_subresource: COLOR_SUBRESOURCE_LAYERS,
+                    dst_offset: offset,
+                    extent: vk::Extent3D {
+                        width: rect.width,
+                        height: rect.height,
+                        depth: 1,
+                    },
+                }
+            })
+            .collect();
+        if !regions.is_empty() {
+            device.cmd_copy_image(
+                command_buffer,
+                self.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.composited,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+            );
+        }
+
+        // Frames are submitted after the copy on the same queue, so this also makes the copy
+        // visible to them.
+        let release = [
+            image_barrier(self.image)
+                .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .new_layout(vk::ImageLayout::GENERAL)
+                .src_queue_family_index(self.queue_family_index)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_EXTERNAL)
+                .build(),
+            image_barrier(self.composited)
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .build(),
+        ];
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &release,
+        );
+
+        device.end_command_buffer(command_buffer)
+    }
+
+    /// Submit the recorded copy to `queue`, synchronized with the orchestrator.
+    ///
+    /// Returns whether the copy was submitted, which it is not if the orchestrator holds
+    /// the keyed mutex of the texture.
+    unsafe fn submit_copy(&self, queue: vk::Queue) -> Result<bool, vk::Result> {
+        let command_buffers = [self.command_buffer];
+        let mut submit_info = vk::SubmitInfo::builder().command_buffers(&command_buffers);
+
+        #[cfg(windows)]
+        let (sync_memory, sync_keys, sync_timeouts) =
+            ([self.memory], [0u64], [OVERLAY_SYNC_TIMEOUT_MS]);
+        #[cfg(windows)]
+        let mut keyed_mutex_info = vk::Win32KeyedMutexAcquireReleaseInfoKHR::builder()
+            .acquire_syncs(&sync_memory)
+            .acquire_keys(&sync_keys)
+            .acquire_timeouts(&sync_timeouts)
+            .release_syncs(&sync_memory)
+            .release_keys(&sync_keys);
+        #[cfg(windows)]
+        {
+            submit_info = submit_info.push_next(&mut keyed_mutex_info);
+        }
+
+        // The semaphores are only waited on once the orchestrator announced that it has
+        // signalled the acquire semaphore, so the wait is bounded by work already submitted.
+        #[cfg(target_os = "linux")]
+        let (wait_semaphores, wait_stages, signal_semaphores) = (
+            [self.acquire_semaphore],
+            [vk::PipelineStageFlags::TRANSFER],
+            [self.release_semaphore],
+        );
+        #[cfg(target_os = "linux")]
+        if self.is_synchronized() {
+            submit_info = submit_info
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&wait_stages)
+                .signal_semaphores(&signal_semaphores);
+        }
+
+        self.device.reset_fences(&[self.fence])?;
+        match self
+            .device
+            .queue_submit(queue, &[submit_info.build()], self.fence)
+        {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                // The fence is waited on before the command buffer is recorded again.
+                self.device
+                    .queue_submit(queue, &[], self.fence)
+                    .unwrap_or(());
+                match e {
+                    vk::Result::TIMEOUT => Ok(false),
+                    e => Err(e),
+                }
+            }
+        }
+    }
+
+    /// Copy the newest frame out of the shared texture on `queue`, without waiting on
+    /// the orchestrator or on a previous copy.
+    fn composite(&self, queue: vk::Queue) -> Option<CompositedFrame> {
+        let has_frame = self.has_frame.get();
+
+        #[cfg(target_os = "linux")]
+        if self.is_synchronized() && !self.ready.get() {
+            // No frame was announced since the last copy.
+            return has_frame.then_some(CompositedFrame::Latest);
+        }
+
+        // The command buffer can not be recorded again while the last copy is in flight.
+        if !unsafe { self.device.get_fence_status(self.fence) }.unwrap_or(false) {
+            return has_frame.then_some(CompositedFrame::Stale);
+        }
+
+        // Without semaphores, the texture is copied whole every frame, since it is not told
+        // what changed, or when.
+        #[cfg(target_os = "linux")]
+        let damage = if self.is_synchronized() {
+            self.damage.replace(OverlayDamage::NONE)
+        } else {
+            OverlayDamage::Full
+        };
+
+        // The keyed mutex is acquired by the submission, so every frame is copied whole,
+        // since it is not told what changed.
+        #[cfg(windows)]
+        let damage = OverlayDamage::Full;
+
+        let rects = damage.rects(self.dimensions);
+        match unsafe {
+            self.record_copy(&rects)
+                .and_then(|()| self.submit_copy(queue))
+        } {
+            Ok(true) => {
+                self.initialized.set(true);
+                self.has_frame.set(true);
+                #[cfg(target_os = "linux")]
+                self.ready.set(false);
+                Some(CompositedFrame::Copied(OverlayTransfer::new(
+                    &rects,
+                    self.dimensions,
+                    self.texel_size,
+                )))
+            }
+            Ok(false) => has_frame.then_some(CompositedFrame::Stale),
+            Err(e) => {
+                eprintln!("[vk] failed to copy overlay frame: {:?}", e);
+                // The frame is copied again, whole, on the next attempt.
+                #[cfg(target_os = "linux")]
+                self.damage.replace(OverlayDamage::Full);
+                has_frame.then_some(CompositedFrame::Stale)
+            }
+        }
+    }
+}
+
+/// Find a memory type of `type_bits` to import the shared texture into,
+/// preferring device local memory.
+fn overlay_memory_type(dispatch: &DeviceDispatchTable, type_bits: u32) -> Result<u32, RenderError> {
+    let memory_properties = unsafe {
+        dispatch
+            .instance_vtable
+            .get_physical_device_memory_properties(dispatch.physical_device)
+    };
+    find_memory_type(
+        &memory_properties,
+        type_bits,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )
+    .or_else(|| {
+        find_memory_type(
+            &memory_properties,
+            type_bits,
+            vk::MemoryPropertyFlags::empty(),
+        )
+    })
+    .ok_or_else(|| {
+        imgui_renderer_vk::RenderError::MemoryTypeError(Box::new("overlay texture")).into()
+    })
+}
+
+/// Import the memory of the shared texture as a dedicated allocation for `image`.
+#[cfg(windows)]
+unsafe fn import_memory(
+    dispatch: &DeviceDispatchTable,
+    descriptor: &OverlayDescriptor<SharedHandle>,
+    image: vk::Image,
+    requirements: vk::MemoryRequirements,
+) -> Result<vk::DeviceMemory, RenderError> {
+    let memory_type = overlay_memory_type(dispatch, requirements.memory_type_bits)?;
+
+    // strict_provenance: handle is an int, this is fine.
+    let mut import_info = vk::ImportMemoryWin32HandleInfoKHR::builder()
+        .handle_type(SHARED_HANDLE_TYPE)
+        .handle(descriptor.handle.0 as vk::HANDLE);
+    let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder().image(image);
+    let alloc_info = vk::MemoryAllocateInfo::builder()
+        .allocation_size(requirements.size)
+        .memory_type_index(memory_type)
+        .push_next(&mut import_info)
+        .push_next(&mut dedicated_info);
+    Ok(dispatch.device_vtable.allocate_memory(&alloc_info, None)?)
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn import_memory(
+    dispatch: &DeviceDispatchTable,
+    descriptor: &OverlayDescriptor<SharedHandle>,
+    image: vk::Image,
+    requirements: vk::MemoryRequirements,
+) -> Result<vk::DeviceMemory, RenderError> {
+    let handle = &descriptor.handle;
+    let handle_type = memory_handle_type(handle);
+
+    // A successful import takes ownership of the fd, but the handle may be imported again
+    // if the overlay moves to another device.
+    let fd = handle
+        .memory
+        .try_clone()
+        .map_err(RenderError::OverlayFdError)?;
+
+    let mut memory_type_bits = requirements.memory_type_bits;
+    let mut size = requirements.size;
+    if handle.is_dma_buf() {
+        let external_memory_fd = ash::extensions::khr::ExternalMemoryFd::new(
+            &dispatch.instance_vtable,
+            &dispatch.device_vtable,
+        );
+        let properties =
+            external_memory_fd.get_memory_fd_properties(handle_type, fd.as_raw_fd())?;
+        memory_type_bits &= properties.memory_type_bits;
+    } else if descriptor.size != 0 {
+        // Opaque fds have to be imported with the size they were exported with.
+        size = descriptor.size;
+    }
+    let memory_type = overlay_memory_type(dispatch, memory_type_bits)?;
+
+    let mut import_info = vk::ImportMemoryFdInfoKHR::builder()
+        .handle_type(handle_type)
+        .fd(fd.as_raw_fd());
+    let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder().image(image);
+    let alloc_info = vk::MemoryAllocateInfo::builder()
+        .allocation_size(size)
+        .memory_type_index(memory_type)
+        .push_next(&mut import_info)
+        .push_next(&mut dedicated_info);
+    let memory = dispatch.device_vtable.allocate_memory(&alloc_info, None)?;
+
+    // The fd is owned by the memory now.
+    let _ = fd.into_raw_fd();
+    Ok(memory)
+}
+
+/// Import a semaphore sent by the orchestrator, or return a null semaphore if none was sent.
+#[cfg(target_os = "linux")]
+unsafe fn import_semaphore(
+    dispatch: &DeviceDispatchTable,
+    fd: Option<&OwnedFd>,
+) -> Result<vk::Semaphore, RenderError> {
+    let fd = match fd {
+        Some(fd) => fd.try_clone().map_err(RenderError::OverlayFdError)?,
+        None => return Ok(vk::Semaphore::null()),
+    };
+
+    let device = &dispatch.device_vtable;
+    let semaphore = device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?;
+
+    let external_semaphore_fd =
+        ash::extensions::khr::ExternalSemaphoreFd::new(&dispatch.instance_vtable, device);
+    let import_info = vk::ImportSemaphoreFdInfoKHR::builder()
+        .semaphore(semaphore)
+        .handle_type(vk::ExternalSemaphoreHandleTypeFlags::OPAQUE_FD)
+        .fd(fd.as_raw_fd());
+    if let Err(e) = external_semaphore_fd.import_semaphore_fd(&import_info) {
+        device.destroy_semaphore(semaphore, None);
+        return Err(e.into());
+    }
+
+    // The fd is owned by the semaphore now.
+    let _ = fd.into_raw_fd();
+    Ok(semaphore)
+}
+
+impl VulkanOverlayBackend {
+    pub fn new() -> VulkanOverlayBackend {
+        VulkanOverlayBackend {
+            device: vk::Device::null(),
+            queue: (vk::Queue::null(), 0),
+            texture: None,
+            filter: OverlayFilter::Linear,
+        }
+    }
+
+    /// Copy frames on `queue` of `queue_family_index`, which the overlay is presented on.
+    ///
+    /// A texture imported for another queue family can not be painted, and is imported again.
+    pub fn set_queue(&mut self, queue: vk::Queue, queue_family_index: u32) {
+        self.queue = (queue, queue_family_index);
+    }
+}
+
+impl Default for VulkanOverlayBackend {
+    fn default() -> Self {
+        VulkanOverlayBackend::new()
+    }
+}
+
+impl OverlayBackend for VulkanOverlayBackend {
+    const NAME: &'static str = "vk";
+    const BACKEND: GraphicsBackends = GraphicsBackends::VULKAN;
+
+    type Handle = SharedHandle;
+    type Target<'a> = &'a DeviceDispatchTable;
+    type SyncGuard<'a> = CompositedFrame;
+
+    #[cfg(windows)]
+    fn duplicate_handle(
+        &self,
+        params: &OverlayTextureEventParams,
+        _format: OverlayPixelFormat,
+    ) -> Result<SharedHandle, HandleError> {
+        let handle = HANDLE(params.handle as isize);
+        let duped_handle = try_duplicate_handle(params.source_pid as u32, handle)?;
+        eprintln!("[vk] duped handle {:x?}", duped_handle);
+        Ok(duped_handle)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn duplicate_handle(
+        &self,
+        params: &OverlayTextureEventParams,
+        format: OverlayPixelFormat,
+    ) -> Result<SharedHandle, HandleError> {
+        let fds = SharedFds::duplicate(params, format)?;
+        eprintln!("[vk] duped fd {}", fds.memory.as_raw_fd());
+        Ok(fds)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn adopt_fds(&self, params: &OverlayTextureFdEventParams) -> Result<SharedHandle, HandleError> {
+        let fds = SharedFds::adopt(params)?;
+        eprintln!(
+            "[vk] received fd {} (fourcc {:#x}, modifier {:#x})",
+            fds.memory.as_raw_fd(),
+            fds.fourcc,
+            fds.modifier
+        );
+        Ok(fds)
+    }
+
+    #[cfg(windows)]
+    fn close_handle(&self, handle: SharedHandle) -> Result<(), HandleError> {
+        try_close_handle(handle)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn close_handle(&self, handle: SharedHandle) -> Result<(), HandleError> {
+        drop(handle);
+        Ok(())
+    }
+
+    fn adapter(&self, dispatch: &&DeviceDispatchTable) -> Option<AdapterIdentity> {
+        Some(dispatch.adapter)
+    }
+
+    fn capabilities(&self, dispatch: &&DeviceDispatchTable) -> OverlayCapabilities {
+        if !dispatch.overlay_extensions {
+            return OverlayCapabilities::NONE;
+        }
+
+        #[cfg(windows)]
+        let capabilities = OverlayCapabilities::WIN32_HANDLE | OverlayCapabilities::KEYED_MUTEX;
+
+        #[cfg(target_os = "linux")]
+        let mut capabilities = OverlayCapabilities::OPAQUE_FD | OverlayCapabilities::SEMAPHORE_FD;
+        #[cfg(target_os = "linux")]
+        if dispatch.overlay_dma_buf {
+            capabilities |= OverlayCapabilities::DMA_BUF;
+        }
+
+        capabilities
+    }
+
+    fn formats(&self, dispatch: &&DeviceDispatchTable) -> OverlayPixelFormats {
+        if dispatch.overlay_extensions {
+            OverlayPixelFormats::ALL
+        } else {
+            OverlayPixelFormats::BGRA8
+        }
+    }
+
+    #[inline]
+    fn ready_to_paint(&self, dispatch: &&DeviceDispatchTable) -> bool {
+        self.texture
+            .as_ref()
+            .map_or(false, |texture| texture.queue_family_index == self.queue.1)
+            && self.device == dispatch.device_vtable.handle()
+    }
+
+    fn import(
+        &mut self,
+        descriptor: &OverlayDescriptor<SharedHandle>,
+        dispatch: &DeviceDispatchTable,
+    ) -> Result<Dimensions, RenderError> {
+        if !dispatch.overlay_extensions {
+            return Err(RenderError::VulkanMissingExtension(OVERLAY_EXTENSION_NAMES));
+        }
+
+        #[cfg(target_os = "linux")]
+        if descriptor.handle.is_dma_buf() && !dispatch.overlay_dma_buf {
+            return Err(RenderError::VulkanMissingExtension(
+                "VK_EXT_external_memory_dma_buf, VK_EXT_image_drm_format_modifier",
+            ));
+        }
+
+        self.texture = Some(VkSharedTexture::create(dispatch, descriptor, self.queue.1)?);
+        self.device = dispatch.device_vtable.handle();
+        Ok(descriptor.dimensions)
+    }
+
+    fn set_filter(&mut self, filter: OverlayFilter) {
+        self.filter = filter;
+    }
+
+    /// Mark the shared texture as holding a frame the orchestrator signalled the acquire
+    /// semaphore for, so that it is copied from the next time the texture is acquired.
+    #[cfg(target_os = "linux")]
+    fn select(&mut self, index: usize, damage: &OverlayDamage) {
+        if let Some(texture) = &mut self.texture {
+            if index == 0 && texture.is_synchronized() {
+                texture.ready.set(true);
+                texture.damage.get_mut().add(damage);
+            }
+        }
+    }
+
+    fn invalidate(&mut self) {
+        self.texture = None;
+    }
+
+    fn acquire_sync(&self) -> Option<CompositedFrame> {
+        self.texture.as_ref()?.composite(self.queue.0)
+    }
+
+    fn texture_id(&self) -> Option<TextureId> {
+        let set = OVERLAY_FILTERS
+            .iter()
+            .position(|filter| *filter == self.filter)?;
+        self.texture
+            .as_ref()
+            .map(|t| t.descriptor_sets[set].as_tex_id())
+    }
+
+    fn texels(&self) -> OverlayTexels {
+        self.texture
+            .as_ref()
+            .map_or(OverlayTexels::RGBA, |t| t.texels)
+    }
+}
+
+unsafe impl Send for VulkanOverlayBackend {}
+unsafe impl Sync for VulkanOverlayBackend {}
@@ -0,0 +1,153 @@
+use ash::vk;
+
+use crate::ipc::cmd::{SwapchainPolicyEventParams, SwapchainPresentMode, SwapchainUsage};
+use crate::vk::sys::DeviceDispatchTable;
+
+/// Overrides applied to the create info of swapchains, configured per game by the orchestrator.
+#[derive(Debug, Clone, Copy)]
+pub struct SwapchainPolicy {
+    present_mode: Option<vk::PresentModeKHR>,
+    usage: vk::ImageUsageFlags,
+    min_image_count: Option<u32>,
+    max_image_count: Option<u32>,
+}
+
+impl Default for SwapchainPolicy {
+    fn default() -> Self {
+        SwapchainPolicy {
+            present_mode: None,
+            // The overlay is drawn directly onto the swapchain images.
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            min_image_count: None,
+            max_image_count: None,
+        }
+    }
+}
+
+impl From<SwapchainPolicyEventParams> for SwapchainPolicy {
+    fn from(params: SwapchainPolicyEventParams) -> Self {
+        let present_mode = match params.present_mode {
+            SwapchainPresentMode::FIFO => Some(vk::PresentModeKHR::FIFO),
+            SwapchainPresentMode::MAILBOX => Some(vk::PresentModeKHR::MAILBOX),
+            SwapchainPresentMode::IMMEDIATE => Some(vk::PresentModeKHR::IMMEDIATE),
+            _ => None,
+        };
+
+        let mut usage = SwapchainPolicy::default().usage;
+        if params.usage.contains(SwapchainUsage::TRANSFER_SRC) {
+            usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
+        if params.usage.contains(SwapchainUsage::TRANSFER_DST) {
+            usage |= vk::ImageUsageFlags::TRANSFER_DST;
+        }
+
+        let count = |count: u32| (count != 0).then_some(count);
+        SwapchainPolicy {
+            present_mode,
+            usage,
+            min_image_count: count(params.min_image_count),
+            max_image_count: count(params.max_image_count),
+        }
+    }
+}
+
+/// The capabilities of a surface, as seen by the device a swapchain is created on.
+struct SurfaceSupport {
+    capabilities: vk::SurfaceCapabilitiesKHR,
+    present_modes: Vec<vk::PresentModeKHR>,
+}
+
+impl SurfaceSupport {
+    unsafe fn query(
+        dispatch: &DeviceDispatchTable,
+        surface: vk::SurfaceKHR,
+    ) -> Result<SurfaceSupport, vk::Result> {
+        let instance = dispatch.instance_vtable.handle();
+        let surface_fn = vk::KhrSurfaceFn::load(|name| {
+            std::mem::transmute((dispatch.get_instance_proc_addr)(instance, name.as_ptr()))
+        });
+
+        let mut capabilities = vk::SurfaceCapabilitiesKHR::default();
+        (surface_fn.get_physical_device_surface_capabilities_khr)(
+            dispatch.physical_device,
+            surface,
+            &mut capabilities,
+        )
+        .result()?;
+
+        let mut count = 0;
+        (surface_fn.get_physical_device_surface_present_modes_khr)(
+            dispatch.physical_device,
+            surface,
+            &mut count,
+            std::ptr::null_mut(),
+        )
+        .result()?;
+        let mut present_modes = vec![vk::PresentModeKHR::default(); count as usize];
+        (surface_fn.get_physical_device_surface_present_modes_khr)(
+            dispatch.physical_device,
+            surface,
+            &mut count,
+            present_modes.as_mut_ptr(),
+        )
+        .result()?;
+        present_modes.truncate(count as usize);
+
+        Ok(SurfaceSupport {
+            capabilities,
+            present_modes,
+        })
+    }
+}
+
+impl SwapchainPolicy {
+    /// Rewrite `create_info` with the overrides the surface supports.
+    ///
+    /// If the surface capabilities can not be queried, only the image usage the overlay
+    /// needs is added, as it is supported by every surface.
+    pub unsafe fn apply(
+        &self,
+        dispatch: &DeviceDispatchTable,
+        create_info: &vk::SwapchainCreateInfoKHR,
+    ) -> vk::SwapchainCreateInfoKHR {
+        let mut create_info = *create_info;
+        let support = match SurfaceSupport::query(dispatch, create_info.surface) {
+            Ok(support) => support,
+            Err(e) => {
+                eprintln!("[vk] unable to query surface capabilities: {:?}", e);
+                create_info.image_usage |= vk::ImageUsageFlags::COLOR_ATTACHMENT;
+                return create_info;
+            }
+        };
+
+        if let Some(present_mode) = self.present_mode {
+            if support.present_modes.contains(&present_mode) {
+                create_info.present_mode = present_mode;
+            } else {
+                eprintln!("[vk] present mode {:?} is not supported", present_mode);
+            }
+        }
+
+        let capabilities = &support.capabilities;
+        let unsupported = self.usage & !capabilities.supported_usage_flags;
+        if !unsupported.is_empty() {
+            eprintln!("[vk] image usage {:?} is not supported", unsupported);
+        }
+        create_info.image_usage |= self.usage & capabilities.supported_usage_flags;
+
+        let mut image_count = create_info.min_image_count;
+        if let Some(min_image_count) = self.min_image_count {
+            image_count = image_count.max(min_image_count);
+        }
+        if let Some(max_image_count) = self.max_image_count {
+            image_count = image_count.min(max_image_count);
+        }
+        // A maximum of 0 means the surface does not limit the number of images.
+        if capabilities.max_image_count != 0 {
+            image_count = image_count.min(capabilities.max_image_count);
+        }
+        create_info.min_image_count = image_count.max(capabilities.min_image_count);
+
+        create_info
+    }
+}
@@ -1,4 +1,4 @@
-use crate::vk::hook_vk::VkHookContext;
+use crate::common::AdapterIdentity;
 use crate::{HookChain, kernel};
 use ash::extensions::khr::Swapchain;
 use ash::vk::{
@@ -6,12 +6,11 @@ use ash::vk::{
     SwapchainKHR,
 };
 use ash::{Device, Instance, vk};
-use std::cell::{LazyCell, OnceCell};
+use std::cell::OnceCell;
 use std::collections::HashMap;
-use std::ffi::{c_char, c_void, CStr};
-use std::sync::{OnceLock, RwLock};
-use dashmap::DashMap;
-use windows::Win32::System::Console::AllocConsole;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::sync::{LazyLock, OnceLock, RwLock};
+use dashmap::{DashMap, DashSet};
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
 #[repr(transparent)]
@@ -45,7 +44,7 @@ struct VkLayerInstanceCreateInfo {
 struct VkLayerInstanceLink {
     p_next: *const VkLayerInstanceLink,
     pfn_next_get_instance_proc_addr: vk::PFN_vkGetInstanceProcAddr,
-    pfn_next_get_device_proc_addr: vk::PFN_vkGetDeviceProcAddr,
+    pfn_next_get_physical_device_proc_addr: Option<PFN_GetPhysicalDeviceProcAddr>,
 }
 
 #[repr(C)]
@@ -71,14 +70,31 @@ struct VkNegotiateLayerInterface {
     pfn_get_instance_proc_addr: vk::PFN_vkGetInstanceProcAddr,
     pfn_get_device_proc_addr: vk::PFN_vkGetDeviceProcAddr,
 
-    // typedef PFN_vkVoidFunction (VKAPI_PTR *PFN_GetPhysicalDeviceProcAddr)(VkInstance instance, const char* pName);
-    pfn_get_physical_device_proc_addr: Option<ash::vk::PFN_vkGetInstanceProcAddr>,
+    pfn_get_physical_device_proc_addr: Option<PFN_GetPhysicalDeviceProcAddr>,
 }
 
+// typedef PFN_vkVoidFunction (VKAPI_PTR *PFN_GetPhysicalDeviceProcAddr)(VkInstance instance, const char* pName);
+#[allow(non_camel_case_types)]
+type PFN_GetPhysicalDeviceProcAddr = vk::PFN_vkGetInstanceProcAddr;
+
+/// The oldest loader interface version the layer can be negotiated down to.
+const MIN_LOADER_LAYER_INTERFACE_VERSION: u32 = 2;
+
+/// The newest loader interface version the layer supports.
+///
+/// Versions 3 to 5 only relax requirements on the loader side, so the layer behaves
+/// the same for every version from 2 onwards.
+const MAX_LOADER_LAYER_INTERFACE_VERSION: u32 = 5;
+
 #[derive(Clone)]
 pub struct InstanceDispatchTable {
     pub get_instance_proc_addr: vk::PFN_vkGetInstanceProcAddr,
+    get_physical_device_proc_addr: Option<PFN_GetPhysicalDeviceProcAddr>,
     pub instance_vtable: Instance,
+    /// The Vulkan version the application created the instance with.
+    api_version: u32,
+    /// The instance extensions the application enabled.
+    extensions: Vec<CString>,
 }
 
 #[derive(Clone)]
@@ -87,20 +103,111 @@ pub struct DeviceDispatchTable {
     pub get_instance_proc_addr: vk::PFN_vkGetInstanceProcAddr,
     pub device_vtable: Device,
     pub instance_vtable: Instance,
+    pub swapchain_vtable: Swapchain,
     pub physical_device: vk::PhysicalDevice,
+    /// Whether the extensions required to import the overlay texture were enabled.
+    pub overlay_extensions: bool,
+    /// Whether dma-buf overlay textures with an explicit DRM format modifier can be imported.
+    pub overlay_dma_buf: bool,
+    /// The adapter the physical device is on.
+    pub adapter: AdapterIdentity,
+    /// The next layer's implementation of the intercepted commands.
+    pub next: NextDeviceFns,
+}
+
+/// The extensions required to import the overlay texture, enabled by the layer if available.
+#[cfg(windows)]
+const OVERLAY_DEVICE_EXTENSIONS: &[&CStr] = &[
+    vk::KhrExternalMemoryFn::name(),
+    vk::KhrExternalMemoryWin32Fn::name(),
+    vk::KhrWin32KeyedMutexFn::name(),
+];
+
+#[cfg(target_os = "linux")]
+const OVERLAY_DEVICE_EXTENSIONS: &[&CStr] = &[
+    vk::KhrExternalMemoryFn::name(),
+    vk::KhrExternalMemoryFdFn::name(),
+    vk::KhrExternalSemaphoreFn::name(),
+    vk::KhrExternalSemaphoreFdFn::name(),
+];
+
+/// The extensions required to import dma-buf overlay textures, in addition to the overlay
+/// extensions. Textures exported as opaque fds can be imported without them.
+#[cfg(windows)]
+const DMA_BUF_DEVICE_EXTENSIONS: &[&CStr] = &[];
+
+#[cfg(target_os = "linux")]
+const DMA_BUF_DEVICE_EXTENSIONS: &[&CStr] = &[
+    vk::ExtExternalMemoryDmaBufFn::name(),
+    vk::ExtImageDrmFormatModifierFn::name(),
+    vk::KhrImageFormatListFn::name(),
+];
+
+/// The extensions the overlay extensions depend on that were promoted to Vulkan 1.1, which
+/// are enabled along with them on devices of Vulkan 1.0.
+const OVERLAY_DEVICE_EXTENSIONS_1_0: &[&CStr] = &[
+    vk::KhrGetMemoryRequirements2Fn::name(),
+    vk::KhrDedicatedAllocationFn::name(),
+];
+
+#[cfg(windows)]
+const DMA_BUF_DEVICE_EXTENSIONS_1_0: &[&CStr] = &[];
+
+#[cfg(target_os = "linux")]
+const DMA_BUF_DEVICE_EXTENSIONS_1_0: &[&CStr] = &[
+    vk::KhrBindMemory2Fn::name(),
+    vk::KhrMaintenance1Fn::name(),
+    vk::KhrSamplerYcbcrConversionFn::name(),
+];
+
+/// The instance extensions the overlay extensions depend on before Vulkan 1.1.
+///
+/// The instance already exists when the device is created, so the overlay extensions are only
+/// enabled if the application enabled these.
+#[cfg(windows)]
+const OVERLAY_INSTANCE_EXTENSIONS_1_0: &[&CStr] = &[
+    vk::KhrGetPhysicalDeviceProperties2Fn::name(),
+    vk::KhrExternalMemoryCapabilitiesFn::name(),
+];
+
+#[cfg(target_os = "linux")]
+const OVERLAY_INSTANCE_EXTENSIONS_1_0: &[&CStr] = &[
+    vk::KhrGetPhysicalDeviceProperties2Fn::name(),
+    vk::KhrExternalMemoryCapabilitiesFn::name(),
+    vk::KhrExternalSemaphoreCapabilitiesFn::name(),
+];
+
+/// The pointer to the loader's dispatch table, stored at the start of every dispatchable handle.
+///
+/// Every handle dispatched through the same table shares a key, so physical devices resolve to
+/// the instance they were enumerated from, and queues and command buffers to their device.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+struct DispatchKey(usize);
+
+impl DispatchKey {
+    unsafe fn of<H: vk::Handle>(handle: H) -> DispatchKey {
+        match handle.as_raw() as usize {
+            0 => DispatchKey(0),
+            handle => DispatchKey(*(handle as *const usize)),
+        }
+    }
 }
 
-#[allow(clippy::type_complexity)]
-static mut DEVICE: LazyCell<DashMap<vk::Device, DeviceDispatchTable>> =
-    LazyCell::new(Default::default);
+static DEVICE: LazyLock<DashMap<DispatchKey, DeviceDispatchTable>> =
+    LazyLock::new(Default::default);
 
-#[allow(clippy::type_complexity)]
-static mut PHYSICAL_DEVICE_MAP: LazyCell<DashMap<vk::PhysicalDevice, vk::Instance>> =
-    LazyCell::new(Default::default);
+static INSTANCE: LazyLock<DashMap<DispatchKey, InstanceDispatchTable>> =
+    LazyLock::new(Default::default);
 
-#[allow(clippy::type_complexity)]
-static mut INSTANCE: LazyCell<DashMap<vk::Instance, InstanceDispatchTable>> =
-    LazyCell::new(Default::default);
+/// The queue family each queue was retrieved from.
+static QUEUE_FAMILY: LazyLock<DashMap<vk::Queue, u32>> = LazyLock::new(Default::default);
+
+/// The devices that hold a handle to the kernel.
+static KERNEL_DEVICES: LazyLock<DashSet<vk::Device>> = LazyLock::new(Default::default);
+
+/// The native window each surface was created for.
+static SURFACE_WINDOW: LazyLock<DashMap<vk::SurfaceKHR, isize>> =
+    LazyLock::new(Default::default);
 
 #[no_mangle]
 unsafe extern "system" fn get_device_proc_addr(
@@ -116,10 +223,13 @@ unsafe extern "system" fn get_device_proc_addr(
         b"vkDestroyDevice" => Some(std::mem::transmute(
             destroy_device as vk::PFN_vkDestroyDevice,
         )),
-        b"vkCreateSwapchainKHR" => Some(std::mem::transmute(
-            hooks::create_swapchain as vk::PFN_vkCreateSwapchainKHR,
+        b"vkGetDeviceQueue" => Some(std::mem::transmute(
+            get_device_queue as vk::PFN_vkGetDeviceQueue,
         )),
-        _ => get_base_device_proc_addr(device, p_name),
+        b"vkGetDeviceQueue2" => Some(std::mem::transmute(
+            get_device_queue2 as vk::PFN_vkGetDeviceQueue2,
+        )),
+        _ => hook::proc_addr(device, name).or_else(|| get_base_device_proc_addr(device, p_name)),
     }
 }
 
@@ -146,6 +256,22 @@ unsafe extern "system" fn get_instance_proc_addr(
         b"vkDestroyDevice" => Some(std::mem::transmute(
             destroy_device as vk::PFN_vkDestroyDevice,
         )),
+        #[cfg(windows)]
+        b"vkCreateWin32SurfaceKHR" => Some(std::mem::transmute(
+            create_win32_surface as vk::PFN_vkCreateWin32SurfaceKHR,
+        )),
+        #[cfg(target_os = "linux")]
+        b"vkCreateXlibSurfaceKHR" => Some(std::mem::transmute(
+            create_xlib_surface as vk::PFN_vkCreateXlibSurfaceKHR,
+        )),
+        #[cfg(target_os = "linux")]
+        b"vkCreateXcbSurfaceKHR" => Some(std::mem::transmute(
+            create_xcb_surface as vk::PFN_vkCreateXcbSurfaceKHR,
+        )),
+        #[cfg(target_os = "linux")]
+        b"vkCreateWaylandSurfaceKHR" => Some(std::mem::transmute(
+            create_wayland_surface as vk::PFN_vkCreateWaylandSurfaceKHR,
+        )),
         _ => get_base_instance_proc_addr(instance, p_name),
     }
 }
@@ -161,7 +287,7 @@ unsafe extern "system" fn get_base_device_proc_addr(
             get_base_device_proc_addr as vk::PFN_vkGetDeviceProcAddr,
         )),
         _ => DEVICE
-            .get(&device)
+            .get(&DispatchKey::of(device))
             .map(|dispatch| (dispatch.get_device_proc_addr)(device, p_name))
             .unwrap_or(None),
     }
@@ -181,26 +307,285 @@ unsafe extern "system" fn get_base_instance_proc_addr(
             get_base_device_proc_addr as vk::PFN_vkGetDeviceProcAddr,
         )),
         _ => INSTANCE
-            .get(&instance)
+            .get(&DispatchKey::of(instance))
             .map(|dispatch| (dispatch.get_instance_proc_addr)(instance, p_name))
             .unwrap_or(None),
     }
 }
 
 pub unsafe fn get_instance_vtable(device_handle: &vk::Device) -> Option<Instance> {
-    if let Some(device) = DEVICE.get(device_handle) {
-        let instance = &device.instance_vtable;
-        return Some(instance.clone());
-    }
-    None
+    DEVICE
+        .get(&DispatchKey::of(*device_handle))
+        .map(|device| device.instance_vtable.clone())
 }
 
 pub unsafe fn get_device_vtable(device_handle: &vk::Device) -> Option<Device> {
-    if let Some(device) = DEVICE.get(device_handle) {
-        let device = &device.device_vtable;
-        return Some(device.clone());
+    DEVICE
+        .get(&DispatchKey::of(*device_handle))
+        .map(|device| device.device_vtable.clone())
+}
+
+pub unsafe fn get_swapchain_vtable(device_handle: &vk::Device) -> Option<Swapchain> {
+    DEVICE
+        .get(&DispatchKey::of(*device_handle))
+        .map(|device| device.swapchain_vtable.clone())
+}
+
+pub unsafe fn get_device_dispatch(device_handle: &vk::Device) -> Option<DeviceDispatchTable> {
+    DEVICE
+        .get(&DispatchKey::of(*device_handle))
+        .map(|device| device.clone())
+}
+
+/// The device a device, queue or command buffer handle is dispatched through.
+pub unsafe fn get_dispatch_device<H: vk::Handle>(handle: H) -> Option<vk::Device> {
+    DEVICE
+        .get(&DispatchKey::of(handle))
+        .map(|device| device.device_vtable.handle())
+}
+
+/// The next layer's implementation of the intercepted commands, for the device a device,
+/// queue or command buffer handle is dispatched through.
+pub unsafe fn get_next_fns<H: vk::Handle>(handle: H) -> Option<NextDeviceFns> {
+    DEVICE.get(&DispatchKey::of(handle)).map(|device| device.next)
+}
+
+pub unsafe fn get_queue_device(queue: &vk::Queue) -> Option<(vk::Device, u32)> {
+    let queue_family_index = *QUEUE_FAMILY.get(queue)?;
+    get_dispatch_device(*queue).map(|device| (device, queue_family_index))
+}
+
+pub unsafe fn get_surface_window(surface: &vk::SurfaceKHR) -> Option<isize> {
+    SURFACE_WINDOW.get(surface).map(|window| *window)
+}
+
+unsafe extern "system" fn get_device_queue(
+    device: vk::Device,
+    queue_family_index: u32,
+    queue_index: u32,
+    p_queue: *mut vk::Queue,
+) {
+    let fp_get_device_queue = DEVICE
+        .get(&DispatchKey::of(device))
+        .map(|dispatch| dispatch.device_vtable.fp_v1_0().get_device_queue);
+    if let Some(fp_get_device_queue) = fp_get_device_queue {
+        fp_get_device_queue(device, queue_family_index, queue_index, p_queue);
+        QUEUE_FAMILY.insert(*p_queue, queue_family_index);
+    }
+}
+
+unsafe extern "system" fn get_device_queue2(
+    device: vk::Device,
+    p_queue_info: *const vk::DeviceQueueInfo2,
+    p_queue: *mut vk::Queue,
+) {
+    let fp_get_device_queue2 = DEVICE
+        .get(&DispatchKey::of(device))
+        .map(|dispatch| dispatch.device_vtable.fp_v1_1().get_device_queue2);
+    if let Some(fp_get_device_queue2) = fp_get_device_queue2 {
+        fp_get_device_queue2(device, p_queue_info, p_queue);
+        QUEUE_FAMILY.insert(*p_queue, (*p_queue_info).queue_family_index);
+    }
+}
+
+/// The signature shared by the `vkCreate*SurfaceKHR` commands, which differ only in the
+/// create info of their platform.
+type FnCreateSurface<I> = unsafe extern "system" fn(
+    vk::Instance,
+    *const I,
+    *const vk::AllocationCallbacks,
+    *mut vk::SurfaceKHR,
+) -> vk::Result;
+
+/// Create a surface with the next layer's `name`, and record the native window that `window`
+/// reads from its create info.
+unsafe fn create_surface<I>(
+    name: &[u8],
+    instance: vk::Instance,
+    p_create_info: *const I,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_surface: *mut vk::SurfaceKHR,
+    window: impl FnOnce(&I) -> isize,
+) -> vk::Result {
+    let fp_create_surface = INSTANCE
+        .get(&DispatchKey::of(instance))
+        .and_then(|dispatch| {
+            (dispatch.get_instance_proc_addr)(instance, name.as_ptr() as *const c_char)
+        });
+
+    let fp_create_surface: FnCreateSurface<I> = match fp_create_surface {
+        Some(fp) => std::mem::transmute(fp),
+        None => return VkResult::ERROR_EXTENSION_NOT_PRESENT,
+    };
+
+    let result = fp_create_surface(instance, p_create_info, p_allocator, p_surface);
+    if result == VkResult::SUCCESS {
+        SURFACE_WINDOW.insert(*p_surface, window(&*p_create_info));
     }
-    None
+    result
+}
+
+#[cfg(windows)]
+unsafe extern "system" fn create_win32_surface(
+    instance: vk::Instance,
+    p_create_info: *const vk::Win32SurfaceCreateInfoKHR,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_surface: *mut vk::SurfaceKHR,
+) -> vk::Result {
+    create_surface(
+        b"vkCreateWin32SurfaceKHR\0",
+        instance,
+        p_create_info,
+        p_allocator,
+        p_surface,
+        |info| info.hwnd as isize,
+    )
+}
+
+/// The window of an Xlib surface is its X window id, as GLX drawables are identified.
+#[cfg(target_os = "linux")]
+unsafe extern "system" fn create_xlib_surface(
+    instance: vk::Instance,
+    p_create_info: *const vk::XlibSurfaceCreateInfoKHR,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_surface: *mut vk::SurfaceKHR,
+) -> vk::Result {
+    create_surface(
+        b"vkCreateXlibSurfaceKHR\0",
+        instance,
+        p_create_info,
+        p_allocator,
+        p_surface,
+        |info| info.window as isize,
+    )
+}
+
+#[cfg(target_os = "linux")]
+unsafe extern "system" fn create_xcb_surface(
+    instance: vk::Instance,
+    p_create_info: *const vk::XcbSurfaceCreateInfoKHR,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_surface: *mut vk::SurfaceKHR,
+) -> vk::Result {
+    create_surface(
+        b"vkCreateXcbSurfaceKHR\0",
+        instance,
+        p_create_info,
+        p_allocator,
+        p_surface,
+        |info| info.window as isize,
+    )
+}
+
+/// The window of a Wayland surface is the address of its `wl_surface`.
+#[cfg(target_os = "linux")]
+unsafe extern "system" fn create_wayland_surface(
+    instance: vk::Instance,
+    p_create_info: *const vk::WaylandSurfaceCreateInfoKHR,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_surface: *mut vk::SurfaceKHR,
+) -> vk::Result {
+    create_surface(
+        b"vkCreateWaylandSurfaceKHR\0",
+        instance,
+        p_create_info,
+        p_allocator,
+        p_surface,
+        |info| info.surface as isize,
+    )
+}
+
+/// Identify the adapter `physical_device` is on.
+unsafe fn adapter_identity(
+    instance: &Instance,
+    api_version: u32,
+    physical_device: vk::PhysicalDevice,
+) -> AdapterIdentity {
+    let properties = instance.get_physical_device_properties(physical_device);
+    let mut adapter = AdapterIdentity {
+        vendor_id: properties.vendor_id,
+        device_id: properties.device_id,
+        ..Default::default()
+    };
+
+    // The device UUID and LUID can only be queried with Vulkan 1.1.
+    if api_version.min(properties.api_version) < vk::API_VERSION_1_1 {
+        return adapter;
+    }
+
+    let mut id_properties = vk::PhysicalDeviceIDProperties::default();
+    let mut properties2 = vk::PhysicalDeviceProperties2::builder().push_next(&mut id_properties);
+    instance.get_physical_device_properties2(physical_device, &mut properties2);
+
+    adapter.uuid = Some(id_properties.device_uuid);
+    adapter.luid =
+        (id_properties.device_luid_valid == vk::TRUE).then_some(id_properties.device_luid);
+    adapter
+}
+
+/// Append the extensions required by the overlay to those requested by the application,
+/// along with the dma-buf import extensions if `dma_buf` is set.
+///
+/// Devices of an `api_version` before Vulkan 1.1 also need the extensions those depend on,
+/// including instance extensions, which have to be among the `instance_extensions` enabled.
+///
+/// Returns `None` if the physical device does not support them.
+unsafe fn overlay_device_extensions(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    create_info: &vk::DeviceCreateInfo,
+    api_version: u32,
+    instance_extensions: &[CString],
+    dma_buf: bool,
+) -> Option<Vec<*const c_char>> {
+    if dma_buf && DMA_BUF_DEVICE_EXTENSIONS.is_empty() {
+        return None;
+    }
+
+    let legacy = api_version < vk::API_VERSION_1_1;
+    if legacy
+        && !OVERLAY_INSTANCE_EXTENSIONS_1_0
+            .iter()
+            .all(|name| instance_extensions.iter().any(|ext| ext.as_c_str() == *name))
+    {
+        return None;
+    }
+
+    let mut extensions = std::slice::from_raw_parts(
+        create_info.pp_enabled_extension_names,
+        create_info.enabled_extension_count as usize,
+    )
+    .to_vec();
+
+    let supported = instance
+        .enumerate_device_extension_properties(physical_device)
+        .unwrap_or_default();
+
+    let is_supported = |name: &CStr| {
+        supported
+            .iter()
+            .any(|ext| CStr::from_ptr(ext.extension_name.as_ptr()) == name)
+    };
+
+    let required = OVERLAY_DEVICE_EXTENSIONS
+        .iter()
+        .chain(OVERLAY_DEVICE_EXTENSIONS_1_0.iter().filter(|_| legacy))
+        .chain(DMA_BUF_DEVICE_EXTENSIONS.iter().filter(|_| dma_buf))
+        .chain(DMA_BUF_DEVICE_EXTENSIONS_1_0.iter().filter(|_| dma_buf && legacy));
+
+    if !required.clone().all(|name| is_supported(name)) {
+        return None;
+    }
+
+    for name in required {
+        let enabled = extensions
+            .iter()
+            .any(|ext| CStr::from_ptr(*ext) == *name);
+        if !enabled {
+            extensions.push(name.as_ptr());
+        }
+    }
+    Some(extensions)
 }
 
 // https://android.googlesource.com/platform/cts/+/6743db1/hostsidetests/gputools/layers/jni/nullLayer.cpp
@@ -239,19 +624,22 @@ unsafe extern "system" fn create_device(
     let gipa = next_layer_info.pfn_next_get_instance_proc_addr;
     let gdpa = next_layer_info.pfn_next_get_device_proc_addr;
 
-    // move chain on for next layer
-    // this is so bad.
-    (*layer_info).p_layer_info = next_layer_info.p_next;
-
     let fp_create_device: vk::PFN_vkCreateDevice = std::mem::transmute(gipa(
         vk::Instance::null(),
         b"vkCreateDevice\0".as_ptr() as *const c_char,
     ));
-    let result = fp_create_device(physical_device, p_create_info, p_allocator, p_device);
 
-    let instance_handle = PHYSICAL_DEVICE_MAP.get(&physical_device)
-        .expect("[vk] no instance found for physical device")
-        .clone();
+    // Physical devices are dispatched through the instance they were enumerated from,
+    // including every member of a device group.
+    let (instance_handle, api_version, instance_extensions) =
+        match INSTANCE.get(&DispatchKey::of(physical_device)) {
+            Some(dispatch) => (
+                dispatch.instance_vtable.handle(),
+                dispatch.api_version,
+                dispatch.extensions.clone(),
+            ),
+            None => return VkResult::ERROR_INITIALIZATION_FAILED,
+        };
 
     // the unhooked instance vtable isn't actually used,
     // except for get_device_proc_addr.
@@ -259,44 +647,113 @@ unsafe extern "system" fn create_device(
         get_instance_proc_addr: get_base_instance_proc_addr,
     };
     let instance = Instance::load(&entry, instance_handle);
+    let device_version = api_version.min(
+        instance
+            .get_physical_device_properties(physical_device)
+            .api_version,
+    );
+
+    // The next layer moves the chain on again when it creates the device, so the chain is
+    // moved on for it from this layer's link before every attempt.
+    let create = |create_info: *const vk::DeviceCreateInfo| {
+        (*layer_info).p_layer_info = next_layer_info.p_next;
+        fp_create_device(physical_device, create_info, p_allocator, p_device)
+    };
+
+    // Request the extensions needed to import the overlay texture along with the application's,
+    // with fewer of them if the device can not be created.
+    let mut overlay_extensions = false;
+    let mut overlay_dma_buf = false;
+    let mut result = VkResult::ERROR_INITIALIZATION_FAILED;
+    for dma_buf in [true, false] {
+        let extensions = match overlay_device_extensions(
+            &instance,
+            physical_device,
+            instance_info,
+            device_version,
+            &instance_extensions,
+            dma_buf,
+        ) {
+            Some(extensions) => extensions,
+            None => continue,
+        };
+        let mut create_info = *instance_info;
+        create_info.enabled_extension_count = extensions.len() as u32;
+        create_info.pp_enabled_extension_names = extensions.as_ptr();
+
+        result = create(&create_info);
+        if result == VkResult::SUCCESS {
+            overlay_extensions = true;
+            overlay_dma_buf = dma_buf;
+            break;
+        }
+        eprintln!("[vk] unable to create device with overlay extensions ({:?})", result);
+    }
+
+    if !overlay_extensions {
+        // Fall back to exactly what the application asked for.
+        eprintln!("[vk] device does not support overlay import extensions");
+        result = create(p_create_info);
+    }
+
+    if result != VkResult::SUCCESS {
+        return result;
+    }
 
     // This is important to not rely on the layer-local GetDeviceProcAddress.
     let mut instance_vtable = instance.fp_v1_0().clone();
     instance_vtable.get_device_proc_addr = gdpa;
 
     let device_vtable = Device::load(&instance_vtable, *p_device);
+    let swapchain_vtable = Swapchain::new(&instance, &device_vtable);
+
+    let adapter = adapter_identity(&instance, api_version, physical_device);
 
     let dispatch = DeviceDispatchTable {
         get_device_proc_addr: gdpa,
         get_instance_proc_addr: gipa,
         device_vtable,
         instance_vtable: instance,
+        swapchain_vtable,
         physical_device,
+        overlay_extensions,
+        overlay_dma_buf,
+        adapter,
+        next: NextDeviceFns::load(*p_device, gdpa),
     };
 
-    let result = (move || {
-        DEVICE.insert(*p_device, dispatch);
-        kernel::acquire().ok()?;
-        Some(result)
-    })()
-    .unwrap_or(VkResult::ERROR_INITIALIZATION_FAILED);
+    DEVICE.insert(DispatchKey::of(*p_device), dispatch);
 
-    std::thread::spawn(|| {
-        println!("[vk] starting kernel");
-        kernel::start().expect("kernel failed to start");
-    });
+    // The game has to keep running without an orchestrator, so the device is created either way.
+    match kernel::acquire() {
+        Ok(context) => {
+            KERNEL_DEVICES.insert(*p_device);
+            VulkanKernel::activate(context);
 
-    return result;
+            // Only the first device to start the kernel will block, the rest return immediately.
+            std::thread::spawn(|| {
+                println!("[vk] starting kernel");
+                kernel::start().unwrap_or_else(|e| eprintln!("[vk] kernel failed to start: {}", e));
+            });
+        }
+        Err(e) => eprintln!("[vk] unable to acquire kernel: {}", e),
+    }
+
+    result
 }
 
 unsafe extern "system" fn destroy_device(
     device: vk::Device,
     p_allocator: *const vk::AllocationCallbacks,
 ) {
-    // todo: delete kernel...
     (|| {
-        kernel::kill();
-        let dispatch = DEVICE.remove(&device);
+        // Releases this device's handle to the kernel, the kernel stops after the last device.
+        if KERNEL_DEVICES.remove(&device).is_some() {
+            kernel::kill();
+        }
+        let key = DispatchKey::of(device);
+        QUEUE_FAMILY.retain(|queue, _| DispatchKey::of(*queue) != key);
+        let dispatch = DEVICE.remove(&key);
         if let Some((_, dispatch)) = dispatch {
             dispatch.device_vtable.destroy_device(p_allocator.as_ref())
         }
@@ -348,6 +805,9 @@ unsafe extern "system" fn create_instance(
     ));
 
     let result = fp_create_instance(p_create_info, p_allocator, p_instance);
+    if result != VkResult::SUCCESS {
+        return result;
+    }
 
     let instance_vtable = Instance::load(
         &StaticFn {
@@ -356,19 +816,31 @@ unsafe extern "system" fn create_instance(
         *p_instance,
     );
 
-    if let Ok(phys_devices) = instance_vtable.enumerate_physical_devices() {
-        for device in phys_devices {
-            PHYSICAL_DEVICE_MAP.insert(device, *p_instance);
+    let api_version = instance_info
+        .p_application_info
+        .as_ref()
+        .map_or(vk::API_VERSION_1_0, |app_info| app_info.api_version);
+
+    let extensions = match instance_info.enabled_extension_count {
+        0 => Vec::new(),
+        count => {
+            std::slice::from_raw_parts(instance_info.pp_enabled_extension_names, count as usize)
+                .iter()
+                .map(|name| CStr::from_ptr(*name).to_owned())
+                .collect()
         }
-    }
+    };
 
     let dispatch = InstanceDispatchTable {
         get_instance_proc_addr: gpa,
+        get_physical_device_proc_addr: next_layer_info.pfn_next_get_physical_device_proc_addr,
         instance_vtable,
+        api_version,
+        extensions,
     };
 
     let result = (move || {
-        INSTANCE.insert(*p_instance, dispatch);
+        INSTANCE.insert(DispatchKey::of(*p_instance), dispatch);
         Some(result)
     })()
     .unwrap_or(VkResult::ERROR_INITIALIZATION_FAILED);
@@ -381,7 +853,7 @@ unsafe extern "system" fn destroy_instance(
     p_allocator: *const vk::AllocationCallbacks,
 ) {
     (|| {
-        let dispatch = INSTANCE.remove(&instance);
+        let dispatch = INSTANCE.remove(&DispatchKey::of(instance));
         if let Some((_, dispatch)) = dispatch {
             dispatch
                 .instance_vtable
@@ -392,8 +864,23 @@ unsafe extern "system" fn destroy_instance(
     .unwrap_or(())
 }
 
-use crate::hook::HookHandle;
-use crate::vk::sys::hooks;
+use crate::vk::hook::{self, NextDeviceFns};
+use crate::vk::VulkanKernel;
+
+/// Resolve physical device functions the loader does not know of.
+///
+/// The layer does not intercept any, so these are resolved by the next layer or driver.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub unsafe extern "system" fn vk_layerGetPhysicalDeviceProcAddr(
+    instance: vk::Instance,
+    p_name: *const c_char,
+) -> vk::PFN_vkVoidFunction {
+    INSTANCE
+        .get(&DispatchKey::of(instance))
+        .and_then(|dispatch| dispatch.get_physical_device_proc_addr)
+        .and_then(|gpdpa| gpdpa(instance, p_name))
+}
 
 #[no_mangle]
 unsafe extern "system" fn vk_main(interface: *mut VkNegotiateLayerInterface) -> VkResult {
@@ -404,27 +891,17 @@ unsafe extern "system" fn vk_main(interface: *mut VkNegotiateLayerInterface) ->
 
     let target_ld = (*interface).loader_layer_interface_version;
 
-    if target_ld < 2 {
-        // We only support Layer Interface Version 2.
+    if target_ld < MIN_LOADER_LAYER_INTERFACE_VERSION {
+        // Version 1 layers are discovered through exported symbols, which we don't provide.
         return VkResult::ERROR_INITIALIZATION_FAILED;
     }
 
-    // Validate init params
-    if target_ld >= 2 {
-        (*interface).loader_layer_interface_version = 2;
-        (*interface).pfn_get_device_proc_addr = get_device_proc_addr;
-        (*interface).pfn_get_instance_proc_addr = get_instance_proc_addr;
-    }
+    (*interface).loader_layer_interface_version =
+        target_ld.min(MAX_LOADER_LAYER_INTERFACE_VERSION);
+    (*interface).pfn_get_device_proc_addr = get_device_proc_addr;
+    (*interface).pfn_get_instance_proc_addr = get_instance_proc_addr;
+    (*interface).pfn_get_physical_device_proc_addr = Some(vk_layerGetPhysicalDeviceProcAddr);
 
-    (*interface).pfn_get_physical_device_proc_addr = None;
-    VkHookContext::init()
-        .expect("todo panic init")
-        .new(Box::new(move |device, create_info, all, mut next| {
-            eprintln!("{:?}", *create_info);
-            let fp = next.fp_next();
-            fp(device, create_info, all, next)
-        }))
-        .expect("TODO: panic message")
-        .persist();
+    // The kernel hooks are installed once the first device is created.
     return VkResult::SUCCESS;
 }
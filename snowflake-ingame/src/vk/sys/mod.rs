@@ -1,11 +1,17 @@
 mod layer;
-mod hooks;
 
+use ash::extensions::khr::Swapchain;
 use ash::{Device, Instance, vk};
 
+pub use layer::DeviceDispatchTable;
+
+use crate::vk::hook::NextDeviceFns;
+
 pub trait HookedVulkanDeviceHandle {
     unsafe fn get_device_vtable(&self) -> Option<Device>;
     unsafe fn get_instance_vtable(&self) -> Option<Instance>;
+    unsafe fn get_swapchain_vtable(&self) -> Option<Swapchain>;
+    unsafe fn get_dispatch(&self) -> Option<DeviceDispatchTable>;
 }
 
 impl HookedVulkanDeviceHandle for vk::Device {
@@ -16,4 +22,58 @@ impl HookedVulkanDeviceHandle for vk::Device {
     unsafe fn get_instance_vtable(&self) -> Option<Instance> {
         layer::get_instance_vtable(self)
     }
-}
\ No newline at end of file
+
+    unsafe fn get_swapchain_vtable(&self) -> Option<Swapchain> {
+        layer::get_swapchain_vtable(self)
+    }
+
+    unsafe fn get_dispatch(&self) -> Option<DeviceDispatchTable> {
+        layer::get_device_dispatch(self)
+    }
+}
+
+pub trait HookedVulkanQueueHandle {
+    /// The device and queue family index the queue was retrieved from.
+    unsafe fn get_queue_device(&self) -> Option<(vk::Device, u32)>;
+}
+
+impl HookedVulkanQueueHandle for vk::Queue {
+    unsafe fn get_queue_device(&self) -> Option<(vk::Device, u32)> {
+        layer::get_queue_device(self)
+    }
+}
+
+pub trait HookedVulkanSurfaceHandle {
+    /// The native window the surface was created for.
+    unsafe fn get_window(&self) -> Option<isize>;
+}
+
+impl HookedVulkanSurfaceHandle for vk::SurfaceKHR {
+    unsafe fn get_window(&self) -> Option<isize> {
+        layer::get_surface_window(self)
+    }
+}
+
+/// A handle that commands are dispatched on, through the device it belongs to.
+pub trait DispatchableHandle {
+    /// The next layer's implementation of the intercepted commands for this handle.
+    unsafe fn get_next_fns(&self) -> Option<NextDeviceFns>;
+}
+
+impl DispatchableHandle for vk::Device {
+    unsafe fn get_next_fns(&self) -> Option<NextDeviceFns> {
+        layer::get_next_fns(*self)
+    }
+}
+
+impl DispatchableHandle for vk::Queue {
+    unsafe fn get_next_fns(&self) -> Option<NextDeviceFns> {
+        layer::get_next_fns(*self)
+    }
+}
+
+impl DispatchableHandle for vk::CommandBuffer {
+    unsafe fn get_next_fns(&self) -> Option<NextDeviceFns> {
+        layer::get_next_fns(*self)
+    }
+}
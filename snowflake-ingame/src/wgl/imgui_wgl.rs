@@ -1,7 +1,8 @@
 use crate::common::{Dimensions, RenderError};
-use crate::wgl::overlay::WGLOverlay;
+use crate::ogl::color_encoding;
+use crate::overlay::OverlayPaint;
 use imgui::{Context, DrawData};
-use imgui_renderer_ogl::{OpenGLImguiRenderer, RenderToken};
+use imgui_renderer_ogl::{Composite, OpenGLImguiRenderer, RenderToken};
 use opengl_bindings::Gl;
 use parking_lot::RwLock;
 use std::sync::Arc;
@@ -27,6 +28,28 @@ impl Render<'_> {
             Err(RenderError::RendererNotReady)
         }
     }
+
+    /// Composite the overlay texture onto the framebuffer, before the ImGui draw data.
+    pub fn composite(&mut self, paint: &OverlayPaint) -> Result<(), RenderError> {
+        let composite = Composite {
+            position: paint.placement.position.map(|p| p as f32),
+            size: paint.placement.size.into(),
+            framebuffer: paint.viewport.into(),
+            opacity: paint.placement.opacity,
+            bgra: paint.texels.bgra,
+            opaque: paint.texels.opaque,
+            premultiplied: paint.texels.premultiplied,
+            decode: color_encoding(paint.texels.encoding),
+            encode: color_encoding(paint.output.written()),
+        };
+
+        if let Some(renderer) = &mut self.render {
+            renderer.composite(paint.texture, &composite)?;
+            Ok(())
+        } else {
+            Err(RenderError::RendererNotReady)
+        }
+    }
 }
 
 impl WGLImguiController {
@@ -54,19 +77,27 @@ impl WGLImguiController {
         self.renderer = None;
     }
 
-    pub fn frame<
-        'a,
-        F: FnOnce(&mut Context, Render, &mut WGLOverlay) -> Result<RenderToken, RenderError>,
-    >(
+    /// Forget the renderer without deleting its GL objects, for when its context is not current.
+    pub fn abandon_renderer(&mut self) {
+        if let Some(renderer) = self.renderer.take() {
+            std::mem::forget(renderer);
+        }
+    }
+
+    pub fn frame<'a, F: FnOnce(&mut Context, Render) -> Result<RenderToken, RenderError>>(
         &mut self,
-        overlay: &mut WGLOverlay,
         f: F,
     ) -> Result<RenderToken, RenderError> {
+        let mut imgui = self.imgui.write();
+        if let Some(renderer) = &self.renderer {
+            renderer.bind_fonts(&mut imgui);
+        }
+
         let renderer = Render {
             render: self.renderer.as_mut(),
         };
 
-        f(&mut self.imgui.write(), renderer, overlay)
+        f(&mut imgui, renderer)
     }
 
     #[must_use]
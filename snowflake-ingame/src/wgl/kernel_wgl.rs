@@ -1,47 +1,39 @@
-use crate::common::{Dimensions, OverlayWindow, RenderError};
+use crate::common::{Dimensions, RenderError};
 use crate::hook::{HookChain, HookHandle};
-use crate::ipc::cmd::{GameWindowCommand, GameWindowCommandType};
-use crate::ipc::IpcHandle;
+use crate::ipc::cmd::GameWindowCommandType;
+use crate::overlay::{
+    claim_window, take_commands, OverlayBackend, OverlayStatus, OverlayTargetPolicy, Ownership,
+    SurfaceMap,
+};
 use crate::wgl::hook::{FnSwapBuffersHook, WGLHookContext};
 use crate::wgl::imgui::WGLImguiController;
-use crate::wgl::overlay::WGLOverlay;
+use crate::wgl::overlay::{WGLOverlay, WGLOverlayBackend};
 use imgui_renderer_ogl::RenderToken;
 use opengl_bindings::Gl;
 use parking_lot::{RwLock, RwLockWriteGuard};
 use std::error::Error;
 use std::ffi::{c_void, CString};
 use std::mem::ManuallyDrop;
-use std::ops::{Deref, DerefMut};
-use std::sync::atomic::{AtomicIsize, Ordering};
 use std::sync::Arc;
 use windows::core::{HRESULT, HSTRING, PCSTR};
-use windows::Win32::Foundation::GetLastError;
+use windows::Win32::Foundation::{GetLastError, HWND};
 use windows::Win32::Graphics::Gdi::{WindowFromDC, HDC};
 use windows::Win32::Graphics::OpenGL::{wglGetCurrentContext, wglGetProcAddress, HGLRC};
 use windows::Win32::System::LibraryLoader::{GetModuleHandleA, GetProcAddress};
-use windows::Win32::UI::WindowsAndMessaging::GetClientRect;
+use windows::Win32::UI::WindowsAndMessaging::{GetClientRect, GetForegroundWindow, IsWindow};
 
 use crate::kernel::common::{FrameKernel, KernelContext};
+use crate::ogl::{framebuffer_output, OwnedGl};
 use crate::win32::wndproc::WndProcHandle;
 
-// this is so bad...
-pub(in crate::wgl) struct OwnedGl(Gl);
-unsafe impl Send for OwnedGl {}
-unsafe impl Sync for OwnedGl {}
-
-impl Deref for OwnedGl {
-    type Target = Gl;
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
-unsafe fn create_wgl_loader() -> Result<impl Fn(&'static str) -> *const c_void, Box<dyn Error>> {
+unsafe fn create_wgl_loader() -> windows::core::Result<impl Fn(&'static str) -> *const c_void> {
     let opengl_instance = GetModuleHandleA(PCSTR(b"opengl32\0".as_ptr()))?;
     if opengl_instance.is_invalid() {
         let error = GetLastError();
-        return Err(windows::core::Error::new(HRESULT(error.0 as i32), HSTRING::new()).into());
+        return Err(windows::core::Error::new(
+            HRESULT(error.0 as i32),
+            HSTRING::new(),
+        ));
     }
 
     Ok(move |s| {
@@ -62,13 +54,32 @@ unsafe fn create_wgl_loader() -> Result<impl Fn(&'static str) -> *const c_void,
     })
 }
 
+/// Overlay and renderer state for a single window and GL context.
+struct WGLSurface {
+    gl: OwnedGl,
+    context: HGLRC,
+    overlay: WGLOverlay,
+    imgui: WGLImguiController,
+}
+
+impl WGLSurface {
+    /// Forget any GL objects owned by this surface without deleting them.
+    ///
+    /// GL objects can only be deleted while their context is current, so
+    /// this must be used when a surface is evicted from a different context.
+    fn abandon(&mut self) {
+        for backend in self.overlay.backends_mut() {
+            backend.abandon();
+        }
+        self.imgui.abandon_renderer();
+    }
+}
+
 pub struct WGLKernel {
-    gl: Arc<RwLock<OwnedGl>>,
     hook: WGLHookContext,
-    ipc: IpcHandle,
-    imgui: Arc<RwLock<WGLImguiController>>,
-    overlay: Arc<RwLock<WGLOverlay>>,
-    ctx: Arc<AtomicIsize>,
+    context: KernelContext,
+    surfaces: Arc<RwLock<SurfaceMap<(isize, isize), WGLSurface>>>,
+    status: OverlayStatus,
     wp: Arc<RwLock<WndProcHandle>>,
 }
 
@@ -76,18 +87,14 @@ impl FrameKernel for WGLKernel {
     type Handle = impl HookHandle;
 
     fn new(context: KernelContext) -> Result<Self, Box<dyn Error>> {
-        let KernelContext { ipc, imgui } = context;
         let gl_gpa = unsafe { create_wgl_loader()? };
         let swap_buffers = unsafe { std::mem::transmute(gl_gpa("wglSwapBuffers")) };
-        let gl = Gl::load_with(gl_gpa);
 
         Ok(WGLKernel {
-            ipc,
             hook: WGLHookContext::init(swap_buffers)?,
-            gl: Arc::new(RwLock::new(OwnedGl(gl))),
-            imgui: Arc::new(RwLock::new(WGLImguiController::new(imgui))),
-            overlay: Arc::new(RwLock::new(WGLOverlay::new())),
-            ctx: Arc::new(AtomicIsize::new(0)),
+            context,
+            surfaces: Arc::new(RwLock::new(SurfaceMap::new(OverlayTargetPolicy::Largest))),
+            status: OverlayStatus::new("wgl"),
             wp: Arc::new(RwLock::new(WndProcHandle::new())),
         })
     }
@@ -98,37 +105,22 @@ impl FrameKernel for WGLKernel {
 
         Ok(handle)
     }
+
+    fn status(&self) -> OverlayStatus {
+        self.status.clone()
+    }
 }
 
 impl WGLKernel {
     fn swapbuffers_impl(
-        gl: &Gl,
-        handle: IpcHandle,
+        context: &KernelContext,
+        status: &OverlayStatus,
         hdc: HDC,
         hglrc: HGLRC,
-        mut overlay: RwLockWriteGuard<WGLOverlay>,
-        mut imgui: RwLockWriteGuard<WGLImguiController>,
+        mut surfaces: RwLockWriteGuard<SurfaceMap<(isize, isize), WGLSurface>>,
         mut wndproc: RwLockWriteGuard<WndProcHandle>,
-    ) -> Result<RenderToken, RenderError> {
-        // Handle update of any overlay here.
-        if let Ok(cmd) = &handle.try_recv() {
-            match cmd.ty {
-                GameWindowCommandType::OVERLAY_TEXTURE => {
-                    eprintln!("[wgl] received overlay texture event");
-                    overlay
-                        .refresh(unsafe { cmd.params.overlay_event })
-                        .unwrap_or_else(|e| eprintln!("[wgl] handle error: {}", e));
-                }
-                _ => {}
-            }
-        }
-
+    ) -> Result<Option<RenderToken>, RenderError> {
         let window = unsafe { WindowFromDC(hdc) };
-        wndproc.attach(window);
-
-        while let Ok(wp) = wndproc.try_recv() {
-            // eprintln!("{:?}", wp);
-        }
 
         let mut client_rect = Default::default();
         unsafe { GetClientRect(window, &mut client_rect) };
@@ -138,75 +130,117 @@ impl WGLKernel {
             height: client_rect.bottom.abs_diff(client_rect.top),
         };
 
-        if !overlay.size_matches_viewpoint(&size) {
-            // if overlay is not ready to initialize then handle = 0.
-            handle.send(GameWindowCommand::window_resize(
-                &size,
-                !overlay.ready_to_initialize(),
-            ))?;
+        let key = (window.0, hglrc.0);
+        surfaces.try_present(key, window.0, size, || {
+            let gl_gpa = unsafe { create_wgl_loader()? };
+            Ok::<_, RenderError>(WGLSurface {
+                gl: OwnedGl(Gl::load_with(gl_gpa)),
+                context: hglrc,
+                overlay: WGLOverlay::with_status(WGLOverlayBackend::new(), status.clone()),
+                imgui: WGLImguiController::new(context.imgui.clone()),
+            })
+        })?;
+
+        // Drop the state of any surface whose window has gone away.
+        for mut surface in surfaces.evict(|window| unsafe { IsWindow(HWND(window)) }.as_bool()) {
+            if surface.context != hglrc {
+                surface.abandon();
+            }
         }
 
-        if !overlay.ready_to_initialize() {
-            return Err(RenderError::OverlayHandleNotReady);
+        surfaces.select_target(unsafe { GetForegroundWindow() }.0);
+
+        if !surfaces.is_target(&key) {
+            return Ok(None);
         }
 
-        overlay
-            .prepare_paint(gl, window, hglrc)
-            .map_err(|e| RenderError::OverlayPaintNotReady(Box::new(e)))?;
+        let ownership = claim_window(window.0, WGLOverlayBackend::NAME);
+
+        // Only the backend that owns the window the commands are routed to receives them,
+        // so that the overlay texture is handed to the overlay that paints it. Every command
+        // that arrived since the last frame is handled, so that frames the orchestrator
+        // announced are not painted late.
+        let cmds = if ownership.is_owner() {
+            take_commands(window.0, &context.ipc)
+        } else {
+            Vec::new()
+        };
+
+        for cmd in &cmds {
+            if cmd.ty == GameWindowCommandType::OVERLAY_TARGET {
+                surfaces.set_policy(unsafe { cmd.params.overlay_target_event }.into());
+            }
+        }
+
+        let WGLSurface {
+            gl, overlay, imgui, ..
+        } = match surfaces.get_mut(&key) {
+            Some(surface) => &mut surface.state,
+            None => return Ok(None),
+        };
+
+        match ownership {
+            Ownership::Owned | Ownership::Acquired => {}
+            Ownership::Revoked => {
+                overlay.reset();
+                return Ok(None);
+            }
+            Ownership::Denied => return Ok(None),
+        }
+
+        // Handle update of any overlay here.
+        for cmd in &cmds {
+            overlay.handle_command(cmd);
+        }
+
+        wndproc.attach(window);
+
+        while let Ok(wp) = wndproc.try_recv() {
+            // eprintln!("{:?}", wp);
+        }
+
+        let output = unsafe { framebuffer_output(gl) };
+        overlay.prepare_frame(&context.ipc, size, output, (gl, window, hglrc))?;
 
         imgui
             .prepare_paint(gl, window, hglrc, size)
             .map_err(|e| RenderError::ImGuiNotReady(Box::new(e)))?;
 
-        imgui.frame(&mut overlay, |ctx, render, overlay| {
-            let ui = ctx.frame();
-            if let Some(_kmt) = overlay.acquire_sync() {
-                overlay.paint(|tid, dim| OverlayWindow::new(&ui, tid, dim));
-            }
-            ui.show_metrics_window(&mut false);
-            ui.show_demo_window(&mut false);
-            let token = render.render(ui.render())?;
-            Ok(token)
-        })
+        imgui
+            .frame(|ctx, mut render| {
+                let ui = ctx.frame();
+                overlay
+                    .acquire_sync()
+                    .paint(|paint| render.composite(paint))?;
+                ui.show_metrics_window(&mut false);
+                ui.show_demo_window(&mut false);
+                let token = render.render(ui.render())?;
+                Ok(token)
+            })
+            .map(Some)
     }
 
     fn make_swap_buffers(&self) -> FnSwapBuffersHook {
-        let handle = self.ipc.clone();
-        let imgui = self.imgui.clone();
-        let overlay = self.overlay.clone();
-        let gl = self.gl.clone();
-        let ctx = self.ctx.clone();
+        let context = self.context.clone();
+        let surfaces = self.surfaces.clone();
+        let status = self.status.clone();
 
         // let wp_r = self.wp_recv.clone();
         let wp_h = self.wp.clone();
         Box::new(move |hdc, mut next| {
             // Deal with this here instead of within the impl
             let hglrc = unsafe { wglGetCurrentContext() };
-            unsafe {
-                let old_ctx = ctx.swap(hglrc.0, Ordering::SeqCst);
-                if old_ctx != hglrc.0 {
-                    let gl_gpa = create_wgl_loader().unwrap();
-                    let mut new_gl = OwnedGl(Gl::load_with(gl_gpa));
-                    std::mem::swap(gl.write().deref_mut(), &mut new_gl);
-                }
-            }
-
-            let handle = handle.clone();
-            let gl = gl.clone();
 
             match WGLKernel::swapbuffers_impl(
-                &gl.read(),
-                handle,
+                &context,
+                &status,
                 hdc,
                 hglrc,
-                overlay.write(),
-                imgui.write(),
+                surfaces.write(),
                 wp_h.write(),
             ) {
                 Ok(_) => {}
-                Err(e) => {
-                    eprintln!("[wgl] {}", e);
-                }
+                Err(e) => status.fail(&e),
             }
             let fp = next.fp_next();
             fp(hdc, next)
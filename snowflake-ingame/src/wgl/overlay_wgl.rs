@@ -1,32 +1,70 @@
+use std::cell::{Cell, RefCell};
+
 use imgui::TextureId;
 use windows::Win32::Foundation::{HANDLE, HWND};
 use windows::Win32::Graphics::OpenGL::HGLRC;
 
 use imgui_renderer_ogl::ImguiTexture;
 use opengl_bindings as gl;
-use opengl_bindings::types::{GLint, GLsizei, GLuint};
+use opengl_bindings::types::{GLenum, GLint, GLsizei, GLuint};
 use opengl_bindings::Gl;
 
-use crate::common::{Dimensions, RenderError};
-use crate::ipc::cmd::OverlayTextureEventParams;
+use crate::common::{AdapterIdentity, Dimensions, RenderError};
+use crate::ipc::cmd::{GraphicsBackends, OverlayCapabilities, OverlayTextureEventParams};
+use crate::ipc::cmd::{OverlayPixelFormat, OverlayPixelFormats};
+use crate::ogl::{adapter_identity, gl_filter, UploadTexture};
+use crate::overlay::{
+    Overlay, OverlayBackend, OverlayDamage, OverlayDescriptor, OverlayFilter, OverlayFormat,
+    OverlayTexels, OverlayTransfer, SharedFrame, SyncedFrame, OVERLAY_SYNC_TIMEOUT_MS,
+};
 use crate::win32::handle::{try_close_handle, try_duplicate_handle, HandleError};
 
-pub(in crate::wgl) struct WGLOverlay {
-    handle: HANDLE,
+pub(in crate::wgl) type WGLOverlay = Overlay<WGLOverlayBackend>;
+
+pub(in crate::wgl) struct WGLOverlayBackend {
     window: HWND,
     context: HGLRC,
-    dimensions: Dimensions,
-    size: u64,
-    texture: Option<GlSharedTexture>,
+    texture: Option<GlCompositedTexture>,
+    /// The texture painted before the shared textures were replaced, which is painted
+    /// until a frame has been copied out of the new ones.
+    retained: RefCell<Option<GlCompositedTexture>>,
+    upload: Option<UploadTexture>,
+    filter: OverlayFilter,
 }
 
+/// A texture shared by the orchestrator, imported from a memory object.
 struct GlSharedTexture {
     gl: Gl,
     texture: GLuint,
     memory: GLuint,
 }
 
-pub(in crate::wgl) struct KeyedMutexHandle<'gl>(&'gl Gl, GLuint, u64);
+/// A private copy of the last frame a keyed mutex was acquired for, which is what is
+/// painted, so that it can be painted again while the orchestrator holds the mutex.
+struct GlCompositedTexture {
+    gl: Gl,
+    texture: GLuint,
+    dimensions: Dimensions,
+    /// How the texels copied into `texture` are read.
+    texels: OverlayTexels,
+    /// The number of bytes of a texel of `texture`.
+    texel_size: u32,
+    /// The textures shared by the orchestrator, which is a single texture unless
+    /// it renders into a ring.
+    shared: Vec<GlSharedTexture>,
+    /// Whether `shared` is a ring, in which only the texture holding the newest frame
+    /// is copied out of.
+    ring: bool,
+    /// The shared texture to copy the next frame out of, or `None` if the newest frame
+    /// of the ring has already been copied.
+    ready: Cell<Option<usize>>,
+    /// Whether a frame has been copied into `texture`.
+    has_frame: Cell<bool>,
+    /// The texels of the ring that changed since the frame in `texture` was copied.
+    damage: RefCell<OverlayDamage>,
+}
+
+struct KeyedMutexHandle<'gl>(&'gl Gl, GLuint, u64);
 impl Drop for KeyedMutexHandle<'_> {
     fn drop(&mut self) {
         unsafe {
@@ -37,8 +75,33 @@ impl Drop for KeyedMutexHandle<'_> {
     }
 }
 
+/// The frame in the texture the overlay is painted from.
+pub(in crate::wgl) enum WGLSyncGuard {
+    /// The latest frame was copied out of the shared texture just now.
+    Copied(OverlayTransfer),
+    /// The latest frame was copied out of the shared texture.
+    Latest,
+    /// The orchestrator held the shared texture, so the last frame copied is painted again.
+    Stale,
+    /// Uploaded textures are not shared, so there is nothing to synchronize.
+    Uploaded,
+}
+
+impl SyncedFrame for WGLSyncGuard {
+    fn is_stale(&self) -> bool {
+        matches!(self, WGLSyncGuard::Stale)
+    }
+
+    fn transfer(&self) -> Option<OverlayTransfer> {
+        match self {
+            WGLSyncGuard::Copied(transfer) => Some(*transfer),
+            _ => None,
+        }
+    }
+}
+
 impl<'gl> KeyedMutexHandle<'gl> {
-    pub fn new(gl: &'gl Gl, mem: GLuint, key: u64, ms: u32) -> Option<Self> {
+    fn new(gl: &'gl Gl, mem: GLuint, key: u64, ms: u32) -> Option<Self> {
         unsafe {
             if gl.AcquireKeyedMutexWin32EXT(mem, key, ms) == gl::TRUE {
                 Some(KeyedMutexHandle(gl, mem, key))
@@ -60,93 +123,219 @@ impl Drop for GlSharedTexture {
     }
 }
 
-impl WGLOverlay {
-    #[inline]
-    pub fn ready_to_initialize(&self) -> bool {
-        self.handle != HANDLE(0)
+impl Drop for GlCompositedTexture {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteTextures(1, &self.texture);
+        }
     }
+}
 
-    #[inline]
-    const fn ready_to_paint(&self) -> bool {
-        self.texture.is_some()
+/// The internal format a Direct3D texture of `format` is imported as, and how its texels
+/// are read.
+///
+/// OpenGL has no BGRA internal format, so BGRA texels are imported as RGBA and swizzled
+/// when they are composited.
+const fn internal_format(format: OverlayFormat) -> (GLenum, OverlayTexels) {
+    match format {
+        OverlayFormat::Bgra8 => (gl::RGBA8, OverlayTexels::BGRA),
+        OverlayFormat::Rgba8 => (gl::RGBA8, OverlayTexels::RGBA),
+        OverlayFormat::Rgba16Float => (gl::RGBA16F, OverlayTexels::RGBA),
+        OverlayFormat::Rgb10A2 => (gl::RGB10_A2, OverlayTexels::RGBA),
     }
+}
 
-    fn invalidate(&mut self) {
-        self.texture = None;
-    }
+impl GlSharedTexture {
+    /// Import the texture described by `descriptor` from its memory object.
+    unsafe fn import(
+        gl: &Gl,
+        descriptor: &OverlayDescriptor<HANDLE>,
+        internal_format: GLenum,
+    ) -> Result<GlSharedTexture, RenderError> {
+        let mut texture = 0;
+        gl.CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
+        gl.TextureParameteri(
+            texture,
+            gl::TEXTURE_TILING_EXT,
+            gl::OPTIMAL_TILING_EXT as GLint,
+        );
 
-    #[inline]
-    pub fn size_matches_viewpoint(&self, size: &Dimensions) -> bool {
-        self.dimensions == *size
-    }
+        let mut memory = 0;
+        gl.CreateMemoryObjectsEXT(1, &mut memory);
 
-    pub fn acquire_sync(&self) -> Option<KeyedMutexHandle> {
-        if let Some(tex_params) = &self.texture {
-            KeyedMutexHandle::new(&tex_params.gl, tex_params.memory, 0, GLuint::MAX)
+        // strict_provenance: handle is an int, this is fine.
+        // https://github.com/microsoft/windows-rs/issues/1643
+        gl.ImportMemoryWin32HandleEXT(
+            memory,
+            descriptor.size,
+            gl::HANDLE_TYPE_D3D11_IMAGE_EXT,
+            descriptor.handle.0 as *mut core::ffi::c_void,
+        );
+
+        if gl.AcquireKeyedMutexWin32EXT(memory, 0, OVERLAY_SYNC_TIMEOUT_MS) == gl::TRUE {
+            // todo: check gl error
+            gl.TextureStorageMem2DEXT(
+                texture,
+                1,
+                internal_format,
+                descriptor.dimensions.width as GLsizei,
+                descriptor.dimensions.height as GLsizei,
+                memory,
+                0,
+            );
+            gl.ReleaseKeyedMutexWin32EXT(memory, 0);
         } else {
-            None
+            gl.DeleteTextures(1, &texture);
+            gl.DeleteMemoryObjectsEXT(1, &memory);
+            // The import is tried again on the next frame.
+            return Err(RenderError::OverlayFrameNotReady);
         }
-    }
 
-    pub fn new() -> WGLOverlay {
-        WGLOverlay {
-            handle: HANDLE::default(),
-            window: HWND::default(),
-            context: HGLRC::default(),
-            dimensions: Dimensions::new(0, 0),
-            size: 0,
-            texture: None,
-        }
+        Ok(GlSharedTexture {
+            gl: gl.clone(),
+            texture,
+            memory,
+        })
     }
+}
 
-    #[must_use]
-    pub fn refresh(&mut self, params: OverlayTextureEventParams) -> Result<(), HandleError> {
-        let owning_pid = params.source_pid;
-        let handle = HANDLE(params.handle as isize);
+impl GlCompositedTexture {
+    /// Create the texture the frames of `shared` are copied into.
+    unsafe fn new(
+        gl: &Gl,
+        dimensions: Dimensions,
+        (internal_format, texels, texel_size): (GLenum, OverlayTexels, u32),
+        shared: Vec<GlSharedTexture>,
+        ring: bool,
+        filter: OverlayFilter,
+    ) -> GlCompositedTexture {
+        let mut texture = 0;
+        gl.CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
+        gl.TextureStorage2D(
+            texture,
+            1,
+            internal_format,
+            dimensions.width as GLsizei,
+            dimensions.height as GLsizei,
+        );
+        gl.TextureParameteri(texture, gl::TEXTURE_MIN_FILTER, gl_filter(filter));
+        gl.TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl_filter(filter));
+        gl.TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+        gl.TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
 
-        self.handle = {
-            let duped_handle = try_duplicate_handle(owning_pid as u32, handle)?;
+        GlCompositedTexture {
+            gl: gl.clone(),
+            texture,
+            dimensions,
+            texels,
+            texel_size,
+            shared,
+            ring,
+            // A single shared texture is copied out of every frame.
+            ready: Cell::new((!ring).then_some(0)),
+            has_frame: Cell::new(false),
+            damage: RefCell::new(OverlayDamage::Full),
+        }
+    }
 
-            // this doesn't do anything if its already null.
-            self.invalidate();
+    unsafe fn set_filter(&self, filter: OverlayFilter) {
+        let gl = &self.gl;
+        gl.TextureParameteri(self.texture, gl::TEXTURE_MIN_FILTER, gl_filter(filter));
+        gl.TextureParameteri(self.texture, gl::TEXTURE_MAG_FILTER, gl_filter(filter));
+    }
 
-            if self.ready_to_initialize() {
-                try_close_handle(self.handle)?;
-            }
+    /// Copy the newest frame out of the shared textures, if the orchestrator does not hold it.
+    fn composite(&self) -> Option<WGLSyncGuard> {
+        let Some(shared) = self.ready.get().and_then(|index| self.shared.get(index)) else {
+            // The newest frame of the ring has already been copied.
+            return self.has_frame.get().then_some(WGLSyncGuard::Latest);
+        };
 
-            eprintln!("[wgl] duped handle {:x?}", duped_handle);
-            duped_handle
+        let gl = &self.gl;
+        let Some(_kmt) = KeyedMutexHandle::new(gl, shared.memory, 0, OVERLAY_SYNC_TIMEOUT_MS)
+        else {
+            return self.has_frame.get().then_some(WGLSyncGuard::Stale);
         };
 
-        self.dimensions = Dimensions {
-            height: params.height,
-            width: params.width,
+        // Every frame is copied whole out of a single shared texture, since it is not told
+        // what changed.
+        let damage = if self.ring {
+            self.damage.replace(OverlayDamage::NONE)
+        } else {
+            OverlayDamage::Full
         };
+        let rects = damage.rects(self.dimensions);
 
-        self.size = params.size;
-        Ok(())
+        // The copies are issued before the mutex is released, which orders them
+        // before the orchestrator's next write.
+        for rect in &rects {
+            unsafe {
+                gl.CopyImageSubData(
+                    shared.texture,
+                    gl::TEXTURE_2D,
+                    0,
+                    rect.x as GLint,
+                    rect.y as GLint,
+                    0,
+                    self.texture,
+                    gl::TEXTURE_2D,
+                    0,
+                    rect.x as GLint,
+                    rect.y as GLint,
+                    0,
+                    rect.width as GLsizei,
+                    rect.height as GLsizei,
+                    1,
+                );
+            }
+        }
+        self.has_frame.set(true);
+        if self.ring {
+            self.ready.set(None);
+        }
+        Some(WGLSyncGuard::Copied(OverlayTransfer::new(
+            &rects,
+            self.dimensions,
+            self.texel_size,
+        )))
     }
+}
 
-    #[must_use]
-    pub fn prepare_paint(
+impl WGLOverlayBackend {
+    pub fn new() -> WGLOverlayBackend {
+        WGLOverlayBackend {
+            window: HWND::default(),
+            context: HGLRC::default(),
+            texture: None,
+            retained: RefCell::new(None),
+            upload: None,
+            filter: OverlayFilter::Linear,
+        }
+    }
+
+    /// Forget the imported textures without deleting them, for when their context is not current.
+    pub fn abandon(&mut self) {
+        if let Some(texture) = self.texture.take() {
+            std::mem::forget(texture);
+        }
+        if let Some(retained) = self.retained.get_mut().take() {
+            std::mem::forget(retained);
+        }
+        if let Some(upload) = self.upload.take() {
+            upload.abandon();
+        }
+    }
+
+    /// Import the textures shared by the orchestrator, and create the texture their frames
+    /// are copied into.
+    fn import_shared(
         &mut self,
-        gl: &Gl,
-        window: HWND,
-        context: HGLRC,
-    ) -> Result<(), RenderError> {
-        if self.ready_to_paint() && self.window == window && self.context == context {
-            return Ok(());
-        }
-
-        self.invalidate();
-
-        if !gl.ImportMemoryWin32HandleEXT.is_loaded()
-            || !gl.TextureStorageMem2DEXT.is_loaded()
-            || !gl.CreateMemoryObjectsEXT.is_loaded()
-            || !gl.DeleteMemoryObjectsEXT.is_loaded()
-            || !gl.AcquireKeyedMutexWin32EXT.is_loaded()
-            || !gl.ReleaseKeyedMutexWin32EXT.is_loaded()
-            || !gl.TextureParameteri.is_loaded()
+        descriptors: &[OverlayDescriptor<HANDLE>],
+        ring: bool,
+        (gl, window, context): (&Gl, HWND, HGLRC),
+    ) -> Result<Dimensions, RenderError> {
+        if !overlay_capabilities(gl)
+            .contains(OverlayCapabilities::WIN32_HANDLE | OverlayCapabilities::KEYED_MUTEX)
         {
             return Err(
                 imgui_renderer_ogl::RenderError::MissingExtensionError(Box::new(
@@ -156,70 +345,226 @@ impl WGLOverlay {
             );
         }
 
-        unsafe {
-            let mut texture = 0;
-            gl.CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
-            gl.TextureParameteri(
-                texture,
-                gl::TEXTURE_TILING_EXT,
-                gl::OPTIMAL_TILING_EXT as GLint,
-            );
-            gl.TextureParameteri(texture, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
-            gl.TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
-            gl.TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
-            gl.TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+        // The orchestrator announces every texture of a ring with the same dimensions and format.
+        let first = descriptors
+            .first()
+            .ok_or(RenderError::OverlayHandleNotReady)?;
+        let (format, encoding) = first.announced()?;
+        let (internal_format, texels) = internal_format(format);
+        let texels = OverlayTexels { encoding, ..texels };
+        let texel_size = format.texel_size();
 
-            gl.TextureParameteriv(
-                texture,
-                gl::TEXTURE_SWIZZLE_RGBA,
-                [gl::BLUE, gl::GREEN, gl::RED, gl::ALPHA].as_ptr() as _,
-            );
+        let shared = descriptors
+            .iter()
+            .map(|descriptor| unsafe { GlSharedTexture::import(gl, descriptor, internal_format) })
+            .collect::<Result<Vec<_>, _>>()?;
 
-            let mut memory = 0;
-            gl.CreateMemoryObjectsEXT(1, &mut memory);
+        let texture = unsafe {
+            GlCompositedTexture::new(
+                gl,
+                first.dimensions,
+                (internal_format, texels, texel_size),
+                shared,
+                ring,
+                self.filter,
+            )
+        };
+        if let Some(previous) = self.texture.replace(texture) {
+            if previous.has_frame.get() {
+                *self.retained.get_mut() = Some(previous);
+            }
+        }
+        self.window = window;
+        self.context = context;
+        Ok(first.dimensions)
+    }
+}
 
-            // strict_provenance: handle is an int, this is fine.
-            // https://github.com/microsoft/windows-rs/issues/1643
-            gl.ImportMemoryWin32HandleEXT(
-                memory,
-                self.size,
-                gl::HANDLE_TYPE_D3D11_IMAGE_EXT,
-                self.handle.0 as *mut core::ffi::c_void,
-            );
+/// The ways a shared texture can be imported with the functions `gl` has loaded.
+///
+/// Frames can always be uploaded from shared memory.
+fn overlay_capabilities(gl: &Gl) -> OverlayCapabilities {
+    let mut capabilities = OverlayCapabilities::SHARED_MEMORY;
+    if gl.ImportMemoryWin32HandleEXT.is_loaded()
+        && gl.TextureStorageMem2DEXT.is_loaded()
+        && gl.CreateMemoryObjectsEXT.is_loaded()
+        && gl.DeleteMemoryObjectsEXT.is_loaded()
+        && gl.TextureParameteri.is_loaded()
+        && gl.TextureStorage2D.is_loaded()
+        && gl.CopyImageSubData.is_loaded()
+    {
+        capabilities |= OverlayCapabilities::WIN32_HANDLE;
+    }
+    if gl.AcquireKeyedMutexWin32EXT.is_loaded() && gl.ReleaseKeyedMutexWin32EXT.is_loaded() {
+        capabilities |= OverlayCapabilities::KEYED_MUTEX;
+    }
+    if capabilities.contains(OverlayCapabilities::WIN32_HANDLE | OverlayCapabilities::KEYED_MUTEX) {
+        capabilities |= OverlayCapabilities::TEXTURE_RING;
+    }
+    capabilities
+}
 
-            if gl.AcquireKeyedMutexWin32EXT(memory, 0, GLuint::MAX) == gl::TRUE {
-                // todo: check gl error
-                gl.TextureStorageMem2DEXT(
-                    texture,
-                    1,
-                    gl::RGBA8,
-                    self.dimensions.width as GLsizei,
-                    self.dimensions.height as GLsizei,
-                    memory,
-                    0,
-                );
-                gl.ReleaseKeyedMutexWin32EXT(memory, 0);
-            } else {
-                gl.DeleteTextures(1, &texture);
-                gl.DeleteMemoryObjectsEXT(1, &memory);
-                return Err(RenderError::OverlayMutexNotReady);
+impl Default for WGLOverlayBackend {
+    fn default() -> Self {
+        WGLOverlayBackend::new()
+    }
+}
+
+impl OverlayBackend for WGLOverlayBackend {
+    const NAME: &'static str = "wgl";
+    const BACKEND: GraphicsBackends = GraphicsBackends::OPENGL;
+
+    type Handle = HANDLE;
+    type Target<'a> = (&'a Gl, HWND, HGLRC);
+    type SyncGuard<'a> = WGLSyncGuard;
+
+    fn duplicate_handle(
+        &self,
+        params: &OverlayTextureEventParams,
+        _format: OverlayPixelFormat,
+    ) -> Result<HANDLE, HandleError> {
+        let duped_handle =
+            try_duplicate_handle(params.source_pid as u32, HANDLE(params.handle as isize))?;
+        eprintln!("[wgl] duped handle {:x?}", duped_handle);
+        Ok(duped_handle)
+    }
+
+    fn close_handle(&self, handle: HANDLE) -> Result<(), HandleError> {
+        try_close_handle(handle)
+    }
+
+    fn capabilities(&self, (gl, _, _): &(&Gl, HWND, HGLRC)) -> OverlayCapabilities {
+        overlay_capabilities(gl)
+    }
+
+    fn formats(&self, (gl, _, _): &(&Gl, HWND, HGLRC)) -> OverlayPixelFormats {
+        // Frames in shared memory are always BGRA8.
+        if overlay_capabilities(gl).contains(OverlayCapabilities::WIN32_HANDLE) {
+            OverlayPixelFormats::ALL
+        } else {
+            OverlayPixelFormats::BGRA8
+        }
+    }
+
+    fn adapter(&self, (gl, _, _): &(&Gl, HWND, HGLRC)) -> Option<AdapterIdentity> {
+        Some(unsafe { adapter_identity(gl, "wgl") })
+    }
+
+    #[inline]
+    fn ready_to_paint(&self, (_, window, context): &(&Gl, HWND, HGLRC)) -> bool {
+        (self.texture.is_some() || self.upload.is_some())
+            && self.window == *window
+            && self.context == *context
+    }
+
+    fn import(
+        &mut self,
+        descriptor: &OverlayDescriptor<HANDLE>,
+        target: (&Gl, HWND, HGLRC),
+    ) -> Result<Dimensions, RenderError> {
+        self.import_shared(std::slice::from_ref(descriptor), false, target)
+    }
+
+    fn import_ring(
+        &mut self,
+        ring: &[OverlayDescriptor<HANDLE>],
+        target: (&Gl, HWND, HGLRC),
+    ) -> Result<Dimensions, RenderError> {
+        self.import_shared(ring, true, target)
+    }
+
+    fn select(&mut self, index: usize, damage: &OverlayDamage) {
+        if let Some(texture) = &mut self.texture {
+            if texture.ring && index < texture.shared.len() {
+                texture.ready.set(Some(index));
+                texture.damage.get_mut().add(damage);
             }
+        }
+    }
 
-            self.texture = Some(GlSharedTexture {
-                gl: gl.clone(),
-                texture,
-                memory,
-            })
+    fn upload(
+        &mut self,
+        frame: &SharedFrame<'_>,
+        (gl, window, context): (&Gl, HWND, HGLRC),
+    ) -> Result<Dimensions, RenderError> {
+        let upload = self
+            .upload
+            .get_or_insert_with(|| unsafe { UploadTexture::new(gl, self.filter) });
+        let dimensions = unsafe { upload.upload(frame)? };
+
+        self.window = window;
+        self.context = context;
+        Ok(dimensions)
+    }
+
+    fn set_filter(&mut self, filter: OverlayFilter) {
+        self.filter = filter;
+        // Commands are handled while the context the textures were created in is current.
+        for texture in self.texture.iter().chain(self.retained.get_mut().iter()) {
+            unsafe { texture.set_filter(filter) };
+        }
+        if let Some(upload) = &mut self.upload {
+            upload.set_filter(filter);
         }
-        Ok(())
     }
 
-    pub fn paint<F: Sized + FnOnce(TextureId, Dimensions)>(&self, f: F) {
+    fn invalidate(&mut self) {
+        self.texture = None;
+        *self.retained.get_mut() = None;
+        self.upload = None;
+    }
+
+    fn acquire_sync(&self) -> Option<WGLSyncGuard> {
         if let Some(texture) = &self.texture {
-            f(texture.texture.as_tex_id(), self.dimensions);
+            match texture.composite() {
+                Some(guard @ (WGLSyncGuard::Copied(_) | WGLSyncGuard::Latest)) => {
+                    self.retained.replace(None);
+                    Some(guard)
+                }
+                None if self.retained.borrow().is_some() || self.upload.is_some() => {
+                    Some(WGLSyncGuard::Stale)
+                }
+                guard => guard,
+            }
+        } else if self.upload.is_some() {
+            Some(WGLSyncGuard::Uploaded)
+        } else {
+            None
+        }
+    }
+
+    fn texture_id(&self) -> Option<TextureId> {
+        // Until a frame has been copied out of the shared textures, whatever was painted
+        // before they were imported is painted instead.
+        let copied = self.texture.as_ref().map(|texture| texture.has_frame.get());
+        if copied == Some(false) {
+            if let Some(retained) = self.retained.borrow().as_ref() {
+                return Some(retained.texture.as_tex_id());
+            }
+        }
+        match (&self.texture, &self.upload) {
+            (Some(_), Some(upload)) if copied == Some(false) => Some(upload.texture().as_tex_id()),
+            (Some(texture), _) => Some(texture.texture.as_tex_id()),
+            (None, Some(upload)) => Some(upload.texture().as_tex_id()),
+            (None, None) => None,
+        }
+    }
+
+    fn texels(&self) -> OverlayTexels {
+        // Uploads are converted from BGRA as they are uploaded.
+        let copied = self.texture.as_ref().map(|texture| texture.has_frame.get());
+        if copied == Some(false) {
+            if let Some(retained) = self.retained.borrow().as_ref() {
+                return retained.texels;
+            }
+        }
+        match (&self.texture, &self.upload) {
+            (Some(_), Some(_)) if copied == Some(false) => OverlayTexels::RGBA,
+            (Some(texture), _) => texture.texels,
+            _ => OverlayTexels::RGBA,
         }
     }
 }
 
-unsafe impl Send for WGLOverlay {}
-unsafe impl Sync for WGLOverlay {}
+unsafe impl Send for WGLOverlayBackend {}
+unsafe impl Sync for WGLOverlayBackend {}
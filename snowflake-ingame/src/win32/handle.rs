@@ -11,6 +11,8 @@ pub enum HandleError {
     CannotDuplicate(WIN32_ERROR),
     #[error("Unable to close handle {0:x?}")]
     CannotClose(WIN32_ERROR),
+    #[error("Unable to map shared memory: {0:x?}")]
+    CannotMap(WIN32_ERROR),
 }
 
 pub fn try_duplicate_handle(source_pid: u32, handle: HANDLE) -> Result<HANDLE, HandleError> {
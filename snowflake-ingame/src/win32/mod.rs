@@ -1,3 +1,4 @@
 pub mod handle;
+pub mod shm;
 pub mod window;
 pub mod wndproc;
@@ -0,0 +1,54 @@
+use windows::Win32::Foundation::{CloseHandle, GetLastError, HANDLE};
+use windows::Win32::System::Memory::{MapViewOfFile, UnmapViewOfFile, FILE_MAP_READ};
+
+use crate::ipc::cmd::OverlaySharedMemoryEventParams;
+use crate::win32::handle::{try_duplicate_handle, HandleError};
+
+/// A read-only view of the shared memory the orchestrator writes overlay frames into.
+pub struct SharedMapping {
+    ptr: *const u8,
+    len: usize,
+}
+
+unsafe impl Send for SharedMapping {}
+unsafe impl Sync for SharedMapping {}
+
+impl SharedMapping {
+    /// Map the file mapping announced with `params` from the orchestrator's process.
+    ///
+    /// The duplicated handle is closed once it has been mapped.
+    pub fn open(params: &OverlaySharedMemoryEventParams) -> Result<SharedMapping, HandleError> {
+        let handle =
+            try_duplicate_handle(params.source_pid as u32, HANDLE(params.handle as isize))?;
+        let len = params.size as usize;
+
+        let ptr = unsafe { MapViewOfFile(handle, FILE_MAP_READ, 0, 0, len) };
+        let error = unsafe { GetLastError() };
+        unsafe { CloseHandle(handle) };
+
+        if ptr.is_null() {
+            return Err(HandleError::CannotMap(error));
+        }
+
+        Ok(SharedMapping {
+            ptr: ptr as *const u8,
+            len,
+        })
+    }
+
+    #[inline]
+    pub fn as_ptr(&self) -> *const u8 {
+        self.ptr
+    }
+
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.len
+    }
+}
+
+impl Drop for SharedMapping {
+    fn drop(&mut self) {
+        unsafe { UnmapViewOfFile(self.ptr as *const core::ffi::c_void) };
+    }
+}
@@ -0,0 +1,141 @@
+//! Locates the library under test, installs the layer manifest generated for it, and acts as
+//! the orchestrator the library connects to.
+#![allow(dead_code)]
+
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+/// The file name of the library under test.
+pub const LIBRARY_NAME: &str = "libsnowflake_ingame.so";
+
+/// The file name of the layer manifest, as generated by the build script.
+const MANIFEST_NAME: &str = "VkLayer_snowflake_ingame.json";
+
+/// The size of a command, the magic and type followed by the largest parameters.
+pub const COMMAND_SIZE: usize = 46;
+pub const COMMAND_MAGIC: u8 = 0x9f;
+
+/// The directory the library under test is built into, `target/<profile>`, whose `deps`
+/// directory the test executable is built into.
+pub fn library_dir() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    exe.parent()
+        .and_then(Path::parent)
+        .expect("the test executable is not in target/<profile>/deps")
+        .to_path_buf()
+}
+
+/// The library built from `vulkan-mock-icd`, a dev-dependency of this package, which holds
+/// both the mock driver and a layer that loses devices on request.
+pub fn mock_icd_library() -> PathBuf {
+    let library_dir = library_dir();
+    [library_dir.clone(), library_dir.join("deps")]
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, |name| {
+                    name.starts_with("libvulkan_mock_icd") && name.ends_with(".so")
+                })
+        })
+        .unwrap_or_else(|| panic!("mock driver not found in {}", library_dir.display()))
+}
+
+/// A directory holding the layer manifest generated by the build script, pointed at the
+/// library under test, to add to `VK_ADD_LAYER_PATH` or `VK_IMPLICIT_LAYER_PATH`.
+///
+/// The build script writes the manifest for a package, naming the library relative to itself.
+/// The manifest is installed into a directory of this test's own, rather than next to the
+/// library, so that the tests do not write into the directories cargo builds into.
+pub fn layer_dir() -> PathBuf {
+    static INSTALL: Once = Once::new();
+
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(env!("CARGO_CRATE_NAME"));
+    INSTALL.call_once(|| {
+        let library = library_dir().join(LIBRARY_NAME);
+        let manifest = std::fs::read_to_string(env!("SNOWFLAKE_INGAME_LAYER_MANIFEST"))
+            .expect("the build script did not generate a layer manifest")
+            .replace(&format!("./{}", LIBRARY_NAME), library.to_str().unwrap());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(MANIFEST_NAME), manifest).unwrap();
+    });
+    dir
+}
+
+/// Listen on the orchestrator's socket in a runtime directory of the test's own, returning the
+/// directory to set as `XDG_RUNTIME_DIR` for the child process the library is loaded into.
+pub fn listen_orchestrator(name: &str) -> (PathBuf, UnixListener) {
+    let runtime_dir =
+        std::env::temp_dir().join(format!("snowflake-ingame-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&runtime_dir).unwrap();
+    let socket = runtime_dir.join(format!(
+        "Snowflake.Orchestration.Renderer-{}.sock",
+        "0".repeat(32)
+    ));
+    let _ = std::fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket).unwrap();
+    listener.set_nonblocking(true).unwrap();
+    (runtime_dir, listener)
+}
+
+/// Accept the connection of the library loaded into `child`, and echo its handshake back.
+///
+/// Returns `None` if the child exits, or does not connect within 20 seconds.
+pub fn accept_library(listener: &UnixListener, child: &mut Child) -> Option<UnixStream> {
+    let deadline = Instant::now() + Duration::from_secs(20);
+    let mut stream = loop {
+        match listener.accept() {
+            Ok((stream, _)) => break stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if child.try_wait().unwrap().is_some() || Instant::now() > deadline {
+                    return None;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Err(e) => panic!("unable to accept the library's connection: {}", e),
+        }
+    };
+    stream.set_nonblocking(false).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(20)))
+        .unwrap();
+
+    let mut command = [0u8; COMMAND_SIZE];
+    stream.read_exact(&mut command).unwrap();
+    stream.write_all(&command).unwrap();
+    Some(stream)
+}
+
+/// Send `command` to the library, with `fd` attached.
+pub fn send_with_fd(stream: &UnixStream, command: &[u8; COMMAND_SIZE], fd: RawFd) {
+    let mut iov = libc::iovec {
+        iov_base: command.as_ptr() as *mut libc::c_void,
+        iov_len: command.len(),
+    };
+    let mut control = [0u64; 4];
+    unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32) as _;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as u32) as _;
+        libc::CMSG_DATA(cmsg).cast::<RawFd>().write_unaligned(fd);
+
+        let sent = libc::sendmsg(stream.as_raw_fd(), &msg, 0);
+        assert_eq!(sent, command.len() as isize, "unable to send the fd");
+    }
+}
@@ -0,0 +1,419 @@
+//! Swaps an EGL pbuffer with the library preloaded, as it would be into a Linux OpenGL game.
+//!
+//! The library can only interpose the swap functions of a process it is preloaded into, so
+//! the tests run themselves again in a child process with `LD_PRELOAD` set. Mesa's llvmpipe on
+//! the surfaceless platform is enough. As EGL is needed, the tests are ignored unless run with
+//! `cargo test -- --ignored`, and fail if it is not available.
+#![cfg(target_os = "linux")]
+
+use std::ffi::{c_char, c_int, c_uint, c_void, CStr};
+use std::io::Read;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::net::UnixListener;
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+mod common;
+
+/// Set in the child process, which runs the test body with the library preloaded.
+const CHILD_ENV: &str = "SNOWFLAKE_INGAME_GL_CHILD";
+
+const EGL_SURFACE_TYPE: c_int = 0x3033;
+const EGL_PBUFFER_BIT: c_int = 0x0001;
+const EGL_RENDERABLE_TYPE: c_int = 0x3040;
+const EGL_OPENGL_BIT: c_int = 0x0008;
+const EGL_RED_SIZE: c_int = 0x3024;
+const EGL_HEIGHT: c_int = 0x3056;
+const EGL_WIDTH: c_int = 0x3057;
+const EGL_NONE: c_int = 0x3038;
+const EGL_OPENGL_API: c_uint = 0x30A2;
+const EGL_TRUE: c_uint = 1;
+
+const GL_COLOR_BUFFER_BIT: c_uint = 0x4000;
+const GL_RGBA: c_uint = 0x1908;
+const GL_UNSIGNED_BYTE: c_uint = 0x1401;
+const GL_NO_ERROR: c_uint = 0;
+
+const SIZE: c_int = 64;
+
+/// The color of the overlay frame written to shared memory, in BGRA.
+const OVERLAY_BGRA: [u8; 4] = [0x20, 0x40, 0x80, 0xff];
+
+const WINDOW_RESIZE: u8 = 2;
+const OVERLAY_STATE: u8 = 9;
+const OVERLAY_CAPABILITIES: u8 = 15;
+const OVERLAY_SHARED_MEMORY: u8 = 16;
+const SHARED_MEMORY: u8 = 1 << 5;
+const OVERLAY_STATE_LOST: u8 = 4;
+
+/// The layout of the shared frame ring, as documented in `docs/protocol.md`.
+const RING_MAGIC: u32 = u32::from_le_bytes(*b"SNFR");
+const RING_VERSION: u32 = 1;
+const RING_HEADER_SIZE: usize = 64;
+const SLOT_HEADER_SIZE: usize = 64;
+const SLOT_COUNT: usize = 2;
+
+/// The library that defines `fp`, as found by the dynamic linker.
+unsafe fn defining_library(fp: *const c_void) -> String {
+    let mut info: libc::Dl_info = std::mem::zeroed();
+    assert_ne!(libc::dladdr(fp, &mut info), 0);
+    CStr::from_ptr(info.dli_fname)
+        .to_string_lossy()
+        .into_owned()
+}
+
+unsafe fn symbol(handle: *mut c_void, name: &[u8]) -> *mut c_void {
+    let fp = libc::dlsym(handle, name.as_ptr().cast());
+    assert!(!fp.is_null(), "{} not found", String::from_utf8_lossy(name));
+    fp
+}
+
+type FnGetProcAddress = unsafe extern "C" fn(*const c_char) -> *const c_void;
+type FnReadPixels = unsafe extern "C" fn(c_int, c_int, c_int, c_int, c_uint, c_uint, *mut c_void);
+
+/// Resolve a GL function through the interposed `eglGetProcAddress`.
+unsafe fn gl_fn<T>(get_proc_address: FnGetProcAddress, name: &[u8]) -> T {
+    let fp = get_proc_address(name.as_ptr().cast());
+    assert!(!fp.is_null(), "{} not found", String::from_utf8_lossy(name));
+    std::mem::transmute_copy(&fp)
+}
+
+/// Create a pbuffer, and swap it through the interposed `eglSwapBuffers`.
+///
+/// Without an orchestrator, the kernel is never activated, so the swaps must reach the driver
+/// through the real `eglSwapBuffers`, leaving the rendered frame untouched. With one sending
+/// frames in shared memory, the swaps must paint the overlay over the frame.
+unsafe fn swap_pbuffer(shared_memory: bool) {
+    let egl = libc::dlopen(b"libEGL.so.1\0".as_ptr().cast(), libc::RTLD_NOW);
+    assert!(!egl.is_null(), "unable to load libEGL.so.1");
+
+    // The library is preloaded, so its definitions are found first.
+    let swap_buffers = symbol(libc::RTLD_DEFAULT, b"eglSwapBuffers\0");
+    let get_proc_address = symbol(libc::RTLD_DEFAULT, b"eglGetProcAddress\0");
+    assert!(defining_library(swap_buffers).contains("snowflake_ingame"));
+    assert!(defining_library(get_proc_address).contains("snowflake_ingame"));
+
+    let get_proc_address: FnGetProcAddress = std::mem::transmute(get_proc_address);
+    assert_eq!(
+        get_proc_address(b"eglSwapBuffers\0".as_ptr().cast()),
+        swap_buffers as *const c_void,
+        "eglGetProcAddress must return the interposed eglSwapBuffers"
+    );
+    let swap_buffers: unsafe extern "C" fn(*mut c_void, *mut c_void) -> c_uint =
+        std::mem::transmute(swap_buffers);
+
+    let get_display: unsafe extern "C" fn(*mut c_void) -> *mut c_void =
+        std::mem::transmute(symbol(egl, b"eglGetDisplay\0"));
+    let initialize: unsafe extern "C" fn(*mut c_void, *mut c_int, *mut c_int) -> c_uint =
+        std::mem::transmute(symbol(egl, b"eglInitialize\0"));
+    let bind_api: unsafe extern "C" fn(c_uint) -> c_uint =
+        std::mem::transmute(symbol(egl, b"eglBindAPI\0"));
+    let choose_config: unsafe extern "C" fn(
+        *mut c_void,
+        *const c_int,
+        *mut *mut c_void,
+        c_int,
+        *mut c_int,
+    ) -> c_uint = std::mem::transmute(symbol(egl, b"eglChooseConfig\0"));
+    let create_pbuffer_surface: unsafe extern "C" fn(
+        *mut c_void,
+        *mut c_void,
+        *const c_int,
+    ) -> *mut c_void = std::mem::transmute(symbol(egl, b"eglCreatePbufferSurface\0"));
+    let create_context: unsafe extern "C" fn(
+        *mut c_void,
+        *mut c_void,
+        *mut c_void,
+        *const c_int,
+    ) -> *mut c_void = std::mem::transmute(symbol(egl, b"eglCreateContext\0"));
+    let make_current: unsafe extern "C" fn(
+        *mut c_void,
+        *mut c_void,
+        *mut c_void,
+        *mut c_void,
+    ) -> c_uint = std::mem::transmute(symbol(egl, b"eglMakeCurrent\0"));
+    let terminate: unsafe extern "C" fn(*mut c_void) -> c_uint =
+        std::mem::transmute(symbol(egl, b"eglTerminate\0"));
+
+    let display = get_display(std::ptr::null_mut());
+    let (mut major, mut minor) = (0, 0);
+    assert!(
+        !display.is_null() && initialize(display, &mut major, &mut minor) == EGL_TRUE,
+        "unable to initialize EGL"
+    );
+
+    let config_attribs = [
+        EGL_SURFACE_TYPE,
+        EGL_PBUFFER_BIT,
+        EGL_RENDERABLE_TYPE,
+        EGL_OPENGL_BIT,
+        EGL_RED_SIZE,
+        8,
+        EGL_NONE,
+    ];
+    let mut config = std::ptr::null_mut();
+    let mut config_count = 0;
+    if bind_api(EGL_OPENGL_API) != EGL_TRUE
+        || choose_config(
+            display,
+            config_attribs.as_ptr(),
+            &mut config,
+            1,
+            &mut config_count,
+        ) != EGL_TRUE
+        || config_count == 0
+    {
+        terminate(display);
+        panic!("no EGL config supports desktop OpenGL pbuffers");
+    }
+
+    let surface_attribs = [EGL_WIDTH, SIZE, EGL_HEIGHT, SIZE, EGL_NONE];
+    let surface = create_pbuffer_surface(display, config, surface_attribs.as_ptr());
+    let context = create_context(display, config, std::ptr::null_mut(), std::ptr::null());
+    assert!(!surface.is_null() && !context.is_null());
+    assert_eq!(make_current(display, surface, surface, context), EGL_TRUE);
+
+    let clear_color: unsafe extern "C" fn(f32, f32, f32, f32) =
+        gl_fn(get_proc_address, b"glClearColor\0");
+    let clear: unsafe extern "C" fn(c_uint) = gl_fn(get_proc_address, b"glClear\0");
+    let finish: unsafe extern "C" fn() = gl_fn(get_proc_address, b"glFinish\0");
+    let read_pixels: FnReadPixels = gl_fn(get_proc_address, b"glReadPixels\0");
+    let get_error: unsafe extern "C" fn() -> c_uint = gl_fn(get_proc_address, b"glGetError\0");
+
+    let swap = |red: f32| {
+        clear_color(red, 0.0, 1.0, 1.0);
+        clear(GL_COLOR_BUFFER_BIT);
+        finish();
+
+        assert_eq!(swap_buffers(display, surface), EGL_TRUE);
+        assert_eq!(get_error(), GL_NO_ERROR);
+
+        // A pbuffer has no back buffer, so the swap leaves the frame in place.
+        let mut pixel = [0u8; 4];
+        read_pixels(
+            SIZE / 2,
+            SIZE / 2,
+            1,
+            1,
+            GL_RGBA,
+            GL_UNSIGNED_BYTE,
+            pixel.as_mut_ptr().cast(),
+        );
+        pixel
+    };
+
+    if shared_memory {
+        // The overlay is painted a few frames after the orchestrator sends it.
+        let [b, g, r, _] = OVERLAY_BGRA;
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let pixel = swap(0.0);
+            if pixel[..3]
+                .iter()
+                .zip([r, g, b])
+                .all(|(p, c)| p.abs_diff(c) <= 2)
+            {
+                break;
+            }
+            assert!(Instant::now() < deadline, "the overlay was never painted");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    } else {
+        for frame in 0..3u8 {
+            let red = frame as f32 / 4.0;
+            let pixel = swap(red);
+            assert_eq!(pixel[1..], [0, 255, 255]);
+            assert!(pixel[0].abs_diff((red * 255.0).round() as u8) <= 1);
+        }
+    }
+
+    make_current(
+        display,
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+    );
+    terminate(display);
+}
+
+#[test]
+#[ignore = "needs EGL and Mesa's llvmpipe"]
+fn egl_swap_buffers_is_interposed() {
+    if std::env::var_os(CHILD_ENV).is_some() {
+        unsafe { swap_pbuffer(false) };
+        return;
+    }
+
+    let library = common::library_dir().join(common::LIBRARY_NAME);
+    assert!(
+        library.exists(),
+        "library not found at {}",
+        library.display()
+    );
+
+    // The test is ignored, so the child is told to run ignored tests.
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args([
+            "egl_swap_buffers_is_interposed",
+            "--exact",
+            "--nocapture",
+            "--ignored",
+        ])
+        .env(CHILD_ENV, "1")
+        .env("LD_PRELOAD", &library)
+        // Render with llvmpipe, without a window system.
+        .env("EGL_PLATFORM", "surfaceless")
+        .env("LIBGL_ALWAYS_SOFTWARE", "1")
+        .output()
+        .expect("unable to run the test with the library preloaded");
+
+    print!("{}", String::from_utf8_lossy(&output.stdout));
+    eprint!("{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.status.success(), "child process failed");
+}
+
+/// A shared frame ring holding a single frame of the overlay color, in a memfd.
+fn create_ring(width: usize, height: usize) -> (OwnedFd, u64) {
+    let row_pitch = width * 4;
+    let slot_size = (SLOT_HEADER_SIZE + row_pitch * height + 63) & !63;
+    let size = RING_HEADER_SIZE + SLOT_COUNT * slot_size;
+
+    unsafe {
+        let fd = libc::memfd_create(b"snowflake-overlay\0".as_ptr().cast(), libc::MFD_CLOEXEC);
+        assert!(fd >= 0, "unable to create memfd");
+        let fd = OwnedFd::from_raw_fd(fd);
+        assert_eq!(libc::ftruncate(fd.as_raw_fd(), size as libc::off_t), 0);
+
+        let ring = libc::mmap(
+            std::ptr::null_mut(),
+            size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd.as_raw_fd(),
+            0,
+        ) as *mut u8;
+        assert_ne!(ring, libc::MAP_FAILED as *mut u8);
+
+        let header = [
+            RING_MAGIC,
+            RING_VERSION,
+            width as u32,
+            height as u32,
+            row_pitch as u32,
+            SLOT_COUNT as u32,
+        ];
+        std::ptr::copy_nonoverlapping(header.as_ptr(), ring.cast(), header.len());
+        ring.add(24).cast::<u64>().write(slot_size as u64);
+        let latest = &*ring.add(32).cast::<AtomicU64>();
+
+        // Frame 1 is written to slot 1.
+        let slot = ring.add(RING_HEADER_SIZE + slot_size);
+        let sequence = &*slot.cast::<AtomicU64>();
+        let frame = &*slot.add(8).cast::<AtomicU64>();
+        sequence.fetch_add(1, Ordering::AcqRel);
+        frame.store(1, Ordering::Relaxed);
+        let pixels = std::slice::from_raw_parts_mut(slot.add(SLOT_HEADER_SIZE), row_pitch * height);
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&OVERLAY_BGRA);
+        }
+        sequence.fetch_add(1, Ordering::Release);
+        latest.store(1, Ordering::Release);
+
+        libc::munmap(ring.cast(), size);
+        (fd, size as u64)
+    }
+}
+
+/// Act as the orchestrator for `child`, sending the overlay as frames in shared memory once
+/// the library reports that it can upload them.
+fn orchestrate(listener: &UnixListener, child: &mut Child) {
+    let mut stream = match common::accept_library(listener, child) {
+        Some(stream) => stream,
+        None => return,
+    };
+
+    let mut command = [0u8; common::COMMAND_SIZE];
+    let mut capabilities = None;
+    let mut ring = None;
+    // The library closes the connection when the child exits.
+    while stream.read_exact(&mut command).is_ok() {
+        assert_eq!(command[0], common::COMMAND_MAGIC);
+        match command[1] {
+            OVERLAY_CAPABILITIES => capabilities = Some(command[3]),
+            OVERLAY_STATE => assert_ne!(
+                command[2], OVERLAY_STATE_LOST,
+                "the overlay was lost, reason {}",
+                command[3]
+            ),
+            WINDOW_RESIZE if ring.is_none() => {
+                let capabilities = capabilities.expect("capabilities were not reported");
+                assert_ne!(
+                    capabilities & SHARED_MEMORY,
+                    0,
+                    "shared memory is not supported"
+                );
+
+                let height = i32::from_le_bytes(command[2..6].try_into().unwrap());
+                let width = i32::from_le_bytes(command[6..10].try_into().unwrap());
+                let (fd, size) = create_ring(width as usize, height as usize);
+
+                let mut params = Vec::with_capacity(common::COMMAND_SIZE);
+                params.extend_from_slice(&[common::COMMAND_MAGIC, OVERLAY_SHARED_MEMORY]);
+                params.extend_from_slice(&0usize.to_le_bytes());
+                params.extend_from_slice(&(std::process::id() as i32).to_le_bytes());
+                params.extend_from_slice(&(-1i32).to_le_bytes());
+                params.extend_from_slice(&(width as u32).to_le_bytes());
+                params.extend_from_slice(&(height as u32).to_le_bytes());
+                params.extend_from_slice(&size.to_le_bytes());
+                params.resize(common::COMMAND_SIZE, 0);
+                common::send_with_fd(
+                    &stream,
+                    params.as_slice().try_into().unwrap(),
+                    fd.as_raw_fd(),
+                );
+                ring = Some(fd);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs EGL and Mesa's llvmpipe"]
+fn shared_memory_overlay_is_painted() {
+    if std::env::var_os(CHILD_ENV).is_some() {
+        unsafe { swap_pbuffer(true) };
+        return;
+    }
+
+    let library = common::library_dir().join(common::LIBRARY_NAME);
+    assert!(
+        library.exists(),
+        "library not found at {}",
+        library.display()
+    );
+
+    // The library connects to the orchestrator's socket in the runtime directory.
+    let (runtime_dir, listener) = common::listen_orchestrator("shared-memory");
+
+    let mut child = Command::new(std::env::current_exe().unwrap())
+        .args([
+            "shared_memory_overlay_is_painted",
+            "--exact",
+            "--nocapture",
+            "--ignored",
+        ])
+        .env(CHILD_ENV, "1")
+        .env("LD_PRELOAD", &library)
+        .env("XDG_RUNTIME_DIR", &runtime_dir)
+        .env("EGL_PLATFORM", "surfaceless")
+        .env("LIBGL_ALWAYS_SOFTWARE", "1")
+        .spawn()
+        .expect("unable to run the test with the library preloaded");
+
+    orchestrate(&listener, &mut child);
+    let status = child.wait().unwrap();
+    let _ = std::fs::remove_dir_all(&runtime_dir);
+    assert!(status.success(), "child process failed");
+}
@@ -0,0 +1,238 @@
+//! Dispatches commands through the layer to the mock driver in `vulkan-mock-icd`.
+//!
+//! The mock driver enumerates two physical devices as one device group, so these tests cover
+//! several instances, several devices per instance, and devices created on a device group,
+//! without needing a Vulkan driver to be installed. The loader is still needed, so the tests
+//! are ignored unless run with `cargo test -- --ignored`, and fail if it can not be found.
+#![cfg(target_os = "linux")]
+
+use std::ffi::{CStr, CString};
+use std::path::{Path, PathBuf};
+
+use ash::vk;
+
+mod common;
+
+const LAYER_NAME: &str = "VK_LAYER_SNOWFLAKE_ingame";
+
+/// Write a driver manifest for the mock driver, returning its path.
+fn write_icd_manifest(library: &Path) -> PathBuf {
+    let manifest = format!(
+        r#"{{ "file_format_version": "1.0.0", "ICD": {{ "library_path": "{}", "api_version": "1.1.0" }} }}"#,
+        library.display()
+    );
+    let path = std::env::temp_dir().join(format!(
+        "snowflake_ingame_mock_icd_{}.json",
+        std::process::id()
+    ));
+    std::fs::write(&path, manifest).unwrap();
+    path
+}
+
+/// The call counters exported by the mock driver, resolved from the copy the loader opened.
+struct MockIcdCounters {
+    begin_command_buffer_calls: extern "C" fn() -> u64,
+    queue_submit_calls: extern "C" fn() -> u64,
+}
+
+impl MockIcdCounters {
+    unsafe fn load(library: &Path) -> MockIcdCounters {
+        let library = CString::new(library.to_str().unwrap()).unwrap();
+        let handle = libc::dlopen(library.as_ptr(), libc::RTLD_NOW | libc::RTLD_NOLOAD);
+        assert!(!handle.is_null(), "mock driver was not loaded");
+
+        let symbol = |name: &[u8]| {
+            let fp = libc::dlsym(handle, name.as_ptr().cast());
+            assert!(!fp.is_null());
+            std::mem::transmute::<_, extern "C" fn() -> u64>(fp)
+        };
+
+        MockIcdCounters {
+            begin_command_buffer_calls: symbol(b"mock_icd_begin_command_buffer_calls\0"),
+            queue_submit_calls: symbol(b"mock_icd_queue_submit_calls\0"),
+        }
+    }
+}
+
+struct TestDevice {
+    device: ash::Device,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+}
+
+impl TestDevice {
+    unsafe fn create(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        group: Option<&[vk::PhysicalDevice]>,
+    ) -> TestDevice {
+        let priorities = [1.0];
+        let queue_infos = [vk::DeviceQueueCreateInfo::builder()
+            .queue_family_index(0)
+            .queue_priorities(&priorities)
+            .build()];
+        let mut group_info =
+            vk::DeviceGroupDeviceCreateInfo::builder().physical_devices(group.unwrap_or_default());
+        let mut device_info = vk::DeviceCreateInfo::builder().queue_create_infos(&queue_infos);
+        if group.is_some() {
+            device_info = device_info.push_next(&mut group_info);
+        }
+
+        let device = instance
+            .create_device(physical_device, &device_info, None)
+            .expect("unable to create device with layer");
+
+        // Queues are looked up by both entry points, which must agree.
+        let queue = device.get_device_queue(0, 0);
+        let queue_info = vk::DeviceQueueInfo2::builder()
+            .queue_family_index(0)
+            .queue_index(0);
+        assert_eq!(queue, device.get_device_queue2(&queue_info));
+
+        let pool_info = vk::CommandPoolCreateInfo::builder().queue_family_index(0);
+        let command_pool = device.create_command_pool(&pool_info, None).unwrap();
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let command_buffer = device.allocate_command_buffers(&allocate_info).unwrap()[0];
+
+        TestDevice {
+            device,
+            queue,
+            command_pool,
+            command_buffer,
+        }
+    }
+
+    /// Record and submit an empty command buffer, checking both commands reach the driver.
+    unsafe fn submit(&self, counters: &MockIcdCounters) {
+        let begin_calls = (counters.begin_command_buffer_calls)();
+        let submit_calls = (counters.queue_submit_calls)();
+
+        self.device
+            .begin_command_buffer(self.command_buffer, &vk::CommandBufferBeginInfo::default())
+            .expect("vkBeginCommandBuffer was not dispatched");
+        self.device.end_command_buffer(self.command_buffer).unwrap();
+
+        let command_buffers = [self.command_buffer];
+        let submits = [vk::SubmitInfo::builder()
+            .command_buffers(&command_buffers)
+            .build()];
+        self.device
+            .queue_submit(self.queue, &submits, vk::Fence::null())
+            .expect("vkQueueSubmit was not dispatched");
+        self.device.queue_wait_idle(self.queue).unwrap();
+
+        assert_eq!((counters.begin_command_buffer_calls)(), begin_calls + 1);
+        assert_eq!((counters.queue_submit_calls)(), submit_calls + 1);
+    }
+
+    unsafe fn destroy(self) {
+        self.device
+            .free_command_buffers(self.command_pool, &[self.command_buffer]);
+        self.device.destroy_command_pool(self.command_pool, None);
+        self.device.destroy_device(None);
+    }
+}
+
+/// An instance with a device created on its device group, and one on a single physical device.
+struct TestInstance {
+    instance: ash::Instance,
+    devices: Vec<TestDevice>,
+}
+
+impl TestInstance {
+    unsafe fn create(entry: &ash::Entry) -> TestInstance {
+        let app_name = CString::new("snowflake-ingame-test").unwrap();
+        let app_info = vk::ApplicationInfo::builder()
+            .application_name(&app_name)
+            .api_version(vk::API_VERSION_1_1);
+        let layer_name = CString::new(LAYER_NAME).unwrap();
+        let layer_names = [layer_name.as_ptr()];
+        let instance_info = vk::InstanceCreateInfo::builder()
+            .application_info(&app_info)
+            .enabled_layer_names(&layer_names);
+
+        let instance = entry
+            .create_instance(&instance_info, None)
+            .expect("unable to create instance with layer");
+
+        let group_count = instance.enumerate_physical_device_groups_len().unwrap();
+        let mut groups = vec![vk::PhysicalDeviceGroupProperties::default(); group_count];
+        instance
+            .enumerate_physical_device_groups(&mut groups)
+            .unwrap();
+        assert_eq!(groups.len(), 1);
+        let group = &groups[0].physical_devices[..groups[0].physical_device_count as usize];
+        assert_eq!(group, instance.enumerate_physical_devices().unwrap());
+
+        // A device created on a device group is dispatched through the first physical device,
+        // while the second physical device is only seen by the layer through its dispatch key.
+        let devices = vec![
+            TestDevice::create(&instance, group[0], Some(group)),
+            TestDevice::create(&instance, group[1], None),
+        ];
+
+        TestInstance { instance, devices }
+    }
+
+    unsafe fn submit(&self, counters: &MockIcdCounters) {
+        for device in &self.devices {
+            device.submit(counters);
+        }
+    }
+
+    unsafe fn destroy(self) {
+        for device in self.devices {
+            device.destroy();
+        }
+        self.instance.destroy_instance(None);
+    }
+}
+
+#[test]
+#[ignore = "needs the Vulkan loader"]
+fn multiple_instances_and_devices() {
+    let library = common::mock_icd_library();
+
+    let manifest = write_icd_manifest(&library);
+    // Newer loaders read VK_DRIVER_FILES, older ones VK_ICD_FILENAMES.
+    std::env::set_var("VK_DRIVER_FILES", &manifest);
+    std::env::set_var("VK_ICD_FILENAMES", &manifest);
+    std::env::set_var("VK_ADD_LAYER_PATH", common::layer_dir());
+
+    let entry = unsafe { ash::Entry::load() }.expect("unable to load Vulkan");
+
+    let layer_available = entry
+        .enumerate_instance_layer_properties()
+        .unwrap()
+        .iter()
+        .any(|layer| {
+            unsafe { CStr::from_ptr(layer.layer_name.as_ptr()) }.to_bytes() == LAYER_NAME.as_bytes()
+        });
+    assert!(
+        layer_available,
+        "layer manifest not found in {}",
+        common::layer_dir().display()
+    );
+
+    unsafe {
+        let first = TestInstance::create(&entry);
+        let counters = MockIcdCounters::load(&library);
+        let second = TestInstance::create(&entry);
+
+        // Interleave commands between instances and devices.
+        first.submit(&counters);
+        second.submit(&counters);
+        first.submit(&counters);
+
+        // Destroying one instance must leave the dispatch of the other intact.
+        first.destroy();
+        second.submit(&counters);
+        second.destroy();
+    }
+
+    std::fs::remove_file(manifest).unwrap();
+}
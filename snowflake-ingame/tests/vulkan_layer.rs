@@ -0,0 +1,909 @@
+//! Loads the layer through the system Vulkan loader.
+//!
+//! These tests need a Vulkan driver to be installed, so they are ignored unless run with
+//! `cargo test -- --ignored`. Mesa's lavapipe is enough, and is preferred when more than one
+//! physical device is available. If no loader or driver can be found, the tests fail.
+#![cfg(target_os = "linux")]
+
+use std::ffi::{CStr, CString};
+use std::io::Read;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ash::vk;
+
+mod common;
+
+const LAYER_NAME: &str = "VK_LAYER_SNOWFLAKE_ingame";
+
+// The loader reads its configuration from the environment, which is shared by every test.
+static ENVIRONMENT: Mutex<()> = Mutex::new(());
+
+fn load_entry() -> ash::Entry {
+    unsafe { ash::Entry::load() }.expect("unable to load Vulkan")
+}
+
+fn layer_available(entry: &ash::Entry) -> bool {
+    entry
+        .enumerate_instance_layer_properties()
+        .unwrap()
+        .iter()
+        .any(|layer| {
+            unsafe { CStr::from_ptr(layer.layer_name.as_ptr()) }.to_bytes() == LAYER_NAME.as_bytes()
+        })
+}
+
+/// Create an instance and a device on it, then tear them down.
+fn create_and_destroy_device(entry: &ash::Entry, layers: &[&CStr]) {
+    let app_name = CString::new("snowflake-ingame-test").unwrap();
+    let app_info = vk::ApplicationInfo::builder()
+        .application_name(&app_name)
+        .api_version(vk::API_VERSION_1_1);
+    let layer_names: Vec<_> = layers.iter().map(|layer| layer.as_ptr()).collect();
+    let instance_info = vk::InstanceCreateInfo::builder()
+        .application_info(&app_info)
+        .enabled_layer_names(&layer_names);
+
+    unsafe {
+        let instance = entry
+            .create_instance(&instance_info, None)
+            .expect("unable to create instance with layer");
+
+        let physical_devices = instance.enumerate_physical_devices().unwrap();
+        let physical_device = physical_devices
+            .iter()
+            .copied()
+            .find(|device| {
+                instance.get_physical_device_properties(*device).device_type
+                    == vk::PhysicalDeviceType::CPU
+            })
+            .or_else(|| physical_devices.first().copied());
+
+        let physical_device = physical_device.expect("no physical devices available");
+
+        // Physical device functions are resolved through vk_layerGetPhysicalDeviceProcAddr
+        // when the loader does not know of them.
+        let mut properties2 = vk::PhysicalDeviceProperties2::default();
+        instance.get_physical_device_properties2(physical_device, &mut properties2);
+
+        let queue_family_index = instance
+            .get_physical_device_queue_family_properties(physical_device)
+            .iter()
+            .position(|family| family.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+            .expect("no graphics queue") as u32;
+
+        let priorities = [1.0];
+        let queue_infos = [vk::DeviceQueueCreateInfo::builder()
+            .queue_family_index(queue_family_index)
+            .queue_priorities(&priorities)
+            .build()];
+        let device_info = vk::DeviceCreateInfo::builder().queue_create_infos(&queue_infos);
+
+        // The orchestrator is not running, which must not prevent the device from being created.
+        let device = instance
+            .create_device(physical_device, &device_info, None)
+            .expect("unable to create device with layer");
+
+        let queue = device.get_device_queue(queue_family_index, 0);
+        assert_ne!(queue, vk::Queue::null());
+        device.device_wait_idle().unwrap();
+
+        device.destroy_device(None);
+        instance.destroy_instance(None);
+    }
+}
+
+#[test]
+#[ignore = "needs the Vulkan loader and a driver, such as Mesa's lavapipe"]
+fn explicit_layer() {
+    let _environment = ENVIRONMENT.lock().unwrap_or_else(|e| e.into_inner());
+    std::env::set_var("VK_ADD_LAYER_PATH", common::layer_dir());
+
+    let entry = load_entry();
+
+    assert!(
+        layer_available(&entry),
+        "layer manifest not found in {}",
+        common::layer_dir().display()
+    );
+
+    let layer_name = CString::new(LAYER_NAME).unwrap();
+    create_and_destroy_device(&entry, &[&layer_name]);
+
+    std::env::remove_var("VK_ADD_LAYER_PATH");
+}
+
+#[test]
+#[ignore = "needs the Vulkan loader and a driver, such as Mesa's lavapipe"]
+fn implicit_layer() {
+    let _environment = ENVIRONMENT.lock().unwrap_or_else(|e| e.into_inner());
+    std::env::set_var("VK_IMPLICIT_LAYER_PATH", common::layer_dir());
+    std::env::set_var("ENABLE_SNOWFLAKE_INGAME_VULKAN", "1");
+
+    let entry = load_entry();
+
+    // VK_IMPLICIT_LAYER_PATH is only supported by recent loaders.
+    assert!(
+        layer_available(&entry),
+        "layer manifest not found, the loader may not support VK_IMPLICIT_LAYER_PATH"
+    );
+    create_and_destroy_device(&entry, &[]);
+
+    // The disable environment variable takes priority over the enable one.
+    std::env::set_var("DISABLE_SNOWFLAKE_INGAME_VULKAN", "1");
+    assert!(!layer_available(&entry));
+    std::env::remove_var("DISABLE_SNOWFLAKE_INGAME_VULKAN");
+
+    std::env::remove_var("ENABLE_SNOWFLAKE_INGAME_VULKAN");
+    std::env::remove_var("VK_IMPLICIT_LAYER_PATH");
+}
+
+/// The name of the layer in `vulkan-mock-icd` that loses devices on request.
+const LOSS_LAYER_NAME: &str = "VK_LAYER_SNOWFLAKE_mock_device_loss";
+
+/// Set in the child process, which presents with the layer while the test orchestrates it.
+const CHILD_ENV: &str = "SNOWFLAKE_INGAME_VK_CHILD";
+
+const WINDOW_RESIZE: u8 = 2;
+const OVERLAY_STATE: u8 = 9;
+const OVERLAY_TEXTURE_FD: u8 = 12;
+const OVERLAY_CAPABILITIES: u8 = 15;
+const OPAQUE_FD: u8 = 1 << 2;
+const OVERLAY_STATE_PAINTABLE: u8 = 3;
+const OVERLAY_STATE_LOST: u8 = 4;
+const FAILURE_DEVICE: u8 = 5;
+
+const DRM_FORMAT_ARGB8888: u32 = u32::from_le_bytes(*b"AR24");
+const DRM_FORMAT_MOD_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+
+/// The color of the overlay texture, in BGRA.
+const OVERLAY_BGRA: [u8; 4] = [0x20, 0x40, 0x80, 0xff];
+
+/// The first CPU device, which is lavapipe, that `accept` holds for.
+unsafe fn find_lavapipe(
+    instance: &ash::Instance,
+    accept: impl Fn(vk::PhysicalDevice) -> bool,
+) -> vk::PhysicalDevice {
+    instance
+        .enumerate_physical_devices()
+        .unwrap()
+        .into_iter()
+        .find(|device| {
+            instance.get_physical_device_properties(*device).device_type
+                == vk::PhysicalDeviceType::CPU
+                && accept(*device)
+        })
+        .expect("lavapipe is not available")
+}
+
+/// A memory type of `type_bits` with `flags`.
+unsafe fn memory_type(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    type_bits: u32,
+    flags: vk::MemoryPropertyFlags,
+) -> u32 {
+    let properties = instance.get_physical_device_memory_properties(physical_device);
+    (0..properties.memory_type_count)
+        .find(|index| {
+            type_bits & (1 << index) != 0
+                && properties.memory_types[*index as usize]
+                    .property_flags
+                    .contains(flags)
+        })
+        .expect("no suitable memory type")
+}
+
+/// Record `command_buffer` with `record`, then submit it and wait for it to complete.
+unsafe fn submit_once(
+    device: &ash::Device,
+    queue: vk::Queue,
+    command_buffer: vk::CommandBuffer,
+    record: impl FnOnce(),
+) {
+    let begin_info =
+        vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device
+        .begin_command_buffer(command_buffer, &begin_info)
+        .unwrap();
+    record();
+    device.end_command_buffer(command_buffer).unwrap();
+
+    let command_buffers = [command_buffer];
+    let submit_info = vk::SubmitInfo::builder().command_buffers(&command_buffers);
+    device
+        .queue_submit(queue, &[submit_info.build()], vk::Fence::null())
+        .unwrap();
+    device.queue_wait_idle(queue).unwrap();
+}
+
+fn image_barrier(
+    image: vk::Image,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) -> vk::ImageMemoryBarrier {
+    vk::ImageMemoryBarrier::builder()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .build()
+}
+
+/// The objects needed to present to a headless surface, and to read back what was presented.
+struct HeadlessSwapchains {
+    device: ash::Device,
+    surface_fn: ash::extensions::khr::Surface,
+    swapchain_fn: ash::extensions::khr::Swapchain,
+    physical_device: vk::PhysicalDevice,
+    surface: vk::SurfaceKHR,
+    format: vk::SurfaceFormatKHR,
+    queue: vk::Queue,
+    fence: vk::Fence,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    pixel: vk::Buffer,
+    pixel_memory: vk::DeviceMemory,
+}
+
+impl HeadlessSwapchains {
+    unsafe fn new(
+        instance: &ash::Instance,
+        device: ash::Device,
+        surface_fn: ash::extensions::khr::Surface,
+        physical_device: vk::PhysicalDevice,
+        surface: vk::SurfaceKHR,
+    ) -> HeadlessSwapchains {
+        // The pixel read back is compared as BGRA.
+        let format = surface_fn
+            .get_physical_device_surface_formats(physical_device, surface)
+            .unwrap()
+            .into_iter()
+            .find(|format| {
+                matches!(
+                    format.format,
+                    vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB
+                )
+            })
+            .expect("the surface does not support BGRA8");
+
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(0);
+        let command_pool = device.create_command_pool(&pool_info, None).unwrap();
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let command_buffer = device.allocate_command_buffers(&alloc_info).unwrap()[0];
+
+        let buffer_info = vk::BufferCreateInfo::builder()
+            .size(4)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let pixel = device.create_buffer(&buffer_info, None).unwrap();
+        let requirements = device.get_buffer_memory_requirements(pixel);
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type(
+                instance,
+                physical_device,
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            ));
+        let pixel_memory = device.allocate_memory(&alloc_info, None).unwrap();
+        device.bind_buffer_memory(pixel, pixel_memory, 0).unwrap();
+
+        HeadlessSwapchains {
+            surface_fn,
+            swapchain_fn: ash::extensions::khr::Swapchain::new(instance, &device),
+            physical_device,
+            surface,
+            format,
+            queue: device.get_device_queue(0, 0),
+            fence: device
+                .create_fence(&vk::FenceCreateInfo::default(), None)
+                .unwrap(),
+            command_pool,
+            command_buffer,
+            pixel,
+            pixel_memory,
+            device,
+        }
+    }
+
+    unsafe fn create(
+        &self,
+        extent: vk::Extent2D,
+        old_swapchain: vk::SwapchainKHR,
+    ) -> vk::SwapchainKHR {
+        let capabilities = self
+            .surface_fn
+            .get_physical_device_surface_capabilities(self.physical_device, self.surface)
+            .unwrap();
+        let create_info = vk::SwapchainCreateInfoKHR::builder()
+            .surface(self.surface)
+            .min_image_count(capabilities.min_image_count.max(2))
+            .image_format(self.format.format)
+            .image_color_space(self.format.color_space)
+            .image_extent(extent)
+            .image_array_layers(1)
+            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC)
+            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .pre_transform(capabilities.current_transform)
+            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+            .present_mode(vk::PresentModeKHR::FIFO)
+            .clipped(true)
+            .old_swapchain(old_swapchain);
+        self.swapchain_fn
+            .create_swapchain(&create_info, None)
+            .expect("unable to create swapchain with layer")
+    }
+
+    /// Acquire the next image of `swapchain`, waiting until it can be used.
+    unsafe fn acquire(&self, swapchain: vk::SwapchainKHR) -> Result<u32, vk::Result> {
+        let (index, _) = self.swapchain_fn.acquire_next_image(
+            swapchain,
+            u64::MAX,
+            vk::Semaphore::null(),
+            self.fence,
+        )?;
+        self.device
+            .wait_for_fences(&[self.fence], true, u64::MAX)
+            .unwrap();
+        self.device.reset_fences(&[self.fence]).unwrap();
+        Ok(index)
+    }
+
+    unsafe fn present(&self, swapchain: vk::SwapchainKHR, index: u32) -> Result<(), vk::Result> {
+        let swapchains = [swapchain];
+        let indices = [index];
+        let present_info = vk::PresentInfoKHR::builder()
+            .swapchains(&swapchains)
+            .image_indices(&indices);
+        // Suboptimal presents are still successful.
+        self.swapchain_fn
+            .queue_present(self.queue, &present_info)
+            .map(|_| ())
+    }
+
+    /// Read the pixel at the center of an acquired image, as it was last presented.
+    unsafe fn read_center(
+        &self,
+        swapchain: vk::SwapchainKHR,
+        index: u32,
+        extent: vk::Extent2D,
+    ) -> [u8; 4] {
+        let image = self.swapchain_fn.get_swapchain_images(swapchain).unwrap()[index as usize];
+        let region = vk::BufferImageCopy::builder()
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_offset(vk::Offset3D {
+                x: extent.width as i32 / 2,
+                y: extent.height as i32 / 2,
+                z: 0,
+            })
+            .image_extent(vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            });
+
+        submit_once(&self.device, self.queue, self.command_buffer, || {
+            self.device.cmd_pipeline_barrier(
+                self.command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[image_barrier(
+                    image,
+                    vk::ImageLayout::PRESENT_SRC_KHR,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                )],
+            );
+            self.device.cmd_copy_image_to_buffer(
+                self.command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.pixel,
+                &[region.build()],
+            );
+            self.device.cmd_pipeline_barrier(
+                self.command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[image_barrier(
+                    image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    vk::ImageLayout::PRESENT_SRC_KHR,
+                )],
+            );
+        });
+
+        let mapped = self
+            .device
+            .map_memory(self.pixel_memory, 0, 4, vk::MemoryMapFlags::empty())
+            .unwrap();
+        let pixel = mapped.cast::<[u8; 4]>().read();
+        self.device.unmap_memory(self.pixel_memory);
+        pixel
+    }
+
+    /// Present `swapchain` until the overlay is painted over its images, checking the layer
+    /// passes the results through.
+    ///
+    /// Headless swapchains keep the contents of their images, so an image acquired again
+    /// still holds the overlay painted when it was last presented. The layer reports its state
+    /// on the frame after it changes, so frames are presented for a while longer to have the
+    /// state the overlay was painted in reach the orchestrator.
+    unsafe fn present_until_painted(&self, swapchain: vk::SwapchainKHR, extent: vk::Extent2D) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut painted_at = None;
+        while painted_at.map_or(true, |at: Instant| {
+            at.elapsed() < Duration::from_millis(200)
+        }) {
+            let index = self
+                .acquire(swapchain)
+                .expect("acquire failed through the layer");
+            let pixel = self.read_center(swapchain, index, extent);
+            if let Err(e) = self.present(swapchain, index) {
+                panic!("present failed through the layer: {:?}", e);
+            }
+
+            if painted_at.is_none()
+                && pixel
+                    .iter()
+                    .zip(OVERLAY_BGRA)
+                    .all(|(p, c)| p.abs_diff(c) <= 2)
+            {
+                painted_at = Some(Instant::now());
+            }
+            assert!(
+                painted_at.is_some() || Instant::now() < deadline,
+                "the overlay was never painted"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    unsafe fn destroy(self) {
+        self.device.device_wait_idle().unwrap();
+        self.device.destroy_buffer(self.pixel, None);
+        self.device.free_memory(self.pixel_memory, None);
+        self.device.destroy_command_pool(self.command_pool, None);
+        self.device.destroy_fence(self.fence, None);
+        self.device.destroy_device(None);
+        self.surface_fn.destroy_surface(self.surface, None);
+    }
+}
+
+/// Switch the loss layer loaded into this process between losing devices and working.
+unsafe fn set_devices_lost(lost: bool) {
+    let library = CString::new(common::mock_icd_library().to_str().unwrap()).unwrap();
+    let handle = libc::dlopen(library.as_ptr(), libc::RTLD_NOW | libc::RTLD_NOLOAD);
+    assert!(!handle.is_null(), "the loss layer was not loaded");
+
+    let fp = libc::dlsym(handle, b"mock_layer_set_devices_lost\0".as_ptr().cast());
+    assert!(!fp.is_null());
+    let set: extern "C" fn(bool) = std::mem::transmute(fp);
+    set(lost);
+    libc::dlclose(handle);
+}
+
+/// Present with the layer above the loss layer, as a game would while the orchestrator
+/// shares an overlay: through a resize, a device loss, and a fullscreen toggle after it.
+unsafe fn recreate_swapchains() {
+    let entry = load_entry();
+
+    let headless_supported = entry
+        .enumerate_instance_extension_properties(None)
+        .unwrap()
+        .iter()
+        .any(|extension| {
+            let name = CStr::from_ptr(extension.extension_name.as_ptr());
+            name == vk::ExtHeadlessSurfaceFn::name()
+        });
+    assert!(
+        headless_supported,
+        "VK_EXT_headless_surface is not supported"
+    );
+
+    // The loss layer is below the layer, between it and the driver.
+    let layer_name = CString::new(LAYER_NAME).unwrap();
+    let loss_layer_name = CString::new(LOSS_LAYER_NAME).unwrap();
+    let layer_names = [layer_name.as_ptr(), loss_layer_name.as_ptr()];
+    let extension_names = [
+        ash::extensions::khr::Surface::name().as_ptr(),
+        vk::ExtHeadlessSurfaceFn::name().as_ptr(),
+    ];
+    let app_info = vk::ApplicationInfo::builder().api_version(vk::API_VERSION_1_1);
+    let instance_info = vk::InstanceCreateInfo::builder()
+        .application_info(&app_info)
+        .enabled_layer_names(&layer_names)
+        .enabled_extension_names(&extension_names);
+    let instance = entry
+        .create_instance(&instance_info, None)
+        .expect("unable to create instance with layer");
+
+    let headless_fn = vk::ExtHeadlessSurfaceFn::load(|name| {
+        std::mem::transmute(entry.get_instance_proc_addr(instance.handle(), name.as_ptr()))
+    });
+    let mut surface = vk::SurfaceKHR::null();
+    (headless_fn.create_headless_surface_ext)(
+        instance.handle(),
+        &vk::HeadlessSurfaceCreateInfoEXT::default(),
+        std::ptr::null(),
+        &mut surface,
+    )
+    .result()
+    .unwrap();
+
+    let surface_fn = ash::extensions::khr::Surface::new(&entry, &instance);
+    let physical_device = find_lavapipe(&instance, |device| {
+        surface_fn
+            .get_physical_device_surface_support(device, 0, surface)
+            .unwrap_or(false)
+    });
+
+    let priorities = [1.0];
+    let queue_infos = [vk::DeviceQueueCreateInfo::builder()
+        .queue_family_index(0)
+        .queue_priorities(&priorities)
+        .build()];
+    let device_extensions = [ash::extensions::khr::Swapchain::name().as_ptr()];
+    let device_info = vk::DeviceCreateInfo::builder()
+        .queue_create_infos(&queue_infos)
+        .enabled_extension_names(&device_extensions);
+    let device = instance
+        .create_device(physical_device, &device_info, None)
+        .expect("unable to create device with layer");
+
+    let swapchains =
+        HeadlessSwapchains::new(&instance, device, surface_fn, physical_device, surface);
+
+    let small = vk::Extent2D {
+        width: 640,
+        height: 480,
+    };
+    let large = vk::Extent2D {
+        width: 1280,
+        height: 720,
+    };
+
+    let first = swapchains.create(small, vk::SwapchainKHR::null());
+    swapchains.present_until_painted(first, small);
+
+    // A resize, handing the overlay over from the old swapchain to the new one.
+    let second = swapchains.create(large, first);
+    swapchains.present_until_painted(second, large);
+    swapchains.swapchain_fn.destroy_swapchain(first, None);
+    swapchains.present_until_painted(second, large);
+
+    // The device is lost while an image is acquired. The layer tears the overlay down, and the
+    // game sees the loss as the driver reported it.
+    let index = swapchains.acquire(second).unwrap();
+    set_devices_lost(true);
+    assert_eq!(
+        swapchains.present(second, index),
+        Err(vk::Result::ERROR_DEVICE_LOST)
+    );
+    assert_eq!(
+        swapchains.acquire(second),
+        Err(vk::Result::ERROR_DEVICE_LOST)
+    );
+    set_devices_lost(false);
+
+    // A fullscreen toggle, destroying the swapchain before creating another, after which the
+    // overlay is shared and painted again.
+    swapchains.swapchain_fn.destroy_swapchain(second, None);
+    let third = swapchains.create(small, vk::SwapchainKHR::null());
+    swapchains.present_until_painted(third, small);
+    swapchains.swapchain_fn.destroy_swapchain(third, None);
+
+    swapchains.destroy();
+    instance.destroy_instance(None);
+}
+
+/// A Vulkan device of the orchestrator's own, which exports the overlay textures as opaque fds.
+struct Orchestrator {
+    instance: ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    device: ash::Device,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    textures: Vec<(vk::Image, vk::DeviceMemory)>,
+}
+
+impl Orchestrator {
+    unsafe fn new(entry: &ash::Entry) -> Orchestrator {
+        let app_info = vk::ApplicationInfo::builder().api_version(vk::API_VERSION_1_1);
+        let instance_info = vk::InstanceCreateInfo::builder().application_info(&app_info);
+        let instance = entry.create_instance(&instance_info, None).unwrap();
+        let physical_device = find_lavapipe(&instance, |_| true);
+
+        let priorities = [1.0];
+        let queue_infos = [vk::DeviceQueueCreateInfo::builder()
+            .queue_family_index(0)
+            .queue_priorities(&priorities)
+            .build()];
+        let device_extensions = [ash::extensions::khr::ExternalMemoryFd::name().as_ptr()];
+        let device_info = vk::DeviceCreateInfo::builder()
+            .queue_create_infos(&queue_infos)
+            .enabled_extension_names(&device_extensions);
+        let device = instance
+            .create_device(physical_device, &device_info, None)
+            .expect("unable to create a device exporting opaque fds");
+
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(0);
+        let command_pool = device.create_command_pool(&pool_info, None).unwrap();
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let command_buffer = device.allocate_command_buffers(&alloc_info).unwrap()[0];
+
+        Orchestrator {
+            queue: device.get_device_queue(0, 0),
+            instance,
+            physical_device,
+            device,
+            command_pool,
+            command_buffer,
+            textures: Vec::new(),
+        }
+    }
+
+    /// Create a texture of the overlay color, returning its memory as an opaque fd along with
+    /// the size of the memory.
+    unsafe fn export_texture(&mut self, width: u32, height: u32) -> (OwnedFd, u64) {
+        let device = &self.device;
+
+        let mut external_info = vk::ExternalMemoryImageCreateInfo::builder()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD);
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk::Format::B8G8R8A8_UNORM)
+            .extent(vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .push_next(&mut external_info);
+        let image = device.create_image(&image_info, None).unwrap();
+
+        // The layer imports the memory as a dedicated allocation.
+        let requirements = device.get_image_memory_requirements(image);
+        let mut export_info = vk::ExportMemoryAllocateInfo::builder()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD);
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder().image(image);
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type(
+                &self.instance,
+                self.physical_device,
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::empty(),
+            ))
+            .push_next(&mut export_info)
+            .push_next(&mut dedicated_info);
+        let memory = device.allocate_memory(&alloc_info, None).unwrap();
+        device.bind_image_memory(image, memory, 0).unwrap();
+        self.textures.push((image, memory));
+
+        let [b, g, r, a] = OVERLAY_BGRA.map(|c| c as f32 / 255.0);
+        let clear_color = vk::ClearColorValue {
+            float32: [r, g, b, a],
+        };
+        let range = image_barrier(
+            image,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        )
+        .subresource_range;
+        submit_once(device, self.queue, self.command_buffer, || {
+            device.cmd_pipeline_barrier(
+                self.command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[image_barrier(
+                    image,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                )],
+            );
+            device.cmd_clear_color_image(
+                self.command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &clear_color,
+                &[range],
+            );
+        });
+
+        let external_memory_fd =
+            ash::extensions::khr::ExternalMemoryFd::new(&self.instance, device);
+        let fd_info = vk::MemoryGetFdInfoKHR::builder()
+            .memory(memory)
+            .handle_type(vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD);
+        let fd = external_memory_fd.get_memory_fd(&fd_info).unwrap();
+        (OwnedFd::from_raw_fd(fd), requirements.size)
+    }
+
+    unsafe fn destroy(self) {
+        self.device.device_wait_idle().unwrap();
+        for (image, memory) in self.textures {
+            self.device.destroy_image(image, None);
+            self.device.free_memory(memory, None);
+        }
+        self.device.destroy_command_pool(self.command_pool, None);
+        self.device.destroy_device(None);
+        self.instance.destroy_instance(None);
+    }
+}
+
+/// Act as the orchestrator for `child`, sharing a texture of the overlay color whenever the
+/// layer asks for one, and returning every state and failure reason the layer reported.
+fn orchestrate(listener: &UnixListener, child: &mut Child) -> Vec<(u8, u8)> {
+    let mut stream = match common::accept_library(listener, child) {
+        Some(stream) => stream,
+        None => return Vec::new(),
+    };
+
+    let entry = load_entry();
+    let mut orchestrator = unsafe { Orchestrator::new(&entry) };
+
+    let mut command = [0u8; common::COMMAND_SIZE];
+    let mut capabilities = None;
+    let mut states = Vec::new();
+    // The library closes the connection when the child exits.
+    while stream.read_exact(&mut command).is_ok() {
+        assert_eq!(command[0], common::COMMAND_MAGIC);
+        match command[1] {
+            OVERLAY_CAPABILITIES => capabilities = Some(command[3]),
+            OVERLAY_STATE => states.push((command[2], command[3])),
+            WINDOW_RESIZE => {
+                let capabilities = capabilities.expect("capabilities were not reported");
+                assert_ne!(capabilities & OPAQUE_FD, 0, "opaque fds are not supported");
+
+                let height = i32::from_le_bytes(command[2..6].try_into().unwrap()) as u32;
+                let width = i32::from_le_bytes(command[6..10].try_into().unwrap()) as u32;
+                let (fd, size) = unsafe { orchestrator.export_texture(width, height) };
+
+                // The fds in the command are replaced by the one sent along with it.
+                let mut params = Vec::with_capacity(common::COMMAND_SIZE);
+                params.extend_from_slice(&[common::COMMAND_MAGIC, OVERLAY_TEXTURE_FD]);
+                for _ in 0..3 {
+                    params.extend_from_slice(&(-1i32).to_le_bytes());
+                }
+                params.extend_from_slice(&width.to_le_bytes());
+                params.extend_from_slice(&height.to_le_bytes());
+                params.extend_from_slice(&DRM_FORMAT_ARGB8888.to_le_bytes());
+                params.extend_from_slice(&DRM_FORMAT_MOD_INVALID.to_le_bytes());
+                params.extend_from_slice(&(size as u32).to_le_bytes());
+                params.resize(common::COMMAND_SIZE, 0);
+                common::send_with_fd(
+                    &stream,
+                    params.as_slice().try_into().unwrap(),
+                    fd.as_raw_fd(),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    unsafe { orchestrator.destroy() };
+    states
+}
+
+/// Write a manifest for the loss layer in `vulkan-mock-icd` into a directory of its own,
+/// returning the directory.
+fn loss_layer_dir() -> PathBuf {
+    let manifest = format!(
+        r#"{{
+  "file_format_version": "1.2.0",
+  "layer": {{
+    "name": "{}",
+    "type": "GLOBAL",
+    "library_path": "{}",
+    "api_version": "1.3.224",
+    "implementation_version": "1",
+    "description": "Loses devices on request",
+    "functions": {{
+      "vkNegotiateLoaderLayerInterfaceVersion": "mock_layer_NegotiateLoaderLayerInterfaceVersion",
+      "vkGetInstanceProcAddr": "mock_layer_GetInstanceProcAddr",
+      "vkGetDeviceProcAddr": "mock_layer_GetDeviceProcAddr"
+    }}
+  }}
+}}
+"#,
+        LOSS_LAYER_NAME,
+        common::mock_icd_library().display()
+    );
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("mock_device_loss");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("VkLayer_mock_device_loss.json"), manifest).unwrap();
+    dir
+}
+
+/// Resizes, loses the device and toggles fullscreen under an orchestrator, checking that the
+/// overlay is painted before the loss and again after the swapchain is recreated.
+#[test]
+#[ignore = "needs the Vulkan loader and Mesa's lavapipe"]
+fn swapchain_recreation() {
+    if std::env::var_os(CHILD_ENV).is_some() {
+        unsafe { recreate_swapchains() };
+        return;
+    }
+
+    let _environment = ENVIRONMENT.lock().unwrap_or_else(|e| e.into_inner());
+    let layer_path = std::env::join_paths([common::layer_dir(), loss_layer_dir()]).unwrap();
+
+    // The layer connects to the orchestrator when the game creates its device, so the game
+    // runs in a child process of its own.
+    let (runtime_dir, listener) = common::listen_orchestrator("swapchain-recreation");
+    let mut child = Command::new(std::env::current_exe().unwrap())
+        .args([
+            "swapchain_recreation",
+            "--exact",
+            "--nocapture",
+            "--ignored",
+        ])
+        .env(CHILD_ENV, "1")
+        .env("VK_ADD_LAYER_PATH", &layer_path)
+        .env("XDG_RUNTIME_DIR", &runtime_dir)
+        .spawn()
+        .expect("unable to run the test as a game");
+
+    let states = orchestrate(&listener, &mut child);
+    let status = child.wait().unwrap();
+    let _ = std::fs::remove_dir_all(&runtime_dir);
+    assert!(status.success(), "child process failed");
+
+    let lost = states
+        .iter()
+        .position(|state| *state == (OVERLAY_STATE_LOST, FAILURE_DEVICE))
+        .expect("the device loss was not reported");
+    let painted = |(state, _): &(u8, u8)| *state == OVERLAY_STATE_PAINTABLE;
+    assert!(
+        states[..lost].iter().any(painted),
+        "the overlay was not painted before the device was lost"
+    );
+    assert!(
+        states[lost..].iter().any(painted),
+        "the overlay was not painted again after the device was lost"
+    );
+}
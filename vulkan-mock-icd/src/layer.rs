@@ -0,0 +1,277 @@
+//! A layer that reports devices as lost on request, loaded below the layer under test over
+//! a real driver.
+//!
+//! While devices are lost, `vkAcquireNextImageKHR` and `vkQueuePresentKHR` return
+//! `VK_ERROR_DEVICE_LOST` without reaching the driver, and every other command is passed
+//! through. The layer is enabled by name through a manifest that renames its entry points, so
+//! that they do not clash with the driver's.
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_void, CStr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use ash::vk;
+use ash::vk::Handle;
+
+/// `LAYER_NEGOTIATE_INTERFACE_STRUCT`.
+const LAYER_NEGOTIATE_INTERFACE_STRUCT: i32 = 1;
+
+/// `VK_LAYER_LINK_INFO`.
+const LAYER_LINK_INFO: i32 = 0;
+
+/// The loader interface version the layer is negotiated to.
+const LOADER_LAYER_INTERFACE_VERSION: u32 = 2;
+
+/// Whether devices are reported as lost, as set by the test.
+static DEVICES_LOST: AtomicBool = AtomicBool::new(false);
+
+/// The next layer's `vkGetInstanceProcAddr`, for each instance by its dispatch key.
+static INSTANCES: Mutex<Option<HashMap<usize, NextInstance>>> = Mutex::new(None);
+
+type NextInstance = vk::PFN_vkGetInstanceProcAddr;
+
+/// The next layer's implementation of the commands the layer calls, for each device by its
+/// dispatch key, which its queues share. Devices are never forgotten, as a device created
+/// later with the same dispatch key replaces them.
+static DEVICES: Mutex<Option<HashMap<usize, NextDevice>>> = Mutex::new(None);
+
+#[derive(Clone, Copy)]
+struct NextDevice {
+    get_device_proc_addr: vk::PFN_vkGetDeviceProcAddr,
+    acquire_next_image: Option<vk::PFN_vkAcquireNextImageKHR>,
+    queue_present: Option<vk::PFN_vkQueuePresentKHR>,
+}
+
+#[repr(C)]
+pub struct NegotiateLayerInterface {
+    s_type: i32,
+    p_next: *const c_void,
+    loader_layer_interface_version: u32,
+    pfn_get_instance_proc_addr: vk::PFN_vkGetInstanceProcAddr,
+    pfn_get_device_proc_addr: vk::PFN_vkGetDeviceProcAddr,
+    pfn_get_physical_device_proc_addr: vk::PFN_vkVoidFunction,
+}
+
+/// `VkLayerInstanceCreateInfo` or `VkLayerDeviceCreateInfo`, with `function` set to
+/// `VK_LAYER_LINK_INFO`.
+#[repr(C)]
+struct LayerCreateInfo<L> {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    function: i32,
+    p_layer_info: *const L,
+}
+
+#[repr(C)]
+struct LayerInstanceLink {
+    p_next: *const LayerInstanceLink,
+    pfn_next_get_instance_proc_addr: vk::PFN_vkGetInstanceProcAddr,
+    pfn_next_get_physical_device_proc_addr: vk::PFN_vkVoidFunction,
+}
+
+#[repr(C)]
+struct LayerDeviceLink {
+    p_next: *const LayerDeviceLink,
+    pfn_next_get_instance_proc_addr: vk::PFN_vkGetInstanceProcAddr,
+    pfn_next_get_device_proc_addr: vk::PFN_vkGetDeviceProcAddr,
+}
+
+/// Report every device as lost while `lost` is set, or as working again once it is cleared.
+#[no_mangle]
+pub extern "C" fn mock_layer_set_devices_lost(lost: bool) {
+    DEVICES_LOST.store(lost, Ordering::SeqCst);
+}
+
+/// The key the loader dispatches a handle with, which a device shares with its queues.
+unsafe fn dispatch_key<H: Handle>(handle: H) -> usize {
+    *(handle.as_raw() as *const usize)
+}
+
+/// Find the link to the next layer in the create info chain starting at `p_next`, and move
+/// the chain on for the next layer.
+unsafe fn next_link<L>(p_next: *const c_void, s_type: vk::StructureType) -> Option<*const L> {
+    let mut info = p_next as *mut LayerCreateInfo<L>;
+    while !info.is_null() && ((*info).s_type != s_type || (*info).function != LAYER_LINK_INFO) {
+        info = (*info).p_next as *mut LayerCreateInfo<L>;
+    }
+    if info.is_null() {
+        return None;
+    }
+
+    // Every link starts with a pointer to the one after it.
+    let link = (*info).p_layer_info;
+    (*info).p_layer_info = *(link as *const *const L);
+    Some(link)
+}
+
+#[no_mangle]
+pub unsafe extern "system" fn mock_layer_NegotiateLoaderLayerInterfaceVersion(
+    interface: *mut NegotiateLayerInterface,
+) -> vk::Result {
+    if (*interface).s_type != LAYER_NEGOTIATE_INTERFACE_STRUCT
+        || (*interface).loader_layer_interface_version < LOADER_LAYER_INTERFACE_VERSION
+    {
+        return vk::Result::ERROR_INITIALIZATION_FAILED;
+    }
+
+    (*interface).loader_layer_interface_version = LOADER_LAYER_INTERFACE_VERSION;
+    (*interface).pfn_get_instance_proc_addr = mock_layer_GetInstanceProcAddr;
+    (*interface).pfn_get_device_proc_addr = mock_layer_GetDeviceProcAddr;
+    (*interface).pfn_get_physical_device_proc_addr = None;
+    vk::Result::SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "system" fn mock_layer_GetInstanceProcAddr(
+    instance: vk::Instance,
+    p_name: *const c_char,
+) -> vk::PFN_vkVoidFunction {
+    let intercept = proc_addr!(CStr::from_ptr(p_name).to_bytes(), {
+        b"vkGetInstanceProcAddr" => mock_layer_GetInstanceProcAddr: vk::PFN_vkGetInstanceProcAddr,
+        b"vkGetDeviceProcAddr" => mock_layer_GetDeviceProcAddr: vk::PFN_vkGetDeviceProcAddr,
+        b"vkCreateInstance" => create_instance: vk::PFN_vkCreateInstance,
+        b"vkCreateDevice" => create_device: vk::PFN_vkCreateDevice,
+    });
+    if intercept.is_some() || instance == vk::Instance::null() {
+        return intercept;
+    }
+
+    let next = INSTANCES
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|instances| instances.get(&dispatch_key(instance)).copied());
+    next.and_then(|gipa| gipa(instance, p_name))
+}
+
+#[no_mangle]
+pub unsafe extern "system" fn mock_layer_GetDeviceProcAddr(
+    device: vk::Device,
+    p_name: *const c_char,
+) -> vk::PFN_vkVoidFunction {
+    let next = next_device(dispatch_key(device))?;
+
+    // Commands are only intercepted if the next layer implements them.
+    let name = CStr::from_ptr(p_name).to_bytes();
+    match name {
+        b"vkAcquireNextImageKHR" if next.acquire_next_image.is_none() => None,
+        b"vkQueuePresentKHR" if next.queue_present.is_none() => None,
+        _ => proc_addr!(name, {
+            b"vkGetDeviceProcAddr" => mock_layer_GetDeviceProcAddr: vk::PFN_vkGetDeviceProcAddr,
+            b"vkAcquireNextImageKHR" => acquire_next_image: vk::PFN_vkAcquireNextImageKHR,
+            b"vkQueuePresentKHR" => queue_present: vk::PFN_vkQueuePresentKHR,
+        })
+        .or_else(|| (next.get_device_proc_addr)(device, p_name)),
+    }
+}
+
+fn next_device(key: usize) -> Option<NextDevice> {
+    DEVICES
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|devices| devices.get(&key).copied())
+}
+
+unsafe extern "system" fn create_instance(
+    p_create_info: *const vk::InstanceCreateInfo,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_instance: *mut vk::Instance,
+) -> vk::Result {
+    let link = match next_link::<LayerInstanceLink>(
+        (*p_create_info).p_next,
+        vk::StructureType::LOADER_INSTANCE_CREATE_INFO,
+    ) {
+        Some(link) => link,
+        None => return vk::Result::ERROR_INITIALIZATION_FAILED,
+    };
+    let gipa = (*link).pfn_next_get_instance_proc_addr;
+
+    let create: vk::PFN_vkCreateInstance =
+        match gipa(vk::Instance::null(), b"vkCreateInstance\0".as_ptr().cast()) {
+            Some(fp) => std::mem::transmute(fp),
+            None => return vk::Result::ERROR_INITIALIZATION_FAILED,
+        };
+    let result = create(p_create_info, p_allocator, p_instance);
+    if result == vk::Result::SUCCESS {
+        INSTANCES
+            .lock()
+            .unwrap()
+            .get_or_insert_with(HashMap::new)
+            .insert(dispatch_key(*p_instance), gipa);
+    }
+    result
+}
+
+unsafe extern "system" fn create_device(
+    physical_device: vk::PhysicalDevice,
+    p_create_info: *const vk::DeviceCreateInfo,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_device: *mut vk::Device,
+) -> vk::Result {
+    let link = match next_link::<LayerDeviceLink>(
+        (*p_create_info).p_next,
+        vk::StructureType::LOADER_DEVICE_CREATE_INFO,
+    ) {
+        Some(link) => link,
+        None => return vk::Result::ERROR_INITIALIZATION_FAILED,
+    };
+    let gipa = (*link).pfn_next_get_instance_proc_addr;
+    let gdpa = (*link).pfn_next_get_device_proc_addr;
+
+    let create: vk::PFN_vkCreateDevice =
+        match gipa(vk::Instance::null(), b"vkCreateDevice\0".as_ptr().cast()) {
+            Some(fp) => std::mem::transmute(fp),
+            None => return vk::Result::ERROR_INITIALIZATION_FAILED,
+        };
+    let result = create(physical_device, p_create_info, p_allocator, p_device);
+    if result != vk::Result::SUCCESS {
+        return result;
+    }
+
+    let device = *p_device;
+    let next = NextDevice {
+        get_device_proc_addr: gdpa,
+        acquire_next_image: gdpa(device, b"vkAcquireNextImageKHR\0".as_ptr().cast())
+            .map(|fp| std::mem::transmute(fp)),
+        queue_present: gdpa(device, b"vkQueuePresentKHR\0".as_ptr().cast())
+            .map(|fp| std::mem::transmute(fp)),
+    };
+    DEVICES
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(dispatch_key(device), next);
+    result
+}
+
+unsafe extern "system" fn acquire_next_image(
+    device: vk::Device,
+    swapchain: vk::SwapchainKHR,
+    timeout: u64,
+    semaphore: vk::Semaphore,
+    fence: vk::Fence,
+    p_image_index: *mut u32,
+) -> vk::Result {
+    if DEVICES_LOST.load(Ordering::SeqCst) {
+        return vk::Result::ERROR_DEVICE_LOST;
+    }
+    match next_device(dispatch_key(device)).and_then(|next| next.acquire_next_image) {
+        Some(fp) => fp(device, swapchain, timeout, semaphore, fence, p_image_index),
+        None => vk::Result::ERROR_DEVICE_LOST,
+    }
+}
+
+unsafe extern "system" fn queue_present(
+    queue: vk::Queue,
+    p_present_info: *const vk::PresentInfoKHR,
+) -> vk::Result {
+    if DEVICES_LOST.load(Ordering::SeqCst) {
+        return vk::Result::ERROR_DEVICE_LOST;
+    }
+    match next_device(dispatch_key(queue)).and_then(|next| next.queue_present) {
+        Some(fp) => fp(queue, p_present_info),
+        None => vk::Result::ERROR_DEVICE_LOST,
+    }
+}